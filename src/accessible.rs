@@ -0,0 +1,164 @@
+use anyhow::Result;
+
+use crate::split;
+use crate::timing::{EntryKind, TimingEntry};
+use crate::vt::Lines;
+
+// Characters progress bars are drawn with
+const BAR_CHARS: &str = "#=█▓▒░■▉▊▋▌▍▎▏▇▆▅▄▃▂▁━─";
+
+// `script export --format text`: the recording as plain text for a screen
+// reader to take a line at a time. Each command is labeled, and so is what
+// it showed:
+//
+//   Recording session.log, 2 commands.
+//
+//   Command 1 of 2: ls
+//   Output, 1 line:
+//   a b
+//   Took 0.5 seconds.
+//
+// The output is read as it showed, without colors, cursor movement or any
+// other control character. A run of progress updates is cut down to the
+// last, runs of a repeated symbol, such as the bar itself or a ruled line,
+// are left out, and runs of spaces read as one. A recording without typed
+// input has its output as one block.
+pub fn render(title: &str, entries: &[TimingEntry], data: &[u8]) -> Result<String> {
+    let commands = split::commands(&split::split(entries, data)?);
+    if commands.is_empty() {
+        let mut text = format!("Recording {}, no commands typed.\n\n", title);
+        output(&mut text, &output_lines(entries, data));
+        return Ok(text);
+    }
+
+    let count = commands.len();
+    let mut text = format!("Recording {}, {} command{}.\n", title, count, if count == 1 { "" } else { "s" });
+    for (i, command) in commands.iter().enumerate() {
+        text.push_str(&format!("\nCommand {} of {}: {}\n", i + 1, count, clean(&command.command)));
+        output(&mut text, &command.output);
+        let took = format!("{:.1} seconds", command.duration.as_secs_f64());
+        match command.exit_code {
+            Some(0) | None => text.push_str(&format!("Took {}.\n", took)),
+            Some(code) => text.push_str(&format!("Failed with exit code {}, after {}.\n", code, took)),
+        }
+    }
+    Ok(text)
+}
+
+// All the output, for a recording that can't be cut into commands
+fn output_lines(entries: &[TimingEntry], data: &[u8]) -> Vec<String> {
+    let mut lines = Lines::default();
+    let mut output = Vec::new();
+    let mut pos = 0;
+    for entry in entries {
+        match entry.kind {
+            EntryKind::Output(size) => {
+                let end = (pos + size).min(data.len());
+                output.extend(lines.feed(&data[pos..end]));
+                pos = end;
+            }
+            EntryKind::Input(size) => pos = (pos + size).min(data.len()),
+            _ => {}
+        }
+    }
+    output.push(lines.take());
+    output
+}
+
+fn output(text: &mut String, lines: &[String]) {
+    let mut kept = Vec::new();
+    let mut collapsed = 0;
+    for line in lines {
+        let cleaned = clean(line);
+        if cleaned.is_empty() {
+            continue;
+        }
+        let progress = is_progress(line);
+        if progress && kept.last().is_some_and(|(_, last)| *last) {
+            kept.pop();
+            collapsed += 1;
+        }
+        kept.push((cleaned, progress));
+    }
+
+    match kept.len() {
+        0 => text.push_str("No output.\n"),
+        1 => text.push_str("Output, 1 line:\n"),
+        n => text.push_str(&format!("Output, {} lines:\n", n)),
+    }
+    for (line, _) in &kept {
+        text.push_str(line);
+        text.push('\n');
+    }
+    match collapsed {
+        0 => {}
+        1 => text.push_str("1 progress update left out.\n"),
+        n => text.push_str(&format!("{} progress updates left out.\n", n)),
+    }
+}
+
+// A percentage, or a bar of four or more
+fn is_progress(line: &str) -> bool {
+    let chars: Vec<char> = line.chars().collect();
+    let percent = chars.iter().enumerate().any(|(i, &c)| c == '%' && i > 0 && chars[i - 1].is_ascii_digit());
+    percent || chars.windows(4).any(|run| run.iter().all(|&c| BAR_CHARS.contains(c)))
+}
+
+// The line without control characters and runs of a repeated symbol, its
+// spaces squeezed
+fn clean(line: &str) -> String {
+    let chars: Vec<char> = line.chars().filter(|c| !c.is_control()).collect();
+    let mut cleaned = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let run = chars[i..].iter().take_while(|&&next| next == c).count();
+        if run >= 4 && !c.is_alphanumeric() && !c.is_whitespace() {
+            cleaned.push(' ');
+        } else {
+            cleaned.extend(&chars[i..i + run]);
+        }
+        i += run;
+    }
+    cleaned.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::parse_timing;
+
+    #[test]
+    fn test_text_is_labeled_and_quiet() {
+        let records = [
+            ("O 0.1", "$ "),
+            ("I 1.0", "ls\r"),
+            ("O 0.0", "ls\r\n"),
+            ("O 0.5", "a  b\r\n$ "),
+            ("I 2.0", "make\r"),
+            ("O 0.0", "make\r\n"),
+            ("O 1.5", "\x1b[31mError\x1b[0m\r\n[####      ] 40%\r\n[##########] 100%\r\n──────────\r\ndone\r\n"),
+            ("O 0.0", "$ "),
+        ];
+        let mut timing = String::new();
+        for (record, data) in &records[..7] {
+            timing.push_str(&format!("{} {}\n", record, data.len()));
+        }
+        timing.push_str("S 0.0 COMMAND_END EXIT_CODE=2\nO 0.0 2\n");
+        let data: String = records.iter().map(|(_, data)| *data).collect();
+
+        assert_eq!(
+            render("s.log", &parse_timing(&timing).unwrap(), data.as_bytes()).unwrap(),
+            "Recording s.log, 2 commands.\n\n\
+             Command 1 of 2: ls\nOutput, 1 line:\na b\nTook 0.5 seconds.\n\n\
+             Command 2 of 2: make\nOutput, 3 lines:\nError\n[ ] 100%\ndone\n1 progress update left out.\n\
+             Failed with exit code 2, after 1.5 seconds.\n"
+        );
+
+        let entries = parse_timing("O 0.1 5\n").unwrap();
+        assert_eq!(
+            render("s.log", &entries, b"hi\r\n\x07").unwrap(),
+            "Recording s.log, no commands typed.\n\nOutput, 1 line:\nhi\n"
+        );
+    }
+}
@@ -0,0 +1,141 @@
+// Private modes that switch the terminal to and from the alternate screen
+const ALT_SCREEN_MODES: [u32; 3] = [47, 1047, 1049];
+
+// Longest private mode sequence we keep around when a read splits it
+const MAX_PENDING: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AltScreenTransition {
+    // Offsets into the fed buffer where the switching sequence starts and
+    // just past where it ends
+    pub start: usize,
+    pub offset: usize,
+    pub active: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct AltScreenTracker {
+    active: bool,
+    pending: Vec<u8>,
+}
+
+impl AltScreenTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn feed(&mut self, data: &[u8]) -> Vec<AltScreenTransition> {
+        let mut transitions = Vec::new();
+
+        // Bytes carried over from the previous read come first; offsets are
+        // reported relative to `data`, so clamp anything inside the carry
+        let carried = self.pending.len();
+        let mut buf = std::mem::take(&mut self.pending);
+        buf.extend_from_slice(data);
+
+        let mut i = 0;
+        while i < buf.len() {
+            if buf[i] != 0x1b {
+                i += 1;
+                continue;
+            }
+
+            let start = i;
+            match parse_private_mode(&buf[i..]) {
+                Parse::Incomplete => {
+                    if buf.len() - i <= MAX_PENDING {
+                        self.pending = buf[i..].to_vec();
+                    }
+                    break;
+                }
+                Parse::NotMode => i += 1,
+                Parse::Mode { len, params, set } => {
+                    i += len;
+                    let switches = params.iter().any(|p| ALT_SCREEN_MODES.contains(p));
+                    if switches && set != self.active {
+                        self.active = set;
+                        transitions.push(AltScreenTransition {
+                            start: start.saturating_sub(carried),
+                            offset: i.saturating_sub(carried),
+                            active: set,
+                        });
+                    }
+                }
+            }
+        }
+
+        transitions
+    }
+}
+
+enum Parse {
+    Incomplete,
+    NotMode,
+    Mode { len: usize, params: Vec<u32>, set: bool },
+}
+
+// Parses `ESC [ ? Pm h` / `ESC [ ? Pm l` at the start of `buf`
+fn parse_private_mode(buf: &[u8]) -> Parse {
+    let prefix = b"\x1b[?";
+    if buf.len() < prefix.len() {
+        return if prefix.starts_with(buf) { Parse::Incomplete } else { Parse::NotMode };
+    }
+    if !buf.starts_with(prefix) {
+        return Parse::NotMode;
+    }
+
+    let mut params = Vec::new();
+    let mut current: Option<u32> = None;
+    for (i, &b) in buf.iter().enumerate().skip(prefix.len()) {
+        match b {
+            b'0'..=b'9' => {
+                let digit = (b - b'0') as u32;
+                current = Some(current.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+            }
+            b';' => params.push(current.take().unwrap_or(0)),
+            b'h' | b'l' => {
+                params.extend(current);
+                return Parse::Mode { len: i + 1, params, set: b == b'h' };
+            }
+            _ => return Parse::NotMode,
+        }
+    }
+
+    Parse::Incomplete
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enter_and_leave() {
+        let mut tracker = AltScreenTracker::new();
+        let t = tracker.feed(b"ab\x1b[?1049hvim\x1b[?1049lcd");
+        assert_eq!(
+            t,
+            vec![
+                AltScreenTransition { start: 2, offset: 10, active: true },
+                AltScreenTransition { start: 13, offset: 21, active: false },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sequence_split_across_reads() {
+        let mut tracker = AltScreenTracker::new();
+        assert!(tracker.feed(b"less\x1b[?10").is_empty());
+        let t = tracker.feed(b"47;1h");
+        assert_eq!(t, vec![AltScreenTransition { start: 0, offset: 5, active: true }]);
+    }
+
+    #[test]
+    fn test_other_modes_ignored() {
+        let mut tracker = AltScreenTracker::new();
+        assert!(tracker.feed(b"\x1b[?25l\x1b[?2004h\x1b[31m").is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::clock::Clock;
+use crate::i18n::{tr, Message};
+use crate::logging::{LogSink, LogStream, SessionInfo, TIMESTAMP_FORMAT};
+use crate::schema::SCHEMA_VERSION;
+
+// Analyzers are commands run once the session is over. Each gets the session
+// as JSON lines on stdin and prints its annotations as one JSON value; the
+// results go to the metadata sidecar next to the log.
+pub struct Analyzers {
+    commands: Vec<String>,
+    spool: PathBuf,
+}
+
+// Writes the session JSONL the analyzers read:
+//
+//   {"type":"start","schema_version":1,"time":"...","command":...,"term":...,"cols":80,"lines":24}
+//   {"type":"o","t":0.25,"data":"..."}          ("i" for input)
+//   {"type":"event","t":1.5,"name":"SIGWINCH","message":"ROWS=24 COLS=80"}
+//   {"type":"close","t":2.0,"exit_code":0}
+pub struct SpoolSink {
+    writer: Option<BufWriter<File>>,
+    clock: Arc<dyn Clock>,
+    start: Option<Instant>,
+}
+
+impl Analyzers {
+    pub fn new(commands: Vec<String>, clock: Arc<dyn Clock>) -> Result<(Analyzers, SpoolSink)> {
+        let spool = std::env::temp_dir().join(format!("rust_script-analyze-{}.jsonl", std::process::id()));
+        // The session may hold anything typed, so only we can read it
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&spool)
+            .with_context(|| format!("Cannot create {}", spool.display()))?;
+
+        let sink = SpoolSink {
+            writer: Some(BufWriter::new(file)),
+            clock,
+            start: None,
+        };
+        Ok((Analyzers { commands, spool }, sink))
+    }
+
+    // Run every analyzer and write what they said to the sidecar, next to
+    // what --meta put there with `merge`. An analyzer that fails is reported
+    // and recorded, but doesn't fail the session.
+    pub fn run(&self, sidecar: &Path, merge: bool) -> Result<()> {
+        let mut results = Vec::new();
+        for command in &self.commands {
+            match self.analyze(command) {
+                Ok(annotations) => results.push(json!({ "command": command, "annotations": annotations })),
+                Err(e) => {
+                    eprint!("{}\r\n", tr(Message::AnalyzerFailed, &[command, &format!("{:#}", e)]));
+                    results.push(json!({ "command": command, "error": format!("{:#}", e) }));
+                }
+            }
+        }
+
+        let mut metadata = match std::fs::read_to_string(sidecar) {
+            Ok(content) if merge => serde_json::from_str(&content).unwrap_or_else(|_| json!({})),
+            _ => json!({}),
+        };
+        metadata["schema_version"] = json!(SCHEMA_VERSION);
+        metadata["analyzers"] = json!(results);
+        std::fs::write(sidecar, serde_json::to_string_pretty(&metadata)? + "\n")
+            .with_context(|| format!("Cannot write {}", sidecar.display()))
+    }
+
+    fn analyze(&self, command: &str) -> Result<Value> {
+        let output = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(File::open(&self.spool)?)
+            .stderr(Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            return Err(anyhow!("{}", output.status));
+        }
+        serde_json::from_slice(&output.stdout).context("output is not JSON")
+    }
+}
+
+impl Drop for Analyzers {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.spool);
+    }
+}
+
+impl SpoolSink {
+    fn elapsed(&self) -> f64 {
+        let secs = self.start.map_or(0.0, |start| self.clock.now().duration_since(start).as_secs_f64());
+        (secs * 1e6).round() / 1e6
+    }
+
+    fn write(&mut self, record: Value) -> Result<()> {
+        let writer = self.writer.as_mut().ok_or_else(|| anyhow!("Analyzer spool is closed"))?;
+        writeln!(writer, "{}", record)?;
+        Ok(())
+    }
+}
+
+impl LogSink for SpoolSink {
+    fn start(&mut self, session: &SessionInfo) -> Result<()> {
+        self.start = Some(self.clock.now());
+        let time = self.clock.wall().format(TIMESTAMP_FORMAT).to_string();
+        self.write(json!({
+            "type": "start",
+            "schema_version": SCHEMA_VERSION,
+            "time": time,
+            "command": session.command,
+            "term": session.tty_type,
+            "cols": session.cols,
+            "lines": session.lines,
+        }))
+    }
+
+    // Not counted toward -o; the spool isn't one of the logs
+    fn log_data(&mut self, stream: LogStream, data: &[u8]) -> Result<usize> {
+        let kind = match stream {
+            LogStream::Input => "i",
+            LogStream::Output => "o",
+            LogStream::Stderr => "e",
+        };
+        let t = self.elapsed();
+        self.write(json!({ "type": kind, "t": t, "data": String::from_utf8_lossy(data) }))?;
+        Ok(0)
+    }
+
+    fn log_event(&mut self, name: &str, message: Option<&str>) -> Result<()> {
+        let t = self.elapsed();
+        self.write(json!({ "type": "event", "t": t, "name": name, "message": message }))
+    }
+
+    fn close(&mut self, exit_status: i32) -> Result<()> {
+        let t = self.elapsed();
+        self.write(json!({ "type": "close", "t": t, "exit_code": exit_status }))?;
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use chrono::DateTime;
+    use std::time::Duration;
+
+    #[test]
+    fn test_analyzers_annotate_the_session() {
+        let start = DateTime::parse_from_rfc3339("2024-01-02T03:04:05+00:00").unwrap();
+        let clock = Arc::new(ManualClock::new(start));
+        let commands = vec![
+            "printf '{\"records\": %d}' $(wc -l)".to_string(),
+            "grep -c '\"type\":\"o\"' >/dev/null && echo not json".to_string(),
+        ];
+        let (analyzers, mut spool) = Analyzers::new(commands, clock.clone()).unwrap();
+
+        spool.start(&SessionInfo::default()).unwrap();
+        clock.advance(Duration::from_millis(250));
+        spool.log_data(LogStream::Output, b"$ ").unwrap();
+        spool.log_event("SIGWINCH", Some("ROWS=24 COLS=80")).unwrap();
+        spool.close(0).unwrap();
+
+        let content = std::fs::read_to_string(&analyzers.spool).unwrap();
+        assert_eq!(content.lines().nth(1).unwrap(), r#"{"data":"$ ","t":0.25,"type":"o"}"#);
+        #[cfg(feature = "schemas")]
+        for line in content.lines() {
+            serde_json::from_str::<crate::schema::Event>(line).unwrap();
+        }
+
+        let sidecar = std::env::temp_dir().join(format!("rust_script-{}-analyzed.meta.json", std::process::id()));
+        analyzers.run(&sidecar, false).unwrap();
+        let metadata: Value = serde_json::from_str(&std::fs::read_to_string(&sidecar).unwrap()).unwrap();
+        std::fs::remove_file(&sidecar).unwrap();
+
+        assert_eq!(metadata["analyzers"][0]["annotations"], json!({ "records": 4 }));
+        assert_eq!(metadata["analyzers"][1]["error"], "output is not JSON: expected ident at line 1 column 2");
+        #[cfg(feature = "schemas")]
+        serde_json::from_value::<crate::schema::Metadata>(metadata).unwrap();
+
+        let spool_path = analyzers.spool.clone();
+        drop(analyzers);
+        assert!(!spool_path.exists());
+    }
+}
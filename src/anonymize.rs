@@ -0,0 +1,362 @@
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::timing::{EntryKind, TimingEntry};
+
+// Paths under these are the same on every machine and stay as they are
+const SYSTEM_DIRS: [&str; 8] = ["bin", "dev", "lib", "lib64", "proc", "sbin", "sys", "usr"];
+
+// Endings that make a dotted word a host name rather than a file name
+const DOMAINS: [&str; 14] = [
+    "com", "net", "org", "io", "dev", "app", "cloud", "local", "lan", "internal", "corp", "intra", "home", "arpa",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Ip,
+    Host,
+    User,
+    Path,
+}
+
+// Replaces host names, IPv4 addresses, user names and file paths with
+// pseudonyms such as host-1, 192.0.2.1, user-1 and /etc/path-1. What each
+// original became is kept in a mapping file (tab separated, original first),
+// so the same name gets the same pseudonym in every recording anonymized
+// with it. Entries added to the file by hand are replaced too, wherever
+// they show as a word.
+pub struct Anonymizer {
+    path: PathBuf,
+    // In the order they were found, for the mapping file
+    names: Vec<(String, String)>,
+    pseudonyms: HashMap<String, String>,
+    used: HashSet<String>,
+    counters: HashMap<Kind, usize>,
+    changed: bool,
+}
+
+struct Match {
+    start: usize,
+    end: usize,
+    replacement: String,
+}
+
+fn is_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'.' | b'-' | b'_')
+}
+
+fn is_path_byte(b: u8) -> bool {
+    is_name_byte(b) || matches!(b, b'/' | b'+' | b'~')
+}
+
+fn is_ipv4(word: &str) -> bool {
+    let parts: Vec<&str> = word.split('.').collect();
+    parts.len() == 4
+        && parts.iter().all(|p| (1..=3).contains(&p.len()) && p.bytes().all(|b| b.is_ascii_digit()) && p.parse::<u8>().is_ok())
+}
+
+fn is_host(word: &str) -> bool {
+    let labels: Vec<&str> = word.split('.').collect();
+    labels.len() >= 3
+        && labels.iter().all(|l| !l.is_empty() && !l.starts_with('-'))
+        && DOMAINS.contains(&labels[labels.len() - 1].to_lowercase().as_str())
+}
+
+fn is_user(word: &str) -> bool {
+    let mut bytes = word.bytes();
+    bytes.next().is_some_and(|b| b.is_ascii_lowercase() || b == b'_')
+        && bytes.all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || matches!(b, b'_' | b'-' | b'.'))
+}
+
+// Who and where this runs, which a recording made here is sure to show
+fn local_names() -> Vec<(Kind, String)> {
+    let mut names = Vec::new();
+    if let Ok(host) = std::fs::read_to_string("/proc/sys/kernel/hostname") {
+        let host = host.trim();
+        if !host.is_empty() && host != "localhost" {
+            names.push((Kind::Host, host.to_string()));
+        }
+    }
+    if let Ok(Some(user)) = nix::unistd::User::from_uid(nix::unistd::getuid()) {
+        if user.name != "root" {
+            names.push((Kind::User, user.name));
+        }
+    }
+    names
+}
+
+impl Anonymizer {
+    pub fn open(path: &Path) -> Result<Anonymizer> {
+        let mut anonymizer = Anonymizer {
+            path: path.to_path_buf(),
+            names: Vec::new(),
+            pseudonyms: HashMap::new(),
+            used: HashSet::new(),
+            counters: HashMap::new(),
+            changed: false,
+        };
+        if path.exists() {
+            let content = std::fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+            let lines = content.lines().filter(|l| !l.trim().is_empty() && !l.starts_with('#'));
+            for line in lines {
+                if let Some((original, pseudonym)) = line.split_once('\t') {
+                    anonymizer.insert(original.to_string(), pseudonym.to_string());
+                }
+            }
+            anonymizer.changed = false;
+        }
+        for (kind, name) in local_names() {
+            anonymizer.pseudonym(kind, &name);
+        }
+        Ok(anonymizer)
+    }
+
+    fn insert(&mut self, original: String, pseudonym: String) {
+        self.used.insert(pseudonym.clone());
+        self.pseudonyms.insert(original.clone(), pseudonym.clone());
+        self.names.push((original, pseudonym));
+        self.changed = true;
+    }
+
+    fn pseudonym(&mut self, kind: Kind, original: &str) -> String {
+        if let Some(pseudonym) = self.pseudonyms.get(original) {
+            return pseudonym.clone();
+        }
+        let counter = self.counters.entry(kind).or_insert(0);
+        let pseudonym = loop {
+            *counter += 1;
+            let n = *counter;
+            let candidate = match kind {
+                Kind::Ip if n < 255 => format!("192.0.2.{}", n),
+                Kind::Ip => format!("ip-{}", n),
+                Kind::Host => format!("host-{}", n),
+                Kind::User => format!("user-{}", n),
+                // Only the part after the top directory is replaced
+                Kind::Path => {
+                    let top = original.split('/').nth(1).unwrap_or_default();
+                    format!("/{}/path-{}", top, n)
+                }
+            };
+            if !self.used.contains(&candidate) {
+                break candidate;
+            }
+        };
+        self.insert(original.to_string(), pseudonym.clone());
+        pseudonym
+    }
+
+    fn find(&mut self, data: &[u8]) -> Vec<Match> {
+        let mut matches = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            let after_word = i > 0 && is_name_byte(data[i - 1]);
+            if data[i] == b'/' && !(i > 0 && is_path_byte(data[i - 1])) {
+                let end = data[i..].iter().position(|&b| !is_path_byte(b)).map_or(data.len(), |n| i + n);
+                let path = String::from_utf8_lossy(&data[i..end]);
+                let path = path.trim_end_matches(['/', '.']);
+                let mut parts = path.split('/').skip(1);
+                let top = parts.next().unwrap_or_default();
+                if parts.next().is_some() && !SYSTEM_DIRS.contains(&top) {
+                    let path = path.to_string();
+                    let replacement = self.pseudonym(Kind::Path, &path);
+                    matches.push(Match { start: i, end: i + path.len(), replacement });
+                }
+                i = end.max(i + 1);
+                continue;
+            }
+            if !data[i].is_ascii_alphanumeric() || after_word {
+                i += 1;
+                continue;
+            }
+
+            let end = data[i..].iter().position(|&b| !is_name_byte(b)).map_or(data.len(), |n| i + n);
+            // Sentences end in dots too
+            let word = String::from_utf8_lossy(&data[i..end]).trim_end_matches('.').to_string();
+            let word_end = i + word.len();
+            if data.get(word_end) == Some(&b'@') && is_user(&word) {
+                let replacement = self.pseudonym(Kind::User, &word);
+                matches.push(Match { start: i, end: word_end, replacement });
+                i = word_end + 1;
+                // user@host: what follows is a host
+                let host_end = data[i..]
+                    .iter()
+                    .position(|&b| !(b.is_ascii_alphanumeric() || matches!(b, b'.' | b'-')))
+                    .map_or(data.len(), |n| i + n);
+                let host = String::from_utf8_lossy(&data[i..host_end]).trim_end_matches('.').to_string();
+                if !host.is_empty() {
+                    let kind = if is_ipv4(&host) { Kind::Ip } else { Kind::Host };
+                    let replacement = self.pseudonym(kind, &host);
+                    matches.push(Match { start: i, end: i + host.len(), replacement });
+                }
+                i = host_end.max(i + 1);
+                continue;
+            }
+
+            let replacement = if is_ipv4(&word) {
+                Some(self.pseudonym(Kind::Ip, &word))
+            } else if is_host(&word) {
+                Some(self.pseudonym(Kind::Host, &word))
+            } else {
+                self.pseudonyms.get(&word).cloned()
+            };
+            if let Some(replacement) = replacement {
+                matches.push(Match { start: i, end: word_end, replacement });
+            }
+            i = end.max(i + 1);
+        }
+        matches
+    }
+
+    pub fn text(&mut self, text: &str) -> String {
+        let chunks = self.stream(&[text.as_bytes()]);
+        String::from_utf8_lossy(&chunks[0]).into_owned()
+    }
+
+    // One stream cut into chunks; a name split across chunks is replaced in
+    // the chunk it starts in, and left out of the ones after
+    pub fn stream(&mut self, chunks: &[&[u8]]) -> Vec<Vec<u8>> {
+        let data = chunks.concat();
+        let mut ends = Vec::with_capacity(chunks.len());
+        let mut end = 0;
+        for chunk in chunks {
+            end += chunk.len();
+            ends.push(end);
+        }
+
+        let matches = self.find(&data);
+        let mut out = vec![Vec::new(); chunks.len()];
+        let mut next = matches.iter().peekable();
+        let (mut i, mut c) = (0, 0);
+        while i < data.len() {
+            while i >= ends[c] {
+                c += 1;
+            }
+            match next.next_if(|m| m.start == i) {
+                Some(m) => {
+                    out[c].extend_from_slice(m.replacement.as_bytes());
+                    i = m.end;
+                }
+                None => {
+                    out[c].push(data[i]);
+                    i += 1;
+                }
+            }
+        }
+        out
+    }
+
+    // Anonymize the input and output streams of a recording, each as a
+    // whole, and the values of its info records
+    pub fn recording(&mut self, entries: &mut [TimingEntry], data: &[u8]) -> Vec<u8> {
+        let mut input = Vec::new();
+        let mut output = Vec::new();
+        let mut pos = 0;
+        for entry in entries.iter() {
+            match entry.kind {
+                EntryKind::Input(size) => {
+                    input.push(&data[pos..pos + size]);
+                    pos += size;
+                }
+                EntryKind::Output(size) => {
+                    output.push(&data[pos..pos + size]);
+                    pos += size;
+                }
+                _ => {}
+            }
+        }
+        let mut input = self.stream(&input).into_iter();
+        let mut output = self.stream(&output).into_iter();
+
+        let mut anonymized = Vec::with_capacity(data.len());
+        for entry in entries.iter_mut() {
+            let chunk = match entry.kind {
+                EntryKind::Input(ref mut size) => input.next().map(|chunk| (size, chunk)),
+                EntryKind::Output(ref mut size) => output.next().map(|chunk| (size, chunk)),
+                EntryKind::Info(_, ref mut value) => {
+                    *value = self.text(value);
+                    None
+                }
+                _ => None,
+            };
+            if let Some((size, chunk)) = chunk {
+                *size = chunk.len();
+                anonymized.extend_from_slice(&chunk);
+            }
+        }
+        anonymized
+    }
+
+    // Write out the mapping, when anything was added to it; it holds the
+    // originals, so only the owner may read it
+    pub fn save(&self) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        let mut content = String::from("# original\tpseudonym\n");
+        for (original, pseudonym) in &self.names {
+            content.push_str(&format!("{}\t{}\n", original, pseudonym));
+        }
+        std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .with_context(|| format!("Cannot write {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::{self, parse_timing};
+
+    fn anonymizer(path: &Path) -> Anonymizer {
+        let mut anonymizer = Anonymizer::open(path).unwrap();
+        // Whoever runs the tests is on the list already
+        anonymizer.names.clear();
+        anonymizer.pseudonyms.clear();
+        anonymizer.used.clear();
+        anonymizer.counters.clear();
+        anonymizer
+    }
+
+    #[test]
+    fn test_names_become_pseudonyms() {
+        let path = std::env::temp_dir().join(format!("rust_script-{}-mapping.tsv", std::process::id()));
+        let mut anon = anonymizer(&path);
+        assert_eq!(
+            anon.text("deploy@web01:~$ ssh 10.1.2.3 cat /etc/nginx/nginx.conf /usr/bin/env /tmp"),
+            "user-1@host-1:~$ ssh 192.0.2.1 cat /etc/path-1 /usr/bin/env /tmp"
+        );
+        assert_eq!(anon.text("web01 is db.prod.example.com, v1.2.3.4a, file.tar.gz"), "host-1 is host-2, v1.2.3.4a, file.tar.gz");
+        assert_eq!(anon.text("cd /home/deploy/app/."), "cd /home/path-2/.");
+        anon.save().unwrap();
+
+        // The same names get the same pseudonyms next time
+        let mut anon = Anonymizer::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(anon.text("ping 10.1.2.3"), "ping 192.0.2.1");
+        assert_eq!(anon.text("ping 10.1.2.4"), "ping 192.0.2.2");
+    }
+
+    #[test]
+    fn test_names_split_across_records() {
+        let path = std::env::temp_dir().join(format!("rust_script-{}-split.tsv", std::process::id()));
+        let mut anon = anonymizer(&path);
+        let mut entries = parse_timing("H 0.000000 COMMAND ssh 10.0.0.7\nO 0.1 6\nI 0.1 2\nO 0.1 5\nO 0.1 3\n").unwrap();
+        let data = anon.recording(&mut entries, b"at 10.ls0.0.7 ok");
+
+        assert_eq!(data, b"at 192.0.2.1ls ok");
+        assert_eq!(timing::info(&entries, "COMMAND"), Some("ssh 192.0.2.1"));
+        let sizes: Vec<_> = entries.iter().filter_map(|e| match e.kind {
+            EntryKind::Input(size) | EntryKind::Output(size) => Some(size),
+            _ => None,
+        }).collect();
+        assert_eq!(sizes, vec![12, 2, 0, 3]);
+    }
+}
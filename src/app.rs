@@ -0,0 +1,241 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+
+#[cfg(feature = "vt")]
+use crate::blame;
+#[cfg(feature = "export")]
+use crate::export::{self, ExportOptions, Watermark};
+#[cfg(feature = "grep")]
+use crate::grep;
+#[cfg(feature = "notebook")]
+use crate::notebook;
+#[cfg(feature = "review")]
+use crate::review;
+#[cfg(feature = "screenshot")]
+use crate::screenshot;
+#[cfg(feature = "selfextract")]
+use crate::selfextract;
+#[cfg(feature = "timeline")]
+use crate::timeline;
+use crate::cli::{Args, Commands};
+use crate::config::{self, Config};
+use crate::failure::{self, Failure};
+use crate::i18n::{tr, Message};
+use crate::replay::ReplayOptions;
+use crate::script_control::ScriptControl;
+use crate::spool::SpoolerOptions;
+use crate::{archive, control, convert, features, history, init, live, replay, schema, selftest, smooth, split, spool, stats, syncreplay, utils};
+
+// The `script` command; main.rs runs it on a single-threaded runtime
+pub async fn main() -> Result<()> {
+    // A player written by `script export --self-extracting` only plays
+    #[cfg(feature = "selfextract")]
+    if let Some(recording) = selfextract::embedded() {
+        return selfextract::play(&recording);
+    }
+
+    let mut args = Args::parse();
+
+    if args.features {
+        print!("{}", features::report());
+        return Ok(());
+    }
+
+    let result = match args.subcommand.take() {
+        Some(Commands::Selftest { soak, max_rss }) => selftest::run(&soak, &max_rss).await.map(|_| 0),
+        Some(Commands::Replay {
+            timing,
+            log_io,
+            identity,
+            tmux_passthrough,
+            interactive,
+            start,
+            max_delay,
+            divisor,
+            speed,
+            throttle,
+            images,
+            resume_style,
+            typescript,
+        }) => {
+            let (timing, log_io, identity, typescript) =
+                (timing.as_deref(), log_io.as_deref(), identity.as_deref(), typescript.as_deref());
+            let options = ReplayOptions {
+                tmux: &tmux_passthrough,
+                interactive,
+                start: start.as_deref(),
+                max_delay,
+                divisor,
+                speed: speed.as_deref(),
+                throttle: throttle.as_deref(),
+                images: &images,
+                resume_style: &resume_style,
+            };
+            replay::run(timing, log_io, identity, typescript, &options).map(|_| 0)
+        }
+        Some(Commands::SyncReplay { skew, recordings }) => syncreplay::run(&recordings, &skew).map(|_| 0),
+        Some(Commands::Last { identity, tmux_passthrough }) => {
+            history::replay_last(identity.as_deref(), &tmux_passthrough).map(|_| 0)
+        }
+        Some(Commands::Redo) => redo().await,
+        Some(Commands::Init { config }) => init::run(config.as_deref()).map(|_| 0),
+        Some(Commands::Live { timing, log_in, log_io, command }) => {
+            live::run(&timing, log_in.as_deref(), log_io.as_deref(), command.as_deref())
+        }
+        #[cfg(feature = "review")]
+        Some(Commands::Review { timing, log_io, output, typescript }) => {
+            review::run(timing.as_deref(), log_io.as_deref(), typescript.as_deref(), output.as_deref()).map(|_| 0)
+        }
+        Some(Commands::Convert { from, to, files, anonymize }) => {
+            convert::run(&from, &to, &files, anonymize.as_deref()).map(|_| 0)
+        }
+        #[cfg(feature = "vt")]
+        Some(Commands::Blame { timing, row, col, at, recording }) => {
+            blame::run(timing.as_deref(), &recording, row, col, at.as_deref()).map(|_| 0)
+        }
+        #[cfg(feature = "screenshot")]
+        Some(Commands::Screenshot { timing, at, ansi, recording }) => {
+            screenshot::run(timing.as_deref(), &recording, at.as_deref(), ansi).map(|_| 0)
+        }
+        #[cfg(feature = "export")]
+        Some(Commands::Export {
+            format,
+            fps,
+            max_duration,
+            timing,
+            output,
+            anonymize,
+            watermark,
+            watermark_image,
+            watermark_position,
+            watermark_opacity,
+            self_extracting,
+            recording,
+        }) => {
+            let watermark = Watermark::new(watermark, watermark_image, &watermark_position, watermark_opacity)
+                .map_err(|e| failure::or_tag(e, Failure::Usage))?;
+            let options = ExportOptions { anonymize, fps, max_duration, self_extracting, watermark };
+            export::run(&format, timing.as_deref(), &recording, output.as_deref(), &options).map(|_| 0)
+        }
+        Some(Commands::Info { json, timing, recording }) => stats::run(timing.as_deref(), &recording, json).map(|_| 0),
+        Some(Commands::ArchiveStats { min_count, top, dir }) => archive::run(&dir, min_count, top).map(|_| 0),
+        Some(Commands::SplitCommands { timing, outdir, runbook, recording }) => {
+            split::run(timing.as_deref(), &recording, outdir.as_deref(), runbook.as_deref()).map(|_| 0)
+        }
+        #[cfg(feature = "grep")]
+        Some(Commands::Grep { pattern, ignore_case, timing, recording }) => {
+            grep::run(&pattern, ignore_case, timing.as_deref(), &recording)
+        }
+        #[cfg(feature = "notebook")]
+        Some(Commands::Notebook { timing, output, recording }) => {
+            notebook::run(timing.as_deref(), &recording, output.as_deref()).map(|_| 0)
+        }
+        #[cfg(feature = "timeline")]
+        Some(Commands::TimelineMerge { json, files }) => timeline::run(&files, json).map(|_| 0),
+        Some(Commands::Smooth { timing, range, keep_original }) => {
+            smooth::run(&timing, &range, keep_original).map(|_| 0)
+        }
+        Some(Commands::Schema { name }) => schema::run(&name).map(|_| 0),
+        Some(Commands::Spooler { dir, exec, retries, retry_delay, interval, once, keep }) => {
+            let options = SpoolerOptions { exec, retries, retry_delay, interval, once, keep };
+            spool::run(&dir, &options).map(|_| 0)
+        }
+        Some(Commands::Ctl { name, command }) => control::send(&name, &command).map(|message| {
+            if !message.is_empty() {
+                println!("{}", message);
+            }
+            0
+        }),
+        None => {
+            let argv = std::env::args_os().skip(1).map(|arg| arg.to_string_lossy().into_owned()).collect();
+            match with_saved_options(args, argv) {
+                Ok((args, argv)) => record(args, argv).await,
+                Err(e) => Err(failure::or_tag(e, Failure::Usage)),
+            }
+        }
+    };
+
+    // The stdin reader may still be parked in a blocking read that runtime
+    // shutdown would wait on forever, so exit directly
+    match result {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("{}: {:?}", tr(Message::Error, &[]), e);
+            std::process::exit(failure::exit_code(&e));
+        }
+    }
+}
+
+// Record again as the last session was, from the directory it ran in so
+// that relative paths lead to the same files
+async fn redo() -> Result<i32> {
+    let last = history::last()?;
+    std::env::set_current_dir(&last.dir).with_context(|| format!("Cannot change to {}", last.dir.display()))?;
+    let args = Args::try_parse_from(std::iter::once("script".to_string()).chain(last.args.iter().cloned()))?;
+    record(args, last.args).await
+}
+
+// The [record] options of the config file go before the command line's,
+// which win over them; the history keeps both, so a redo doesn't depend on
+// the config file
+fn with_saved_options(args: Args, argv: Vec<String>) -> Result<(Args, Vec<String>)> {
+    let saved = Config::load(args.config.as_deref())?.record_options;
+    if saved.is_empty() {
+        return Ok((args, argv));
+    }
+    let argv: Vec<String> = saved.into_iter().chain(argv).collect();
+    let args = Args::try_parse_from(std::iter::once("script".to_string()).chain(argv.iter().cloned()))?;
+    Ok((args, argv))
+}
+
+// Returns the exit code for the process: the child's with --return, else 0.
+// `argv` are the arguments the recording was started with, for the history.
+async fn record(args: Args, argv: Vec<String>) -> Result<i32> {
+    let rc_wanted = args.return_exit_code;
+
+    // Debug builds always check for descriptor leaks
+    let fds_before = if args.debug || cfg!(debug_assertions) {
+        Some(utils::open_fds()?)
+    } else {
+        None
+    };
+
+    // A shell's startup file that starts script would otherwise record
+    // the recording
+    if let Some(outer) = config::script().filter(|_| !args.force_nested) {
+        return Err(anyhow!("Already recorded to {} ($SCRIPT is set); --force-nested records this session too", outer))
+            .context(Failure::Usage);
+    }
+
+    // Initialize the script control structure
+    let mut control = ScriptControl::new(args).map_err(|e| failure::or_tag(e, Failure::Usage))?;
+    if let Err(e) = history::add(&control, argv) {
+        eprintln!("{}", tr(Message::HistoryFailed, &[&format!("{:#}", e)]));
+    }
+
+    // Run the script session
+    let result = control.run().await
+        .context("Failed to run script session");
+
+    // Restore the terminal before leaving
+    let terminated_by = control.terminated_by;
+    drop(control);
+
+    if let Some(before) = fds_before {
+        let leaks = utils::fd_leaks(&before)?;
+        if !leaks.is_empty() {
+            eprintln!("{}", tr(Message::FdsLeaked, &[]));
+            for leak in &leaks {
+                eprintln!("  {}", leak);
+            }
+            result?;
+            return Err(anyhow!("{} file descriptors leaked", leaks.len()));
+        }
+    }
+
+    let status = result?;
+    if let Some(signal) = terminated_by {
+        return Ok(128 + signal);
+    }
+    Ok(if rc_wanted { status } else { 0 })
+}
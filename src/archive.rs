@@ -0,0 +1,205 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::compression;
+use crate::convert;
+use crate::logging::TIMESTAMP_FORMAT;
+use crate::split;
+use crate::timing::{self, TimingEntry};
+use crate::utils;
+
+// Figures over a whole directory of recordings that show how it is used,
+// not what was done: no command lines, no output, and tools used only a
+// few times folded together, as one rare name can say a lot
+#[derive(Debug, Default)]
+pub struct ArchiveStats {
+    pub sessions: usize,
+    // Recordings that couldn't be read, e.g. encrypted ones
+    pub unreadable: usize,
+    pub total: Duration,
+    pub commands: usize,
+    pub per_day: BTreeMap<NaiveDate, usize>,
+    pub tools: HashMap<String, usize>,
+}
+
+// The program a command line runs: its first word after any variable
+// assignments, without the directory
+fn tool(command: &str) -> Option<String> {
+    let word = command.split_whitespace().find(|word| !word.contains('='))?;
+    let name = word.rsplit('/').next().unwrap_or(word);
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+fn start_time(entries: &[TimingEntry]) -> Option<DateTime<chrono::FixedOffset>> {
+    timing::info(entries, "START_TIME").and_then(|t| DateTime::parse_from_str(t, TIMESTAMP_FORMAT).ok())
+}
+
+impl ArchiveStats {
+    pub fn add(&mut self, entries: &[TimingEntry], data: &[u8]) -> Result<()> {
+        let duration = timing::info(entries, "DURATION")
+            .and_then(|d| d.parse::<f64>().ok())
+            .and_then(utils::secs_to_duration)
+            .unwrap_or_else(|| entries.iter().map(|e| e.delay).sum());
+        self.sessions += 1;
+        self.total = self.total.saturating_add(duration);
+
+        for slice in split::split(entries, data)? {
+            self.commands += 1;
+            if let Some(day) = start_time(&slice.entries) {
+                *self.per_day.entry(day.date_naive()).or_default() += 1;
+            }
+            if let Some(tool) = tool(&slice.command) {
+                *self.tools.entry(tool).or_default() += 1;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn report(&self, min_count: usize, top: usize) -> String {
+        let time = |d: Duration| {
+            let s = d.as_secs();
+            format!("{:02}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+        };
+        let mut out = format!("Sessions: {}", self.sessions);
+        if self.unreadable > 0 {
+            out.push_str(&format!(" ({} unreadable)", self.unreadable));
+        }
+        out.push_str(&format!("\nTotal time: {}\n", time(self.total)));
+        if self.sessions > 0 {
+            out.push_str(&format!("Average session: {}\n", time(self.total / self.sessions as u32)));
+        }
+        out.push_str(&format!("Commands: {}\n", self.commands));
+
+        if !self.per_day.is_empty() {
+            out.push_str("\nCommands per day:\n");
+            for (day, count) in &self.per_day {
+                out.push_str(&format!("  {}  {}\n", day, count));
+            }
+        }
+
+        let mut tools: Vec<(&String, &usize)> = self.tools.iter().filter(|(_, &count)| count >= min_count).collect();
+        tools.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        tools.truncate(top);
+        if !tools.is_empty() {
+            out.push_str(&format!("\nTop tools (used {} times or more):\n", min_count));
+            let shown: usize = tools.iter().map(|(_, &count)| count).sum();
+            let others = self.tools.values().sum::<usize>() - shown;
+            let width = tools
+                .iter()
+                .map(|(name, _)| name.chars().count())
+                .chain((others > 0).then_some("(other)".len()))
+                .max()
+                .unwrap_or(0);
+            for (name, count) in &tools {
+                out.push_str(&format!("  {:width$}  {}\n", name, count, width = width));
+            }
+            if others > 0 {
+                out.push_str(&format!("  {:width$}  {}\n", "(other)", others, width = width));
+            }
+        }
+        out
+    }
+}
+
+// The log an advanced timing file names, looked for next to it when the
+// name doesn't lead anywhere from here
+fn log_of(timing_path: &Path, entries: &[TimingEntry]) -> Option<PathBuf> {
+    let name = Path::new(timing::info(entries, "OUTPUT_LOG")?);
+    let dir = timing_path.parent().unwrap_or(Path::new("."));
+    [name.to_path_buf(), dir.join(name), dir.join(name.file_name()?)].into_iter().find(|path| path.is_file())
+}
+
+// The recordings under `dir`: asciicast and ttyrec files, and logs found
+// through their advanced timing files
+fn find(dir: &Path, found: &mut Vec<(Option<PathBuf>, PathBuf)>, unreadable: &mut usize) -> Result<()> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("Cannot read {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            find(&path, found, unreadable)?;
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let name = name.trim_end_matches(".gz").trim_end_matches(".zst");
+        if name.ends_with(".cast") || name.ends_with(".ttyrec") {
+            found.push((None, path));
+            continue;
+        }
+        // Logs and anything else fail to parse as timing files
+        let Ok(content) = compression::read_to_string(&path) else { continue };
+        let Ok(entries) = timing::parse_timing(&content) else { continue };
+        if timing::info(&entries, "START_TIME").is_none() {
+            continue;
+        }
+        match log_of(&path, &entries) {
+            Some(log) => found.push((Some(path), log)),
+            None => *unreadable += 1,
+        }
+    }
+    Ok(())
+}
+
+pub fn archive_stats(dir: &Path) -> Result<ArchiveStats> {
+    let mut stats = ArchiveStats::default();
+    let mut recordings = Vec::new();
+    find(dir, &mut recordings, &mut stats.unreadable)?;
+
+    for (timing, recording) in recordings {
+        let loaded = convert::load_recording(timing.as_deref(), &recording);
+        if loaded.and_then(|(entries, data)| stats.add(&entries, &data)).is_err() {
+            stats.unreadable += 1;
+        }
+    }
+    Ok(stats)
+}
+
+pub fn run(dir: &Path, min_count: usize, top: usize) -> Result<()> {
+    print!("{}", archive_stats(dir)?.report(min_count, top));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_without_content() {
+        let dir = std::env::temp_dir().join(format!("rust_script-{}-archive", std::process::id()));
+        std::fs::create_dir_all(dir.join("alice")).unwrap();
+        let session = |name: &str, start: &str, typed: &[&str]| {
+            let mut timing = format!(
+                "H 0.000000 START_TIME {}\nH 0.000000 OUTPUT_LOG {}.log\nH 0.000000 INPUT_LOG {}.log\nH 0.000000 DURATION 90.0\n",
+                start, name, name
+            );
+            let mut log = String::from("Script started on x\n");
+            for line in typed {
+                timing.push_str(&format!("I 1.0 {}\nO 0.1 2\n", line.len() + 1));
+                log.push_str(&format!("{}\r\r\n", line));
+            }
+            std::fs::write(dir.join(format!("{}.tm", name)), timing).unwrap();
+            std::fs::write(dir.join(format!("{}.log", name)), log).unwrap();
+        };
+        session("alice/a", "2024-01-02 23:59:58+00:00", &["git status", "/usr/bin/git log", "FOO=1 make"]);
+        session("b", "2024-01-03 10:00:00+00:00", &["git push"]);
+        std::fs::write(dir.join("c.tm"), "H 0.000000 START_TIME 2024-01-03 10:00:00+00:00\nO 0.1 5\n").unwrap();
+
+        let stats = archive_stats(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!((stats.sessions, stats.unreadable, stats.commands), (2, 1, 4));
+        assert_eq!(stats.total, Duration::from_secs(180));
+
+        let report = stats.report(2, 10);
+        assert!(report.contains("Average session: 00:01:30\n"));
+        // The first session's commands ran past midnight
+        assert!(report.contains("  2024-01-02  1\n  2024-01-03  3\n"));
+        assert!(report.contains("  git      3\n  (other)  1\n"));
+        assert!(!report.contains("make") && !report.contains("status"));
+    }
+}
@@ -0,0 +1,246 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::logging::TIMESTAMP_FORMAT;
+use crate::timing::{self, EntryKind, TimingEntry};
+use crate::utils;
+
+// Terminal size written when the recording doesn't say
+const DEFAULT_SIZE: (u64, u64) = (80, 24);
+
+// asciicast v2 starts with a JSON header object on the first line
+pub fn is_asciicast(content: &[u8]) -> bool {
+    let first = content.split(|&b| b == b'\n').next().unwrap_or_default();
+    serde_json::from_slice::<Value>(first)
+        .map(|header| header.get("version").and_then(Value::as_u64) == Some(2))
+        .unwrap_or(false)
+}
+
+// Turn a cast into timing entries plus the data they refer to, so it plays
+// through the same path as a typescript. Input events are kept in the data
+// and skipped on replay; resizes become SIGWINCH events. The header's size
+// and start time become H records, as in an advanced timing file.
+pub fn parse(content: &str) -> Result<(Vec<TimingEntry>, Vec<u8>)> {
+    let mut lines = content.lines().enumerate();
+    let (_, header) = lines.next().ok_or_else(|| anyhow!("asciicast file is empty"))?;
+    let header: Value = serde_json::from_str(header)?;
+    if header.get("version").and_then(Value::as_u64) != Some(2) {
+        return Err(anyhow!("Only asciicast version 2 is supported"));
+    }
+
+    let mut entries = Vec::new();
+    let mut data = Vec::new();
+    let mut last = 0.0f64;
+
+    let mut info = |name: &str, value: String| {
+        entries.push(TimingEntry {
+            delay: Duration::ZERO,
+            kind: EntryKind::Info(name.to_string(), value),
+            wall: None,
+        })
+    };
+    if let Some(start) = header.get("timestamp").and_then(Value::as_i64).and_then(|t| DateTime::from_timestamp(t, 0)) {
+        info("START_TIME", start.fixed_offset().format(TIMESTAMP_FORMAT).to_string());
+    }
+    if let Some(width) = header.get("width").and_then(Value::as_u64) {
+        info("COLUMNS", width.to_string());
+    }
+    if let Some(height) = header.get("height").and_then(Value::as_u64) {
+        info("LINES", height.to_string());
+    }
+
+    for (i, line) in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = || anyhow!("asciicast line {}: invalid event", i + 1);
+        let event: Value = serde_json::from_str(line).map_err(|_| invalid())?;
+        let (time, code, payload) = match event.as_array().map(Vec::as_slice) {
+            Some([time, code, payload]) => (
+                time.as_f64().ok_or_else(invalid)?,
+                code.as_str().ok_or_else(invalid)?,
+                payload.as_str().ok_or_else(invalid)?,
+            ),
+            _ => return Err(invalid()),
+        };
+
+        // Event times are absolute; out-of-order ones play immediately. The
+        // offsets a reader adds up come to the times, so they must fit too.
+        utils::secs_to_duration(time.max(0.0)).ok_or_else(invalid)?;
+        let delay = utils::secs_to_duration((time - last).max(0.0)).ok_or_else(invalid)?;
+        last = last.max(time);
+
+        let kind = match code {
+            "o" => EntryKind::Output(payload.len()),
+            "i" => EntryKind::Input(payload.len()),
+            "r" => {
+                let (cols, rows) = payload.split_once('x').ok_or_else(invalid)?;
+                EntryKind::Signal("SIGWINCH".to_string(), Some(format!("ROWS={} COLS={}", rows, cols)))
+            }
+            // Markers and unknown event types carry no terminal data
+            _ => EntryKind::Signal(code.to_string(), Some(payload.to_string())),
+        };
+        if matches!(kind, EntryKind::Output(_) | EntryKind::Input(_)) {
+            data.extend_from_slice(payload.as_bytes());
+        }
+        entries.push(TimingEntry { delay, kind, wall: None });
+    }
+
+    Ok((entries, data))
+}
+
+// Decode one stream chunk, holding back a character split across records
+fn decode(pending: &mut Vec<u8>, chunk: &[u8]) -> String {
+    pending.extend_from_slice(chunk);
+    let complete = match std::str::from_utf8(pending) {
+        Ok(_) => pending.len(),
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => pending.len(),
+    };
+    let text = String::from_utf8_lossy(&pending[..complete]).into_owned();
+    pending.drain(..complete);
+    text
+}
+
+// Write entries and their data as a cast. The recording's markers
+// (--auto-marker) become markers to jump to, labeled with their command
+// lines, or failing those the commands marked by the shell (OSC 133);
+// signals other than resizes and asciicast's own event types have no
+// equivalent and are dropped.
+pub fn write(entries: &[TimingEntry], data: &[u8]) -> Result<String> {
+    let size = |name, default| {
+        timing::info(entries, name)
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|&v| v > 0)
+            .unwrap_or(default)
+    };
+    let mut header = json!({
+        "version": 2,
+        "width": size("COLUMNS", DEFAULT_SIZE.0),
+        "height": size("LINES", DEFAULT_SIZE.1),
+    });
+    if let Some(start) = timing::info(entries, "START_TIME").and_then(|t| DateTime::parse_from_str(t, TIMESTAMP_FORMAT).ok()) {
+        header["timestamp"] = json!(start.with_timezone(&Utc).timestamp());
+    }
+
+    let auto_markers = !timing::markers(entries).is_empty();
+    let mut out = format!("{}\n", header);
+    let mut time = Duration::ZERO;
+    let mut pos = 0;
+    let (mut pending_out, mut pending_in) = (Vec::new(), Vec::new());
+
+    for entry in entries {
+        time += entry.delay;
+        let (code, payload) = match entry.kind {
+            EntryKind::Output(size) | EntryKind::Input(size) => {
+                let chunk = data
+                    .get(pos..pos + size)
+                    .ok_or_else(|| anyhow!("log data is shorter than its timing"))?;
+                pos += size;
+                match entry.kind {
+                    EntryKind::Output(_) => ("o", decode(&mut pending_out, chunk)),
+                    _ => ("i", decode(&mut pending_in, chunk)),
+                }
+            }
+            EntryKind::Signal(ref name, Some(ref message)) if name == "SIGWINCH" => {
+                let field = |key: &str| {
+                    message.split(' ').find_map(|f| f.strip_prefix(key)).unwrap_or_default().to_string()
+                };
+                ("r", format!("{}x{}", field("COLS="), field("ROWS=")))
+            }
+            EntryKind::Signal(ref name, ref message) if name == "MARKER" => {
+                let command = message.as_deref().and_then(|message| message.split_once("COMMAND="));
+                ("m", command.map_or("", |(_, command)| command).to_string())
+            }
+            EntryKind::Signal(ref name, _) if name == "COMMAND_START" && !auto_markers => ("m", String::new()),
+            EntryKind::Signal(ref code, ref message) if code.len() == 1 => {
+                (code.as_str(), message.clone().unwrap_or_default())
+            }
+            _ => continue,
+        };
+        if payload.is_empty() && code != "m" {
+            continue;
+        }
+        out.push_str(&json!([(time.as_secs_f64() * 1e6).round() / 1e6, code, payload]).to_string());
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cast() {
+        let cast = "{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+                    [0.5, \"o\", \"$ \"]\n\
+                    [1.0, \"i\", \"l\"]\n\
+                    [1.25, \"r\", \"100x30\"]\n\
+                    [1.5, \"o\", \"l\\u001b[0m\"]\n";
+        assert!(is_asciicast(cast.as_bytes()));
+        assert!(!is_asciicast(b"Script started on 2024-01-02\n"));
+
+        let (entries, data) = parse(cast).unwrap();
+        assert_eq!(data, b"$ ll\x1b[0m");
+        assert_eq!(entries[0].kind, EntryKind::Info("COLUMNS".into(), "80".into()));
+        assert_eq!(
+            entries[2],
+            TimingEntry { delay: Duration::from_millis(500), kind: EntryKind::Output(2), wall: None }
+        );
+        assert_eq!(entries[3].kind, EntryKind::Input(1));
+        assert_eq!(entries[4].kind, EntryKind::Signal("SIGWINCH".into(), Some("ROWS=30 COLS=100".into())));
+        assert_eq!(entries[5].delay, Duration::from_millis(250));
+        assert_eq!(entries[5].kind, EntryKind::Output(5));
+        assert!(parse("{\"version\": 2}\n[1e30, \"o\", \"x\"]\n").is_err());
+
+        // Written back, the cast is the same apart from formatting
+        assert_eq!(
+            write(&entries, &data).unwrap(),
+            "{\"height\":24,\"version\":2,\"width\":80}\n\
+             [0.5,\"o\",\"$ \"]\n\
+             [1.0,\"i\",\"l\"]\n\
+             [1.25,\"r\",\"100x30\"]\n\
+             [1.5,\"o\",\"l\\u001b[0m\"]\n"
+        );
+    }
+
+    #[test]
+    fn test_write_keeps_split_characters_whole() {
+        let entries = vec![
+            TimingEntry { delay: Duration::ZERO, kind: EntryKind::Output(1), wall: None },
+            TimingEntry { delay: Duration::from_millis(100), kind: EntryKind::Output(1), wall: None },
+        ];
+        let cast = write(&entries, "é".as_bytes()).unwrap();
+        assert!(cast.ends_with("\n[0.1,\"o\",\"é\"]\n"), "{}", cast);
+    }
+
+    #[test]
+    fn test_write_marks_shell_commands() {
+        let entries = vec![
+            TimingEntry { delay: Duration::ZERO, kind: EntryKind::Output(2), wall: None },
+            TimingEntry {
+                delay: Duration::from_millis(100),
+                kind: EntryKind::Signal("COMMAND_START".into(), None),
+                wall: None,
+            },
+            TimingEntry {
+                delay: Duration::ZERO,
+                kind: EntryKind::Signal("COMMAND_END".into(), Some("EXIT_CODE=0".into())),
+                wall: None,
+            },
+        ];
+        let mut entries = entries;
+        let cast = write(&entries, b"$ ").unwrap();
+        assert!(cast.ends_with("[0.0,\"o\",\"$ \"]\n[0.1,\"m\",\"\"]\n"), "{}", cast);
+
+        // Markers of its own take their place
+        let kind = EntryKind::Signal("MARKER".into(), Some("AUTO=command COMMAND=ls -l".into()));
+        entries.insert(1, TimingEntry { delay: Duration::from_millis(50), kind, wall: None });
+        let cast = write(&entries, b"$ ").unwrap();
+        assert!(cast.ends_with("[0.0,\"o\",\"$ \"]\n[0.05,\"m\",\"ls -l\"]\n"), "{}", cast);
+    }
+}
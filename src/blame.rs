@@ -0,0 +1,157 @@
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::convert;
+use crate::logging::TIMESTAMP_FORMAT;
+use crate::timing::{self, parse_offset, window_size, EntryKind, TimingEntry};
+use crate::typed::TypedLines;
+use crate::vt::Screen;
+
+// The output record that put a character on screen
+#[derive(Debug, PartialEq)]
+pub struct Blame {
+    pub ch: char,
+    // Counting output records from 1
+    pub record: usize,
+    pub offset: Duration,
+    pub data: Vec<u8>,
+    // The last line typed before it, when the recording has input
+    pub command: Option<String>,
+}
+
+fn format_offset(offset: Duration) -> String {
+    let ms = offset.as_millis();
+    format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+// The last line entered in some input
+fn last_command(input: &[u8]) -> Option<String> {
+    TypedLines::default().feed(input).pop()
+}
+
+// Play the output up to `at` and find out which record last wrote the
+// character at (row, col), counted from 0
+pub fn blame(entries: &[TimingEntry], data: &[u8], row: usize, col: usize, at: Duration) -> Result<Option<Blame>> {
+    // A size of 0 means the recording didn't know
+    let size = |name| timing::info(entries, name).and_then(|v| v.parse().ok()).filter(|&v| v > 0);
+    let mut screen = Screen::new(size("COLUMNS").unwrap_or(80), size("LINES").unwrap_or(24));
+
+    // Where each output record starts in the data, and the input before it
+    let mut records = Vec::new();
+    let mut input = Vec::new();
+    let mut offset = Duration::ZERO;
+    let mut pos = 0;
+    for entry in entries {
+        offset += entry.delay;
+        if offset > at {
+            break;
+        }
+        match entry.kind {
+            EntryKind::Output(size) => {
+                let end = (pos + size).min(data.len());
+                screen.feed(&data[pos..end], records.len());
+                records.push((offset, pos..end, input.len()));
+                pos = end;
+            }
+            EntryKind::Input(size) => {
+                let end = (pos + size).min(data.len());
+                input.extend_from_slice(&data[pos..end]);
+                pos = end;
+            }
+            EntryKind::Signal(ref name, Some(ref message)) if name == "SIGWINCH" => {
+                if let Some((cols, rows)) = window_size(message) {
+                    screen.resize(cols, rows);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cell = screen.cell(row, col).ok_or_else(|| {
+        let (cols, rows) = screen.size();
+        anyhow!("Row {}, column {} is outside the {}x{} screen", row + 1, col + 1, cols, rows)
+    })?;
+    Ok(cell.origin.map(|record| {
+        let (offset, ref range, typed) = records[record];
+        Blame {
+            ch: cell.ch,
+            record: record + 1,
+            offset,
+            data: data[range.clone()].to_vec(),
+            command: last_command(&input[..typed]),
+        }
+    }))
+}
+
+pub fn run(timing: Option<&Path>, recording: &Path, row: usize, col: usize, at: Option<&str>) -> Result<()> {
+    if row == 0 || col == 0 {
+        return Err(anyhow!("Rows and columns count from 1"));
+    }
+    let at = at.map(parse_offset).transpose()?.unwrap_or(Duration::MAX);
+    let (entries, data) = convert::load_recording(timing, recording)?;
+
+    let Some(found) = blame(&entries, &data, row - 1, col - 1, at)? else {
+        println!("Row {}, column {} is blank: nothing written there is still on screen", row, col);
+        return Ok(());
+    };
+
+    println!("Row {}, column {} shows {:?}", row, col, found.ch);
+    let start = timing::info(&entries, "START_TIME").and_then(|t| DateTime::parse_from_str(t, TIMESTAMP_FORMAT).ok());
+    match start {
+        Some(start) => {
+            let when = start + chrono::Duration::from_std(found.offset)?;
+            println!(
+                "Written by output record {} at {} ({})",
+                found.record,
+                format_offset(found.offset),
+                when.format(TIMESTAMP_FORMAT)
+            );
+        }
+        None => println!("Written by output record {} at {}", found.record, format_offset(found.offset)),
+    }
+
+    let preview: String = String::from_utf8_lossy(&found.data).escape_debug().take(72).collect();
+    println!("  {}", preview);
+    match (found.command, timing::info(&entries, "COMMAND")) {
+        (Some(typed), _) => println!("Last input before it: {}", typed),
+        (None, Some(command)) => println!("Session command: {}", command),
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blame_finds_the_last_writer() {
+        let entries = timing::parse_timing(
+            "H 0.000000 COLUMNS 20\nH 0.000000 LINES 5\n\
+             O 0.5 2\nI 1.0 14\nO 0.1 19\nO 2.0 10\nS 0.1 SIGWINCH ROWS=5 COLS=10\n",
+        )
+        .unwrap();
+        let data = [&b"$ "[..], b"echo ok\x7f\x7foops\r", b"echo oops\r\noops\r\n$ ", b"\x1b[2;1HOOPS"].concat();
+
+        // Before the fix-up, row 2 still shows what the command printed
+        let found = blame(&entries, &data, 1, 0, parse_offset("1.6").unwrap()).unwrap().unwrap();
+        assert_eq!(found.ch, 'o');
+        assert_eq!(found.record, 2);
+        assert_eq!(found.offset, Duration::from_millis(1600));
+        assert_eq!(found.command.as_deref(), Some("echo oops"));
+
+        let found = blame(&entries, &data, 1, 0, Duration::MAX).unwrap().unwrap();
+        assert_eq!((found.ch, found.record), ('O', 3));
+        assert_eq!(found.data, b"\x1b[2;1HOOPS");
+        assert!(blame(&entries, &data, 1, 15, parse_offset("1.6").unwrap()).unwrap().is_none());
+        // The window shrank at the end
+        assert!(blame(&entries, &data, 1, 15, Duration::MAX).is_err());
+    }
+
+    #[test]
+    fn test_format_offset() {
+        assert_eq!(format_offset(Duration::from_millis(3_723_004)), "01:02:03.004");
+    }
+}
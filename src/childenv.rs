@@ -0,0 +1,113 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+
+// --env, --env-file and --clear-env: the environment the recorded child
+// starts with. The recorder's own is emptied first with --clear-env, then
+// the file's variables are applied, then those on the command line, so
+// `--env` has the last word. `NAME=VALUE` sets a variable and a bare `NAME`
+// removes it. In the file, blank lines and `#` comments are skipped, a
+// leading `export ` is allowed, and a value in matching quotes loses them:
+//
+//   # demo.env
+//   export PS1='$ '
+//   LANG=C.UTF-8
+//   HISTFILE
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChildEnv {
+    clear: bool,
+    changes: Vec<(String, Option<String>)>,
+}
+
+impl ChildEnv {
+    pub fn new(clear: bool, vars: &[String], file: Option<&Path>) -> Result<ChildEnv> {
+        let mut changes = Vec::new();
+        if let Some(file) = file {
+            let content = std::fs::read_to_string(file).with_context(|| format!("Cannot read {}", file.display()))?;
+            for (i, line) in content.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+                let change = parse(line).with_context(|| format!("{}:{}", file.display(), i + 1))?;
+                changes.push(change);
+            }
+        }
+        for var in vars {
+            changes.push(parse(var)?);
+        }
+        Ok(ChildEnv { clear, changes })
+    }
+
+    // Set before the user's changes, which can override or remove it, and
+    // kept by --clear-env
+    pub fn preset(&mut self, name: &str, value: &str) {
+        self.changes.insert(0, (name.to_string(), Some(value.to_string())));
+    }
+
+    // In the child, after fork: it has a thread of its own to change
+    pub fn apply(&self) {
+        if self.clear {
+            for (name, _) in std::env::vars_os() {
+                std::env::remove_var(name);
+            }
+        }
+        for (name, value) in &self.changes {
+            match value {
+                Some(value) => std::env::set_var(name, value),
+                None => std::env::remove_var(name),
+            }
+        }
+    }
+}
+
+fn parse(var: &str) -> Result<(String, Option<String>)> {
+    let (name, value) = match var.split_once('=') {
+        Some((name, value)) => (name, Some(unquote(value).to_string())),
+        None => (var, None),
+    };
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match valid {
+        true => Ok((name.to_string(), value)),
+        false => Err(anyhow!("Invalid environment variable: '{}' (NAME=VALUE, or NAME to remove it)", var)),
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value.strip_prefix(quote).and_then(|value| value.strip_suffix(quote)) {
+            return inner;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_then_command_line() {
+        let file = std::env::temp_dir().join(format!("rust_script-{}-demo.env", std::process::id()));
+        std::fs::write(&file, "# demo\n\nexport PS1='$ '\nLANG=C.UTF-8\nHISTFILE\n").unwrap();
+        let env = ChildEnv::new(true, &["LANG=de_DE.UTF-8".to_string(), "EMPTY=".to_string()], Some(&file)).unwrap();
+        assert!(env.clear);
+        assert_eq!(
+            env.changes,
+            [
+                ("PS1".to_string(), Some("$ ".to_string())),
+                ("LANG".to_string(), Some("C.UTF-8".to_string())),
+                ("HISTFILE".to_string(), None),
+                ("LANG".to_string(), Some("de_DE.UTF-8".to_string())),
+                ("EMPTY".to_string(), Some(String::new())),
+            ]
+        );
+
+        std::fs::write(&file, "A=1\n2B=x\n").unwrap();
+        let error = ChildEnv::new(false, &[], Some(&file)).unwrap_err();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(format!("{:#}", error), format!("{}:2: Invalid environment variable: '2B=x' \
+            (NAME=VALUE, or NAME to remove it)", file.display()));
+    }
+}
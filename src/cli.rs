@@ -0,0 +1,708 @@
+use clap::Parser;
+use std::path::PathBuf;
+
+use crate::failure;
+
+/// Make a typescript of a terminal session
+#[derive(Parser, Debug, Default)]
+#[command(name = "script")]
+#[command(about = "Make a typescript of a terminal session")]
+#[command(version = "1.0.0")]
+#[command(args_conflicts_with_subcommands = true)]
+// Later options win, so those on the command line override the config file's
+#[command(args_override_self = true)]
+#[command(after_help = failure::EXIT_STATUS_HELP)]
+pub struct Args {
+    #[command(subcommand)]
+    pub subcommand: Option<Commands>,
+
+    /// Log stdin to file
+    #[arg(short = 'I', long = "log-in")]
+    pub log_in: Option<PathBuf>,
+
+    /// Log stdout to file (default)
+    #[arg(short = 'O', long = "log-out")]
+    pub log_out: Option<PathBuf>,
+
+    /// Log stdin and stdout to file
+    #[arg(short = 'B', long = "log-io")]
+    pub log_io: Option<PathBuf>,
+
+    /// Log the child's stderr to file, through a pipe of its own rather than the terminal
+    #[arg(long = "log-err", value_name = "FILE")]
+    pub log_err: Option<PathBuf>,
+
+    /// Log stdout to file in ttyrec format
+    #[arg(long = "ttyrec", value_name = "FILE")]
+    pub ttyrec: Option<PathBuf>,
+
+    /// Log stdout to file as plain text, without escape sequences and with overwritten text resolved
+    #[arg(long = "plain", value_name = "FILE")]
+    pub plain: Option<PathBuf>,
+
+    /// Log timing information to file
+    #[arg(short = 'T', long = "log-timing")]
+    pub log_timing: Option<PathBuf>,
+
+    /// Deprecated alias to -T (default file is stderr)
+    #[arg(short = 't', long = "timing")]
+    pub timing: Option<Option<PathBuf>>,
+
+    /// Force to 'classic' or 'advanced' timing format, or write 'plain' text logs
+    #[arg(short = 'm', long = "logging-format")]
+    pub logging_format: Option<String>,
+
+    /// Also stamp each record of the advanced timing log with the time of day, to the microsecond
+    #[arg(long = "wall-clock")]
+    pub wall_clock: bool,
+
+    /// Label the recording, e.g. ticket=OPS-12, in the advanced timing log; may be repeated
+    #[arg(long = "label", value_name = "KEY=VALUE")]
+    pub label: Vec<String>,
+
+    /// Append to the log file
+    #[arg(short = 'a', long = "append")]
+    pub append: bool,
+
+    /// Run command rather than interactive shell
+    #[arg(short = 'c', long = "command")]
+    pub command: Option<String>,
+
+    /// Return exit code of the child process
+    #[arg(short = 'e', long = "return")]
+    pub return_exit_code: bool,
+
+    /// Run flush after each write
+    #[arg(short = 'f', long = "flush")]
+    pub flush: bool,
+
+    /// Use output file even when it is a link
+    #[arg(long = "force")]
+    pub force: bool,
+
+    /// Echo input in session (auto, always or never)
+    #[arg(short = 'E', long = "echo")]
+    pub echo: Option<String>,
+
+    /// Terminate if output files exceed size
+    #[arg(short = 'o', long = "output-limit")]
+    pub output_limit: Option<String>,
+
+    /// Continue the log in FILE.1, FILE.2, ... each time it reaches this size
+    #[arg(long = "rotate-size", value_name = "SIZE")]
+    pub rotate_size: Option<String>,
+
+    /// Write <log>.idx beside each log of the session's bytes, with a seek point this many seconds apart
+    #[arg(long = "seek-index", value_name = "SECONDS", conflicts_with = "rotate_size")]
+    pub seek_index: Option<u64>,
+
+    /// Go on writing a log in this directory when its disk fills up or fails, rather than ending the session
+    #[arg(long = "failover-dir", value_name = "DIR")]
+    pub failover_dir: Option<PathBuf>,
+
+    /// URL to POST a JSON alert to, with curl, when a log fails over
+    #[arg(long = "failover-webhook", value_name = "URL", requires = "failover_dir")]
+    pub failover_webhook: Option<String>,
+
+    /// Space to keep free where the logs are written (e.g. 500M); the session won't start with less
+    #[arg(long = "reserve", value_name = "SIZE")]
+    pub reserve: Option<String>,
+
+    /// Only warn when there is less free space than --reserve, rather than refusing to start
+    #[arg(long = "reserve-warn", requires = "reserve")]
+    pub reserve_warn: bool,
+
+    /// Be quiet
+    #[arg(short = 'q', long = "quiet")]
+    pub quiet: bool,
+
+    /// Don't read stdin or touch its terminal, only record the output (for batch runs, e.g. in CI)
+    #[arg(long = "no-stdin")]
+    pub no_stdin: bool,
+
+    /// Type the contents of this file into the session in place of the keyboard
+    #[arg(long = "stdin-file", value_name = "FILE", conflicts_with = "no_stdin")]
+    pub stdin_file: Option<PathBuf>,
+
+    /// Type --stdin-file at this many characters a second (default: a line at a time, as fast as it is read)
+    #[arg(long = "cps", value_name = "N", requires = "stdin_file")]
+    pub cps: Option<f64>,
+
+    /// Send each line of --stdin-file once the session shows a prompt (OSC 133 marks or --prompt-regex)
+    #[arg(long = "wait-prompt", requires = "stdin_file")]
+    pub wait_prompt: bool,
+
+    /// End the session once no process has the terminal open (pty), or as soon as the command exits (child)
+    #[arg(long = "wait-for", value_name = "WHAT", default_value = "pty")]
+    pub wait_for: String,
+
+    /// Also write the output as plain text lines, each stamped with the time
+    #[arg(long = "transcript", value_name = "FILE")]
+    pub transcript: Option<PathBuf>,
+
+    /// Don't log output while the session is in the alternate screen
+    #[arg(long = "skip-alt-screen")]
+    pub skip_alt_screen: bool,
+
+    /// Warn when the session is idle for this many seconds with processes stuck in uninterruptible sleep
+    #[arg(long = "watchdog", value_name = "SECONDS")]
+    pub watchdog: Option<u64>,
+
+    /// Command run when the watchdog fires (SCRIPT_CHILD_PID and SCRIPT_STALLED_PIDS are set)
+    #[arg(long = "watchdog-hook", requires = "watchdog")]
+    pub watchdog_hook: Option<String>,
+
+    /// Bound the recorder's own memory for queued log data and, through a cgroup, its CPU use (e.g. mem=64M,cpu=10%)
+    #[arg(long = "self-limit", value_name = "LIMITS")]
+    pub self_limit: Option<String>,
+
+    /// Record a heartbeat every this many seconds with the byte counts and whether the child is running
+    #[arg(long = "heartbeat", value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub heartbeat: Option<u64>,
+
+    /// Record command boundaries where the last line of output matches this regex, e.g. '[$#] $', for shells without OSC 133
+    #[arg(long = "prompt-regex", value_name = "REGEX")]
+    pub prompt_regex: Option<String>,
+
+    /// Type RESPONSE whenever the last line of output matches the regex PATTERN, as 'PATTERN=>RESPONSE'; may be repeated
+    #[arg(long = "expect", value_name = "RULE")]
+    pub expect: Vec<String>,
+
+    /// Read --expect rules from a file, one a line
+    #[arg(long = "expect-file", value_name = "FILE")]
+    pub expect_file: Option<PathBuf>,
+
+    /// Set NAME=VALUE in the session's environment, or remove NAME given alone; may be repeated
+    #[arg(long = "env", value_name = "NAME=VALUE")]
+    pub env: Vec<String>,
+
+    /// Read --env variables from a file, one a line, before those given with --env
+    #[arg(long = "env-file", value_name = "FILE")]
+    pub env_file: Option<PathBuf>,
+
+    /// Start the session with an empty environment, but for --env and --env-file
+    #[arg(long = "clear-env")]
+    pub clear_env: bool,
+
+    /// Record even inside a session that is being recorded already ($SCRIPT is set)
+    #[arg(long = "force-nested")]
+    pub force_nested: bool,
+
+    /// Drop a marker (chapter) in the recording at every command typed, or at an interval: every-command, every-5m
+    #[arg(long = "auto-marker", value_name = "WHEN")]
+    pub auto_marker: Option<String>,
+
+    /// Leave at least this long (e.g. 30s) between the markers of --auto-marker every-command
+    #[arg(long = "auto-marker-gap", value_name = "DURATION", requires = "auto_marker")]
+    pub auto_marker_gap: Option<String>,
+
+    /// Hash the files under these paths (comma-separated) before and after the session, into <log>.snapshot
+    #[cfg(feature = "snapshot")]
+    #[arg(long = "snapshot-paths", value_name = "PATHS")]
+    pub snapshot_paths: Option<String>,
+
+    /// What --snapshot-paths keeps of the files: hash, or copy them too
+    #[cfg(feature = "snapshot")]
+    #[arg(long = "snapshot-mode", value_name = "MODE", default_value = "hash", requires = "snapshot_paths")]
+    pub snapshot_mode: String,
+
+    /// Write the session to a directory of its own under this one, handed over with a manifest once complete
+    #[arg(long = "spool", value_name = "DIR")]
+    pub spool: Option<PathBuf>,
+
+    /// Digest for fingerprinting files: sha256 (default), sha512 or blake3
+    #[arg(long = "hash", value_name = "ALGORITHM")]
+    pub hash: Option<String>,
+
+    /// Record the command line, working directory and parent of every program the session runs (needs root)
+    #[arg(long = "trace-exec")]
+    pub trace_exec: bool,
+
+    /// Record what the session opens, writes, creates, deletes or renames under these paths, comma-separated (needs root)
+    #[arg(long = "trace-files", value_name = "PATHS", conflicts_with = "drop_privs")]
+    pub trace_files: Option<String>,
+
+    /// Command to run on the finished session (JSON lines on stdin, JSON annotations on stdout); may be repeated
+    #[cfg(feature = "analyzers")]
+    #[arg(long = "analyzer", value_name = "COMMAND")]
+    pub analyzer: Vec<String>,
+
+    /// Write <log>.meta.json with the session's host, user, start time, command lines and notable events
+    #[cfg(feature = "timeline")]
+    #[arg(long = "meta")]
+    pub meta: bool,
+
+    /// Drop all capabilities and set no_new_privs once the session is set up. Started as root,
+    /// the recorder also becomes the user who ran it through sudo (SUDO_UID/SUDO_GID) or who
+    /// owns the terminal; when that is root too, it keeps uid 0 and with it root's file access
+    #[arg(long = "drop-privs")]
+    pub drop_privs: bool,
+
+    /// With --drop-privs, also move the recorder into a user and mount namespace of its own
+    #[arg(long = "drop-privs-namespace", requires = "drop_privs")]
+    pub drop_privs_namespace: bool,
+
+    /// Config file (default: ~/.config/rust_script/config)
+    #[arg(long = "config", value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Accept commands from `script ctl NAME` while recording
+    #[arg(long = "name", value_name = "NAME")]
+    pub name: Option<String>,
+
+    /// Ask COMMAND about each `script ctl` command (SCRIPT_PEER_UID, SCRIPT_PEER_GID, SCRIPT_PEER_PID
+    /// and SCRIPT_REQUEST in its environment, the request on stdin); it is refused unless COMMAND exits 0
+    #[arg(long = "auth-cmd", value_name = "COMMAND", requires = "name")]
+    pub auth_cmd: Option<String>,
+
+    /// Run the recorder with SCHED_FIFO at this priority (1-99)
+    #[arg(long = "rt-priority", value_name = "PRIORITY", value_parser = clap::value_parser!(i32).range(1..=99))]
+    pub rt_priority: Option<i32>,
+
+    /// Pin the recorder to these CPUs (e.g. 0,2-3)
+    #[arg(long = "cpu-affinity", value_name = "CPUS")]
+    pub cpu_affinity: Option<String>,
+
+    /// Size of reads from the session (default: 8k, grown automatically on sustained full reads)
+    #[arg(long = "read-buffer", value_name = "SIZE")]
+    pub read_buffer: Option<String>,
+
+    /// Compress the log files (gzip, zstd[:LEVEL] or none; default: by the file name)
+    #[arg(long = "compress", value_name = "METHOD")]
+    pub compress: Option<String>,
+
+    /// Encrypt the log files to an age recipient (age1...) or the recipients in a file; may be repeated
+    #[arg(long = "encrypt", value_name = "RECIPIENT")]
+    pub encrypt: Vec<String>,
+
+    /// Use only FIPS 140 approved cryptography: SHA-2 digests, and no age encryption (also SCRIPT_FIPS=1)
+    #[arg(long = "fips")]
+    pub fips: bool,
+
+    /// Size of the log file write buffers
+    #[arg(long = "write-buffer", value_name = "SIZE")]
+    pub write_buffer: Option<String>,
+
+    /// Check that every file descriptor opened for the session is closed at the end
+    #[arg(long = "debug")]
+    pub debug: bool,
+
+    /// List the optional features this binary was built with
+    #[arg(long = "features")]
+    pub features: bool,
+
+    /// Output file (default: typescript)
+    pub file: Option<PathBuf>,
+
+    /// Program and arguments to run without a shell, after --, as in `script log -- make -j4`
+    #[arg(last = true, value_name = "PROGRAM", conflicts_with = "command")]
+    pub exec: Vec<String>,
+}
+
+// Variants are named after their subcommands, split-commands among them
+#[allow(clippy::enum_variant_names)]
+#[derive(clap::Subcommand, Debug)]
+pub enum Commands {
+    /// Exercise the recording pipeline with synthetic traffic and check its health
+    Selftest {
+        /// How long to run the synthetic session (e.g. 30s, 10m, 1h)
+        #[arg(long = "soak", default_value = "30s")]
+        soak: String,
+
+        /// Fail if the recorder's peak memory use exceeds this size
+        #[arg(long = "max-rss", default_value = "64M")]
+        max_rss: String,
+    },
+
+    /// Play back a recorded session with its original timing
+    Replay {
+        /// Timing file (classic or advanced format; not needed for asciicast)
+        #[arg(short = 't', long = "timing")]
+        timing: Option<PathBuf>,
+
+        /// Log holding both input and output, as written by -B
+        #[arg(short = 'B', long = "log-io")]
+        log_io: Option<PathBuf>,
+
+        /// Identity file for encrypted recordings (default: $SCRIPT_IDENTITY)
+        #[arg(short = 'i', long = "identity")]
+        identity: Option<PathBuf>,
+
+        /// Pass the output through tmux to the terminal it runs in (auto, always or never; auto when $TMUX is set)
+        #[arg(long = "tmux-passthrough", value_name = "WHEN", default_value = "auto")]
+        tmux_passthrough: String,
+
+        /// Play under a control bar: space pauses, arrows seek 10s, +/- change the speed, . steps, q quits
+        #[arg(long = "interactive")]
+        interactive: bool,
+
+        /// Start at this time (e.g. 01:30:00), from the nearest point in <log>.idx when there is one
+        #[arg(long = "start", value_name = "TIME")]
+        start: Option<String>,
+
+        /// Cut pauses longer than this many seconds down to it
+        #[arg(short = 'm', long = "maxdelay", visible_alias = "idle-limit", value_name = "SECONDS")]
+        max_delay: Option<f64>,
+
+        /// Play this many times faster (0.5 for half speed)
+        #[arg(short = 'd', long = "divisor", value_name = "N", conflicts_with = "speed")]
+        divisor: Option<f64>,
+
+        /// Playback speed, as x2 or x0.5
+        #[arg(long = "speed", value_name = "SPEED")]
+        speed: Option<String>,
+
+        /// Play no faster than a line of this many bits a second (e.g. 9600bps, 56k, 2M)
+        #[arg(long = "throttle", value_name = "RATE", conflicts_with = "interactive")]
+        throttle: Option<String>,
+
+        /// Show inline images (Sixel, iTerm2), or write [image] in their place (auto, always or never; auto when the terminal is known to show them)
+        #[arg(long = "images", value_name = "WHEN", default_value = "auto")]
+        images: String,
+
+        /// How to end: newline, as scriptreplay does, or screen, leaving the screen and cursor the session ended with
+        #[arg(long = "resume-style", value_name = "STYLE", default_value = "newline", conflicts_with = "interactive")]
+        resume_style: String,
+
+        /// Output log, asciicast v2 or ttyrec file (default: typescript)
+        typescript: Option<PathBuf>,
+    },
+
+    /// Play several recordings together, lined up by the time each started
+    SyncReplay {
+        /// Move a recording on the timeline: its place among the recordings, from 1, and seconds (e.g. 2=-0.25)
+        #[arg(long = "skew", value_name = "N=SECONDS", allow_hyphen_values = true)]
+        skew: Vec<String>,
+
+        /// Recordings, each a log with its timing file as TIMING:LOG, or an asciicast or ttyrec file
+        #[arg(required = true)]
+        recordings: Vec<String>,
+    },
+
+    /// Play back the most recent recorded session
+    Last {
+        /// Identity file for encrypted recordings (default: $SCRIPT_IDENTITY)
+        #[arg(short = 'i', long = "identity")]
+        identity: Option<PathBuf>,
+
+        /// Pass the output through tmux to the terminal it runs in (auto, always or never; auto when $TMUX is set)
+        #[arg(long = "tmux-passthrough", value_name = "WHEN", default_value = "auto")]
+        tmux_passthrough: String,
+    },
+
+    /// Record again with the options and command of the most recent session, from its directory
+    Redo,
+
+    /// Ask how to record, then print the command line that does it and maybe save it as the default options
+    Init {
+        /// Config file to save to (default: ~/.config/rust_script/config)
+        #[arg(long = "config", value_name = "FILE")]
+        config: Option<PathBuf>,
+    },
+
+    /// Re-run a recorded session by typing its input into a new shell
+    Live {
+        /// Advanced timing file with input records
+        #[arg(short = 't', long = "timing")]
+        timing: PathBuf,
+
+        /// Input log, as written by -I
+        #[arg(short = 'I', long = "log-in", conflicts_with = "log_io", required_unless_present = "log_io")]
+        log_in: Option<PathBuf>,
+
+        /// Log holding both input and output, as written by -B
+        #[arg(short = 'B', long = "log-io")]
+        log_io: Option<PathBuf>,
+
+        /// Run the command instead of an interactive shell
+        #[arg(short = 'c', long = "command")]
+        command: Option<String>,
+    },
+
+    /// Step through secrets and full-screen programs in a recording and write a sanitized copy
+    #[cfg(feature = "review")]
+    Review {
+        /// Timing file to adjust when spans are cut
+        #[arg(short = 't', long = "timing")]
+        timing: Option<PathBuf>,
+
+        /// Log holding both input and output, as written by -B
+        #[arg(short = 'B', long = "log-io")]
+        log_io: Option<PathBuf>,
+
+        /// Where to write the sanitized log (default: <log>.sanitized)
+        #[arg(short = 'o', long = "output")]
+        output: Option<PathBuf>,
+
+        /// Output log (default: typescript)
+        typescript: Option<PathBuf>,
+    },
+
+    /// Convert a recording between formats
+    Convert {
+        /// classic, advanced, asciicast or ttyrec
+        #[arg(long = "from")]
+        from: String,
+
+        /// classic, advanced, asciicast or ttyrec
+        #[arg(long = "to")]
+        to: String,
+
+        /// Input then output files; classic and advanced take a timing file and a log
+        #[arg(required = true, num_args = 2..=4)]
+        files: Vec<PathBuf>,
+
+        /// Replace host names, IP addresses, user names and paths with pseudonyms kept in this mapping file
+        #[arg(long = "anonymize", value_name = "MAPPING")]
+        anonymize: Option<PathBuf>,
+    },
+
+    /// Find out which output record put the character at a screen position there
+    #[cfg(feature = "vt")]
+    Blame {
+        /// Timing file (classic or advanced format; not needed for asciicast or ttyrec)
+        #[arg(short = 't', long = "timing")]
+        timing: Option<PathBuf>,
+
+        /// Row on screen, from 1
+        #[arg(long = "row")]
+        row: usize,
+
+        /// Column on screen, from 1
+        #[arg(long = "col")]
+        col: usize,
+
+        /// Look at the screen this far into the recording (e.g. 00:10:00; default: the end)
+        #[arg(long = "at", value_name = "TIME")]
+        at: Option<String>,
+
+        /// Typescript, asciicast v2 or ttyrec file
+        recording: PathBuf,
+    },
+
+    /// Print the screen as it stood at some point of a recording
+    #[cfg(feature = "screenshot")]
+    Screenshot {
+        /// Timing file (classic or advanced format; not needed for asciicast or ttyrec)
+        #[arg(short = 't', long = "timing")]
+        timing: Option<PathBuf>,
+
+        /// Show the screen this far into the recording (e.g. 00:05:30; default: the end)
+        #[arg(long = "at", value_name = "TIME")]
+        at: Option<String>,
+
+        /// Keep the colors and attributes, as ANSI escape sequences
+        #[arg(long = "ansi")]
+        ansi: bool,
+
+        /// Typescript, asciicast v2 or ttyrec file
+        recording: PathBuf,
+    },
+
+    /// Render a recording into a file to share, such as a standalone HTML page with a player
+    #[cfg(feature = "export")]
+    Export {
+        /// What to write: html, gif, or text for screen readers
+        #[arg(long = "format", default_value = "html")]
+        format: String,
+
+        /// Frames per second of a GIF
+        #[arg(long = "fps", default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..=50))]
+        fps: u32,
+
+        /// Stop a GIF this far into the recording (e.g. 00:01:30 or 90)
+        #[arg(long = "max-duration", value_name = "TIME")]
+        max_duration: Option<String>,
+
+        /// Timing file (classic or advanced format; not needed for asciicast or ttyrec)
+        #[arg(short = 't', long = "timing")]
+        timing: Option<PathBuf>,
+
+        /// Where to write it (default: standard output)
+        #[arg(short = 'o', long = "output")]
+        output: Option<PathBuf>,
+
+        /// Replace host names, IP addresses, user names and paths with pseudonyms kept in this mapping file
+        #[arg(long = "anonymize", value_name = "MAPPING")]
+        anonymize: Option<PathBuf>,
+
+        /// Put this text over the page or GIF, e.g. a copyright line
+        #[arg(long = "watermark", value_name = "TEXT")]
+        watermark: Option<String>,
+
+        /// Put this image over the page (PNG, JPEG, GIF, WebP or SVG) or the GIF (a GIF image)
+        #[arg(long = "watermark-image", value_name = "FILE", conflicts_with = "watermark")]
+        watermark_image: Option<PathBuf>,
+
+        /// Where the watermark goes: top-left, top-right, bottom-left, bottom-right, or bottom as a footer
+        #[arg(long = "watermark-position", value_name = "WHERE", default_value = "bottom-right")]
+        watermark_position: String,
+
+        /// How opaque the watermark is, from 0 to 1
+        #[arg(long = "watermark-opacity", value_name = "OPACITY", default_value_t = 0.5)]
+        watermark_opacity: f64,
+
+        /// Write a program that plays the recording when run, with nothing else needed, instead of --format
+        #[arg(
+            long = "self-extracting",
+            value_name = "FILE",
+            conflicts_with_all = ["format", "output", "fps", "max_duration", "watermark", "watermark_image"]
+        )]
+        self_extracting: Option<PathBuf>,
+
+        /// Typescript, asciicast v2 or ttyrec file
+        recording: PathBuf,
+    },
+
+    /// Show the duration, byte counts, idle time, terminal size and exit code of a recording
+    #[command(alias = "stats")]
+    Info {
+        /// Print the figures as a JSON object
+        #[arg(long = "json")]
+        json: bool,
+
+        /// Timing file (classic or advanced format; not needed for asciicast or ttyrec)
+        #[arg(short = 't', long = "timing")]
+        timing: Option<PathBuf>,
+
+        /// Typescript, asciicast v2 or ttyrec file
+        recording: PathBuf,
+    },
+
+    /// Sum up a directory of recordings: sessions, time, commands per day and the tools most used, without any content
+    ArchiveStats {
+        /// Leave out tools used fewer times than this, as "(other)"
+        #[arg(long = "min-count", default_value_t = 5)]
+        min_count: usize,
+
+        /// How many tools to list
+        #[arg(long = "top", default_value_t = 10)]
+        top: usize,
+
+        /// Directory searched for advanced timing files with their logs, asciicast and ttyrec files
+        dir: PathBuf,
+    },
+
+    /// Write each command typed in a recording as its own asciicast file
+    SplitCommands {
+        /// Timing file (classic or advanced format; not needed for asciicast or ttyrec)
+        #[arg(short = 't', long = "timing")]
+        timing: Option<PathBuf>,
+
+        /// Directory for the per-command files
+        #[arg(long = "outdir", required_unless_present = "runbook")]
+        outdir: Option<PathBuf>,
+
+        /// Also write the commands and the start of their output as a runbook (YAML) to check a rerun against
+        #[arg(long = "runbook", value_name = "FILE")]
+        runbook: Option<PathBuf>,
+
+        /// Typescript written with -B, or an asciicast v2 file with input events
+        recording: PathBuf,
+    },
+
+    /// Search the output of a recording and print the matching lines with the time they appeared
+    #[cfg(feature = "grep")]
+    Grep {
+        /// Regular expression searched for in each line of output, without colors
+        pattern: String,
+
+        /// Ignore case
+        #[arg(short = 'i', long = "ignore-case")]
+        ignore_case: bool,
+
+        /// Timing file (classic or advanced format; not needed for asciicast or ttyrec)
+        #[arg(short = 't', long = "timing")]
+        timing: Option<PathBuf>,
+
+        /// Typescript, asciicast v2 or ttyrec file
+        recording: PathBuf,
+    },
+
+    /// Export the commands typed in a recording as a Jupyter notebook, one cell per command
+    #[cfg(feature = "notebook")]
+    Notebook {
+        /// Timing file (classic or advanced format; not needed for asciicast or ttyrec)
+        #[arg(short = 't', long = "timing")]
+        timing: Option<PathBuf>,
+
+        /// Where to write the notebook (default: standard output)
+        #[arg(short = 'o', long = "output")]
+        output: Option<PathBuf>,
+
+        /// Typescript written with -B, or an asciicast v2 file with input events
+        recording: PathBuf,
+    },
+
+    /// Merge the timelines of sessions recorded with --meta into one report
+    #[cfg(feature = "timeline")]
+    TimelineMerge {
+        /// Print JSON rather than a Markdown table
+        #[arg(long = "json")]
+        json: bool,
+
+        /// Metadata sidecars (<log>.meta.json)
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+
+    /// Even out the typing rhythm in an advanced timing file
+    Smooth {
+        /// Timing file to rewrite
+        #[arg(short = 't', long = "timing")]
+        timing: PathBuf,
+
+        /// Range keystroke delays are clamped into
+        #[arg(long = "range", default_value = "30ms-80ms")]
+        range: String,
+
+        /// Save the untouched timing file as <timing>.orig
+        #[arg(long = "keep-original")]
+        keep_original: bool,
+    },
+
+    /// Print the JSON Schema of the JSON handed to other programs
+    Schema {
+        /// event (the session JSON lines analyzers read), metadata (<log>.meta.json) or webhook
+        name: String,
+    },
+
+    /// Hand the sessions finished in a --spool directory to a collector
+    Spooler {
+        /// Directory the sessions are spooled to
+        dir: PathBuf,
+
+        /// Command run with `sh -c` for each session, its directory as $1
+        #[arg(long = "exec", value_name = "COMMAND")]
+        exec: String,
+
+        /// Failed hand-offs retried before the session is moved to .failed
+        #[arg(long = "retries", default_value_t = 5)]
+        retries: u32,
+
+        /// Wait before the first retry, doubled after each
+        #[arg(long = "retry-delay", default_value = "30s")]
+        retry_delay: String,
+
+        /// How often to look for finished sessions
+        #[arg(long = "interval", default_value = "5s")]
+        interval: String,
+
+        /// Look once, then exit
+        #[arg(long = "once")]
+        once: bool,
+
+        /// Move handed-off sessions to .done rather than deleting them
+        #[arg(long = "keep")]
+        keep: bool,
+    },
+
+    /// Send a command to a session started with --name
+    Ctl {
+        /// Name of the session
+        name: String,
+
+        /// stop-child, cont-child, lock-input, unlock-input, reopen or status
+        command: String,
+    },
+}
@@ -0,0 +1,76 @@
+use chrono::{DateTime, FixedOffset, Local};
+#[cfg(test)]
+use std::sync::Mutex;
+#[cfg(test)]
+use std::time::Duration;
+use std::time::Instant;
+
+pub trait Clock: Send + Sync {
+    // Monotonic time, used for all timing deltas
+    fn now(&self) -> Instant;
+
+    // Wall-clock time, used for headers and start/stop stamps
+    fn wall(&self) -> DateTime<FixedOffset>;
+
+    // Block until `now()` has reached the deadline
+    fn sleep_until(&self, deadline: Instant);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall(&self) -> DateTime<FixedOffset> {
+        Local::now().fixed_offset()
+    }
+
+    fn sleep_until(&self, deadline: Instant) {
+        let now = Instant::now();
+        if deadline > now {
+            std::thread::sleep(deadline - now);
+        }
+    }
+}
+
+// A clock that only moves when told to, so timing output is reproducible
+#[cfg(test)]
+pub struct ManualClock {
+    base: Instant,
+    wall_base: DateTime<FixedOffset>,
+    elapsed: Mutex<Duration>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new(wall_base: DateTime<FixedOffset>) -> Self {
+        ManualClock {
+            base: Instant::now(),
+            wall_base,
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.base + *self.elapsed.lock().unwrap()
+    }
+
+    fn wall(&self) -> DateTime<FixedOffset> {
+        self.wall_base + *self.elapsed.lock().unwrap()
+    }
+
+    // Time jumps straight to the deadline
+    fn sleep_until(&self, deadline: Instant) {
+        let mut elapsed = self.elapsed.lock().unwrap();
+        *elapsed = (*elapsed).max(deadline.saturating_duration_since(self.base));
+    }
+}
@@ -0,0 +1,245 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+#[cfg(feature = "gzip")]
+use flate2::{bufread::MultiGzDecoder, write::GzEncoder};
+
+use crate::encryption::{self, Recipients, AGE_MAGIC};
+use crate::failure::Failure;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+// zstd's own default; 1 is fastest, 22 smallest
+const ZSTD_DEFAULT_LEVEL: i32 = 3;
+const ZSTD_LEVELS: std::ops::RangeInclusive<i32> = 1..=22;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd(i32),
+}
+
+impl Compression {
+    // "gzip", "zstd", "zstd:19" or "none"
+    pub fn parse(name: &str) -> Result<Compression> {
+        let name = name.to_lowercase();
+        let (method, level) = match name.split_once(':') {
+            Some((method, level)) => (method, Some(level)),
+            None => (name.as_str(), None),
+        };
+        match (method, level) {
+            ("none", None) => Ok(Compression::None),
+            ("gzip", None) => Ok(Compression::Gzip),
+            ("zstd", None) => Ok(Compression::Zstd(ZSTD_DEFAULT_LEVEL)),
+            ("zstd", Some(level)) => match level.parse() {
+                Ok(level) if ZSTD_LEVELS.contains(&level) => Ok(Compression::Zstd(level)),
+                _ => Err(anyhow!("Invalid zstd level: '{}' (1-22)", level)),
+            },
+            _ => Err(anyhow!("Unsupported compression: '{}' (gzip, zstd[:level] or none)", name)),
+        }
+    }
+
+    // Without --compress, a log named *.gz or *.zst is compressed
+    pub fn from_path(path: &Path) -> Compression {
+        match path.extension() {
+            Some(ext) if ext == "gz" => Compression::Gzip,
+            Some(ext) if ext == "zst" => Compression::Zstd(ZSTD_DEFAULT_LEVEL),
+            _ => Compression::None,
+        }
+    }
+
+    // The error for a method this build lacks
+    fn check(self) -> Result<()> {
+        match self {
+            #[cfg(not(feature = "gzip"))]
+            Compression::Gzip => Err(anyhow!("This build cannot compress logs with gzip (feature `gzip`)")),
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd(_) => Err(anyhow!("This build cannot compress logs with zstd (feature `zstd`)")),
+            _ => Ok(()),
+        }
+    }
+}
+
+// One layer of a log file: the file itself, the encryption or a
+// compressor, each with an end to write out
+pub(crate) trait Stream: Write + Send {
+    fn finish(self: Box<Self>) -> std::io::Result<()>;
+}
+
+impl Stream for BufWriter<File> {
+    fn finish(mut self: Box<Self>) -> std::io::Result<()> {
+        self.flush()
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl Stream for GzEncoder<Box<dyn Stream>> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        (*self).finish()?.finish()
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Stream for zstd::stream::write::Encoder<'static, Box<dyn Stream>> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        (*self).finish()?.finish()
+    }
+}
+
+#[cfg(feature = "encrypt")]
+impl Stream for age::stream::StreamWriter<Box<dyn Stream>> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        (*self).finish()?.finish()
+    }
+}
+
+// The file end of a log: compressed first, then encrypted
+pub(crate) struct FileWriter {
+    stream: Box<dyn Stream>,
+    compressed: bool,
+}
+
+impl FileWriter {
+    // Fails before touching the file if this build can't write it
+    pub(crate) fn open(
+        options: &OpenOptions,
+        path: &Path,
+        buffer_size: usize,
+        compression: Compression,
+        recipients: Option<&Recipients>,
+    ) -> Result<Self> {
+        compression.check()?;
+        let file = options.open(path).context(Failure::Output(path.to_path_buf()))?;
+        #[allow(unused_mut)]
+        let mut stream: Box<dyn Stream> = Box::new(BufWriter::with_capacity(buffer_size, file));
+
+        #[cfg(feature = "encrypt")]
+        if let Some(recipients) = recipients {
+            stream = Box::new(recipients.wrap(stream)?);
+        }
+        #[cfg(not(feature = "encrypt"))]
+        let _ = recipients;
+
+        let stream: Box<dyn Stream> = match compression {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Box::new(GzEncoder::new(stream, flate2::Compression::default())),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(level) => Box::new(zstd::stream::write::Encoder::new(stream, level)?),
+            _ => stream,
+        };
+        Ok(FileWriter {
+            stream,
+            compressed: compression != Compression::None,
+        })
+    }
+
+    pub(crate) fn write_all(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.stream.write_all(data)
+    }
+
+    // Flushing a compressed stream after every burst of output would cost
+    // most of what compression saves, so it reaches the file in blocks
+    pub(crate) fn flush(&mut self) -> std::io::Result<()> {
+        if self.compressed {
+            return Ok(());
+        }
+        self.stream.flush()
+    }
+
+    // Write out the end of every layer
+    pub(crate) fn finish(self) -> std::io::Result<()> {
+        self.stream.finish()
+    }
+}
+
+// Open a recording for reading, decrypting and decompressing it as its
+// first bytes say, whatever its name
+pub fn open(path: &Path) -> Result<Box<dyn BufRead>> {
+    open_with(path, None)
+}
+
+// The same, decrypting with the identities in `identity` rather than
+// $SCRIPT_IDENTITY
+pub fn open_with(path: &Path, identity: Option<&Path>) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    decode(Box::new(BufReader::new(file)), identity).with_context(|| format!("Cannot read {}", path.display()))
+}
+
+fn decode(mut input: Box<dyn BufRead>, identity: Option<&Path>) -> Result<Box<dyn BufRead>> {
+    let start = input.fill_buf()?;
+
+    if start.starts_with(AGE_MAGIC) {
+        return decode(encryption::decrypt(input, identity)?, identity);
+    }
+    if start.starts_with(GZIP_MAGIC) {
+        #[cfg(feature = "gzip")]
+        return Ok(Box::new(BufReader::new(MultiGzDecoder::new(input))));
+        #[cfg(not(feature = "gzip"))]
+        return Err(anyhow!("This build cannot read gzip files (feature `gzip`)"));
+    }
+    if start.starts_with(ZSTD_MAGIC) {
+        #[cfg(feature = "zstd")]
+        return Ok(Box::new(BufReader::new(zstd::stream::read::Decoder::with_buffer(input)?)));
+        #[cfg(not(feature = "zstd"))]
+        return Err(anyhow!("This build cannot read zstd files (feature `zstd`)"));
+    }
+    Ok(input)
+}
+
+pub fn read(path: &Path) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    open(path)?
+        .read_to_end(&mut content)
+        .with_context(|| format!("Cannot read {}", path.display()))?;
+    Ok(content)
+}
+
+pub fn read_to_string(path: &Path) -> Result<String> {
+    String::from_utf8(read(path)?).with_context(|| format!("{} is not text", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_compression() {
+        assert_eq!(Compression::parse("zstd:19").unwrap(), Compression::Zstd(19));
+        assert_eq!(Compression::parse("ZSTD").unwrap(), Compression::Zstd(3));
+        assert!(Compression::parse("zstd:0").is_err());
+        assert!(Compression::parse("gzip:9").is_err());
+        assert!(Compression::parse("lzma").is_err());
+        assert_eq!(Compression::from_path(Path::new("session.log.gz")), Compression::Gzip);
+        assert_eq!(Compression::from_path(Path::new("session.tm.zst")), Compression::Zstd(3));
+        assert_eq!(Compression::from_path(Path::new("typescript")), Compression::None);
+    }
+
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    #[test]
+    fn test_compressed_files_read_back() {
+        for (name, compression) in [("log.gz", Compression::Gzip), ("log.zst", Compression::Zstd(19)), ("log", Compression::None)] {
+            let path = std::env::temp_dir().join(format!("rust_script-{}-{}", std::process::id(), name));
+            let mut options = OpenOptions::new();
+            options.create(true).write(true).truncate(true);
+            let mut writer = FileWriter::open(&options, &path, 64, compression, None).unwrap();
+            writer.write_all(b"Script started\n").unwrap();
+            writer.finish().unwrap();
+
+            // A second gzip member, as appending writes
+            if compression == Compression::Gzip {
+                let mut writer = FileWriter::open(options.append(true).truncate(false), &path, 64, compression, None).unwrap();
+                writer.write_all(b"again\n").unwrap();
+                writer.finish().unwrap();
+            }
+
+            let content = read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let expected = if compression == Compression::Gzip { "Script started\nagain\n" } else { "Script started\n" };
+            assert_eq!(content, expected, "{}", name);
+        }
+    }
+}
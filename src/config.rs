@@ -0,0 +1,349 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::macros::{self, MacroTable};
+
+// Settings read from the config file, an INI-style list of sections:
+//
+//   [macros]
+//   prefix = ^A
+//   1 = make test\r
+//
+//   [record]
+//   options = -B session.log -T session.tm -m advanced --compress zstd
+#[derive(Debug, Default)]
+pub struct Config {
+    pub macros: MacroTable,
+    // Put before the options of every recording, which win over them
+    pub record_options: Vec<String>,
+}
+
+// The shell util-linux falls back to when SHELL is unset
+pub const DEFAULT_SHELL: &str = "/bin/sh";
+
+// $SHELL split into the program and any options given with it, such as
+// "/bin/bash --login". The words are passed on as they are, never through
+// another shell.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shell {
+    pub path: String,
+    pub options: Vec<String>,
+}
+
+impl Shell {
+    fn parse(value: Option<&str>) -> Shell {
+        let mut words = value.unwrap_or_default().split_whitespace().map(str::to_string);
+        match words.next() {
+            Some(path) => Shell { path, options: words.collect() },
+            None => Shell { path: DEFAULT_SHELL.to_string(), options: Vec::new() },
+        }
+    }
+}
+
+// Environment variables count as unset when empty, as in util-linux
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+pub fn shell() -> Shell {
+    Shell::parse(env("SHELL").as_deref())
+}
+
+// Without TERM nothing is recorded for it
+pub fn term() -> Option<String> {
+    env("TERM")
+}
+
+// Timing format ("classic" or "advanced") used when -m isn't given
+pub fn default_format() -> Option<String> {
+    env("SCRIPT_DEFAULT_FORMAT")
+}
+
+// Where the default typescript goes when no log file is named
+pub fn default_dir() -> Option<PathBuf> {
+    env("SCRIPT_DEFAULT_DIR").map(PathBuf::from)
+}
+
+// Set inside a tmux session
+pub fn tmux() -> bool {
+    env("TMUX").is_some()
+}
+
+// Whether the terminal shows Sixel or iTerm2 inline images, as far as its
+// name tells. LC_TERMINAL names iTerm2 over ssh and inside tmux too.
+pub fn inline_images() -> bool {
+    let programs = [env("TERM_PROGRAM"), env("LC_TERMINAL")];
+    let term = env("TERM").unwrap_or_default();
+    programs.iter().flatten().any(|program| matches!(program.as_str(), "iTerm.app" | "iTerm2" | "WezTerm" | "mintty"))
+        || ["foot", "mlterm", "contour", "yaft"].iter().any(|name| term.starts_with(name))
+        || term.contains("sixel")
+}
+
+// Set in the sessions we record, to the output log: this process is
+// being recorded already
+pub fn script() -> Option<String> {
+    env("SCRIPT")
+}
+
+// FIPS mode for every command, as --fips is for recording
+pub fn fips() -> bool {
+    env("SCRIPT_FIPS").is_some_and(|value| value != "0")
+}
+
+// Identity file for reading encrypted recordings
+#[cfg(feature = "encrypt")]
+pub fn identity() -> Option<PathBuf> {
+    env("SCRIPT_IDENTITY").map(PathBuf::from)
+}
+
+// The keyboard layout in XKB's terms ("de", "us(dvorak)"), which decides what
+// keys the recorded input bytes came from: XKB_DEFAULT_LAYOUT and _VARIANT as
+// Wayland compositors read them, else the console keyboard setup of
+// Debian-like systems
+pub fn keyboard_layout() -> Option<String> {
+    if let Some(layout) = env("XKB_DEFAULT_LAYOUT") {
+        return Some(xkb_layout(&layout, env("XKB_DEFAULT_VARIANT").as_deref()));
+    }
+    let content = std::fs::read_to_string("/etc/default/keyboard").ok()?;
+    keyboard_file_layout(&content)
+}
+
+// XKBLAYOUT="de" and XKBVARIANT="nodeadkeys" in /etc/default/keyboard
+fn keyboard_file_layout(content: &str) -> Option<String> {
+    let setting = |name: &str| {
+        content.lines().find_map(|line| {
+            let value = line.trim().strip_prefix(name)?.strip_prefix('=')?;
+            Some(value.trim_matches('"').to_string()).filter(|value| !value.is_empty())
+        })
+    };
+    let layout = setting("XKBLAYOUT")?;
+    Some(xkb_layout(&layout, setting("XKBVARIANT").as_deref()))
+}
+
+// Layouts and variants are parallel lists: "us,de" with ",nodeadkeys" is
+// "us,de(nodeadkeys)"
+fn xkb_layout(layouts: &str, variants: Option<&str>) -> String {
+    let mut variants = variants.unwrap_or_default().split(',');
+    layouts
+        .split(',')
+        .map(|layout| match variants.next() {
+            Some(variant) if !variant.is_empty() => format!("{}({})", layout, variant),
+            _ => layout.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// The locale input is encoded and typed in, as setlocale(LC_CTYPE) picks it
+pub fn input_locale() -> Option<String> {
+    env("LC_ALL").or_else(|| env("LC_CTYPE")).or_else(|| env("LANG"))
+}
+
+// An input method composes text from several keystrokes, so the input log
+// holds what was committed rather than what was pressed
+pub fn input_method() -> Option<String> {
+    env("XMODIFIERS")
+        .and_then(|modifiers| modifiers.strip_prefix("@im=").map(str::to_string))
+        .or_else(|| env("GTK_IM_MODULE"))
+        .or_else(|| env("QT_IM_MODULE"))
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("rust_script").join("config"))
+}
+
+// The sessions recorded by this user, for `script last` and `script redo`
+pub fn history_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))
+        .map(|dir| dir.join("rust_script").join("history"))
+}
+
+impl Config {
+    // A file given explicitly must exist; the default one is optional
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Config::default())
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Cannot read config file {}", path.display()))
+            }
+        };
+
+        Config::parse(&content).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Config> {
+        let mut config = Config::default();
+        let mut section = String::new();
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_string();
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("line {}: expected `key = value`", i + 1))?;
+            let (key, value) = (key.trim(), value.trim());
+
+            match section.as_str() {
+                "macros" if key == "prefix" => {
+                    config.macros.prefix =
+                        Some(macros::parse_key(value).with_context(|| format!("line {}", i + 1))?);
+                }
+                "macros" => {
+                    let key = macros::parse_key(key).with_context(|| format!("line {}", i + 1))?;
+                    let text = macros::unescape(value).with_context(|| format!("line {}", i + 1))?;
+                    config.macros.macros.insert(key, text);
+                }
+                "record" if key == "options" => {
+                    config.record_options = split_words(value).with_context(|| format!("line {}", i + 1))?;
+                }
+                _ => return Err(anyhow!("line {}: unknown setting `{}` in [{}]", i + 1, key, section)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+// The words of a command line as a shell would split them: quoted with '...'
+// or "...", or a single character escaped with a backslash
+pub fn split_words(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("unterminated quote in `{}`", line)),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => word.extend(chars.next()),
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("unterminated quote in `{}`", line)),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+// The other way around, quoting only the words that need it
+pub fn join_words(words: &[String]) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_=.,:/+@%".contains(c);
+    words
+        .iter()
+        .map(|word| match word {
+            word if !word.is_empty() && word.chars().all(plain) => word.clone(),
+            word => format!("'{}'", word.replace('\'', "'\\''")),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// The config file with its [record] options replaced, the rest kept as it was
+pub fn with_record_options(content: &str, options: &[String]) -> String {
+    let mut out = String::new();
+    let mut in_record = false;
+    for line in content.lines() {
+        if let Some(name) = line.trim().strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_record = name.trim() == "record";
+        }
+        if !in_record {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    if !out.is_empty() && !out.ends_with("\n\n") {
+        out.push('\n');
+    }
+    out.push_str(&format!("[record]\noptions = {}\n", join_words(options)));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyboard_layout() {
+        let content = "XKBMODEL=\"pc105\"\nXKBLAYOUT=\"us,de\"\nXKBVARIANT=\",nodeadkeys\"\n";
+        assert_eq!(keyboard_file_layout(content).as_deref(), Some("us,de(nodeadkeys)"));
+        assert_eq!(keyboard_file_layout("XKBLAYOUT=\"fr\"\nXKBVARIANT=\"\"\n").as_deref(), Some("fr"));
+        assert_eq!(keyboard_file_layout("XKBMODEL=\"pc105\"\n"), None);
+        assert_eq!(xkb_layout("us", Some("dvorak")), "us(dvorak)");
+    }
+
+    #[test]
+    fn test_parse_macros() {
+        let config = Config::parse("# demo\n[macros]\nprefix = ^B\n1 = make test\\r\n").unwrap();
+        assert_eq!(config.macros.prefix, Some(0x02));
+        assert_eq!(config.macros.macros[&b'1'], b"make test\r");
+        assert!(Config::parse("[macros]\nnot a setting\n").is_err());
+        assert!(Config::parse("[other]\nx = 1\n").is_err());
+    }
+
+    #[test]
+    fn test_record_options() {
+        let options = ["-B", "my session.log", "--label", "note=it's", "-q"].map(String::from);
+        let line = join_words(&options);
+        assert_eq!(line, "-B 'my session.log' --label 'note=it'\\''s' -q");
+        assert_eq!(split_words(&line).unwrap(), options);
+        assert_eq!(split_words(r#"-c "echo \"hi\"" a\ b ''"#).unwrap(), ["-c", "echo \"hi\"", "a b", ""]);
+        assert!(split_words("-c 'echo").is_err());
+
+        let content = "[record]\noptions = -q\n\n[macros]\n1 = ls\\r\n";
+        let saved = with_record_options(content, &options);
+        assert_eq!(saved, format!("[macros]\n1 = ls\\r\n\n[record]\noptions = {}\n", line));
+        let config = Config::parse(&saved).unwrap();
+        assert_eq!(config.record_options, options);
+        assert_eq!(config.macros.macros[&b'1'], b"ls\r");
+    }
+
+    #[test]
+    fn test_shell_with_options() {
+        let shell = Shell::parse(Some("/bin/bash  --login -O extglob"));
+        assert_eq!(shell.path, "/bin/bash");
+        assert_eq!(shell.options, ["--login", "-O", "extglob"]);
+        assert_eq!(Shell::parse(Some("/bin/zsh")).options, Vec::<String>::new());
+        assert_eq!(Shell::parse(Some(" ")).path, DEFAULT_SHELL);
+        assert_eq!(Shell::parse(None).path, DEFAULT_SHELL);
+    }
+}
@@ -0,0 +1,336 @@
+use anyhow::{anyhow, Context, Result};
+use nix::unistd::geteuid;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+// How long a client gets to send its command once connected
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Longest command line taken, newline included; every command is far
+// shorter
+const MAX_REQUEST: usize = 4096;
+
+// How long --auth-cmd gets to decide before the command is refused
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Directory holding the control sockets of this user's sessions
+fn socket_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("rust_script"),
+        None => std::env::temp_dir().join(format!("rust_script-{}", geteuid())),
+    }
+}
+
+pub fn socket_path(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        return Err(anyhow!("Invalid session name: {}", name));
+    }
+    Ok(socket_dir().join(format!("{}.sock", name)))
+}
+
+// Commands read but not yet taken by the session
+const REQUEST_QUEUE: usize = 16;
+
+// Listening side, owned by the recording session
+pub struct ControlSocket {
+    listener: Arc<UnixListener>,
+    path: PathBuf,
+    owner: u32,
+    // Asked about every command (--auth-cmd)
+    auth_cmd: Option<Arc<str>>,
+}
+
+pub struct ControlRequest {
+    stream: UnixStream,
+    pub command: String,
+}
+
+// The commands sent to the session, accepted and read in tasks of their
+// own: the session's loop takes them when it gets to them, and a command
+// half read isn't lost when the loop wakes for something else first
+pub struct ControlRequests {
+    receiver: mpsc::Receiver<Result<ControlRequest>>,
+    task: JoinHandle<()>,
+}
+
+impl ControlSocket {
+    pub fn bind(name: &str, auth_cmd: Option<&str>) -> Result<Self> {
+        let path = socket_path(name)?;
+        if let Some(dir) = path.parent() {
+            std::fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+            check_private(dir)?;
+        }
+        let mut socket = ControlSocket::listen(path, name)?;
+        socket.auth_cmd = auth_cmd.map(Arc::from);
+        Ok(socket)
+    }
+
+    fn listen(path: PathBuf, name: &str) -> Result<Self> {
+        // A socket nobody answers on is left over from a session that died
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                return Err(anyhow!("A session named {} is already running", name));
+            }
+            std::fs::remove_file(&path)?;
+        }
+
+        // Nobody else can connect between bind and chmod
+        let umask = nix::sys::stat::umask(nix::sys::stat::Mode::from_bits_truncate(0o077));
+        let listener = UnixListener::bind(&path);
+        nix::sys::stat::umask(umask);
+        let listener = listener.with_context(|| format!("Cannot create control socket {}", path.display()))?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+
+        Ok(ControlSocket {
+            listener: Arc::new(listener),
+            path,
+            owner: std::process::id(),
+            auth_cmd: None,
+        })
+    }
+
+    // Start taking commands; only the session's user (or root) may send
+    // one, and only those --auth-cmd allows
+    pub fn requests(&self) -> ControlRequests {
+        let (sender, receiver) = mpsc::channel(REQUEST_QUEUE);
+        let listener = self.listener.clone();
+        let auth_cmd = self.auth_cmd.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        let _ = sender.send(Err(e.into())).await;
+                        return;
+                    }
+                };
+                // A slow client doesn't hold up the next one
+                tokio::spawn(read_request(stream, sender.clone(), auth_cmd.clone()));
+            }
+        });
+        ControlRequests { receiver, task }
+    }
+}
+
+async fn read_request(mut stream: UnixStream, sender: mpsc::Sender<Result<ControlRequest>>, auth_cmd: Option<Arc<str>>) {
+    let Ok(peer) = stream.peer_cred() else {
+        return;
+    };
+    if peer.uid() != geteuid().as_raw() && peer.uid() != 0 {
+        let _ = stream.write_all(b"error permission denied\n").await;
+        return;
+    }
+
+    let mut line = String::new();
+    let mut reader = tokio::io::BufReader::new(&mut stream).take(MAX_REQUEST as u64);
+    let read = tokio::time::timeout(REQUEST_TIMEOUT, reader.read_line(&mut line)).await;
+    // Nothing to do if the client went away or never said anything
+    let Ok(Ok(1..)) = read else {
+        return;
+    };
+    if !line.ends_with('\n') && line.len() >= MAX_REQUEST {
+        let _ = stream.write_all(b"error command too long\n").await;
+        return;
+    }
+
+    let command = line.trim().to_string();
+    if let Some(auth_cmd) = auth_cmd {
+        if !authorize(&auth_cmd, &peer, &command).await {
+            let _ = stream.write_all(b"error permission denied\n").await;
+            return;
+        }
+    }
+    let _ = sender.send(Ok(ControlRequest { stream, command })).await;
+}
+
+// Whether --auth-cmd lets the peer send the command: it gets both in the
+// environment, and the command on stdin too, and allows it by exiting 0.
+// One that cannot run or takes too long refuses it.
+async fn authorize(auth_cmd: &str, peer: &tokio::net::unix::UCred, command: &str) -> bool {
+    let child = tokio::process::Command::new("/bin/sh")
+        .arg("-c")
+        .arg(auth_cmd)
+        .env("SCRIPT_PEER_UID", peer.uid().to_string())
+        .env("SCRIPT_PEER_GID", peer.gid().to_string())
+        .env("SCRIPT_PEER_PID", peer.pid().map(|pid| pid.to_string()).unwrap_or_default())
+        .env("SCRIPT_REQUEST", command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn();
+    let Ok(mut child) = child else {
+        return false;
+    };
+    let decided = async {
+        if let Some(mut stdin) = child.stdin.take() {
+            // A hook that doesn't read it may have exited already
+            let _ = stdin.write_all(format!("{}\n", command).as_bytes()).await;
+        }
+        child.wait().await
+    };
+    matches!(tokio::time::timeout(AUTH_TIMEOUT, decided).await, Ok(Ok(status)) if status.success())
+}
+
+impl ControlRequests {
+    pub async fn next(&mut self) -> Result<ControlRequest> {
+        match self.receiver.recv().await {
+            Some(request) => request,
+            None => std::future::pending().await,
+        }
+    }
+
+    // Stop taking commands, with the socket no longer in use once it returns
+    pub async fn close(mut self) {
+        self.task.abort();
+        let _ = (&mut self.task).await;
+    }
+}
+
+impl Drop for ControlRequests {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// In /tmp another user could have made the directory first, to listen in
+// place of the session or read what goes through it
+fn check_private(dir: &Path) -> Result<()> {
+    let meta = std::fs::symlink_metadata(dir).with_context(|| format!("Cannot read {}", dir.display()))?;
+    if !meta.file_type().is_dir() || meta.uid() != geteuid().as_raw() || meta.mode() & 0o777 != 0o700 {
+        return Err(anyhow!("{} is not a directory of this user's alone (mode 0700)", dir.display()));
+    }
+    Ok(())
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        // A forked child that fails to exec must not remove the parent's socket
+        if std::process::id() == self.owner {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl ControlRequest {
+    pub async fn reply(mut self, result: Result<String>) -> Result<()> {
+        let line = match result {
+            Ok(message) => format!("ok {}\n", message),
+            Err(e) => format!("error {}\n", e),
+        };
+        self.stream.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+// Client side, used by `script ctl`
+pub fn send(name: &str, command: &str) -> Result<String> {
+    let path = socket_path(name)?;
+    let mut stream = std::os::unix::net::UnixStream::connect(&path)
+        .with_context(|| format!("No session named {} is running", name))?;
+    writeln!(stream, "{}", command)?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+    let reply = reply.trim_end();
+
+    match reply.split_once(' ').unwrap_or((reply, "")) {
+        ("ok", message) => Ok(message.to_string()),
+        ("error", message) => Err(anyhow!("{}", message)),
+        _ => Err(anyhow!("Unexpected reply from session {}: {}", name, reply)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket_path_rejects_bad_names() {
+        assert!(socket_path("build-42").unwrap().ends_with("build-42.sock"));
+        assert!(socket_path("").is_err());
+        assert!(socket_path("../x").is_err());
+        assert!(socket_path(".hidden").is_err());
+    }
+
+    #[test]
+    fn test_socket_dir_must_be_private() {
+        let dir = std::env::temp_dir().join(format!("rust_script-{}-control", std::process::id()));
+        std::fs::DirBuilder::new().mode(0o755).create(&dir).unwrap();
+        let open = check_private(&dir);
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+        let private = check_private(&dir);
+        let link = dir.with_extension("link");
+        std::os::unix::fs::symlink(&dir, &link).unwrap();
+        let linked = check_private(&link);
+        std::fs::remove_file(&link).unwrap();
+        std::fs::remove_dir(&dir).unwrap();
+        assert!(open.is_err() && private.is_ok() && linked.is_err());
+    }
+
+    // Taken even when whoever waits for it gives up now and then, as the
+    // session's loop does whenever something else happens first
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_command_survives_the_loop_waking() {
+        let path = std::env::temp_dir().join(format!("rust_script-{}-ctl.sock", std::process::id()));
+        let socket = ControlSocket::listen(path.clone(), "test").unwrap();
+        let mut requests = socket.requests();
+
+        let client = std::thread::spawn(move || {
+            let mut stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+            stream.write_all(b"sta").unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+            stream.write_all(b"tus\n").unwrap();
+            let mut reply = String::new();
+            BufReader::new(&stream).read_line(&mut reply).unwrap();
+            reply
+        });
+        let request = loop {
+            tokio::select! {
+                request = requests.next() => break request.unwrap(),
+                _ = tokio::time::sleep(Duration::from_millis(1)) => {}
+            }
+        };
+        assert_eq!(request.command, "status");
+        request.reply(Ok("CHILD=running".to_string())).await.unwrap();
+        assert_eq!(tokio::task::spawn_blocking(move || client.join().unwrap()).await.unwrap(), "ok CHILD=running\n");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_auth_cmd_and_overlong_commands_refused() {
+        let path = std::env::temp_dir().join(format!("rust_script-{}-auth.sock", std::process::id()));
+        let mut socket = ControlSocket::listen(path.clone(), "test").unwrap();
+        let hook = r#"read line; test "$line" = "$SCRIPT_REQUEST" && test "$SCRIPT_PEER_UID" = "$(id -u)" && test "$line" = status"#;
+        socket.auth_cmd = Some(Arc::from(hook));
+        let mut requests = socket.requests();
+
+        let send = |command: String| {
+            let path = path.clone();
+            let client = std::thread::spawn(move || {
+                let mut stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+                stream.write_all(command.as_bytes()).unwrap();
+                let mut reply = String::new();
+                BufReader::new(&stream).read_line(&mut reply).unwrap();
+                reply
+            });
+            tokio::task::spawn_blocking(move || client.join().unwrap())
+        };
+
+        let allowed = send("status\n".to_string());
+        let request = requests.next().await.unwrap();
+        assert_eq!(request.command, "status");
+        request.reply(Ok("CHILD=running".to_string())).await.unwrap();
+        assert_eq!(allowed.await.unwrap(), "ok CHILD=running\n");
+
+        assert_eq!(send("lock-input\n".to_string()).await.unwrap(), "error permission denied\n");
+        assert_eq!(send(format!("{}\n", "x".repeat(MAX_REQUEST))).await.unwrap(), "error command too long\n");
+    }
+}
@@ -0,0 +1,244 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::anonymize::Anonymizer;
+#[cfg(feature = "asciicast")]
+use crate::asciicast;
+use crate::compression;
+use crate::logging::TIMESTAMP_FORMAT;
+use crate::timing::{self, EntryKind, TimingEntry};
+use crate::ttyrec;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Classic,
+    Advanced,
+    Asciicast,
+    Ttyrec,
+}
+
+// Log names that only make sense for the files they were written with
+const LOG_NAMES: [&str; 3] = ["TIMING_LOG", "OUTPUT_LOG", "INPUT_LOG"];
+
+impl Format {
+    pub fn parse(name: &str) -> Result<Format> {
+        match name.to_lowercase().as_str() {
+            "classic" => Ok(Format::Classic),
+            "advanced" => Ok(Format::Advanced),
+            "asciicast" => Ok(Format::Asciicast),
+            "ttyrec" => Ok(Format::Ttyrec),
+            _ => Err(anyhow!("Unsupported format: '{}' (classic, advanced, asciicast or ttyrec)", name)),
+        }
+    }
+
+    // Typescripts come as a timing file plus a log; the others stand alone
+    fn files(self) -> usize {
+        match self {
+            Format::Classic | Format::Advanced => 2,
+            Format::Asciicast | Format::Ttyrec => 1,
+        }
+    }
+}
+
+// Read a recording as timing entries plus the data of every input and
+// output record, in order
+fn load(format: Format, files: &[PathBuf]) -> Result<(Vec<TimingEntry>, Vec<u8>)> {
+    let read = |path: &Path| compression::read(path);
+
+    match format {
+        Format::Classic | Format::Advanced => {
+            let content = String::from_utf8(read(&files[0])?)
+                .with_context(|| format!("{} is not a timing file", files[0].display()))?;
+            let entries = timing::parse_timing(&content)?;
+            let log = read(&files[1])?;
+            load_typescript(entries, &log)
+        }
+        #[cfg(feature = "asciicast")]
+        Format::Asciicast => {
+            let content = String::from_utf8(read(&files[0])?)
+                .with_context(|| format!("{} is not an asciicast file", files[0].display()))?;
+            asciicast::parse(&content)
+        }
+        #[cfg(not(feature = "asciicast"))]
+        Format::Asciicast => Err(anyhow!("This build cannot read asciicast files (feature `asciicast`)")),
+        Format::Ttyrec => ttyrec::parse(&read(&files[0])?),
+    }
+}
+
+// The log holds input only when it was written with -B, which the timing
+// file records as the same name for both logs. Input records without data
+// are dropped; their delay moves to the next record.
+fn load_typescript(entries: Vec<TimingEntry>, log: &[u8]) -> Result<(Vec<TimingEntry>, Vec<u8>)> {
+    let input_in_log = matches!(
+        (timing::info(&entries, "INPUT_LOG"), timing::info(&entries, "OUTPUT_LOG")),
+        (Some(input), Some(output)) if input == output
+    );
+
+    let mut log = log;
+    let pending = timing::skip_header(&mut log)?;
+    let mut log = pending.as_slice().chain(log);
+
+    let mut kept = Vec::with_capacity(entries.len());
+    let mut data = Vec::new();
+    let mut carry = Duration::ZERO;
+    for mut entry in entries {
+        let size = match entry.kind {
+            EntryKind::Output(size) => size,
+            EntryKind::Input(size) if input_in_log => size,
+            EntryKind::Input(_) => {
+                carry += entry.delay;
+                continue;
+            }
+            _ => 0,
+        };
+        let start = data.len();
+        data.resize(start + size, 0);
+        log.read_exact(&mut data[start..]).context("log file is shorter than its timing file")?;
+
+        entry.delay += std::mem::take(&mut carry);
+        kept.push(entry);
+    }
+
+    Ok((kept, data))
+}
+
+pub(crate) fn save(format: Format, entries: &[TimingEntry], data: &[u8], files: &[PathBuf]) -> Result<()> {
+    let write = |path: &Path, content: &[u8]| {
+        std::fs::write(path, content).with_context(|| format!("Cannot write {}", path.display()))
+    };
+
+    match format {
+        Format::Classic | Format::Advanced => {
+            let (timing_path, log_path) = (&files[0], &files[1]);
+            let advanced = format == Format::Advanced;
+
+            // The classic format has nowhere to say the log holds input
+            let has_input = advanced && entries.iter().any(|e| matches!(e.kind, EntryKind::Input(_)));
+            let mut log = typescript_header(entries).into_bytes();
+            let mut pos = 0;
+            for entry in entries {
+                if let EntryKind::Output(size) | EntryKind::Input(size) = entry.kind {
+                    if has_input || matches!(entry.kind, EntryKind::Output(_)) {
+                        log.extend_from_slice(&data[pos..pos + size]);
+                    }
+                    pos += size;
+                }
+            }
+
+            let mut entries: Vec<TimingEntry> = entries
+                .iter()
+                .filter(|e| !matches!(e.kind, EntryKind::Info(ref name, _) if LOG_NAMES.contains(&name.as_str())))
+                .cloned()
+                .collect();
+            if advanced {
+                let mut names = vec![("TIMING_LOG", timing_path), ("OUTPUT_LOG", log_path)];
+                if has_input {
+                    names.push(("INPUT_LOG", log_path));
+                }
+                for (i, (name, path)) in names.into_iter().enumerate() {
+                    entries.insert(i, TimingEntry {
+                        delay: Duration::ZERO,
+                        kind: EntryKind::Info(name.to_string(), path.display().to_string()),
+                        wall: None,
+                    });
+                }
+            }
+
+            write(timing_path, timing::format_timing(&entries, advanced).as_bytes())?;
+            write(log_path, &log)
+        }
+        #[cfg(feature = "asciicast")]
+        Format::Asciicast => write(&files[0], asciicast::write(entries, data)?.as_bytes()),
+        #[cfg(not(feature = "asciicast"))]
+        Format::Asciicast => Err(anyhow!("This build cannot write asciicast files (feature `asciicast`)")),
+        Format::Ttyrec => write(&files[0], &ttyrec::write(entries, data)?),
+    }
+}
+
+// The first line of a typescript, which the timing file doesn't count
+fn typescript_header(entries: &[TimingEntry]) -> String {
+    let start = timing::info(entries, "START_TIME")
+        .and_then(|t| DateTime::parse_from_str(t, TIMESTAMP_FORMAT).ok())
+        .unwrap_or_else(|| Local::now().fixed_offset());
+    format!("Script started on {}\n", start.format(TIMESTAMP_FORMAT))
+}
+
+// Read a recording of any kind, telling them apart the way replay does:
+// ttyrec and asciicast files stand alone, typescripts need their timing file
+pub fn load_recording(timing: Option<&Path>, recording: &Path) -> Result<(Vec<TimingEntry>, Vec<u8>)> {
+    let content = compression::read(recording)?;
+    let has_extension = |name: &str| recording.extension().is_some_and(|ext| ext == name);
+
+    if timing.is_none() && (has_extension("ttyrec") || ttyrec::looks_like_ttyrec(&content)) {
+        return ttyrec::parse(&content);
+    }
+    #[cfg(feature = "asciicast")]
+    if has_extension("cast") || asciicast::is_asciicast(&content) {
+        let content = String::from_utf8(content)
+            .with_context(|| format!("{} is not an asciicast file", recording.display()))?;
+        return asciicast::parse(&content);
+    }
+    #[cfg(not(feature = "asciicast"))]
+    if has_extension("cast") {
+        return Err(anyhow!("This build cannot read asciicast files (feature `asciicast`)"));
+    }
+
+    let timing = timing.ok_or_else(|| anyhow!("--timing is required unless reading an asciicast or ttyrec file"))?;
+    let entries = compression::read_to_string(timing).context("Cannot read timing file")?;
+    load_typescript(timing::parse_timing(&entries)?, &content)
+}
+
+pub fn run(from: &str, to: &str, files: &[PathBuf], anonymize: Option<&Path>) -> Result<()> {
+    let (from, to) = (Format::parse(from)?, Format::parse(to)?);
+    if files.len() != from.files() + to.files() {
+        return Err(anyhow!(
+            "Expected {} input and {} output file(s), got {} file(s)",
+            from.files(),
+            to.files(),
+            files.len()
+        ));
+    }
+    let (inputs, outputs) = files.split_at(from.files());
+
+    let (mut entries, mut data) = load(from, inputs)?;
+    if let Some(mapping) = anonymize {
+        let mut anonymizer = Anonymizer::open(mapping)?;
+        data = anonymizer.recording(&mut entries, &data);
+        anonymizer.save()?;
+    }
+    save(to, &entries, &data, outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typescript_conversions() {
+        let timing = "H 0.000000 START_TIME 2024-01-02 03:04:05+00:00\n\
+                      H 0.000000 OUTPUT_LOG io.log\n\
+                      H 0.000000 INPUT_LOG io.log\n\
+                      O 0.100000 2\nI 0.500000 3\nO 0.100000 4\n";
+        let log = b"Script started on 2024-01-02 03:04:05+00:00 [x]\n$ ls\rls\r\n\nScript done\n";
+        let (entries, data) = load_typescript(timing::parse_timing(timing).unwrap(), log).unwrap();
+        assert_eq!(data, b"$ ls\rls\r\n");
+        assert_eq!(entries.len(), 6);
+
+        // Without -B the input isn't in the log, so only its delay stays
+        let output_only = timing.replace("INPUT_LOG io.log", "INPUT_LOG in.log");
+        let (entries, data) = load_typescript(
+            timing::parse_timing(&output_only).unwrap(),
+            b"Script started on x\n$ ls\r\n",
+        )
+        .unwrap();
+        assert_eq!(data, b"$ ls\r\n");
+        assert_eq!(timing::format_timing(&entries, false), "0.100000 2\n0.600000 4\n");
+        assert!(load_typescript(timing::parse_timing(timing).unwrap(), b"$ ").is_err());
+
+        assert_eq!(typescript_header(&entries), "Script started on 2024-01-02 03:04:05+00:00\n");
+        assert!(Format::parse("gif").is_err());
+    }
+}
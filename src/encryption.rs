@@ -0,0 +1,152 @@
+use anyhow::{anyhow, Result};
+use std::io::BufRead;
+use std::path::Path;
+#[cfg(feature = "encrypt")]
+use anyhow::Context;
+#[cfg(feature = "encrypt")]
+use std::io::Write;
+#[cfg(feature = "encrypt")]
+use std::sync::Arc;
+
+#[cfg(feature = "encrypt")]
+use age::x25519;
+
+#[cfg(feature = "encrypt")]
+use crate::sensitive::{Locked, SecretBytes};
+
+// Every age file starts with this line
+pub const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+
+// Why FIPS mode won't encrypt or decrypt
+pub const NOT_FIPS: &str = "age encryption (X25519, ChaCha20-Poly1305) is not FIPS 140 approved";
+
+// Who a log is encrypted to: age X25519 recipients ("age1..."), given
+// directly or as files with one per line. Each log gets a file key of its
+// own; any one of the matching identities can decrypt it, e.g. with
+// `age -d -i key.txt`.
+#[derive(Clone)]
+pub struct Recipients {
+    #[cfg(feature = "encrypt")]
+    keys: Arc<Vec<x25519::Recipient>>,
+}
+
+impl Recipients {
+    #[cfg(feature = "encrypt")]
+    pub fn parse(values: &[String]) -> Result<Recipients> {
+        let parse = |key: &str| key.parse::<x25519::Recipient>().map_err(|e| anyhow!("Invalid recipient '{}': {}", key, e));
+        let mut keys = Vec::new();
+        for value in values {
+            if value.starts_with("age1") {
+                keys.push(parse(value)?);
+                continue;
+            }
+            let content = std::fs::read_to_string(value).with_context(|| format!("Cannot read recipients file {}", value))?;
+            let lines = content.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#'));
+            for line in lines {
+                keys.push(parse(line)?);
+            }
+        }
+        if keys.is_empty() {
+            return Err(anyhow!("No recipients to encrypt to"));
+        }
+        Ok(Recipients { keys: Arc::new(keys) })
+    }
+
+    #[cfg(not(feature = "encrypt"))]
+    pub fn parse(_values: &[String]) -> Result<Recipients> {
+        Err(anyhow!("This build cannot encrypt logs (feature `encrypt`)"))
+    }
+
+    // Start an age file on `output`; what is written to the result ends up
+    // encrypted, once it is finished
+    #[cfg(feature = "encrypt")]
+    pub(crate) fn wrap<W: Write>(&self, output: W) -> Result<age::stream::StreamWriter<W>> {
+        let encryptor = age::Encryptor::with_recipients(self.keys.iter().map(|key| key as &dyn age::Recipient))?;
+        Ok(encryptor.wrap_output(output)?)
+    }
+}
+
+// Open an age file with the identities in `identity`, or in $SCRIPT_IDENTITY
+#[cfg(feature = "encrypt")]
+pub fn decrypt<R: BufRead + 'static>(input: R, identity: Option<&Path>) -> Result<Box<dyn BufRead>> {
+    if crate::config::fips() {
+        return Err(anyhow!("{} (SCRIPT_FIPS is set)", NOT_FIPS));
+    }
+    let identity = identity
+        .map(Path::to_path_buf)
+        .or_else(crate::config::identity)
+        .ok_or_else(|| anyhow!("The recording is encrypted; give an identity file with --identity or SCRIPT_IDENTITY"))?;
+    let identities = read_identities(&identity).with_context(|| format!("Cannot read identity file {}", identity.display()))?;
+
+    let decryptor = age::Decryptor::new_buffered(input)?;
+    let reader = decryptor
+        .decrypt(identities.iter().map(|i| i as &dyn age::Identity))
+        .with_context(|| format!("Cannot decrypt with {}", identity.display()))?;
+    Ok(Box::new(std::io::BufReader::new(reader)))
+}
+
+// The age identities in an identity file, one per line. The file's bytes
+// and the keys parsed from them stay in locked memory and are wiped once
+// the recording is open.
+#[cfg(feature = "encrypt")]
+fn read_identities(path: &Path) -> Result<Locked<x25519::Identity>> {
+    let content = SecretBytes::read(path)?;
+    let text = std::str::from_utf8(&content).map_err(|_| anyhow!("The file is not UTF-8"))?;
+    let lines: Vec<(usize, &str)> = text
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+        .collect();
+    // Sized up front, so no unlocked copies of keys are left behind
+    let mut identities = Vec::with_capacity(lines.len());
+    for (number, line) in lines {
+        // The line itself is not shown, being a key
+        identities.push(line.parse().map_err(|_| anyhow!("Line {} is not an age identity", number + 1))?);
+    }
+    Ok(Locked::new(identities))
+}
+
+#[cfg(not(feature = "encrypt"))]
+pub fn decrypt<R: BufRead + 'static>(_input: R, _identity: Option<&Path>) -> Result<Box<dyn BufRead>> {
+    Err(anyhow!("This build cannot read encrypted recordings (feature `encrypt`)"))
+}
+
+#[cfg(all(test, feature = "encrypt"))]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_encrypt_to_recipient_and_back() {
+        let key = x25519::Identity::generate();
+        let recipients = Recipients::parse(&[key.to_public().to_string()]).unwrap();
+
+        let mut writer = recipients.wrap(Vec::new()).unwrap();
+        writer.write_all(b"password: hunter2\n").unwrap();
+        let encrypted = writer.finish().unwrap();
+        assert!(encrypted.starts_with(AGE_MAGIC));
+        assert!(!encrypted.windows(7).any(|w| w == b"hunter2"));
+
+        use age::secrecy::ExposeSecret;
+        let identity = std::env::temp_dir().join(format!("rust_script-{}-identity.txt", std::process::id()));
+        std::fs::write(&identity, format!("{}\n", key.to_string().expose_secret())).unwrap();
+        let mut plain = String::new();
+        let result = decrypt(std::io::Cursor::new(encrypted), Some(&identity)).map(|mut r| r.read_to_string(&mut plain));
+        std::fs::remove_file(&identity).unwrap();
+        result.unwrap().unwrap();
+        assert_eq!(plain, "password: hunter2\n");
+
+        assert!(Recipients::parse(&["age1nope".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_bad_identity_line_is_not_shown() {
+        let identity = std::env::temp_dir().join(format!("rust_script-{}-bad-identity.txt", std::process::id()));
+        std::fs::write(&identity, "# key\nAGE-SECRET-KEY-1NOTAKEY\n").unwrap();
+        let result = read_identities(&identity);
+        std::fs::remove_file(&identity).unwrap();
+        let e = format!("{:#}", result.err().unwrap());
+        assert_eq!(e, "Line 2 is not an age identity");
+    }
+}
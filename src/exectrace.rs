@@ -0,0 +1,335 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::{Duration, Instant};
+
+// --trace-exec: every program run in the session, from the kernel's process
+// events connector, as an X record in the advanced timing log with the pid,
+// the parent's pid, the working directory and the command line:
+//
+//   X 0.412000 4242 4240 /home/alice make -C build
+//
+// Only the child and what it forks, however deep, are followed. Each field
+// is one word: whitespace, control characters and backslashes in it are
+// written as \xNN (\\ for a backslash), and an empty field as \0. A program
+// gone before it could be looked at has an empty working directory and
+// command line. Listening to the connector needs root or CAP_NET_ADMIN.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exec {
+    pub pid: i32,
+    pub ppid: i32,
+    pub cwd: String,
+    pub argv: Vec<String>,
+}
+
+pub(crate) fn escape(field: &str) -> String {
+    if field.is_empty() {
+        return "\\0".to_string();
+    }
+    let mut out = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            c if c.is_ascii_whitespace() || c.is_ascii_control() => out.push_str(&format!("\\x{:02x}", c as u8)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub(crate) fn unescape(word: &str) -> Option<String> {
+    if word == "\\0" {
+        return Some(String::new());
+    }
+    let mut out = String::with_capacity(word.len());
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => out.push('\\'),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                out.push(u8::from_str_radix(&hex, 16).ok()? as char);
+            }
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+impl Exec {
+    // The fields of the X record after its time
+    pub fn fields(&self) -> String {
+        let mut fields = format!("{} {} {}", self.pid, self.ppid, escape(&self.cwd));
+        for arg in &self.argv {
+            fields.push(' ');
+            fields.push_str(&escape(arg));
+        }
+        fields
+    }
+
+    pub fn parse(fields: &[&str]) -> Option<Exec> {
+        let [pid, ppid, cwd, argv @ ..] = fields else {
+            return None;
+        };
+        Some(Exec {
+            pid: pid.parse().ok()?,
+            ppid: ppid.parse().ok()?,
+            cwd: unescape(cwd)?,
+            argv: argv.iter().map(|arg| unescape(arg)).collect::<Option<_>>()?,
+        })
+    }
+
+    // Read while the process is still there
+    fn look_up(pid: i32, ppid: i32) -> Exec {
+        let cwd = std::fs::read_link(format!("/proc/{}/cwd", pid)).map(|cwd| cwd.to_string_lossy().into_owned());
+        let cmdline = std::fs::read(format!("/proc/{}/cmdline", pid)).unwrap_or_default();
+        let argv = cmdline
+            .strip_suffix(b"\0")
+            .unwrap_or(&cmdline)
+            .split(|&byte| byte == 0)
+            .filter(|_| !cmdline.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        Exec { pid, ppid, cwd: cwd.unwrap_or_default(), argv }
+    }
+}
+
+// linux/connector.h and linux/cn_proc.h
+const CN_IDX_PROC: u32 = 1;
+const CN_VAL_PROC: u32 = 1;
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+const PROC_EVENT_FORK: u32 = 0x1;
+const PROC_EVENT_EXEC: u32 = 0x2;
+const PROC_EVENT_EXIT: u32 = 0x8000_0000;
+const NLMSG_HEADER: usize = 16;
+const CN_MSG_HEADER: usize = 20;
+// what, cpu and timestamp_ns come before the event's data
+const EVENT_DATA: usize = NLMSG_HEADER + CN_MSG_HEADER + 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ProcEvent {
+    Fork { parent: i32, child: i32 },
+    Exec { pid: i32 },
+    Exit { pid: i32 },
+}
+
+fn field(message: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(message.get(at..at + 4)?.try_into().ok()?))
+}
+
+// The events of a datagram from the connector. Those about threads, whose
+// pid isn't their thread group's, are left out.
+fn parse_events(mut datagram: &[u8]) -> Vec<ProcEvent> {
+    let mut events = Vec::new();
+    while let Some(len) = field(datagram, 0) {
+        let len = len as usize;
+        if len < NLMSG_HEADER || len > datagram.len() {
+            break;
+        }
+        let message = &datagram[..len];
+        let data = |i: usize| field(message, EVENT_DATA + 4 * i).map(|value| value as i32);
+        let event = match field(message, NLMSG_HEADER + CN_MSG_HEADER) {
+            Some(PROC_EVENT_FORK) => match (data(1), data(2), data(3)) {
+                (Some(parent), Some(pid), Some(child)) if pid == child => Some(ProcEvent::Fork { parent, child }),
+                _ => None,
+            },
+            Some(PROC_EVENT_EXEC) => match (data(0), data(1)) {
+                (Some(pid), Some(tgid)) if pid == tgid => Some(ProcEvent::Exec { pid }),
+                _ => None,
+            },
+            Some(PROC_EVENT_EXIT) => match (data(0), data(1)) {
+                (Some(pid), Some(tgid)) if pid == tgid => Some(ProcEvent::Exit { pid }),
+                _ => None,
+            },
+            _ => None,
+        };
+        events.extend(event);
+        // Messages are padded to 4 bytes
+        datagram = datagram.get((len + 3) & !3..).unwrap_or_default();
+    }
+    events
+}
+
+// How long a process that has exited still counts as the session's, for
+// the file events of --trace-files read after its exit
+const EXIT_GRACE: Duration = Duration::from_secs(5);
+
+// The processes of the session, each with its parent, and those that have
+// exited lately
+#[derive(Default)]
+struct Tree {
+    parents: HashMap<i32, i32>,
+    exited: HashMap<i32, Instant>,
+}
+
+impl Tree {
+    // The pid and parent of a process of the session that ran a program
+    fn event(&mut self, event: ProcEvent) -> Option<(i32, i32)> {
+        match event {
+            ProcEvent::Fork { parent, child } if self.parents.contains_key(&parent) => {
+                self.parents.insert(child, parent);
+                self.exited.remove(&child);
+                None
+            }
+            ProcEvent::Exec { pid } => self.parents.get(&pid).map(|&ppid| (pid, ppid)),
+            ProcEvent::Exit { pid } => {
+                if self.parents.remove(&pid).is_some() {
+                    self.exited.insert(pid, Instant::now());
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct ExecTracer {
+    socket: OwnedFd,
+    tree: Tree,
+}
+
+impl ExecTracer {
+    // Subscribed from here on; events are kept in the socket until read, so
+    // nothing the child does after set_root is missed
+    pub fn new() -> Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_CONNECTOR,
+            )
+        };
+        if fd < 0 {
+            return Err(anyhow!("Cannot open the process events connector: {}", std::io::Error::last_os_error()));
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_pid = std::process::id();
+        address.nl_groups = CN_IDX_PROC;
+        let size = std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+        if unsafe { libc::bind(fd, &address as *const libc::sockaddr_nl as *const libc::sockaddr, size) } < 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(anyhow!("--trace-exec needs root or CAP_NET_ADMIN to listen to process events: {}", e));
+        }
+
+        let mut request = Vec::with_capacity(NLMSG_HEADER + CN_MSG_HEADER + 4);
+        let len = (NLMSG_HEADER + CN_MSG_HEADER + 4) as u32;
+        request.extend_from_slice(&len.to_ne_bytes());
+        request.extend_from_slice(&(libc::NLMSG_DONE as u16).to_ne_bytes());
+        request.extend_from_slice(&0u16.to_ne_bytes());
+        request.extend_from_slice(&0u32.to_ne_bytes());
+        request.extend_from_slice(&std::process::id().to_ne_bytes());
+        for value in [CN_IDX_PROC, CN_VAL_PROC, 0, 0] {
+            request.extend_from_slice(&value.to_ne_bytes());
+        }
+        request.extend_from_slice(&4u16.to_ne_bytes());
+        request.extend_from_slice(&0u16.to_ne_bytes());
+        request.extend_from_slice(&PROC_CN_MCAST_LISTEN.to_ne_bytes());
+        if unsafe { libc::send(fd, request.as_ptr() as *const libc::c_void, request.len(), 0) } < 0 {
+            return Err(anyhow!("Cannot listen to process events: {}", std::io::Error::last_os_error()));
+        }
+        Ok(ExecTracer { socket, tree: Tree::default() })
+    }
+
+    // The session's child; its parent is the recorder
+    pub fn set_root(&mut self, pid: i32) {
+        self.tree.parents.insert(pid, std::process::id() as i32);
+    }
+
+    // Whether a process is, or lately was, one of the session's, as of the
+    // last read
+    pub fn contains(&self, pid: i32) -> bool {
+        self.tree.parents.contains_key(&pid) || self.tree.exited.get(&pid).is_some_and(|at| at.elapsed() < EXIT_GRACE)
+    }
+
+    // The programs run since the last read, without waiting for more
+    pub fn read(&mut self) -> Result<Vec<Exec>> {
+        let mut execs = Vec::new();
+        let mut datagram = [0u8; 4096];
+        self.tree.exited.retain(|_, at| at.elapsed() < EXIT_GRACE);
+        loop {
+            let buffer = datagram.as_mut_ptr() as *mut libc::c_void;
+            let n = unsafe { libc::recv(self.socket.as_raw_fd(), buffer, datagram.len(), 0) };
+            if n < 0 {
+                let e = std::io::Error::last_os_error();
+                match e.raw_os_error() {
+                    Some(libc::EAGAIN) => break,
+                    // Events were dropped while the recorder was busy; go on
+                    // with the next ones
+                    Some(libc::ENOBUFS) | Some(libc::EINTR) => continue,
+                    _ => return Err(anyhow!("Cannot read process events: {}", e)),
+                }
+            }
+            for event in parse_events(&datagram[..n as usize]) {
+                if let Some((pid, ppid)) = self.tree.event(event) {
+                    execs.push(Exec::look_up(pid, ppid));
+                }
+            }
+        }
+        Ok(execs)
+    }
+}
+
+impl AsRawFd for ExecTracer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(what: u32, data: &[i32]) -> Vec<u8> {
+        let len = EVENT_DATA + 4 * data.len();
+        let mut message = vec![0u8; len];
+        message[..4].copy_from_slice(&(len as u32).to_ne_bytes());
+        message[NLMSG_HEADER + CN_MSG_HEADER..][..4].copy_from_slice(&what.to_ne_bytes());
+        for (i, value) in data.iter().enumerate() {
+            message[EVENT_DATA + 4 * i..][..4].copy_from_slice(&value.to_ne_bytes());
+        }
+        message
+    }
+
+    #[test]
+    fn test_follow_the_session_tree() {
+        let mut datagram = message(PROC_EVENT_FORK, &[100, 100, 200, 200]);
+        datagram.extend(message(PROC_EVENT_FORK, &[200, 200, 201, 200]));
+        datagram.extend(message(PROC_EVENT_EXEC, &[200, 200]));
+        datagram.extend(message(PROC_EVENT_FORK, &[300, 300, 301, 301]));
+        datagram.extend(message(PROC_EVENT_EXEC, &[301, 301]));
+        datagram.extend(message(PROC_EVENT_EXIT, &[200, 200, 0, 0]));
+        datagram.extend(message(PROC_EVENT_EXEC, &[200, 200]));
+        let events = parse_events(&datagram);
+        assert_eq!(events.len(), 6);
+
+        let mut tree = Tree::default();
+        tree.parents.insert(100, 1);
+        let execs: Vec<_> = events.into_iter().filter_map(|event| tree.event(event)).collect();
+        // The fork outside the session and the thread are left out
+        assert_eq!(execs, [(200, 100)]);
+        assert!(tree.exited.contains_key(&200) && !tree.exited.contains_key(&301));
+    }
+
+    #[test]
+    fn test_exec_fields() {
+        let exec = Exec {
+            pid: 4242,
+            ppid: 4240,
+            cwd: "/home/alice/my dir".to_string(),
+            argv: vec!["grep".to_string(), "a\\b\tc".to_string(), String::new()],
+        };
+        assert_eq!(exec.fields(), "4242 4240 /home/alice/my\\x20dir grep a\\\\b\\x09c \\0");
+        let fields = exec.fields();
+        let words: Vec<&str> = fields.split(' ').collect();
+        assert_eq!(Exec::parse(&words), Some(exec));
+        assert_eq!(Exec::parse(&["1", "2"]), None);
+        assert_eq!(Exec::parse(&["1", "2", "bad\\q"]), None);
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+
+#[cfg(feature = "expect")]
+use regex::Regex;
+
+#[cfg(feature = "expect")]
+use crate::macros;
+use crate::vt::Lines;
+
+// --expect: answers typed into the session when its output asks for them,
+// for recording an interactive program unattended. A rule reads
+//
+//   pattern=>response
+//
+// and whenever the unfinished last line of output (colors and cursor
+// movement taken out) matches the pattern, a regex, the response goes to
+// the session as if typed, with \r, \n, \t, \e and \\ as in macros. A line
+// is answered once, by the first rule that matches it; --expect-file holds
+// rules a line each, with blank lines and # comments.
+pub struct Expect {
+    rules: Vec<Rule>,
+    lines: Lines,
+    // The unfinished line has had its answer
+    answered: bool,
+}
+
+struct Rule {
+    #[cfg(feature = "expect")]
+    regex: Regex,
+    response: Vec<u8>,
+}
+
+impl Rule {
+    #[cfg(feature = "expect")]
+    fn parse(rule: &str) -> Result<Rule> {
+        let (pattern, response) = rule.split_once("=>").ok_or_else(|| anyhow!("Expected pattern=>response"))?;
+        let regex = Regex::new(pattern).with_context(|| format!("Invalid pattern '{}'", pattern))?;
+        Ok(Rule { regex, response: macros::unescape(response)? })
+    }
+
+    #[cfg(not(feature = "expect"))]
+    fn parse(_rule: &str) -> Result<Rule> {
+        Err(anyhow!("This build cannot answer the session's output (feature `expect`)"))
+    }
+
+    #[cfg(feature = "expect")]
+    fn matches(&self, line: &str) -> bool {
+        self.regex.is_match(line)
+    }
+
+    #[cfg(not(feature = "expect"))]
+    fn matches(&self, _line: &str) -> bool {
+        false
+    }
+}
+
+impl Expect {
+    pub fn new(rules: &[String], file: Option<&Path>) -> Result<Expect> {
+        let mut parsed = Vec::new();
+        for rule in rules {
+            parsed.push(Rule::parse(rule).with_context(|| format!("Invalid --expect '{}'", rule))?);
+        }
+        if let Some(path) = file {
+            let content = std::fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+            for (i, line) in content.lines().enumerate() {
+                if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                    continue;
+                }
+                parsed.push(Rule::parse(line).with_context(|| format!("{}: line {}", path.display(), i + 1))?);
+            }
+        }
+        Ok(Expect { rules: parsed, lines: Lines::default(), answered: false })
+    }
+
+    // Feed output; returns the number of the rule that answered it (from
+    // 1, in the order given) and its response
+    pub fn output(&mut self, data: &[u8]) -> Option<(usize, Vec<u8>)> {
+        if !self.lines.feed(data).is_empty() {
+            self.answered = false;
+        }
+        let line = self.lines.current();
+        if self.answered || line.is_empty() {
+            return None;
+        }
+        let (i, rule) = self.rules.iter().enumerate().find(|(_, rule)| rule.matches(&line))?;
+        self.answered = true;
+        Some((i + 1, rule.response.clone()))
+    }
+}
+
+#[cfg(all(test, feature = "expect"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expect_answers_each_line_once() {
+        let rules = [r"[Pp]assword: $=>hunter2\r".to_string(), r"\[y/N\] $=>y\r".to_string()];
+        let mut expect = Expect::new(&rules, None).unwrap();
+        assert_eq!(expect.output(b"Reading\r\n\x1b[1mPass"), None);
+        assert_eq!(expect.output(b"word: "), Some((1, b"hunter2\r".to_vec())));
+        // Its echo doesn't make it ask again, a new line does
+        assert_eq!(expect.output(b"*******"), None);
+        assert_eq!(expect.output(b"\r\nContinue? [y/N] "), Some((2, b"y\r".to_vec())));
+        assert_eq!(expect.output(b"y\r\nDone\r\n"), None);
+
+        assert!(Expect::new(&["no arrow".to_string()], None).is_err());
+        assert!(Expect::new(&["(=>x".to_string()], None).is_err());
+    }
+}
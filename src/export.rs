@@ -0,0 +1,174 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::accessible;
+use crate::anonymize::Anonymizer;
+use crate::convert;
+#[cfg(any(feature = "html", feature = "gif"))]
+use crate::frames::frames;
+#[cfg(feature = "html")]
+use crate::html;
+#[cfg(feature = "gif")]
+use crate::raster;
+#[cfg(feature = "selfextract")]
+use crate::selfextract;
+#[cfg(any(feature = "html", feature = "gif"))]
+use crate::timing;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Html,
+    Gif,
+    Text,
+}
+
+impl ExportFormat {
+    pub fn parse(name: &str) -> Result<ExportFormat> {
+        match name.to_lowercase().as_str() {
+            "html" => Ok(ExportFormat::Html),
+            "gif" => Ok(ExportFormat::Gif),
+            "text" => Ok(ExportFormat::Text),
+            _ => Err(anyhow!("Unsupported export format: '{}' (html, gif or text)", name)),
+        }
+    }
+}
+
+// Where a watermark goes: a corner, or centered along the bottom as a footer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Bottom,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mark {
+    Text(String),
+    // PNG, JPEG, GIF, WebP or SVG on a page; GIF on a GIF
+    Image(PathBuf),
+}
+
+// What --watermark puts over an exported page or GIF
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(not(any(feature = "html", feature = "gif")), allow(dead_code))]
+pub struct Watermark {
+    pub mark: Mark,
+    pub position: Position,
+    // From 0, not shown, to 1
+    pub opacity: f64,
+}
+
+impl Watermark {
+    pub fn new(text: Option<String>, image: Option<PathBuf>, position: &str, opacity: f64) -> Result<Option<Watermark>> {
+        let mark = match (text, image) {
+            (Some(text), None) => Mark::Text(text),
+            (None, Some(image)) => Mark::Image(image),
+            (None, None) => return Ok(None),
+            (Some(_), Some(_)) => return Err(anyhow!("A watermark is either text or an image")),
+        };
+        let position = match position {
+            "top-left" => Position::TopLeft,
+            "top-right" => Position::TopRight,
+            "bottom-left" => Position::BottomLeft,
+            "bottom-right" => Position::BottomRight,
+            "bottom" => Position::Bottom,
+            _ => {
+                return Err(anyhow!(
+                    "Invalid --watermark-position: '{}' (top-left, top-right, bottom-left, bottom-right or bottom)",
+                    position
+                ))
+            }
+        };
+        if !(0.0..=1.0).contains(&opacity) {
+            return Err(anyhow!("Invalid --watermark-opacity: {} (from 0 to 1)", opacity));
+        }
+        Ok(Some(Watermark { mark, position, opacity }))
+    }
+}
+
+// How a recording is exported beyond its format
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub anonymize: Option<PathBuf>,
+    // GIF only; the duration as `blame --at` takes it
+    #[cfg_attr(not(feature = "gif"), allow(dead_code))]
+    pub fps: u32,
+    #[cfg_attr(not(feature = "gif"), allow(dead_code))]
+    pub max_duration: Option<String>,
+    // Instead of the format, a player program with the recording in it
+    pub self_extracting: Option<PathBuf>,
+    // Over an HTML page or a GIF
+    pub watermark: Option<Watermark>,
+}
+
+pub fn run(format: &str, timing: Option<&Path>, recording: &Path, output: Option<&Path>, options: &ExportOptions) -> Result<()> {
+    let format = ExportFormat::parse(format)?;
+    let (mut entries, mut data) = convert::load_recording(timing, recording)?;
+    if let Some(mapping) = &options.anonymize {
+        let mut anonymizer = Anonymizer::open(mapping)?;
+        data = anonymizer.recording(&mut entries, &data);
+        anonymizer.save()?;
+    }
+    #[cfg(feature = "selfextract")]
+    if let Some(ref path) = options.self_extracting {
+        return selfextract::write(path, &entries, &data);
+    }
+    #[cfg(not(feature = "selfextract"))]
+    if options.self_extracting.is_some() {
+        return Err(anyhow!("This build cannot write self-extracting players (feature `selfextract`)"));
+    }
+
+    if options.watermark.is_some() && format == ExportFormat::Text {
+        return Err(anyhow!("A watermark is for HTML pages and GIFs, not text"));
+    }
+    let title = recording.file_name().map_or_else(|| recording.display().to_string(), |name| name.to_string_lossy().into_owned());
+    let content = match format {
+        #[cfg(feature = "html")]
+        ExportFormat::Html => {
+            html::render(&title, &frames(&entries, &data), &timing::markers(&entries), options.watermark.as_ref())?.into_bytes()
+        }
+        #[cfg(not(feature = "html"))]
+        ExportFormat::Html => return Err(anyhow!("This build cannot export HTML pages (feature `html`)")),
+        #[cfg(feature = "gif")]
+        ExportFormat::Gif => {
+            let max_duration = options.max_duration.as_deref().map(timing::parse_offset).transpose()?;
+            raster::render(&frames(&entries, &data), options.fps, max_duration, options.watermark.as_ref())?
+        }
+        #[cfg(not(feature = "gif"))]
+        ExportFormat::Gif => return Err(anyhow!("This build cannot export GIF animations (feature `gif`)")),
+        ExportFormat::Text => accessible::render(&title, &entries, &data)?.into_bytes(),
+    };
+    match output {
+        Some(path) => std::fs::write(path, content).with_context(|| format!("Cannot write {}", path.display())),
+        None => {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(&content)?;
+            Ok(stdout.flush()?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(ExportFormat::parse("HTML").unwrap(), ExportFormat::Html);
+        assert_eq!(ExportFormat::parse("text").unwrap(), ExportFormat::Text);
+        assert!(ExportFormat::parse("pdf").is_err());
+    }
+
+    #[test]
+    fn test_watermark_options() {
+        let mark = Watermark::new(Some("(c) docs".to_string()), None, "bottom", 0.3).unwrap().unwrap();
+        assert_eq!(mark, Watermark { mark: Mark::Text("(c) docs".to_string()), position: Position::Bottom, opacity: 0.3 });
+        assert_eq!(Watermark::new(None, None, "bottom-right", 0.5).unwrap(), None);
+        assert!(Watermark::new(Some("x".to_string()), None, "middle", 0.5).is_err());
+        assert!(Watermark::new(Some("x".to_string()), None, "top-left", 1.5).is_err());
+        assert!(Watermark::new(Some("x".to_string()), Some(PathBuf::from("logo.png")), "top-left", 0.5).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use crate::schema::SCHEMA_VERSION;
+
+// --failover-dir: when the disk under a log fills up or fails, the log goes
+// on in a file of the same name in the fallback directory rather than ending
+// the session, and an event records the move:
+//
+//   S 0.000000 STORAGE_FAILOVER FROM=/var/log/s.log TO=/srv/spare/s.log ERROR=ENOSPC
+//
+// The old file keeps everything written up to the failure. What was still
+// on its way to the failed disk, back to its last good flush, is written
+// again at the start of the new file, so some of it can be in both but none
+// is missing. A log fails over once; should the fallback fail too, the
+// session ends as it would have.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageFailover {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub error: &'static str,
+}
+
+impl StorageFailover {
+    pub fn message(&self) -> String {
+        format!("FROM={} TO={} ERROR={}", self.from.display(), self.to.display(), self.error)
+    }
+
+    fn json(&self, host: &str) -> String {
+        let string = |value: &str| format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""));
+        format!(
+            "{{\"schema_version\":{},\"event\":\"STORAGE_FAILOVER\",\"host\":{},\"from\":{},\"to\":{},\"error\":{}}}",
+            SCHEMA_VERSION,
+            string(host),
+            string(&self.from.to_string_lossy()),
+            string(&self.to.to_string_lossy()),
+            string(self.error)
+        )
+    }
+}
+
+// The write errors a log fails over on, by their errno names
+pub fn storage_error(e: &std::io::Error) -> Option<&'static str> {
+    match e.raw_os_error() {
+        Some(libc::ENOSPC) => Some("ENOSPC"),
+        Some(libc::EDQUOT) => Some("EDQUOT"),
+        Some(libc::EIO) => Some("EIO"),
+        _ => None,
+    }
+}
+
+// Where a log goes on in the fallback directory
+pub fn fallback_path(dir: &Path, log: &Path) -> PathBuf {
+    dir.join(log.file_name().unwrap_or(log.as_os_str()))
+}
+
+// POST the failover to --failover-webhook as JSON. curl does the HTTP, so
+// https and proxies work as they do for everything else on the machine; it
+// runs in the background and its failures are ignored, as the session has
+// to go on either way.
+pub fn alert(url: &str, failover: &StorageFailover) {
+    let host = std::fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+    let _ = tokio::process::Command::new("curl")
+        .args(["-fsS", "-m", "10", "-H", "Content-Type: application/json", "--data-binary"])
+        .arg(failover.json(host.trim()))
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failover_records() {
+        assert_eq!(storage_error(&std::io::Error::from_raw_os_error(libc::ENOSPC)), Some("ENOSPC"));
+        assert_eq!(storage_error(&std::io::Error::from_raw_os_error(libc::EACCES)), None);
+        assert_eq!(fallback_path(Path::new("/srv/spare"), Path::new("/var/log/s.log")), PathBuf::from("/srv/spare/s.log"));
+
+        let failover = StorageFailover {
+            from: PathBuf::from("/var/log/s.log"),
+            to: PathBuf::from("/srv/spare/s.log"),
+            error: "EIO",
+        };
+        assert_eq!(failover.message(), "FROM=/var/log/s.log TO=/srv/spare/s.log ERROR=EIO");
+        assert_eq!(
+            failover.json("web\"1"),
+            "{\"schema_version\":1,\"event\":\"STORAGE_FAILOVER\",\"host\":\"web\\\"1\",\
+             \"from\":\"/var/log/s.log\",\"to\":\"/srv/spare/s.log\",\"error\":\"EIO\"}"
+        );
+        #[cfg(feature = "schemas")]
+        serde_json::from_str::<crate::schema::FailoverAlert>(&failover.json("web1")).unwrap();
+    }
+}
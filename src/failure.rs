@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+
+// Ways the recorder itself can fail, each with its own exit status so
+// wrappers can tell them apart from each other and from the child's status
+#[derive(Debug, thiserror::Error)]
+pub enum Failure {
+    #[error("Invalid arguments")]
+    Usage,
+    #[error("Cannot open {}", .0.display())]
+    Output(PathBuf),
+    #[error("Cannot set up the pseudo-terminal")]
+    Pty,
+    #[error("Cannot start the shell: {0}")]
+    Exec(String),
+    #[error("Output size limit exceeded")]
+    OutputLimit,
+}
+
+// Status for any other error, as util-linux uses
+pub const EXIT_FAILURE: i32 = 1;
+
+// Shown at the end of --help
+pub const EXIT_STATUS_HELP: &str = "\
+Exit status:
+  0      success (the child's status with -e)
+  1      other recorder failure
+  2      invalid arguments
+  3      cannot open a log file
+  4      cannot set up the pseudo-terminal
+  5      cannot start the shell or command
+  6      output limit (-o) reached
+  128+N  recorder terminated by signal N";
+
+impl Failure {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Usage => 2,
+            Failure::Output(_) => 3,
+            Failure::Pty => 4,
+            Failure::Exec(_) => 5,
+            Failure::OutputLimit => 6,
+        }
+    }
+}
+
+// Finds the failure class anywhere in the error's context chain
+pub fn exit_code(e: &anyhow::Error) -> i32 {
+    e.downcast_ref::<Failure>().map_or(EXIT_FAILURE, Failure::exit_code)
+}
+
+// Tag an error that doesn't have a failure class yet
+pub fn or_tag(e: anyhow::Error, failure: Failure) -> anyhow::Error {
+    if e.downcast_ref::<Failure>().is_some() {
+        e
+    } else {
+        e.context(failure)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_exit_code_found_under_context() {
+        let open: anyhow::Result<()> = Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+            .context(Failure::Output(PathBuf::from("/x/log")));
+        let e = open.context("Failed to run script session").unwrap_err();
+        assert_eq!(exit_code(&e), 3);
+        assert_eq!(e.root_cause().to_string(), "entity not found");
+
+        assert_eq!(exit_code(&Failure::OutputLimit.into()), 6);
+        assert_eq!(exit_code(&anyhow!("other")), EXIT_FAILURE);
+        assert_eq!(exit_code(&or_tag(anyhow!("bad size"), Failure::Usage)), 2);
+        assert_eq!(exit_code(&or_tag(Failure::Pty.into(), Failure::Usage)), 4);
+    }
+}
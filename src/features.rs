@@ -0,0 +1,32 @@
+// Optional parts of the build and whether this binary has them
+pub const FEATURES: &[(&str, bool)] = &[
+    ("asciicast", cfg!(feature = "asciicast")),
+    ("review", cfg!(feature = "review")),
+    ("analyzers", cfg!(feature = "analyzers")),
+    ("gzip", cfg!(feature = "gzip")),
+    ("zstd", cfg!(feature = "zstd")),
+    ("encrypt", cfg!(feature = "encrypt")),
+    ("notebook", cfg!(feature = "notebook")),
+    ("prompts", cfg!(feature = "prompts")),
+    ("gif", cfg!(feature = "gif")),
+    ("grep", cfg!(feature = "grep")),
+    ("timeline", cfg!(feature = "timeline")),
+    ("schemas", cfg!(feature = "schemas")),
+    ("expect", cfg!(feature = "expect")),
+    ("blake3", cfg!(feature = "blake3")),
+    ("vt", cfg!(feature = "vt")),
+    ("screenshot", cfg!(feature = "screenshot")),
+    ("player", cfg!(feature = "player")),
+    ("export", cfg!(feature = "export")),
+    ("html", cfg!(feature = "html")),
+    ("selfextract", cfg!(feature = "selfextract")),
+    ("snapshot", cfg!(feature = "snapshot")),
+];
+
+// One line per feature, "+name" when built in and "-name" when not
+pub fn report() -> String {
+    FEATURES
+        .iter()
+        .map(|(name, enabled)| format!("{}{}\n", if *enabled { '+' } else { '-' }, name))
+        .collect()
+}
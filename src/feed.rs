@@ -0,0 +1,105 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+use std::time::Duration;
+
+// How often unpaced input moves on to its next line
+const LINE_INTERVAL: Duration = Duration::from_millis(10);
+
+// The slowest --cps, a character every 1000 seconds
+const MIN_CPS: f64 = 1e-3;
+
+// --stdin-file: input typed into the session from a file in place of the
+// keyboard, for recordings that come out the same every time. It goes a
+// line at a time as fast as the session takes it, or at --cps characters a
+// second as if someone were typing; with --wait-prompt each line waits for
+// the session to show a prompt (OSC 133 marks or --prompt-regex).
+pub struct Feed {
+    data: Vec<u8>,
+    pos: usize,
+    cps: Option<f64>,
+    wait_prompt: bool,
+    // A prompt has shown since the last line was sent
+    at_prompt: bool,
+}
+
+impl Feed {
+    pub fn new(data: Vec<u8>, cps: Option<f64>, wait_prompt: bool) -> Result<Feed> {
+        if cps.is_some_and(|cps| !(cps >= MIN_CPS && cps.is_finite())) {
+            return Err(anyhow!("--cps must be a number of characters a second, at least {}", MIN_CPS));
+        }
+        Ok(Feed { data, pos: 0, cps, wait_prompt, at_prompt: false })
+    }
+
+    pub fn load(path: &Path, cps: Option<f64>, wait_prompt: bool) -> Result<Feed> {
+        let data = std::fs::read(path).with_context(|| format!("Cannot read {}", path.display()))?;
+        Feed::new(data, cps, wait_prompt)
+    }
+
+    // How often `next` is asked for more
+    pub fn interval(&self) -> Duration {
+        self.cps.map_or(LINE_INTERVAL, |cps| Duration::from_secs_f64(1.0 / cps))
+    }
+
+    pub fn prompt(&mut self) {
+        self.at_prompt = true;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    // Whether the input ends in the middle of a line
+    pub fn line_open(&self) -> bool {
+        !self.data.ends_with(b"\n")
+    }
+
+    // What to send now, if anything: a character when typing at --cps, the
+    // rest of the line otherwise
+    pub fn next(&mut self) -> Option<Vec<u8>> {
+        if self.is_done() || (self.wait_prompt && !self.at_prompt) {
+            return None;
+        }
+        let rest = &self.data[self.pos..];
+        let line = rest.iter().position(|&b| b == b'\n').map_or(rest.len(), |end| end + 1);
+        let len = match self.cps {
+            // A whole UTF-8 character, however many bytes it takes
+            Some(_) => 1 + rest[1..line].iter().take_while(|&&b| b & 0xc0 == 0x80).count(),
+            None => line,
+        };
+        let chunk = rest[..len].to_vec();
+        self.pos += len;
+        if chunk.ends_with(b"\n") {
+            self.at_prompt = false;
+        }
+        Some(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feed_paces_the_input() {
+        let mut feed = Feed::new(b"ls\necho h\xc3\xa9".to_vec(), None, false).unwrap();
+        assert_eq!(feed.interval(), LINE_INTERVAL);
+        assert_eq!(feed.next().as_deref(), Some(&b"ls\n"[..]));
+        assert_eq!(feed.next().as_deref(), Some(&b"echo h\xc3\xa9"[..]));
+        assert!(feed.is_done() && feed.line_open());
+        assert_eq!(feed.next(), None);
+
+        // Typed a character at a time, each line once a prompt shows
+        let mut feed = Feed::new("é\nx\n".as_bytes().to_vec(), Some(20.0), true).unwrap();
+        assert_eq!(feed.interval(), Duration::from_millis(50));
+        assert_eq!(feed.next(), None);
+        feed.prompt();
+        assert_eq!(feed.next().as_deref(), Some("é".as_bytes()));
+        assert_eq!(feed.next().as_deref(), Some(&b"\n"[..]));
+        assert_eq!(feed.next(), None);
+        feed.prompt();
+        assert_eq!(feed.next().as_deref(), Some(&b"x"[..]));
+
+        assert!(Feed::new(Vec::new(), Some(0.0), false).is_err());
+        assert!(Feed::new(Vec::new(), Some(1e-300), false).is_err());
+    }
+}
@@ -0,0 +1,343 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::exectrace::escape;
+
+// --trace-files: the files under the given paths that the session's
+// processes open, write, create, delete or rename, from fanotify, as events
+// on the session's timeline:
+//
+//   S 2.104000 FILE_WRITE PID=4250 PATH=/etc/nginx/nginx.conf
+//   S 2.110000 FILE_RENAME PID=4251 FROM=/srv/app/.env.tmp TO=/srv/app/.env
+//
+// The whole filesystem of each path is watched, and only what happens under
+// the paths is kept. Which processes are the session's comes from the
+// process events connector, as for --trace-exec. Paths are escaped as the
+// fields of X records are. A write is reported when the file is closed,
+// once however many writes it took. Needs root (CAP_SYS_ADMIN to watch a
+// filesystem, CAP_DAC_READ_SEARCH to find the paths) and Linux 5.17.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access {
+    Open(PathBuf),
+    Write(PathBuf),
+    Create(PathBuf),
+    Delete(PathBuf),
+    Rename(PathBuf, PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileEvent {
+    pub pid: i32,
+    pub access: Access,
+}
+
+fn path_field(path: &Path) -> String {
+    escape(&path.to_string_lossy())
+}
+
+impl FileEvent {
+    pub fn name(&self) -> &'static str {
+        match self.access {
+            Access::Open(_) => "FILE_OPEN",
+            Access::Write(_) => "FILE_WRITE",
+            Access::Create(_) => "FILE_CREATE",
+            Access::Delete(_) => "FILE_DELETE",
+            Access::Rename(..) => "FILE_RENAME",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self.access {
+            Access::Open(ref path) | Access::Write(ref path) | Access::Create(ref path) | Access::Delete(ref path) => {
+                format!("PID={} PATH={}", self.pid, path_field(path))
+            }
+            Access::Rename(ref from, ref to) => {
+                format!("PID={} FROM={} TO={}", self.pid, path_field(from), path_field(to))
+            }
+        }
+    }
+
+    fn paths(&self) -> Vec<&Path> {
+        match self.access {
+            Access::Open(ref path) | Access::Write(ref path) | Access::Create(ref path) | Access::Delete(ref path) => {
+                vec![path]
+            }
+            Access::Rename(ref from, ref to) => vec![from, to],
+        }
+    }
+}
+
+const MASK: u64 = libc::FAN_OPEN | libc::FAN_CLOSE_WRITE | libc::FAN_CREATE | libc::FAN_DELETE | libc::FAN_RENAME;
+// struct fanotify_event_metadata
+const METADATA_LEN: usize = 24;
+
+// A name reported with an event: which one it is, the filesystem, the
+// handle of the directory (a struct file_handle) and the name in it
+#[derive(Debug, Clone, PartialEq)]
+struct Name {
+    info_type: u8,
+    fsid: [u8; 8],
+    handle: Vec<u8>,
+    name: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct RawEvent {
+    mask: u64,
+    pid: i32,
+    names: Vec<Name>,
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn name_record(record: &[u8]) -> Option<Name> {
+    let handle_bytes = u32_at(record, 12)? as usize;
+    let handle = record.get(12..20 + handle_bytes)?.to_vec();
+    let name = record.get(20 + handle_bytes..)?;
+    let name = name.split(|&byte| byte == 0).next().unwrap_or_default().to_vec();
+    Some(Name { info_type: record[0], fsid: record.get(4..12)?.try_into().ok()?, handle, name })
+}
+
+fn parse_events(mut buffer: &[u8]) -> Vec<RawEvent> {
+    let mut events = Vec::new();
+    while let Some(len) = u32_at(buffer, 0) {
+        let len = len as usize;
+        if len < METADATA_LEN || len > buffer.len() {
+            break;
+        }
+        let event = &buffer[..len];
+        let metadata_len = u16::from_ne_bytes([event[6], event[7]]) as usize;
+        let mask = u64::from_ne_bytes(event[8..16].try_into().unwrap());
+        let pid = i32::from_ne_bytes(event[20..24].try_into().unwrap());
+
+        let mut names = Vec::new();
+        let mut records = event.get(metadata_len..).unwrap_or_default();
+        while records.len() >= 4 {
+            let record_len = u16::from_ne_bytes([records[2], records[3]]) as usize;
+            if record_len < 4 || record_len > records.len() {
+                break;
+            }
+            let record = &records[..record_len];
+            if matches!(
+                record[0],
+                libc::FAN_EVENT_INFO_TYPE_DFID_NAME
+                    | libc::FAN_EVENT_INFO_TYPE_OLD_DFID_NAME
+                    | libc::FAN_EVENT_INFO_TYPE_NEW_DFID_NAME
+            ) {
+                names.extend(name_record(record));
+            }
+            records = &records[record_len..];
+        }
+        events.push(RawEvent { mask, pid, names });
+        buffer = &buffer[len..];
+    }
+    events
+}
+
+// What an event says happened, in order, given the paths of its names;
+// events not yet read are merged, so one may say a file was opened and
+// written
+fn accesses(event: &RawEvent, path: impl Fn(&Name) -> Option<PathBuf>) -> Vec<FileEvent> {
+    let named = |info_type: u8| event.names.iter().find(|name| name.info_type == info_type).and_then(&path);
+    let mut accesses = Vec::new();
+    if let Some(file) = named(libc::FAN_EVENT_INFO_TYPE_DFID_NAME) {
+        for (bit, access) in [
+            (libc::FAN_CREATE, Access::Create as fn(PathBuf) -> Access),
+            (libc::FAN_OPEN, Access::Open),
+            (libc::FAN_CLOSE_WRITE, Access::Write),
+            (libc::FAN_DELETE, Access::Delete),
+        ] {
+            if event.mask & bit != 0 {
+                accesses.push(FileEvent { pid: event.pid, access: access(file.clone()) });
+            }
+        }
+    }
+    if event.mask & libc::FAN_RENAME != 0 {
+        let from = named(libc::FAN_EVENT_INFO_TYPE_OLD_DFID_NAME);
+        let to = named(libc::FAN_EVENT_INFO_TYPE_NEW_DFID_NAME);
+        if let (Some(from), Some(to)) = (from, to) {
+            accesses.push(FileEvent { pid: event.pid, access: Access::Rename(from, to) });
+        }
+    }
+    accesses
+}
+
+fn fsid(fd: RawFd) -> Option<[u8; 8]> {
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstatfs(fd, &mut stat) } < 0 {
+        return None;
+    }
+    Some(unsafe { std::mem::transmute::<libc::fsid_t, [u8; 8]>(stat.f_fsid) })
+}
+
+pub struct FileTracer {
+    fanotify: OwnedFd,
+    // A directory on each filesystem watched, to open the handles of its
+    // directories from
+    mounts: HashMap<[u8; 8], OwnedFd>,
+    paths: Vec<PathBuf>,
+}
+
+impl FileTracer {
+    pub fn new(paths: &[PathBuf]) -> Result<Self> {
+        let flags = libc::FAN_CLASS_NOTIF | libc::FAN_CLOEXEC | libc::FAN_NONBLOCK | libc::FAN_REPORT_DFID_NAME;
+        let fd = unsafe { libc::fanotify_init(flags, (libc::O_RDONLY | libc::O_LARGEFILE) as libc::c_uint) };
+        if fd < 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(anyhow!("--trace-files needs root (CAP_SYS_ADMIN) and Linux 5.17 to watch files: {}", e));
+        }
+        let fanotify = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut tracer = FileTracer { fanotify, mounts: HashMap::new(), paths: Vec::new() };
+        for path in paths {
+            let path = path.canonicalize().with_context(|| format!("Cannot watch {}", path.display()))?;
+            let c_path = CString::new(path.as_os_str().as_bytes())?;
+            let flags = libc::FAN_MARK_ADD | libc::FAN_MARK_FILESYSTEM;
+            if unsafe { libc::fanotify_mark(fd, flags, MASK, libc::AT_FDCWD, c_path.as_ptr()) } < 0 {
+                let e = std::io::Error::last_os_error();
+                return Err(anyhow!("Cannot watch the filesystem of {}: {}", path.display(), e));
+            }
+            let dir = if path.is_dir() { path.clone() } else { path.parent().unwrap_or(&path).to_path_buf() };
+            let mount = std::fs::File::open(&dir).with_context(|| format!("Cannot open {}", dir.display()))?;
+            let mount = OwnedFd::from(mount);
+            if let Some(fsid) = fsid(mount.as_raw_fd()) {
+                tracer.mounts.entry(fsid).or_insert(mount);
+            }
+            tracer.paths.push(path);
+        }
+        Ok(tracer)
+    }
+
+    // Where a name is now: its directory found from the handle, and the name
+    // in it. None once the directory is gone.
+    fn path(&self, name: &Name) -> Option<PathBuf> {
+        let mount = self.mounts.get(&name.fsid)?;
+        // open_by_handle_at wants a struct file_handle, which is 4-aligned
+        let mut handle = vec![0u32; name.handle.len().div_ceil(4)];
+        let len = name.handle.len();
+        unsafe { std::ptr::copy_nonoverlapping(name.handle.as_ptr(), handle.as_mut_ptr() as *mut u8, len) };
+        let flags = libc::O_PATH | libc::O_CLOEXEC;
+        let fd = unsafe { libc::syscall(libc::SYS_open_by_handle_at, mount.as_raw_fd(), handle.as_mut_ptr(), flags) };
+        if fd < 0 {
+            return None;
+        }
+        let dir = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        let dir = std::fs::read_link(format!("/proc/self/fd/{}", dir.as_raw_fd())).ok()?;
+        Some(match name.name.as_slice() {
+            b"" | b"." => dir,
+            file => dir.join(OsStr::from_bytes(file)),
+        })
+    }
+
+    // The accesses under the paths since the last read, by any process; the
+    // caller keeps the session's
+    pub fn read(&mut self) -> Result<Vec<FileEvent>> {
+        let mut found = Vec::new();
+        let mut buffer = vec![0u8; 16384];
+        loop {
+            let into = buffer.as_mut_ptr() as *mut libc::c_void;
+            let n = unsafe { libc::read(self.fanotify.as_raw_fd(), into, buffer.len()) };
+            if n < 0 {
+                let e = std::io::Error::last_os_error();
+                match e.raw_os_error() {
+                    Some(libc::EAGAIN) => break,
+                    Some(libc::EINTR) => continue,
+                    _ => return Err(anyhow!("Cannot read file events: {}", e)),
+                }
+            }
+            for event in parse_events(&buffer[..n as usize]) {
+                for access in accesses(&event, |name| self.path(name)) {
+                    if access.paths().iter().any(|path| self.paths.iter().any(|under| path.starts_with(under))) {
+                        found.push(access);
+                    }
+                }
+            }
+        }
+        Ok(found)
+    }
+}
+
+impl AsRawFd for FileTracer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fanotify.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name_record(info_type: u8, dir: u8, name: &str) -> Vec<u8> {
+        // fsid, then a file_handle of 8 bytes, then the name
+        let mut record = vec![info_type, 0, 0, 0];
+        record.extend([7; 8]);
+        record.extend(8u32.to_ne_bytes());
+        record.extend(1i32.to_ne_bytes());
+        record.extend([dir; 8]);
+        record.extend(name.as_bytes());
+        record.push(0);
+        while record.len() % 4 != 0 {
+            record.push(0);
+        }
+        let len = record.len() as u16;
+        record[2..4].copy_from_slice(&len.to_ne_bytes());
+        record
+    }
+
+    fn event(mask: u64, pid: i32, records: &[Vec<u8>]) -> Vec<u8> {
+        let mut event = vec![0u8; METADATA_LEN];
+        event[6..8].copy_from_slice(&(METADATA_LEN as u16).to_ne_bytes());
+        event[8..16].copy_from_slice(&mask.to_ne_bytes());
+        event[16..20].copy_from_slice(&(-1i32).to_ne_bytes());
+        event[20..24].copy_from_slice(&pid.to_ne_bytes());
+        for record in records {
+            event.extend(record);
+        }
+        let len = event.len() as u32;
+        event[..4].copy_from_slice(&len.to_ne_bytes());
+        event
+    }
+
+    #[test]
+    fn test_file_events() {
+        let mut buffer = event(
+            libc::FAN_OPEN | libc::FAN_CLOSE_WRITE,
+            42,
+            &[name_record(libc::FAN_EVENT_INFO_TYPE_DFID_NAME, 1, "nginx.conf")],
+        );
+        buffer.extend(event(
+            libc::FAN_RENAME,
+            43,
+            &[
+                name_record(libc::FAN_EVENT_INFO_TYPE_OLD_DFID_NAME, 2, ".env.tmp"),
+                name_record(libc::FAN_EVENT_INFO_TYPE_NEW_DFID_NAME, 2, ".env"),
+            ],
+        ));
+        let events = parse_events(&buffer);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].names[0].fsid, [7; 8]);
+        assert_eq!(events[0].names[0].handle.len(), 16);
+
+        // The directories by the first byte of their handles
+        let path = |name: &Name| {
+            let dir = if name.handle[8] == 1 { "/etc/nginx" } else { "/srv/my app" };
+            Some(Path::new(dir).join(OsStr::from_bytes(&name.name)))
+        };
+        let found: Vec<FileEvent> = events.iter().flat_map(|event| accesses(event, path)).collect();
+        let lines: Vec<String> = found.iter().map(|event| format!("{} {}", event.name(), event.message())).collect();
+        assert_eq!(
+            lines,
+            [
+                "FILE_OPEN PID=42 PATH=/etc/nginx/nginx.conf",
+                "FILE_WRITE PID=42 PATH=/etc/nginx/nginx.conf",
+                "FILE_RENAME PID=43 FROM=/srv/my\\x20app/.env.tmp TO=/srv/my\\x20app/.env",
+            ]
+        );
+    }
+}
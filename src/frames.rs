@@ -0,0 +1,156 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::images::Image;
+use crate::timing::{self, window_size, EntryKind, TimingEntry};
+use crate::vt::{Screen, Style};
+
+// Output closer together than this shows as one frame
+const FRAME_INTERVAL: Duration = Duration::from_millis(40);
+
+// The screen as it stood at some point of a recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub offset: Duration,
+    pub cols: usize,
+    // One per row, trailing blanks left out
+    pub lines: Vec<String>,
+    // The same rows, as runs of characters in one style
+    pub runs: Vec<Vec<Run>>,
+}
+
+// Characters that look alike and link to the same place, if anywhere. An
+// inline image starts a run of its own, at its top left corner.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub text: String,
+    pub style: Style,
+    pub link: Option<String>,
+    pub image: Option<Arc<Image>>,
+}
+
+#[cfg(test)]
+impl Frame {
+    // A frame of unstyled text
+    pub fn plain(offset: Duration, cols: usize, lines: &[&str]) -> Frame {
+        let run = |line: &&str| Run { text: line.to_string(), style: Style::DEFAULT, link: None, image: None };
+        Frame {
+            offset,
+            cols,
+            lines: lines.iter().map(|line| line.to_string()).collect(),
+            runs: lines.iter().map(|line| if line.is_empty() { vec![] } else { vec![run(line)] }).collect(),
+        }
+    }
+}
+
+fn snapshot(screen: &Screen, offset: Duration) -> Frame {
+    let (cols, rows) = screen.size();
+    let lines = (0..rows)
+        .map(|row| {
+            let line: String = (0..cols).filter_map(|col| screen.cell(row, col)).map(|cell| cell.ch).collect();
+            line.trim_end().to_string()
+        })
+        .collect();
+    let runs = (0..rows).map(|row| styled_row(screen, row)).collect();
+    Frame { offset, cols, lines, runs }
+}
+
+// Blanks that show nothing are left out at the end of a row
+fn styled_row(screen: &Screen, row: usize) -> Vec<Run> {
+    let (cols, _) = screen.size();
+    let cells: Vec<_> = (0..cols).filter_map(|col| screen.cell(row, col)).collect();
+    let shown = cells
+        .iter()
+        .rposition(|cell| cell.ch != ' ' || cell.style != Style::DEFAULT || cell.link.is_some() || cell.image.is_some())
+        .map_or(0, |last| last + 1);
+
+    let mut runs: Vec<Run> = Vec::new();
+    for cell in &cells[..shown] {
+        let link = cell.link.and_then(|link| screen.link(link)).map(str::to_string);
+        let image = cell.image.and_then(|image| screen.image(image)).cloned();
+        match runs.last_mut() {
+            Some(run) if run.style == cell.style && run.link == link && image.is_none() => run.text.push(cell.ch),
+            _ => runs.push(Run { text: cell.ch.to_string(), style: cell.style, link, image }),
+        }
+    }
+    runs
+}
+
+struct Player {
+    screen: Screen,
+    frames: Vec<Frame>,
+    // The first and last change since the last frame
+    changed: Option<(Duration, Duration)>,
+}
+
+impl Player {
+    // Called before each change to the screen
+    fn change(&mut self, offset: Duration) {
+        if let Some((first, last)) = self.changed {
+            if offset - first >= FRAME_INTERVAL {
+                self.frames.push(snapshot(&self.screen, last));
+                self.changed = None;
+            }
+        }
+        self.changed = Some((self.changed.map_or(offset, |(first, _)| first), offset));
+    }
+}
+
+// Play the output through the terminal emulator, taking a frame whenever
+// the screen has settled for a moment, and one at the end
+pub fn frames(entries: &[TimingEntry], data: &[u8]) -> Vec<Frame> {
+    // A size of 0 means the recording didn't know
+    let size = |name| timing::info(entries, name).and_then(|v| v.parse().ok()).filter(|&v| v > 0);
+    let screen = Screen::new(size("COLUMNS").unwrap_or(80), size("LINES").unwrap_or(24));
+    let mut player = Player { frames: vec![snapshot(&screen, Duration::ZERO)], screen, changed: None };
+
+    let mut offset = Duration::ZERO;
+    let mut pos = 0;
+    for entry in entries {
+        offset += entry.delay;
+        match entry.kind {
+            EntryKind::Output(size) => {
+                player.change(offset);
+                let end = (pos + size).min(data.len());
+                player.screen.feed(&data[pos..end], 0);
+                pos = end;
+            }
+            EntryKind::Input(size) => pos = (pos + size).min(data.len()),
+            EntryKind::Signal(ref name, Some(ref message)) if name == "SIGWINCH" => {
+                if let Some((cols, rows)) = window_size(message) {
+                    player.change(offset);
+                    player.screen.resize(cols, rows);
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some((_, last)) = player.changed {
+        player.frames.push(snapshot(&player.screen, last));
+    }
+    player.frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::parse_timing;
+
+    #[test]
+    fn test_frames_settle_and_resize() {
+        let entries = parse_timing(
+            "H 0.000000 COLUMNS 10\nH 0.000000 LINES 2\nO 0.5 2\nO 0.01 4\nI 1.0 2\nO 0.0 4\n\
+             S 2.0 SIGWINCH ROWS=3 COLS=12\n",
+        )
+        .unwrap();
+        let frames = frames(&entries, b"$ ls\r\nlsa  b");
+
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0].lines, vec!["", ""]);
+        // The echo right after the prompt joins it
+        assert_eq!(frames[1].offset, Duration::from_millis(510));
+        assert_eq!(frames[1].lines, vec!["$ ls", ""]);
+        assert_eq!(frames[2].lines, vec!["$ ls", "a  b"]);
+        assert_eq!((frames[3].cols, frames[3].lines.len()), (12, 3));
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset};
+use regex::RegexBuilder;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::convert;
+use crate::timing::{EntryKind, TimingEntry};
+use crate::vt::Lines;
+
+// A line of output and when it was finished: into the session, and the
+// time of day if the recording was stamped with --wall-clock
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub offset: Duration,
+    pub wall: Option<DateTime<FixedOffset>>,
+    pub text: String,
+}
+
+// The output as the lines it reads as, colors and overwritten text gone
+pub fn lines(entries: &[TimingEntry], data: &[u8]) -> Vec<Line> {
+    let mut lines = Lines::default();
+    let mut found = Vec::new();
+    let mut offset = Duration::ZERO;
+    let mut wall = None;
+    let mut pos = 0;
+    for entry in entries {
+        offset += entry.delay;
+        wall = entry.wall.or(wall);
+        match entry.kind {
+            EntryKind::Output(size) => {
+                let end = (pos + size).min(data.len());
+                found.extend(lines.feed(&data[pos..end]).into_iter().map(|text| Line { offset, wall, text }));
+                pos = end;
+            }
+            EntryKind::Input(size) => pos = (pos + size).min(data.len()),
+            _ => {}
+        }
+    }
+    let last = lines.take();
+    if !last.is_empty() {
+        found.push(Line { offset, wall, text: last });
+    }
+    found
+}
+
+// 01:02:03.456 into the session, or 2024-01-02 03:04:05.456 by the clock
+fn when(line: &Line) -> String {
+    match line.wall {
+        Some(wall) => wall.format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+        None => timestamp(line.offset),
+    }
+}
+
+fn timestamp(offset: Duration) -> String {
+    let ms = offset.as_millis();
+    format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+// Returns the exit status, 1 when nothing matched, as grep does
+pub fn run(pattern: &str, ignore_case: bool, timing: Option<&Path>, recording: &Path) -> Result<i32> {
+    let regex = RegexBuilder::new(pattern).case_insensitive(ignore_case).build().context("Invalid pattern")?;
+    let (entries, data) = convert::load_recording(timing, recording)?;
+
+    let mut matched = false;
+    for line in lines(&entries, &data).into_iter().filter(|line| regex.is_match(&line.text)) {
+        println!("{}: {}", when(&line), line.text);
+        matched = true;
+    }
+    Ok(if matched { 0 } else { 1 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::parse_timing;
+
+    #[test]
+    fn test_lines_keep_their_time() {
+        let entries = parse_timing("O 1.5 8\nI 0.2 3\nO 3600.0 38\nO 0.25 4\n").unwrap();
+        let data = b"$ make\r\nls\r\x1b[31merror\x1b[0m: full\r\n50%\r100%\r\n$ ls\r\na  b";
+        let lines = lines(&entries, data);
+
+        let shown: Vec<(String, &str)> = lines.iter().map(|line| (timestamp(line.offset), line.text.as_str())).collect();
+        assert_eq!(
+            shown,
+            vec![
+                ("00:00:01.500".to_string(), "$ make"),
+                ("01:00:01.700".to_string(), "error: full"),
+                ("01:00:01.700".to_string(), "100%"),
+                ("01:00:01.700".to_string(), "$ ls"),
+                ("01:00:01.950".to_string(), "a  b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stamped_lines_keep_the_time_of_day() {
+        let entries = parse_timing(
+            "H 0.000000 TIMESTAMPS wall-clock\nO 1.5 8 @2024-01-02T03:04:05.250000+01:00\n\
+             O 0.25 4 @2024-01-02T03:04:07.500000+01:00\n",
+        )
+        .unwrap();
+        let lines = lines(&entries, b"$ make\r\nok\r\n");
+        let shown: Vec<String> = lines.iter().map(when).collect();
+        // The clock was stepped by two seconds; the offset still counts only the pauses
+        assert_eq!(shown, ["2024-01-02 03:04:05.250", "2024-01-02 03:04:07.500"]);
+        assert_eq!(lines[1].offset, Duration::from_millis(1750));
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256, Sha512};
+use std::io::{self, Write};
+
+// The digest the recorder fingerprints files with (--hash), for the
+// --snapshot-paths and --spool manifests. SHA-256 unless told otherwise;
+// --fips allows only the algorithms FIPS 140 approves, the SHA-2 ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn parse(name: &str) -> Result<HashAlgorithm> {
+        match name.to_lowercase().replace('-', "").as_str() {
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "sha512" => Ok(HashAlgorithm::Sha512),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            _ => Err(anyhow!("Unsupported hash algorithm: '{}' (sha256, sha512 or blake3)", name)),
+        }
+    }
+
+    // Also the extension of the files listing digests, as `sha256sum` and
+    // its kind name them
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    pub fn is_fips_approved(self) -> bool {
+        matches!(self, HashAlgorithm::Sha256 | HashAlgorithm::Sha512)
+    }
+
+    // The algorithm if it may be used: built in, and approved in FIPS mode
+    pub fn check(self, fips: bool) -> Result<HashAlgorithm> {
+        if fips && !self.is_fips_approved() {
+            return Err(anyhow!("--hash {} is not FIPS 140 approved (sha256 or sha512 with --fips)", self.name()));
+        }
+        if self == HashAlgorithm::Blake3 && !cfg!(feature = "blake3") {
+            return Err(anyhow!("This build cannot hash with BLAKE3 (feature `blake3`)"));
+        }
+        Ok(self)
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            #[cfg(feature = "blake3")]
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            // `check` turns it down first
+            #[cfg(not(feature = "blake3"))]
+            HashAlgorithm::Blake3 => unreachable!("BLAKE3 is not built in"),
+        }
+    }
+}
+
+// Data written to it is hashed
+pub enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    #[cfg(feature = "blake3")]
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    // The digest in lowercase hex
+    pub fn finish(self) -> String {
+        let digest: Vec<u8> = match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+            #[cfg(feature = "blake3")]
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+        };
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha512(hasher) => hasher.update(data),
+            #[cfg(feature = "blake3")]
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(algorithm: HashAlgorithm, data: &[u8]) -> String {
+        let mut hasher = algorithm.hasher();
+        hasher.write_all(data).unwrap();
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_algorithms_and_fips() {
+        // As sha256sum and sha512sum have them
+        let sha256 = "2e7d2c03a9507ae265ecf5b5356885a53393a2029d241394997265a1a25aefc6";
+        assert_eq!(digest(HashAlgorithm::Sha256, b"c"), sha256);
+        assert!(digest(HashAlgorithm::Sha512, b"c").starts_with("acc28db2beb7b42baa1cb0243d401ccb"));
+        assert_eq!(HashAlgorithm::parse("SHA-512").unwrap(), HashAlgorithm::Sha512);
+        assert!(HashAlgorithm::parse("md5").is_err());
+
+        assert_eq!(HashAlgorithm::Sha512.check(true).unwrap(), HashAlgorithm::Sha512);
+        let refused = HashAlgorithm::Blake3.check(true).unwrap_err();
+        assert_eq!(refused.to_string(), "--hash blake3 is not FIPS 140 approved (sha256 or sha512 with --fips)");
+        assert_eq!(HashAlgorithm::Blake3.check(false).is_ok(), cfg!(feature = "blake3"));
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn test_blake3() {
+        let empty = "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262";
+        assert_eq!(digest(HashAlgorithm::Blake3, b""), empty);
+    }
+}
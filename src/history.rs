@@ -0,0 +1,188 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::logging::{LogFormat, TIMESTAMP_FORMAT};
+use crate::replay;
+use crate::script_control::ScriptControl;
+
+// A session in this user's history, kept one per line, newest last:
+//
+//   START_TIME <tab> DIRECTORY <tab> TIMING <tab> LOG <tab> io|out <tab> ARGUMENTS...
+//
+// with tabs, newlines and backslashes in the fields escaped. The arguments
+// are the ones the recording was started with, so it can be started again
+// from the same directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub started: String,
+    pub dir: PathBuf,
+    pub timing: Option<PathBuf>,
+    pub log: Option<PathBuf>,
+    // The log holds the input too (-B)
+    pub log_io: bool,
+    pub args: Vec<String>,
+}
+
+fn escape(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+impl Session {
+    // The logs a recording about to start writes, with their paths made
+    // absolute so they can be found from anywhere
+    pub fn of(control: &ScriptControl, args: Vec<String>) -> Result<Session> {
+        let dir = std::env::current_dir().context("Cannot get the current directory")?;
+        let is_timing = |format: LogFormat| matches!(format, LogFormat::TimingSimple | LogFormat::TimingMulti);
+        let timing = control.out_logs.iter().find(|log| is_timing(log.format())).map(|log| dir.join(log.path()));
+        let log = control
+            .out_logs
+            .iter()
+            .find(|log| matches!(log.format(), LogFormat::Raw | LogFormat::TtyRec))
+            .map(|log| log.path());
+        let log_io = log.is_some_and(|path| control.in_logs.iter().any(|input| input.path() == path));
+        Ok(Session {
+            started: chrono::Local::now().format(TIMESTAMP_FORMAT).to_string(),
+            log: log.map(|path| dir.join(path)),
+            dir,
+            timing,
+            log_io,
+            args,
+        })
+    }
+
+    fn to_line(&self) -> String {
+        let path = |path: &Option<PathBuf>| path.as_deref().map(|p| escape(&p.to_string_lossy())).unwrap_or_default();
+        let mut fields = vec![
+            escape(&self.started),
+            escape(&self.dir.to_string_lossy()),
+            path(&self.timing),
+            path(&self.log),
+            (if self.log_io { "io" } else { "out" }).to_string(),
+        ];
+        fields.extend(self.args.iter().map(|arg| escape(arg)));
+        fields.join("\t")
+    }
+
+    fn parse(line: &str) -> Result<Session> {
+        let fields: Vec<String> = line.split('\t').map(unescape).collect();
+        if fields.len() < 5 {
+            return Err(anyhow!("Invalid history line: {}", line));
+        }
+        let path = |field: &String| (!field.is_empty()).then(|| PathBuf::from(field));
+        Ok(Session {
+            started: fields[0].clone(),
+            dir: PathBuf::from(&fields[1]),
+            timing: path(&fields[2]),
+            log: path(&fields[3]),
+            log_io: fields[4] == "io",
+            args: fields[5..].to_vec(),
+        })
+    }
+}
+
+fn history_path() -> Result<PathBuf> {
+    config::history_path().ok_or_else(|| anyhow!("Cannot find the session history: neither XDG_STATE_HOME nor HOME is set"))
+}
+
+fn append(path: &Path, session: &Session) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    }
+    // Command lines can hold things only their user should see
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).mode(0o600).open(path)?;
+    file.write_all(format!("{}\n", session.to_line()).as_bytes())?;
+    Ok(())
+}
+
+fn read_last(path: &Path) -> Result<Session> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Cannot read {}", path.display())),
+    };
+    let line = content.lines().rev().find(|line| !line.is_empty());
+    line.map(Session::parse).unwrap_or_else(|| Err(anyhow!("No sessions recorded yet")))
+}
+
+// Add the session `control` is about to record, started with `args`
+pub fn add(control: &ScriptControl, args: Vec<String>) -> Result<()> {
+    let path = history_path()?;
+    append(&path, &Session::of(control, args)?).with_context(|| format!("Cannot write {}", path.display()))
+}
+
+pub fn last() -> Result<Session> {
+    read_last(&history_path()?)
+}
+
+// `script last`: play the most recent session back
+pub fn replay_last(identity: Option<&Path>, tmux: &str) -> Result<()> {
+    let session = last()?;
+    let log = session.log.as_deref().ok_or_else(|| anyhow!("The last session, started {}, has no log to replay", session.started))?;
+    let (log_io, typescript) = if session.log_io { (Some(log), None) } else { (None, Some(log)) };
+    let options = replay::ReplayOptions {
+        tmux,
+        interactive: false,
+        start: None,
+        max_delay: None,
+        divisor: None,
+        speed: None,
+        throttle: None,
+        images: "auto",
+        resume_style: "newline",
+    };
+    replay::run(session.timing.as_deref(), log_io, identity, typescript, &options)
+        .with_context(|| format!("Cannot replay the last session, started {}", session.started))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_round_trip() {
+        let path = std::env::temp_dir().join(format!("rust_script-{}-history", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert!(read_last(&path).is_err());
+
+        let mut session = Session {
+            started: "2024-01-02 10:00:00+00:00".to_string(),
+            dir: PathBuf::from("/home/a/tab\there"),
+            timing: Some(PathBuf::from("/home/a/s.tm")),
+            log: Some(PathBuf::from("/home/a/s.log")),
+            log_io: true,
+            args: vec!["-B".into(), "s.log".into(), "-c".into(), "printf 'a\\tb\\n'\nls".into()],
+        };
+        append(&path, &session).unwrap();
+        session.timing = None;
+        session.log_io = false;
+        session.args.clear();
+        append(&path, &session).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        assert_eq!(read_last(&path).unwrap(), session);
+        assert_eq!(Session::parse(content.lines().next().unwrap()).unwrap().args[3], "printf 'a\\tb\\n'\nls");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,397 @@
+use anyhow::{anyhow, Context, Result};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::export::{Mark, Position, Watermark};
+use crate::frames::{Frame, Run};
+use crate::images::{Image, Picture};
+use crate::vt::Style;
+
+// The player: frames are [seconds, columns, rows, [[row, html], ...]], each
+// holding the rows that changed since the one before. Inline images are
+// data URLs in IMAGES, which rows point at with data-image.
+const PLAYER: &str = r#"const screen = document.getElementById('screen');
+const button = document.getElementById('play');
+const seek = document.getElementById('seek');
+const clock = document.getElementById('clock');
+const chapters = document.getElementById('chapters');
+const end = FRAMES[FRAMES.length - 1][0];
+let lines = [], shown = -1, at = 0, playing = false, started = 0;
+
+function apply(frame) {
+  const [, cols, rows, changes] = frame;
+  lines.length = rows;
+  for (let row = 0; row < rows; row++) lines[row] = lines[row] || '';
+  for (const [row, text] of changes) lines[row] = text;
+  screen.style.width = cols + 'ch';
+}
+
+function time(t) {
+  const s = Math.floor(t);
+  return Math.floor(s / 60) + ':' + String(s % 60).padStart(2, '0');
+}
+
+function show(t) {
+  let i = shown;
+  if (i >= 0 && FRAMES[i][0] > t) { lines = []; i = -1; }
+  while (i + 1 < FRAMES.length && FRAMES[i + 1][0] <= t) apply(FRAMES[++i]);
+  if (i !== shown) {
+    shown = i;
+    screen.innerHTML = lines.join('\n');
+    for (const img of screen.querySelectorAll('img[data-image]')) img.src = IMAGES[img.dataset.image];
+  }
+  at = t;
+  seek.value = t;
+  clock.textContent = time(t) + ' / ' + time(end);
+}
+
+function tick(now) {
+  if (!playing) return;
+  const t = (now - started) / 1000;
+  if (t >= end) { show(end); pause(); return; }
+  show(t);
+  requestAnimationFrame(tick);
+}
+
+function play() {
+  if (at >= end) at = 0;
+  playing = true;
+  button.textContent = 'Pause';
+  started = performance.now() - at * 1000;
+  requestAnimationFrame(tick);
+}
+
+function pause() {
+  playing = false;
+  button.textContent = 'Play';
+}
+
+button.onclick = () => playing ? pause() : play();
+seek.max = end;
+seek.oninput = () => {
+  show(Number(seek.value));
+  started = performance.now() - at * 1000;
+};
+if (chapters) chapters.onchange = () => {
+  show(Number(chapters.value));
+  started = performance.now() - at * 1000;
+};
+document.onkeydown = e => {
+  if (e.key === ' ') { e.preventDefault(); button.click(); }
+};
+show(0);
+"#;
+
+const STYLE: &str = "body { background: #222; color: #ddd; font-family: sans-serif; margin: 2em; }
+#screen { background: #000; color: #ccc; font: 14px/1.2 monospace; padding: 0.5em; margin: 0; white-space: pre; overflow: hidden; box-sizing: content-box; }
+#screen a { color: inherit; }
+#screen img { position: absolute; }
+#view { position: relative; display: inline-block; }
+#watermark { position: absolute; pointer-events: none; color: #fff; font: 12px sans-serif; }
+#watermark img { display: block; max-width: 100%; }
+#controls { display: flex; gap: 1em; align-items: center; margin-top: 0.5em; }
+#seek { flex: 1; }
+#clock { font-family: monospace; }
+";
+
+// The screen's own colors, as STYLE sets them; inverse text swaps them
+const FOREGROUND: (u8, u8, u8) = (0xcc, 0xcc, 0xcc);
+const BACKGROUND: (u8, u8, u8) = (0, 0, 0);
+
+// Schemes an OSC 8 link may have to be followed from the page
+const LINK_SCHEMES: &[&str] = &["http:", "https:", "ftp:", "mailto:", "file:"];
+
+// A page playing the frames back by itself, with nothing to load, and the
+// recording's markers to jump to
+pub fn render(title: &str, frames: &[Frame], markers: &[(Duration, String)], watermark: Option<&Watermark>) -> Result<String> {
+    let mut data = String::from("[\n");
+    let mut images = Vec::new();
+    let mut previous: Option<&Frame> = None;
+    for frame in frames {
+        let resized = previous.is_none_or(|p| p.cols != frame.cols || p.lines.len() != frame.lines.len());
+        let changes: Vec<String> = frame
+            .runs
+            .iter()
+            .enumerate()
+            .filter(|&(row, runs)| resized || previous.is_some_and(|p| p.runs[row] != *runs))
+            .map(|(row, runs)| format!("[{},{}]", row, js_string(&row_html(runs, &mut images))))
+            .collect();
+        data.push_str(&format!(
+            "[{:.3},{},{},[{}]],\n",
+            frame.offset.as_secs_f64(),
+            frame.cols,
+            frame.lines.len(),
+            changes.join(",")
+        ));
+        previous = Some(frame);
+    }
+    data.push(']');
+    let images: Vec<String> = images.iter().map(|image| js_string(&data_url(image))).collect();
+
+    let mut chapters = String::new();
+    if !markers.is_empty() {
+        chapters.push_str("<select id=\"chapters\">\n<option value=\"0\">Start</option>\n");
+        for (offset, command) in markers {
+            let secs = offset.as_secs();
+            let label = format!("{}:{:02} {}", secs / 60, secs % 60, command);
+            let value = offset.as_secs_f64();
+            chapters.push_str(&format!("<option value=\"{:.3}\">{}</option>\n", value, html_escape(label.trim())));
+        }
+        chapters.push_str("</select>\n");
+    }
+
+    let watermark = match watermark {
+        Some(watermark) => watermark_html(watermark)?,
+        None => String::new(),
+    };
+
+    Ok(format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>\n{STYLE}</style>\n</head>\n\
+         <body>\n<h1>{title}</h1>\n<div id=\"view\">\n<pre id=\"screen\"></pre>\n{watermark}</div>\n<div id=\"controls\">\n\
+         <button id=\"play\">Play</button>\n<input id=\"seek\" type=\"range\" min=\"0\" step=\"any\" value=\"0\">\n\
+         <span id=\"clock\"></span>\n{chapters}</div>\n\
+         <script>\nconst FRAMES = {data};\nconst IMAGES = [{images}];\n{PLAYER}</script>\n</body>\n</html>\n",
+        images = images.join(",\n"),
+        title = html_escape(title),
+    ))
+}
+
+// Laid over the screen; an image goes into the page as a data URL
+fn watermark_html(watermark: &Watermark) -> Result<String> {
+    let at = match watermark.position {
+        Position::TopLeft => "top:0.5em;left:0.5em",
+        Position::TopRight => "top:0.5em;right:0.5em",
+        Position::BottomLeft => "bottom:0.5em;left:0.5em",
+        Position::BottomRight => "bottom:0.5em;right:0.5em",
+        Position::Bottom => "bottom:0.5em;left:0;right:0;text-align:center",
+    };
+    let content = match watermark.mark {
+        Mark::Text(ref text) => html_escape(text),
+        Mark::Image(ref path) => {
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+            let mime = match extension.as_str() {
+                "png" => "image/png",
+                "jpg" | "jpeg" => "image/jpeg",
+                "gif" => "image/gif",
+                "webp" => "image/webp",
+                "svg" => "image/svg+xml",
+                _ => {
+                    return Err(anyhow!(
+                        "Can't tell what kind of image the watermark is from its name: {} (PNG, JPEG, GIF, WebP or SVG)",
+                        path.display()
+                    ))
+                }
+            };
+            let image = std::fs::read(path).with_context(|| format!("Failed to read watermark image: {}", path.display()))?;
+            format!("<img src=\"data:{};base64,{}\" alt=\"\">", mime, base64(&image))
+        }
+    };
+    Ok(format!("<div id=\"watermark\" style=\"{};opacity:{}\">{}</div>\n", at, watermark.opacity, content))
+}
+
+fn data_url(image: &Image) -> String {
+    match image.picture {
+        Picture::File { mime, ref base64 } => format!("data:{};base64,{}", mime, base64),
+        Picture::Pixels { width, height, ref rgb } => format!("data:image/bmp;base64,{}", base64(&bmp(width, height, rgb))),
+    }
+}
+
+// A 24-bit BMP: the bottom row first, each padded to four bytes
+fn bmp(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let row = (width * 3).div_ceil(4) * 4;
+    let size = 54 + row * height;
+    let mut bmp = Vec::with_capacity(size);
+    bmp.extend_from_slice(b"BM");
+    for value in [size as u32, 0, 54, 40, width as u32, height as u32] {
+        bmp.extend_from_slice(&value.to_le_bytes());
+    }
+    // One plane of 24 bits a pixel, uncompressed, at 72 dpi
+    bmp.extend_from_slice(&[1, 0, 24, 0]);
+    for value in [0, (row * height) as u32, 2835, 2835, 0, 0] {
+        bmp.extend_from_slice(&value.to_le_bytes());
+    }
+    for y in (0..height).rev() {
+        for pixel in rgb[y * width * 3..(y + 1) * width * 3].chunks_exact(3) {
+            bmp.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+        }
+        bmp.resize(bmp.len() + row - width * 3, 0);
+    }
+    bmp
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// A row as the terminal showed it: colored, bold and so on, and linked.
+// An image is laid over the cells it covers, from where its run starts;
+// `images` collects each once, however many frames show it.
+fn row_html(runs: &[Run], images: &mut Vec<Arc<Image>>) -> String {
+    let mut html = String::new();
+    for run in runs {
+        if let Some(ref image) = run.image {
+            let index = images.iter().position(|seen| Arc::ptr_eq(seen, image)).unwrap_or_else(|| {
+                images.push(image.clone());
+                images.len() - 1
+            });
+            let height = image.rows as f64 * 1.2;
+            html.push_str(&format!("<img data-image=\"{}\" style=\"width:{}ch;height:{:.1}em\" alt=\"\">", index, image.cols, height));
+        }
+        let css = css(run.style);
+        let mut text = html_escape(&run.text);
+        if !css.is_empty() {
+            text = format!("<span style=\"{}\">{}</span>", css, text);
+        }
+        let scheme = |link: &str| LINK_SCHEMES.iter().any(|scheme| link.get(..scheme.len()).is_some_and(|s| s.eq_ignore_ascii_case(scheme)));
+        match run.link {
+            Some(ref link) if scheme(link) => html.push_str(&format!("<a href=\"{}\">{}</a>", html_escape(link), text)),
+            _ => html.push_str(&text),
+        }
+    }
+    html
+}
+
+// Bit n of the attributes is SGR n
+fn css(style: Style) -> String {
+    let attr = |n: u16| style.attrs & (1 << n) != 0;
+    let (mut fg, mut bg) = (style.fg.rgb(), style.bg.rgb());
+    if attr(7) {
+        (fg, bg) = (Some(bg.unwrap_or(BACKGROUND)), Some(fg.unwrap_or(FOREGROUND)));
+    }
+
+    let mut rules = Vec::new();
+    let hex = |(r, g, b): (u8, u8, u8)| format!("#{:02x}{:02x}{:02x}", r, g, b);
+    if let Some(fg) = fg {
+        rules.push(format!("color:{}", hex(fg)));
+    }
+    if let Some(bg) = bg {
+        rules.push(format!("background:{}", hex(bg)));
+    }
+    if attr(1) {
+        rules.push("font-weight:bold".to_string());
+    }
+    if attr(3) {
+        rules.push("font-style:italic".to_string());
+    }
+    let lines: Vec<&str> = [(4, "underline"), (9, "line-through")].into_iter().filter(|&(n, _)| attr(n)).map(|(_, line)| line).collect();
+    if !lines.is_empty() {
+        rules.push(format!("text-decoration:{}", lines.join(" ")));
+    }
+    rules.join(";")
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// A JavaScript string literal that can't end the script element early
+fn js_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '<' | '>' | '&' => quoted.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch if ch.is_control() => quoted.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_as_changed_rows() {
+        let frame = |ms, lines: &[&str]| Frame::plain(Duration::from_millis(ms), 10, lines);
+        let frames = [frame(0, &["", ""]), frame(1500, &["$ ls", ""]), frame(2000, &["$ ls", "</script>"])];
+        let page = render("a<b", &frames, &[], None).unwrap();
+
+        assert!(page.contains("<title>a&lt;b</title>"));
+        assert!(page.contains("[0.000,10,2,[[0,\"\"],[1,\"\"]]],\n[1.500,10,2,[[0,\"$ ls\"]]],\n"));
+        assert!(page.contains("[2.000,10,2,[[1,\"\\u0026lt;/script\\u0026gt;\"]]],\n]"));
+        assert_eq!(page.matches("</script>").count(), 1);
+        assert!(!page.contains("<select"));
+
+        let page = render("s", &frames, &[(Duration::from_millis(61500), "ls <dir>".to_string())], None).unwrap();
+        assert!(page.contains("<option value=\"61.500\">1:01 ls &lt;dir&gt;</option>\n</select>\n</div>"));
+    }
+
+    #[test]
+    fn test_watermark() {
+        let frames = [Frame::plain(Duration::ZERO, 10, &[""])];
+        let text = Watermark { mark: Mark::Text("(c) <us>".to_string()), position: Position::Bottom, opacity: 0.3 };
+        let page = render("s", &frames, &[], Some(&text)).unwrap();
+        assert!(page.contains(
+            "<pre id=\"screen\"></pre>\n<div id=\"watermark\" style=\"bottom:0.5em;left:0;right:0;text-align:center;opacity:0.3\">\
+             (c) &lt;us&gt;</div>\n</div>"
+        ));
+
+        let path = std::env::temp_dir().join(format!("rust_script-{}-mark.png", std::process::id()));
+        std::fs::write(&path, b"\x89PNG").unwrap();
+        let image = Watermark { mark: Mark::Image(path.clone()), position: Position::TopLeft, opacity: 1.0 };
+        let page = render("s", &frames, &[], Some(&image));
+        std::fs::remove_file(&path).unwrap();
+        assert!(page.unwrap().contains("style=\"top:0.5em;left:0.5em;opacity:1\"><img src=\"data:image/png;base64,iVBORw==\" alt=\"\"></div>"));
+
+        let unknown = Watermark { mark: Mark::Image("logo.bmp".into()), ..image };
+        assert!(render("s", &frames, &[], Some(&unknown)).is_err());
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b""), "");
+    }
+
+    #[test]
+    fn test_rows_keep_styles_and_links() {
+        use crate::frames::frames;
+        use crate::timing::parse_timing;
+
+        let data: &[u8] = b"\x1b[1;38;5;196mok\x1b[0m \x1b[7mx\x1b[0m \x1b]8;;https://e.com/?a&b\x1b\\link\x1b]8;;\x1b\\ \
+                            \x1b]8;;javascript:alert(1)\x07js\x1b]8;;\x07";
+        let timing = format!("H 0.000000 COLUMNS 20\nH 0.000000 LINES 1\nO 0.000000 {}\n", data.len());
+        let frames = frames(&parse_timing(&timing).unwrap(), data);
+        assert_eq!(
+            row_html(&frames.last().unwrap().runs[0], &mut Vec::new()),
+            "<span style=\"color:#ff0000;font-weight:bold\">ok</span> \
+             <span style=\"color:#000000;background:#cccccc\">x</span> \
+             <a href=\"https://e.com/?a&amp;b\">link</a> js"
+        );
+    }
+
+    #[test]
+    fn test_inline_images() {
+        use crate::frames::frames;
+        use crate::timing::parse_timing;
+
+        let data: &[u8] = b"a\x1bPq#1;2;100;0;0~~\x1b\\b\r\n\x1b]1337;File=inline=1:iVBORw0KGgoAAAANSUhEUgAAAGQAAAAo\x07\r\n";
+        let timing = format!("H 0.000000 COLUMNS 20\nH 0.000000 LINES 6\nO 0.000000 {}\n", data.len());
+        let frames = frames(&parse_timing(&timing).unwrap(), data);
+        let page = render("s", &frames, &[], None).unwrap();
+
+        // Each image once, drawn where the cursor was
+        assert!(page.contains(r#"[0,"a\u003cimg data-image=\"0\" style=\"width:1ch;height:1.2em\" alt=\"\"\u003e "],[1," b"],"#));
+        assert!(page.contains(r#"[2,"\u003cimg data-image=\"1\" style=\"width:10ch;height:2.4em\" alt=\"\"\u003e "]"#));
+        assert!(page.contains("const IMAGES = [\"data:image/bmp;base64,Qk1"));
+        assert!(page.contains(",\n\"data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAGQAAAAo\"];\n"));
+
+        // Two red pixels over a pixel of padding, each row
+        let bmp = bmp(2, 1, &[255, 0, 0, 255, 0, 0]);
+        assert_eq!(bmp.len(), 62);
+        assert_eq!(&bmp[54..], &[0, 0, 255, 0, 0, 255, 0, 0]);
+    }
+}
@@ -0,0 +1,213 @@
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+// Messages shown to the user. Each has an English text with `{}` for its
+// arguments and, where we have one, a translation with the same arguments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Message {
+    Started,
+    StartedNoLog,
+    Done,
+    OutputLimit,
+    WatchdogIdle,
+    FdsLeaked,
+    Error,
+    LiveStarted,
+    LiveDone,
+    #[cfg(feature = "analyzers")]
+    AnalyzerFailed,
+    CpuLimitUnavailable,
+    HistoryFailed,
+    StorageFailover,
+    SpaceLow,
+    Degraded,
+    Spooled,
+}
+
+fn english(message: Message) -> &'static str {
+    match message {
+        Message::Started => "Script started, output log file is '{}'.",
+        Message::StartedNoLog => "Script started.",
+        Message::Done => "Script done.",
+        Message::OutputLimit => "Script terminated, max output files size {} exceeded.",
+        Message::WatchdogIdle => "script: session idle for {}s with processes in uninterruptible sleep: {}",
+        Message::FdsLeaked => "script: file descriptors left open after the session:",
+        Message::Error => "Error",
+        Message::LiveStarted => ">>> script live: Starting your typescript execution by {}.",
+        Message::LiveDone => ">>> script live: Done.",
+        #[cfg(feature = "analyzers")]
+        Message::AnalyzerFailed => "script: analyzer `{}` failed: {}",
+        Message::CpuLimitUnavailable => "script: cannot limit the recorder's CPU use: {}",
+        Message::HistoryFailed => "script: cannot add the session to the history: {}",
+        Message::StorageFailover => "script: cannot write {}, going on in {}",
+        Message::SpaceLow => "script: only {} free in {}, less than the {} reserved",
+        Message::Degraded => "script: {}; recording in cooked mode, with input passed on a line at a time",
+        Message::Spooled => "Script spooled the session to {}.",
+    }
+}
+
+fn translation(language: &str, message: Message) -> Option<&'static str> {
+    let text = match (language, message) {
+        ("de", Message::Started) => "Skript gestartet, Ausgabeprotokolldatei ist »{}«.",
+        ("de", Message::StartedNoLog) => "Skript gestartet.",
+        ("de", Message::Done) => "Skript beendet.",
+        ("de", Message::OutputLimit) => "Skript abgebrochen, maximale Größe der Ausgabedateien von {} überschritten.",
+        ("de", Message::WatchdogIdle) => "script: Sitzung seit {}s untätig, Prozesse in nicht unterbrechbarem Schlaf: {}",
+        ("de", Message::FdsLeaked) => "script: nach der Sitzung noch geöffnete Dateideskriptoren:",
+        ("de", Message::Error) => "Fehler",
+        ("de", Message::LiveStarted) => ">>> script live: Ausführung des Typescripts mit {} wird gestartet.",
+        ("de", Message::LiveDone) => ">>> script live: Fertig.",
+        #[cfg(feature = "analyzers")]
+        ("de", Message::AnalyzerFailed) => "script: Analyseprogramm `{}` fehlgeschlagen: {}",
+        ("de", Message::CpuLimitUnavailable) => "script: CPU-Nutzung des Aufzeichners kann nicht begrenzt werden: {}",
+        ("de", Message::HistoryFailed) => "script: Sitzung kann nicht in den Verlauf aufgenommen werden: {}",
+        ("de", Message::StorageFailover) => "script: {} kann nicht geschrieben werden, weiter in {}",
+        ("de", Message::SpaceLow) => "script: nur {} frei in {}, weniger als die reservierten {}",
+        ("de", Message::Degraded) => "script: {}; Aufzeichnung im kanonischen Modus, Eingaben werden zeilenweise weitergegeben",
+        ("de", Message::Spooled) => "Skript hat die Sitzung nach {} übergeben.",
+
+        ("es", Message::Started) => "Script iniciado, el fichero de registro de salida es «{}».",
+        ("es", Message::StartedNoLog) => "Script iniciado.",
+        ("es", Message::Done) => "Script terminado.",
+        ("es", Message::OutputLimit) => "Script interrumpido, se superó el tamaño máximo de los ficheros de salida ({}).",
+        ("es", Message::WatchdogIdle) => "script: sesión inactiva durante {} s con procesos en espera no interrumpible: {}",
+        ("es", Message::FdsLeaked) => "script: descriptores de fichero abiertos tras la sesión:",
+        ("es", Message::Error) => "Error",
+        ("es", Message::LiveStarted) => ">>> script live: iniciando la ejecución del typescript con {}.",
+        ("es", Message::LiveDone) => ">>> script live: hecho.",
+        #[cfg(feature = "analyzers")]
+        ("es", Message::AnalyzerFailed) => "script: falló el analizador `{}`: {}",
+        ("es", Message::CpuLimitUnavailable) => "script: no se puede limitar el uso de CPU del grabador: {}",
+        ("es", Message::HistoryFailed) => "script: no se puede añadir la sesión al historial: {}",
+        ("es", Message::StorageFailover) => "script: no se puede escribir {}, se continúa en {}",
+        ("es", Message::SpaceLow) => "script: solo quedan {} libres en {}, menos de los {} reservados",
+        ("es", Message::Degraded) => "script: {}; se graba en modo canónico, con la entrada pasada línea a línea",
+        ("es", Message::Spooled) => "Script dejó la sesión en {}.",
+
+        ("fr", Message::Started) => "Script démarré, le fichier journal de sortie est « {} ».",
+        ("fr", Message::StartedNoLog) => "Script démarré.",
+        ("fr", Message::Done) => "Script terminé.",
+        ("fr", Message::OutputLimit) => "Script interrompu, taille maximale des fichiers de sortie ({}) dépassée.",
+        ("fr", Message::WatchdogIdle) => "script : session inactive depuis {} s avec des processus en sommeil non interruptible : {}",
+        ("fr", Message::FdsLeaked) => "script : descripteurs de fichier encore ouverts après la session :",
+        ("fr", Message::Error) => "Erreur",
+        ("fr", Message::LiveStarted) => ">>> script live : lancement de l'exécution du typescript avec {}.",
+        ("fr", Message::LiveDone) => ">>> script live : terminé.",
+        #[cfg(feature = "analyzers")]
+        ("fr", Message::AnalyzerFailed) => "script : échec de l'analyseur `{}` : {}",
+        ("fr", Message::CpuLimitUnavailable) => "script : impossible de limiter l'usage CPU de l'enregistreur : {}",
+        ("fr", Message::HistoryFailed) => "script : impossible d'ajouter la session à l'historique : {}",
+        ("fr", Message::StorageFailover) => "script : impossible d'écrire {}, suite dans {}",
+        ("fr", Message::SpaceLow) => "script : seulement {} libres dans {}, moins que les {} réservés",
+        ("fr", Message::Degraded) => "script : {} ; enregistrement en mode canonique, l'entrée étant transmise ligne par ligne",
+        ("fr", Message::Spooled) => "Script a déposé la session dans {}.",
+
+        _ => return None,
+    };
+    Some(text)
+}
+
+// The language part of a locale such as "de_DE.UTF-8"; None for C/POSIX
+fn language(locale: &str) -> Option<&str> {
+    let language = locale.split(['_', '.', '@']).next().unwrap_or_default();
+    match language {
+        "" | "C" | "POSIX" => None,
+        language => Some(language),
+    }
+}
+
+// Messages follow LC_ALL, then LC_MESSAGES, then LANG, as with gettext
+fn current_language() -> Option<&'static str> {
+    static LANGUAGE: OnceLock<Option<String>> = OnceLock::new();
+    LANGUAGE
+        .get_or_init(|| {
+            ["LC_ALL", "LC_MESSAGES", "LANG"]
+                .iter()
+                .filter_map(|name| std::env::var(name).ok())
+                .find(|value| !value.is_empty())
+                .and_then(|locale| language(&locale).map(str::to_string))
+        })
+        .as_deref()
+}
+
+fn format(template: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut args = args.iter();
+    let mut parts = template.split("{}");
+    if let Some(first) = parts.next() {
+        out.push_str(first);
+    }
+    for part in parts {
+        if let Some(arg) = args.next() {
+            out.push_str(&arg.to_string());
+        }
+        out.push_str(part);
+    }
+    out
+}
+
+// The message in the user's language, with `args` filled in
+pub fn tr(message: Message, args: &[&dyn Display]) -> String {
+    let template = current_language()
+        .and_then(|language| translation(language, message))
+        .unwrap_or_else(|| english(message));
+    format(template, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGES: &[Message] = &[
+        Message::Started,
+        Message::StartedNoLog,
+        Message::Done,
+        Message::OutputLimit,
+        Message::WatchdogIdle,
+        Message::FdsLeaked,
+        Message::Error,
+        Message::LiveStarted,
+        Message::LiveDone,
+        #[cfg(feature = "analyzers")]
+        Message::AnalyzerFailed,
+        Message::CpuLimitUnavailable,
+        Message::HistoryFailed,
+        Message::StorageFailover,
+        Message::SpaceLow,
+        Message::Degraded,
+        Message::Spooled,
+    ];
+
+    const LANGUAGES: [&str; 3] = ["de", "es", "fr"];
+
+    #[test]
+    fn test_locale_language() {
+        assert_eq!(language("de_DE.UTF-8"), Some("de"));
+        assert_eq!(language("fr_FR@euro"), Some("fr"));
+        assert_eq!(language("es"), Some("es"));
+        assert_eq!(language("C.UTF-8"), None);
+        assert_eq!(language("POSIX"), None);
+    }
+
+    #[test]
+    fn test_translations_are_complete() {
+        for language in LANGUAGES {
+            for &message in MESSAGES {
+                let text = translation(language, message)
+                    .unwrap_or_else(|| panic!("{:?} has no {} translation", message, language));
+                assert_eq!(
+                    text.matches("{}").count(),
+                    english(message).matches("{}").count(),
+                    "{:?} in {}",
+                    message,
+                    language
+                );
+            }
+        }
+        assert_eq!(format(english(Message::Started), &[&"typescript"]), "Script started, output log file is 'typescript'.");
+        assert_eq!(
+            format(translation("de", Message::WatchdogIdle).unwrap(), &[&30, &"12 13"]),
+            "script: Sitzung seit 30s untätig, Prozesse in nicht unterbrechbarem Schlaf: 12 13"
+        );
+    }
+}
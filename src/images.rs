@@ -0,0 +1,484 @@
+// Inline images in the output, as the Sixel and iTerm2 protocols draw them:
+//
+//   ESC P [params] q <sixels> ST          Sixel (xterm, mlterm, foot, ...)
+//   ESC ] 1337 ; File= <args> : <base64> ST   iTerm2 (also WezTerm, mintty)
+//
+// ST is ESC \, or BEL for the OSC. A terminal without them shows the data
+// as text, so the recorder logs each image as an IMAGE event, replay can
+// leave them out, and the screen of vt.rs places them for the HTML export.
+
+#[cfg(feature = "vt")]
+use std::sync::Arc;
+
+// Longest start of a Sixel image, ESC P and its parameters, before the q
+const MAX_PREFIX: usize = 32;
+
+// What goes out in place of an image left out
+const PLACEHOLDER: &[u8] = b"[image]";
+
+const ITERM2: &[u8] = b"\x1b]1337;File=";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Sixel,
+    Iterm2,
+}
+
+impl Format {
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Sixel => "sixel",
+            Format::Iterm2 => "iterm2",
+        }
+    }
+}
+
+// An image seen in the output: its protocol, and its size in bytes from
+// the ESC that starts it to the end of the terminator
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Found {
+    pub format: Format,
+    pub bytes: usize,
+}
+
+impl Found {
+    // The message of its IMAGE event
+    pub fn message(&self) -> String {
+        format!("FORMAT={} BYTES={}", self.format.name(), self.bytes)
+    }
+}
+
+#[derive(Debug, Default)]
+enum State {
+    #[default]
+    Ground,
+    // The start of a sequence, held back until it tells whether it is an
+    // image
+    Start(Vec<u8>),
+    // In an image: its bytes so far, and whether the last of them was ESC
+    Image(Format, usize, bool),
+}
+
+// Finds images across reads however they are split. Only the few bytes
+// that may start one are kept between reads, never the image itself.
+#[derive(Debug, Default)]
+pub struct ImageScanner {
+    state: State,
+}
+
+impl ImageScanner {
+    // The images that end in `data`
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Found> {
+        self.scan(data, None)
+    }
+
+    // `data` with each image replaced by [image]. The start of a sequence
+    // at the end of `data` comes out with the next read.
+    pub fn strip(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut text = Vec::with_capacity(data.len());
+        self.scan(data, Some(&mut text));
+        text
+    }
+
+    fn scan(&mut self, data: &[u8], mut text: Option<&mut Vec<u8>>) -> Vec<Found> {
+        let mut keep = |bytes: &[u8]| {
+            if let Some(ref mut text) = text {
+                text.extend_from_slice(bytes);
+            }
+        };
+        let mut found = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            let byte = data[i];
+            match std::mem::take(&mut self.state) {
+                State::Ground => {
+                    // Text goes through in one piece, up to the next ESC
+                    let end = data[i..].iter().position(|&b| b == 0x1b).map_or(data.len(), |n| i + n);
+                    keep(&data[i..end]);
+                    if end < data.len() {
+                        self.state = State::Start(vec![0x1b]);
+                    }
+                    i = end + 1;
+                    continue;
+                }
+                State::Start(mut held) => {
+                    held.push(byte);
+                    match image_start(&held) {
+                        Some(Some(format)) => {
+                            keep(PLACEHOLDER);
+                            self.state = State::Image(format, held.len(), false);
+                        }
+                        Some(None) => self.state = State::Start(held),
+                        // Some other sequence goes through as it came, and
+                        // the byte that told is looked at again
+                        None => {
+                            held.pop();
+                            keep(&held);
+                            continue;
+                        }
+                    }
+                }
+                State::Image(format, bytes, escape) => match byte {
+                    b'\\' if escape => found.push(Found { format, bytes: bytes + 1 }),
+                    0x07 if format == Format::Iterm2 => found.push(Found { format, bytes: bytes + 1 }),
+                    // CAN and SUB cancel it
+                    0x18 | 0x1a => found.push(Found { format, bytes: bytes + 1 }),
+                    // ESC starting another sequence ends it too
+                    _ if escape => {
+                        found.push(Found { format, bytes: bytes - 1 });
+                        self.state = State::Start(vec![0x1b]);
+                        continue;
+                    }
+                    _ => self.state = State::Image(format, bytes + 1, byte == 0x1b),
+                },
+            }
+            i += 1;
+        }
+        found
+    }
+}
+
+// Whether the bytes that start a sequence start an image: Some(Some) they
+// do, Some(None) it is too soon to tell, None they don't
+fn image_start(held: &[u8]) -> Option<Option<Format>> {
+    match held.get(1) {
+        None => Some(None),
+        Some(b']') if ITERM2.starts_with(held) => Some((held.len() == ITERM2.len()).then_some(Format::Iterm2)),
+        // Each byte before the last was a parameter, or this wouldn't be held
+        Some(b'P') => match held[2..].last() {
+            None => Some(None),
+            Some(b'q') => Some(Some(Format::Sixel)),
+            Some(b'0'..=b'9' | b';') if held.len() < MAX_PREFIX => Some(None),
+            Some(_) => None,
+        },
+        Some(_) => None,
+    }
+}
+
+// Pixels of a cell, as in xterm's default font, to size images in cells
+#[cfg(feature = "vt")]
+const CELL: (usize, usize) = (10, 20);
+
+// Largest Sixel image drawn, in pixels each way
+#[cfg(feature = "vt")]
+const MAX_PIXELS: usize = 2048;
+
+// An image as the screen holds it, and the cells it covers
+#[cfg(feature = "vt")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub cols: usize,
+    pub rows: usize,
+    #[cfg_attr(not(feature = "html"), allow(dead_code))]
+    pub picture: Picture,
+}
+
+#[cfg(feature = "vt")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(not(feature = "html"), allow(dead_code))]
+pub enum Picture {
+    // What a Sixel image draws, as RGB; pixels it leaves alone are black
+    Pixels { width: usize, height: usize, rgb: Vec<u8> },
+    // An image file, as iTerm2 sends it
+    File { mime: &'static str, base64: String },
+}
+
+// A Sixel image from what its DCS says, "[params]q<sixels>"
+#[cfg(feature = "vt")]
+pub fn sixel(text: &[u8]) -> Option<Arc<Image>> {
+    let start = text.iter().position(|&b| b == b'q')?;
+    if !text[..start].iter().all(|&b| b.is_ascii_digit() || b == b';') {
+        return None;
+    }
+    let sixels = &text[start + 1..];
+    let (mut width, mut height) = (0, 0);
+    let declared = draw_sixels(sixels, |x, y, _| {
+        width = width.max(x + 1);
+        height = height.max(y + 1);
+    });
+    let (width, height) = (width.max(declared.0), height.max(declared.1));
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let mut rgb = vec![0; width * height * 3];
+    draw_sixels(sixels, |x, y, color| rgb[(y * width + x) * 3..][..3].copy_from_slice(&color));
+    let (cols, rows) = (width.div_ceil(CELL.0), height.div_ceil(CELL.1));
+    Some(Arc::new(Image { cols, rows, picture: Picture::Pixels { width, height, rgb } }))
+}
+
+// The colors a VT340 starts with, in percent
+#[cfg(feature = "vt")]
+const VT340: [[usize; 3]; 16] = [
+    [0, 0, 0],
+    [20, 20, 80],
+    [80, 13, 13],
+    [20, 80, 20],
+    [80, 20, 80],
+    [20, 80, 80],
+    [80, 80, 20],
+    [53, 53, 53],
+    [26, 26, 26],
+    [33, 33, 60],
+    [60, 26, 26],
+    [33, 60, 33],
+    [60, 33, 60],
+    [33, 60, 60],
+    [60, 60, 33],
+    [80, 80, 80],
+];
+
+// Calls `paint` for each pixel the sixels set, with its color; returns the
+// size the raster attributes (") declare, if any
+#[cfg(feature = "vt")]
+fn draw_sixels(sixels: &[u8], mut paint: impl FnMut(usize, usize, [u8; 3])) -> (usize, usize) {
+    let percent = |[r, g, b]: [usize; 3]| [r, g, b].map(|v| (v.min(100) * 255 / 100) as u8);
+    let mut registers = [[0; 3]; 256];
+    for (register, color) in registers.iter_mut().zip(VT340) {
+        *register = percent(color);
+    }
+    let (mut x, mut y, mut color, mut declared): (usize, usize, usize, _) = (0, 0, 0, (0, 0));
+    let mut i = 0;
+    while let Some(&byte) = sixels.get(i) {
+        i += 1;
+        let mut repeat = 1;
+        let sixel = match byte {
+            b'"' => {
+                let n = numbers(sixels, &mut i);
+                declared = (n.get(2).copied().unwrap_or(0).min(MAX_PIXELS), n.get(3).copied().unwrap_or(0).min(MAX_PIXELS));
+                continue;
+            }
+            b'#' => {
+                let n = numbers(sixels, &mut i);
+                color = n.first().copied().unwrap_or(0) % registers.len();
+                match n[..] {
+                    [_, 1, h, l, s] => registers[color] = hls(h, l, s),
+                    [_, 2, r, g, b] => registers[color] = percent([r, g, b]),
+                    _ => {}
+                }
+                continue;
+            }
+            b'!' => {
+                repeat = numbers(sixels, &mut i).first().copied().unwrap_or(1).max(1);
+                match sixels.get(i) {
+                    Some(&sixel @ 0x3f..=0x7e) => {
+                        i += 1;
+                        sixel
+                    }
+                    _ => continue,
+                }
+            }
+            b'$' => {
+                x = 0;
+                continue;
+            }
+            b'-' => {
+                x = 0;
+                y += 6;
+                continue;
+            }
+            0x3f..=0x7e => byte,
+            _ => continue,
+        };
+        for bit in (0..6).filter(|bit| (sixel - 0x3f) & (1 << bit) != 0 && y + bit < MAX_PIXELS) {
+            for px in x..x.saturating_add(repeat).min(MAX_PIXELS) {
+                paint(px, y + bit, registers[color]);
+            }
+        }
+        x = x.saturating_add(repeat);
+    }
+    declared
+}
+
+// The numbers, separated by semicolons, from `i` on
+#[cfg(feature = "vt")]
+fn numbers(text: &[u8], i: &mut usize) -> Vec<usize> {
+    let end = text[*i..].iter().position(|&b| !(b.is_ascii_digit() || b == b';')).map_or(text.len(), |n| *i + n);
+    let numbers = String::from_utf8_lossy(&text[*i..end]).split(';').map(|n| n.parse().unwrap_or(0)).collect();
+    *i = end;
+    numbers
+}
+
+// Sixel hues put blue at 0 degrees, red at 120 and green at 240; lightness
+// and saturation are percentages
+#[cfg(feature = "vt")]
+fn hls(h: usize, l: usize, s: usize) -> [u8; 3] {
+    let (h, l, s) = (((h + 240) % 360) as f64, l.min(100) as f64 / 100.0, s.min(100) as f64 / 100.0);
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    let (r, g, b) = match (h / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    [r, g, b].map(|v| ((v + l - c / 2.0) * 255.0).round() as u8)
+}
+
+// An iTerm2 image from what its OSC says, "1337;File=args:base64". Only
+// one shown inline (inline=1), and that a page can show (PNG, JPEG or
+// GIF), is placed. Its width and height are cells, pixels (px), percent of
+// the screen of `cols` by `rows` cells (%), or auto, its own size.
+#[cfg(feature = "vt")]
+pub fn iterm2(text: &str, (screen_cols, screen_rows): (usize, usize)) -> Option<Arc<Image>> {
+    let (args, data) = text.strip_prefix("1337;File=")?.split_once(':')?;
+    let arg = |name: &str| args.split(';').find_map(|arg| arg.strip_prefix(name)?.strip_prefix('='));
+    if arg("inline") != Some("1") {
+        return None;
+    }
+    let base64: String = data.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    let (mime, (width, height)) = kind(&decode_base64(&base64)?)?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let cells = |spec: Option<&str>, cell: usize, screen: usize| match spec.unwrap_or("auto") {
+        "auto" => None,
+        spec if spec.ends_with("px") => spec[..spec.len() - 2].parse().ok().map(|px: usize| px.div_ceil(cell)),
+        spec if spec.ends_with('%') => spec[..spec.len() - 1].parse().ok().map(|percent: usize| screen * percent.min(100) / 100),
+        spec => spec.parse().ok(),
+    };
+    // Given one side, the other keeps the image's shape unless told not to
+    let aspect = (height * CELL.0) as f64 / (width * CELL.1) as f64;
+    let stretch = arg("preserveAspectRatio") == Some("0");
+    let natural = (width.div_ceil(CELL.0), height.div_ceil(CELL.1));
+    let (cols, rows) = match (cells(arg("width"), CELL.0, screen_cols), cells(arg("height"), CELL.1, screen_rows)) {
+        (Some(cols), Some(rows)) => (cols, rows),
+        (Some(cols), None) if !stretch => (cols, (cols as f64 * aspect).ceil() as usize),
+        (None, Some(rows)) if !stretch => ((rows as f64 / aspect).ceil() as usize, rows),
+        (cols, rows) => (cols.unwrap_or(natural.0), rows.unwrap_or(natural.1)),
+    };
+    // Too wide for the screen, it is scaled down to fit
+    let (cols, rows) = match cols > screen_cols {
+        true => (screen_cols, rows * screen_cols / cols),
+        false => (cols, rows),
+    };
+    Some(Arc::new(Image { cols: cols.max(1), rows: rows.max(1), picture: Picture::File { mime, base64 } }))
+}
+
+// The type of an image file and its size in pixels
+#[cfg(feature = "vt")]
+fn kind(file: &[u8]) -> Option<(&'static str, (usize, usize))> {
+    let be16 = |at: usize| Some(u16::from_be_bytes([*file.get(at)?, *file.get(at + 1)?]) as usize);
+    let le16 = |at: usize| Some(u16::from_le_bytes([*file.get(at)?, *file.get(at + 1)?]) as usize);
+    let be32 = |at: usize| Some(u32::from_be_bytes(file.get(at..at + 4)?.try_into().ok()?) as usize);
+    if file.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(("image/png", (be32(16)?, be32(20)?)))
+    } else if file.starts_with(b"GIF8") {
+        Some(("image/gif", (le16(6)?, le16(8)?)))
+    } else if file.starts_with(&[0xff, 0xd8]) {
+        // The size is in the frame header, one of the SOF segments
+        let mut at = 2;
+        while file.get(at) == Some(&0xff) {
+            let marker = *file.get(at + 1)?;
+            if (0xc0..=0xcf).contains(&marker) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+                return Some(("image/jpeg", (be16(at + 7)?, be16(at + 5)?)));
+            }
+            at += 2 + be16(at + 2)?;
+        }
+        None
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "vt")]
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut bits, mut count) = (0u32, 0);
+    for &c in text.trim_end_matches('=').as_bytes() {
+        bits = (bits << 6 | value(c)? as u32) & 0xfff;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_images_found_and_stripped() {
+        let sixel = b"\x1bP0;1q#0;2;100;0;0!4~-~\x1b\\";
+        let iterm2 = b"\x1b]1337;File=inline=1:iVBORw==\x07";
+        let mut data = b"a\x1b[1mb".to_vec();
+        data.extend_from_slice(sixel);
+        data.extend_from_slice(b"\x1b]0;title\x07c");
+        data.extend_from_slice(iterm2);
+        data.extend_from_slice(b"\x1bP1$r\x1b\\d");
+
+        let mut scanner = ImageScanner::default();
+        assert_eq!(
+            scanner.feed(&data),
+            vec![Found { format: Format::Sixel, bytes: sixel.len() }, Found { format: Format::Iterm2, bytes: iterm2.len() }]
+        );
+        assert_eq!(scanner.feed(b"x"), vec![]);
+        assert_eq!(ImageScanner::default().strip(&data), b"a\x1b[1mb[image]\x1b]0;title\x07c[image]\x1bP1$r\x1b\\d");
+
+        // A byte at a time, as reads may split it
+        let mut scanner = ImageScanner::default();
+        let text: Vec<u8> = data.iter().flat_map(|byte| scanner.strip(std::slice::from_ref(byte))).collect();
+        assert_eq!(text, ImageScanner::default().strip(&data));
+        let mut scanner = ImageScanner::default();
+        let found: Vec<Found> = data.iter().flat_map(|byte| scanner.feed(std::slice::from_ref(byte))).collect();
+        assert_eq!(found, ImageScanner::default().feed(&data));
+
+        // An image cut short by another sequence, or cancelled
+        let mut scanner = ImageScanner::default();
+        assert_eq!(scanner.strip(b"\x1bPq~~\x1b[31mx\x1bPq~\x18y"), b"[image]\x1b[31mx[image]y");
+        assert_eq!(ImageScanner::default().feed(b"\x1bPq~~\x1b[31m"), vec![Found { format: Format::Sixel, bytes: 5 }]);
+        assert_eq!(Found { format: Format::Sixel, bytes: 5 }.message(), "FORMAT=sixel BYTES=5");
+    }
+
+    #[cfg(feature = "vt")]
+    #[test]
+    fn test_sixel_pixels() {
+        // Two columns of six red pixels, then one of blue in the next band
+        let image = sixel(b"0;1q#1;2;100;0;0!2~-#2;1;0;50;100@").unwrap();
+        assert_eq!((image.cols, image.rows), (1, 1));
+        let Picture::Pixels { width, height, ref rgb } = image.picture else { panic!("not pixels") };
+        assert_eq!((width, height), (2, 7));
+        assert_eq!(&rgb[..6], &[255, 0, 0, 255, 0, 0]);
+        assert_eq!(&rgb[12 * 3..12 * 3 + 6], &[0, 0, 255, 0, 0, 0]);
+
+        let declared = sixel(b"q\"1;1;25;45~").unwrap();
+        assert_eq!((declared.cols, declared.rows), (3, 3));
+        assert_eq!(sixel(b"1$r"), None);
+        assert_eq!(sixel(b"q"), None);
+    }
+
+    #[cfg(feature = "vt")]
+    #[test]
+    fn test_iterm2_sizes() {
+        // The start of a PNG of 100x40 pixels
+        let base64 = "iVBORw0KGgoAAAANSUhEUgAAAGQAAAAo".to_string();
+        let image = |args: &str| iterm2(&format!("1337;File={}:{}", args, base64), (80, 24)).map(|image| (image.cols, image.rows));
+
+        assert_eq!(image("inline=1"), Some((10, 2)));
+        assert_eq!(image("name=eA==;inline=1;width=20"), Some((20, 4)));
+        assert_eq!(image("inline=1;height=50%;preserveAspectRatio=0"), Some((10, 12)));
+        assert_eq!(image("inline=1;width=200px;height=3"), Some((20, 3)));
+        assert_eq!(image("inline=1;width=160"), Some((80, 16)));
+        assert_eq!(image("width=20"), None);
+        let picture = iterm2(&format!("1337;File=inline=1:{}", base64), (80, 24)).unwrap().picture.clone();
+        assert_eq!(picture, Picture::File { mime: "image/png", base64: base64.clone() });
+
+        assert_eq!(kind(&[0xff, 0xd8, 0xff, 0xe0, 0, 4, 0, 0, 0xff, 0xc0, 0, 17, 8, 0, 30, 0, 60]), Some(("image/jpeg", (60, 30))));
+        assert_eq!(kind(b"GIF89a\x05\x00\x07\x00"), Some(("image/gif", (5, 7))));
+        assert_eq!(decode_base64("TWE="), Some(b"Ma".to_vec()));
+        assert_eq!(decode_base64("T*"), None);
+    }
+}
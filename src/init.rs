@@ -0,0 +1,225 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::utils;
+
+// `script init`: a few questions on how to record, answered with the
+// command line that records that way, for those who would rather not read
+// through every option. Saved, the options become the [record] options of
+// the config file, which every recording then starts from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Typescript,
+    // The typescript and an advanced timing file, for replay
+    Timed,
+    Ttyrec,
+    Plain,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Answers {
+    pub log: String,
+    pub format: Format,
+    // The keys typed go into the log as well (-B)
+    pub input: bool,
+    pub compress: Option<String>,
+    pub skip_alt_screen: bool,
+    pub labels: Vec<String>,
+}
+
+// The compressions this build can write
+fn compressions() -> Vec<&'static str> {
+    let mut methods = vec!["none"];
+    if cfg!(feature = "gzip") {
+        methods.push("gzip");
+    }
+    if cfg!(feature = "zstd") {
+        methods.push("zstd");
+    }
+    methods
+}
+
+pub struct Prompt<R, W> {
+    input: R,
+    out: W,
+}
+
+impl<R: BufRead, W: Write> Prompt<R, W> {
+    pub fn new(input: R, out: W) -> Self {
+        Prompt { input, out }
+    }
+
+    // The answer, or the default for an empty one
+    fn ask(&mut self, question: &str, default: &str) -> Result<String> {
+        match default {
+            "" => write!(self.out, "{} ", question)?,
+            default => write!(self.out, "{} [{}] ", question, default)?,
+        }
+        self.out.flush()?;
+        let mut answer = String::new();
+        if self.input.read_line(&mut answer)? == 0 {
+            return Err(anyhow!("No answer to: {}", question));
+        }
+        Ok(match answer.trim() {
+            "" => default.to_string(),
+            answer => answer.to_string(),
+        })
+    }
+
+    // Asked again until the answer is one of the choices
+    fn choose(&mut self, question: &str, choices: &[&str], default: &str) -> Result<String> {
+        loop {
+            let answer = self.ask(&format!("{} ({})", question, choices.join("/")), default)?;
+            if choices.contains(&answer.as_str()) {
+                return Ok(answer);
+            }
+            writeln!(self.out, "Please answer {}", choices.join(", "))?;
+        }
+    }
+
+    fn yes(&mut self, question: &str, default: bool) -> Result<bool> {
+        Ok(self.choose(question, &["y", "n"], if default { "y" } else { "n" })? == "y")
+    }
+
+    pub fn answers(&mut self) -> Result<Answers> {
+        let log = self.ask("Log file", "typescript")?;
+        let formats = ["typescript", "timed", "ttyrec", "text"];
+        let question = "Format: the typescript alone, with timing for replay, ttyrec or plain text";
+        let format = match self.choose(question, &formats, "timed")?.as_str() {
+            "typescript" => Format::Typescript,
+            "timed" => Format::Timed,
+            "ttyrec" => Format::Ttyrec,
+            _ => Format::Plain,
+        };
+        let input = matches!(format, Format::Typescript | Format::Timed)
+            && self.yes("Record what you type too? Passwords typed at a prompt end up in the log", false)?;
+        let methods = compressions();
+        let compress = match methods.len() {
+            1 => None,
+            _ => Some(self.choose("Compress the log", &methods, "none")?).filter(|method| method != "none"),
+        };
+        let skip_alt_screen = self.yes("Leave full-screen programs (editors, pagers) out of the log?", false)?;
+
+        // Labels go in the advanced timing log
+        let labels = match format {
+            Format::Timed => self.labels()?,
+            _ => Vec::new(),
+        };
+
+        Ok(Answers { log, format, input, compress, skip_alt_screen, labels })
+    }
+
+    fn labels(&mut self) -> Result<Vec<String>> {
+        loop {
+            let answer = self.ask("Labels as KEY=VALUE, separated by spaces (e.g. ticket=OPS-12), or none:", "")?;
+            let labels = config::split_words(&answer).and_then(|words| {
+                words.iter().try_for_each(|word| utils::parse_label(word).map(|_| ()))?;
+                Ok(words)
+            });
+            match labels {
+                Ok(labels) => return Ok(labels),
+                Err(e) => writeln!(self.out, "{}", e)?,
+            }
+        }
+    }
+}
+
+// "session.log" is timed by "session.tm"
+fn timing_path(log: &str) -> String {
+    Path::new(log).with_extension("tm").display().to_string()
+}
+
+pub fn command_line(answers: &Answers) -> Vec<String> {
+    let log = answers.log.clone();
+    let stream = if answers.input { "-B" } else { "-O" }.to_string();
+    let mut options = match answers.format {
+        Format::Typescript => vec![stream, log],
+        Format::Timed => {
+            let timing = timing_path(&log);
+            vec![stream, log, "-T".into(), timing, "-m".into(), "advanced".into()]
+        }
+        Format::Ttyrec => vec!["--ttyrec".into(), log],
+        Format::Plain => vec!["--plain".into(), log],
+    };
+    if let Some(ref method) = answers.compress {
+        options.extend(["--compress".to_string(), method.clone()]);
+    }
+    if answers.skip_alt_screen {
+        options.push("--skip-alt-screen".into());
+    }
+    for label in &answers.labels {
+        options.extend(["--label".to_string(), label.clone()]);
+    }
+    options
+}
+
+fn save(path: &Path, options: &[String]) -> Result<()> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, config::with_record_options(&content, options))?;
+    Ok(())
+}
+
+pub fn run(config: Option<&Path>) -> Result<()> {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    let mut prompt = Prompt::new(stdin.lock(), stdout.lock());
+    writeln!(prompt.out, "A few questions on how to record; Enter takes the answer in brackets.\n")?;
+    let options = command_line(&prompt.answers()?);
+    writeln!(prompt.out, "\nTo record this way:\n\n    script {}\n", config::join_words(&options))?;
+
+    let path: PathBuf = config
+        .map(Path::to_path_buf)
+        .or_else(config::default_path)
+        .ok_or_else(|| anyhow!("No config file to save to ($HOME is not set)"))?;
+    if prompt.yes(&format!("Save these as the options of every recording, in {}?", path.display()), false)? {
+        save(&path, &options).with_context(|| format!("Cannot write {}", path.display()))?;
+        writeln!(prompt.out, "Saved. `script` now records this way; options given to it win over these.")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(input: &str) -> (Result<Answers>, String) {
+        let mut out = Vec::new();
+        let answers = Prompt::new(input.as_bytes(), &mut out).answers();
+        (answers, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_answers_make_a_command_line() {
+        // Compression is only asked about when the build has any
+        let compress = if compressions().len() > 1 { "\n" } else { "" };
+        let input = format!("session.log\n\nyes\ny\n{}\nticket=OPS-12 'note=db restart'\n", compress);
+        let (answers, out) = answer(&input);
+        let answers = answers.unwrap();
+        assert!(out.contains("Please answer y, n\n"));
+        assert_eq!(
+            command_line(&answers),
+            [
+                "-B", "session.log", "-T", "session.tm", "-m", "advanced", "--label", "ticket=OPS-12", "--label",
+                "note=db restart",
+            ]
+        );
+
+        let input = format!("s.ttyrec\nttyrec\n{}y\n", compress);
+        let answers = answer(&input).0.unwrap();
+        assert_eq!(command_line(&answers), ["--ttyrec", "s.ttyrec", "--skip-alt-screen"]);
+
+        // A bad label is asked for again; running out of answers is an error
+        let (answers, out) = answer(&format!("\n\n\n{}\nticket\n", compress));
+        assert!(out.contains("Invalid label: 'ticket'"));
+        assert!(answers.is_err());
+    }
+}
@@ -0,0 +1,105 @@
+//! Terminal session recording in the style of util-linux `script`.
+//!
+//! The `script` binary is a thin layer over this crate; [`SessionBuilder`]
+//! runs a recorded session from other programs.
+
+#[cfg(feature = "export")]
+mod accessible;
+mod altscreen;
+#[cfg(feature = "analyzers")]
+mod analyzer;
+mod anonymize;
+#[doc(hidden)]
+pub mod app;
+mod archive;
+#[cfg(feature = "asciicast")]
+mod asciicast;
+#[cfg(feature = "vt")]
+mod blame;
+mod childenv;
+mod cli;
+mod clock;
+mod compression;
+mod config;
+mod control;
+mod convert;
+mod encryption;
+mod exectrace;
+mod expect;
+#[cfg(feature = "export")]
+mod export;
+mod failover;
+mod feed;
+mod failure;
+mod features;
+mod filetrace;
+#[cfg(any(feature = "html", feature = "gif"))]
+mod frames;
+#[cfg(feature = "grep")]
+mod grep;
+mod hash;
+mod history;
+#[cfg(feature = "html")]
+mod html;
+mod i18n;
+mod images;
+mod init;
+mod live;
+mod logging;
+mod macros;
+mod marker;
+#[cfg(feature = "timeline")]
+mod meta;
+#[cfg(feature = "notebook")]
+mod notebook;
+mod osc133;
+#[cfg(feature = "player")]
+mod player;
+mod privileges;
+mod prompt;
+mod pty_session;
+#[cfg(feature = "gif")]
+mod raster;
+mod replay;
+mod reserve;
+#[cfg(feature = "review")]
+mod review;
+mod runbook;
+mod scheduling;
+// Public only for the serde types of the `schemas` feature
+#[cfg(feature = "schemas")]
+pub mod schema;
+#[cfg(not(feature = "schemas"))]
+mod schema;
+#[cfg(feature = "screenshot")]
+mod screenshot;
+mod script_control;
+#[cfg(feature = "review")]
+mod secrets;
+mod seekindex;
+#[cfg(feature = "selfextract")]
+mod selfextract;
+mod selftest;
+#[cfg(any(feature = "encrypt", feature = "review"))]
+mod sensitive;
+mod session;
+mod smooth;
+#[cfg(feature = "snapshot")]
+mod snapshot;
+mod stats;
+mod sudo;
+mod split;
+mod spool;
+mod syncreplay;
+#[cfg(feature = "timeline")]
+mod timeline;
+mod timing;
+mod transcript;
+mod ttyrec;
+mod typed;
+mod utils;
+mod vt;
+mod watchdog;
+
+pub use logging::{LogSink, LogStream, SessionInfo};
+pub use session::{SessionBuilder, SessionResult};
@@ -0,0 +1,220 @@
+use anyhow::{anyhow, Context, Result};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{fork, ForkResult, Pid};
+use std::io::{BufReader, Read, Write};
+use std::iter::Peekable;
+use std::os::fd::BorrowedFd;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::slice::Iter;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::config;
+use crate::i18n::{tr, Message};
+use crate::pty_session::PtySession;
+use crate::timing::{self, EntryKind, TimingEntry};
+use crate::script_control;
+use crate::utils;
+
+// Longest we sit in poll() before checking on the child again
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Pick the keystrokes out of a log, each with its offset from the start of
+// the session. When the log also holds the output (-B), output records
+// consume their bytes too.
+pub fn input_schedule<R: Read>(
+    entries: &[TimingEntry],
+    mut log: R,
+    output_in_log: bool,
+) -> Result<Vec<(Duration, Vec<u8>)>> {
+    let pending = timing::skip_header(&mut log)?;
+    let mut log = pending.as_slice().chain(log);
+
+    let mut schedule = Vec::new();
+    let mut offset = Duration::ZERO;
+    for entry in entries {
+        offset += entry.delay;
+        let (size, keep) = match entry.kind {
+            EntryKind::Input(size) => (size, true),
+            EntryKind::Output(size) if output_in_log => (size, false),
+            _ => continue,
+        };
+        let mut buf = vec![0u8; size];
+        log.read_exact(&mut buf).context("log file is shorter than its timing file")?;
+        if keep {
+            schedule.push((offset, buf));
+        }
+    }
+
+    Ok(schedule)
+}
+
+pub fn run(timing: &Path, log_in: Option<&Path>, log_io: Option<&Path>, command: Option<&str>) -> Result<i32> {
+    let log_path = log_in.or(log_io).ok_or_else(|| anyhow!("An input log (-I or -B) is required"))?;
+    let content = std::fs::read_to_string(timing)
+        .with_context(|| format!("Cannot read timing file {}", timing.display()))?;
+    let entries = timing::parse_timing(&content)?;
+    if !entries.iter().any(|e| matches!(e.kind, EntryKind::Input(_))) {
+        return Err(anyhow!(
+            "{} has no input records; record with -I or -B and an advanced timing file",
+            timing.display()
+        ));
+    }
+
+    let log = std::fs::File::open(log_path).with_context(|| format!("Cannot open {}", log_path.display()))?;
+    let schedule = input_schedule(&entries, BufReader::new(log), log_io.is_some())?;
+
+    println!("{}", tr(Message::LiveStarted, &[&config::shell().path]));
+
+    let mut pty = PtySession::new(utils::is_stdin_tty())?;
+    pty.setup()?;
+
+    let status = match unsafe { fork() }? {
+        ForkResult::Parent { child } => feed(pty.get_master_fd(), child, &schedule, Arc::new(SystemClock))?,
+        ForkResult::Child => {
+            pty.init_slave()?;
+            return script_control::exec_shell(&config::shell(), command).map(|_| 1);
+        }
+    };
+    drop(pty);
+
+    println!("\n{}", tr(Message::LiveDone, &[]));
+    Ok(status)
+}
+
+// Type the recorded input into the child at its original pace while passing
+// its output, and anything typed meanwhile, through. Returns the child's
+// exit status.
+fn feed(master_fd: RawFd, child: Pid, schedule: &[(Duration, Vec<u8>)], clock: Arc<dyn Clock>) -> Result<i32> {
+    let master = unsafe { BorrowedFd::borrow_raw(master_fd) };
+    let stdin = unsafe { BorrowedFd::borrow_raw(libc::STDIN_FILENO) };
+    let mut stdout = std::io::stdout();
+    let mut buf = [0u8; 8192];
+    let mut stdin_open = true;
+
+    let start = clock.now();
+    let mut next = schedule.iter().peekable();
+
+    loop {
+        let (keys, wait) = due(&mut next, start, clock.now());
+        for data in keys {
+            write_all(master_fd, data)?;
+        }
+
+        let mut fds = vec![PollFd::new(&master, PollFlags::POLLIN)];
+        if stdin_open {
+            fds.push(PollFd::new(&stdin, PollFlags::POLLIN));
+        }
+        match poll(&mut fds, wait.as_millis().max(1) as i32) {
+            Ok(_) => {}
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        }
+        let ready = |fd: &PollFd| fd.revents().is_some_and(|r| !r.is_empty());
+        let (master_ready, stdin_ready) = (ready(&fds[0]), fds.get(1).is_some_and(ready));
+
+        if master_ready {
+            if let Ok(n @ 1..) = nix::unistd::read(master_fd, &mut buf) {
+                stdout.write_all(&buf[..n])?;
+                stdout.flush()?;
+            }
+        }
+        if stdin_ready {
+            match nix::unistd::read(libc::STDIN_FILENO, &mut buf) {
+                Ok(0) | Err(_) => stdin_open = false,
+                Ok(n) => write_all(master_fd, &buf[..n])?,
+            }
+        }
+
+        let status = match waitpid(child, Some(WaitPidFlag::WNOHANG))? {
+            WaitStatus::StillAlive => continue,
+            WaitStatus::Exited(_, code) => code,
+            WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+            _ => 1,
+        };
+
+        // Pick up whatever the child wrote last
+        let flags = nix::fcntl::fcntl(master_fd, nix::fcntl::FcntlArg::F_GETFL)?;
+        nix::fcntl::fcntl(master_fd, nix::fcntl::FcntlArg::F_SETFL(nix::fcntl::OFlag::from_bits_truncate(flags) | nix::fcntl::OFlag::O_NONBLOCK))?;
+        while let Ok(n @ 1..) = nix::unistd::read(master_fd, &mut buf) {
+            stdout.write_all(&buf[..n])?;
+        }
+        stdout.flush()?;
+        return Ok(status);
+    }
+}
+
+// The keystrokes due by `now`, and how long to wait for the next ones
+fn due<'a>(
+    next: &mut Peekable<Iter<'a, (Duration, Vec<u8>)>>,
+    start: Instant,
+    now: Instant,
+) -> (Vec<&'a [u8]>, Duration) {
+    let mut keys = Vec::new();
+    while let Some((_, data)) = next.next_if(|(offset, _)| start + *offset <= now) {
+        keys.push(data.as_slice());
+    }
+    let wait = next
+        .peek()
+        .map(|(offset, _)| (start + *offset).saturating_duration_since(now))
+        .unwrap_or(POLL_INTERVAL)
+        .min(POLL_INTERVAL);
+    (keys, wait)
+}
+
+fn write_all(fd: RawFd, mut data: &[u8]) -> Result<()> {
+    while !data.is_empty() {
+        match nix::unistd::write(fd, data) {
+            Ok(n) => data = &data[n..],
+            Err(nix::errno::Errno::EINTR) => {}
+            Err(e) => return Err(anyhow!("Error writing to master PTY: {}", e)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use chrono::DateTime;
+
+    #[test]
+    fn test_input_schedule() {
+        let entries = timing::parse_timing("O 0.1 2\nI 0.5 3\nS 0.2 SIGWINCH ROWS=24 COLS=80\nO 0.1 4\nI 0.25 5\n").unwrap();
+        let log = &b"Script started on x\n$ ls\rls\r\nexit\r"[..];
+
+        let schedule = input_schedule(&entries, log, true).unwrap();
+        assert_eq!(
+            schedule,
+            vec![(Duration::from_millis(600), b"ls\r".to_vec()), (Duration::from_millis(1150), b"exit\r".to_vec())]
+        );
+
+        // An input-only log holds just the keystrokes
+        let schedule = input_schedule(&entries, &b"ls\rexit\r"[..], false).unwrap();
+        assert_eq!(schedule[1].1, b"exit\r");
+        assert!(input_schedule(&entries, &b"ls\r"[..], false).is_err());
+    }
+
+    #[test]
+    fn test_keys_come_due_on_the_clock() {
+        let clock = ManualClock::new(DateTime::parse_from_rfc3339("2024-01-02T03:04:05+00:00").unwrap());
+        let schedule = [
+            (Duration::from_millis(30), b"ls\r".to_vec()),
+            (Duration::from_millis(130), b"exit\r".to_vec()),
+        ];
+        let mut next = schedule.iter().peekable();
+        let start = clock.now();
+
+        assert_eq!(due(&mut next, start, clock.now()), (vec![], Duration::from_millis(30)));
+        clock.advance(Duration::from_millis(40));
+        assert_eq!(due(&mut next, start, clock.now()), (vec![&b"ls\r"[..]], POLL_INTERVAL));
+        clock.advance(Duration::from_millis(60));
+        assert_eq!(due(&mut next, start, clock.now()), (vec![], Duration::from_millis(30)));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(due(&mut next, start, clock.now()), (vec![&b"exit\r"[..]], POLL_INTERVAL));
+    }
+}
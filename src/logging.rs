@@ -1,37 +1,311 @@
-use anyhow::{anyhow, Result};
-use chrono::Local;
+use anyhow::{anyhow, Context, Result};
 use std::fs::OpenOptions;
-use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender};
 use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
 
+use crate::clock::Clock;
+use crate::compression::{Compression, FileWriter};
+use crate::encryption::Recipients;
+use crate::exectrace::Exec;
+use crate::failover::{self, StorageFailover};
+use crate::seekindex::{self, SeekIndex};
+use crate::ttyrec;
+use crate::vt::Lines;
+
+// Same layout util-linux uses for session start/stop stamps
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%:z";
+
+// --wall-clock: RFC 3339 to the microsecond, as syslog and most application
+// logs can be matched against
+pub const WALL_CLOCK_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6f%:z";
+
+pub const DEFAULT_BUFFER_SIZE: usize = 8192;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LogFormat {
     Raw,
     TimingSimple,
     TimingMulti,
+    TtyRec,
+    // The output as the lines it reads as, without escape sequences
+    Plain,
 }
 
 #[derive(Debug, Clone)]
 pub enum LogStream {
     Input,
     Output,
+    // The child's stderr, kept apart from the terminal with --log-err
+    Stderr,
+}
+
+// What a log is told about the session when it starts
+#[derive(Debug, Clone, Default)]
+pub struct SessionInfo {
+    pub is_term: bool,
+    pub tty_type: Option<String>,
+    pub tty_name: Option<String>,
+    pub cols: u16,
+    pub lines: u16,
+    pub command: Option<String>,
+    // Why the terminal was left in cooked mode, when it was
+    pub degraded: Option<String>,
+}
+
+// Somewhere a session is recorded to. The logs named on the command line are
+// ScriptLoggers; more can be added with ScriptControl::add_sink.
+pub trait LogSink: Send {
+    fn start(&mut self, session: &SessionInfo) -> Result<()>;
+
+    // Returns the number of bytes written, which counts toward -o
+    fn log_data(&mut self, stream: LogStream, data: &[u8]) -> Result<usize>;
+
+    // Signals and the recorder's own events
+    fn log_event(&mut self, name: &str, message: Option<&str>) -> Result<()>;
+
+    fn close(&mut self, exit_status: i32) -> Result<()>;
+
+    // On SIGHUP: go on in a new file at the same path if the old one was
+    // moved away, as logrotate does before signalling
+    fn reopen(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    // A move to the --failover-dir not yet recorded, reported once
+    fn take_failover(&mut self) -> Option<StorageFailover> {
+        None
+    }
+}
+
+// Device and inode, to tell whether a path still names the file written to
+pub(crate) fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).ok().map(|meta| (meta.dev(), meta.ino()))
+}
+
+// How many chunks may wait for a log's writer thread before logging waits
+// for the disk to catch up
+const WRITE_QUEUE: usize = 1024;
+
+// Bytes queued for all the log writer threads together, which
+// --self-limit mem bounds
+static QUEUED: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn queued_bytes() -> usize {
+    QUEUED.load(Ordering::Relaxed)
+}
+
+// Writes a log file on a thread of its own, so a slow disk doesn't stall the
+// session. The file is flushed whenever the queue runs empty. Once a write
+// fails the thread stops writing but keeps taking chunks until the queue is
+// dropped, so nothing sent is lost: what the file may not have got is handed
+// back by `take_unwritten`.
+pub(crate) struct BackgroundWriter {
+    queue: Option<SyncSender<Vec<u8>>>,
+    failed: Arc<AtomicBool>,
+    thread: Option<JoinHandle<(std::io::Result<()>, Unwritten)>>,
+    unwritten: Unwritten,
+    // Until the thread starts, the file and what was sent for it
+    held: Option<(FileWriter, Vec<Vec<u8>>)>,
+}
+
+// The chunks since the last good flush and those queued after the failure,
+// oldest first, and whether anything reached the file before them
+#[derive(Default)]
+pub(crate) struct Unwritten {
+    pub(crate) chunks: Vec<Vec<u8>>,
+    pub(crate) started: bool,
 }
 
+// Set while the recorder holds privileges --drop-privs is about to give up.
+// Capabilities are per thread, so writers made meanwhile start their threads
+// only once it is cleared.
+static HELD: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn hold_writers() {
+    HELD.store(true, Ordering::Relaxed);
+}
+
+pub(crate) fn release_writers() {
+    HELD.store(false, Ordering::Relaxed);
+}
+
+impl BackgroundWriter {
+    pub(crate) fn spawn(writer: FileWriter) -> Self {
+        let mut background = BackgroundWriter {
+            queue: None,
+            failed: Arc::new(AtomicBool::new(false)),
+            thread: None,
+            unwritten: Unwritten::default(),
+            held: Some((writer, Vec::new())),
+        };
+        if !HELD.load(Ordering::Relaxed) {
+            background.launch();
+        }
+        background
+    }
+
+    // Start the thread, which gets what was sent before first
+    fn launch(&mut self) {
+        let Some((writer, held)) = self.held.take() else {
+            return;
+        };
+        let (queue, chunks) = sync_channel::<Vec<u8>>(WRITE_QUEUE);
+        let stopped = self.failed.clone();
+        self.thread = Some(std::thread::spawn(move || write_out(writer, chunks, &stopped)));
+        for chunk in held {
+            // Counted in QUEUED when it was sent
+            let _ = queue.send(chunk);
+        }
+        self.queue = Some(queue);
+    }
+
+    // Fails, handing the chunk back, once the thread has stopped on a write
+    // error
+    pub(crate) fn send(&mut self, chunk: Vec<u8>) -> std::result::Result<(), Vec<u8>> {
+        if !HELD.load(Ordering::Relaxed) {
+            self.launch();
+        }
+        if self.failed.load(Ordering::Relaxed) {
+            return Err(chunk);
+        }
+        let size = chunk.len();
+        QUEUED.fetch_add(size, Ordering::Relaxed);
+        if let Some((_, ref mut held)) = self.held {
+            held.push(chunk);
+            return Ok(());
+        }
+        let sent = match self.queue {
+            Some(ref queue) => queue.send(chunk).map_err(|e| e.0),
+            None => Err(chunk),
+        };
+        if sent.is_err() {
+            QUEUED.fetch_sub(size, Ordering::Relaxed);
+        }
+        sent
+    }
+
+    // Write out everything queued and stop the thread
+    pub(crate) fn finish(&mut self) -> std::io::Result<()> {
+        // Still held, as when the session fails before privileges are
+        // dropped: written out here rather than on a thread of its own
+        if let Some((writer, held)) = self.held.take() {
+            let (queue, chunks) = channel();
+            for chunk in held {
+                let _ = queue.send(chunk);
+            }
+            drop(queue);
+            let (result, unwritten) = write_out(writer, chunks, &self.failed);
+            self.unwritten = unwritten;
+            return result;
+        }
+        self.queue.take();
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+        let (result, unwritten) = thread
+            .join()
+            .unwrap_or_else(|_| (Err(std::io::Error::other("log writer panicked")), Unwritten::default()));
+        self.unwritten = unwritten;
+        result
+    }
+
+    // What a failed thread left, after `finish`
+    pub(crate) fn take_unwritten(&mut self) -> Unwritten {
+        std::mem::take(&mut self.unwritten)
+    }
+}
+
+impl Drop for BackgroundWriter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+// What the writer thread runs, or `finish` in place for a writer still held
+fn write_out(
+    mut writer: FileWriter,
+    chunks: Receiver<Vec<u8>>,
+    stopped: &AtomicBool,
+) -> (std::io::Result<()>, Unwritten) {
+    let mut unwritten = Unwritten::default();
+    let mut error = None;
+    while let Ok(chunk) = chunks.recv() {
+        let mut next = Some(chunk);
+        while let Some(chunk) = next {
+            QUEUED.fetch_sub(chunk.len(), Ordering::Relaxed);
+            if error.is_none() {
+                error = writer.write_all(&chunk).err();
+            }
+            unwritten.chunks.push(chunk);
+            next = chunks.try_recv().ok();
+        }
+        if error.is_none() {
+            error = writer.flush().err();
+        }
+        match error {
+            Some(_) => stopped.store(true, Ordering::Relaxed),
+            None => {
+                unwritten.started = true;
+                unwritten.chunks.clear();
+            }
+        }
+    }
+    match error {
+        Some(e) => (Err(e), unwritten),
+        None => (writer.finish(), Unwritten::default()),
+    }
+}
+
+// Where a log is with --rotate-size: the part being written (0 is the log's
+// own path) and how much went into it
+#[derive(Default)]
+struct Rotation {
+    part: u32,
+    written: u64,
+    session: SessionInfo,
+}
+
+// A log file in one of the formats. Clones write to the same file, so one
+// log can take both streams.
 #[derive(Clone)]
 pub struct ScriptLogger {
     path: PathBuf,
     format: LogFormat,
     append: bool,
-    writer: Arc<Mutex<Option<BufWriter<std::fs::File>>>>,
+    writer: Arc<Mutex<Option<BackgroundWriter>>>,
     start_time: Arc<Mutex<Option<Instant>>>,
     last_time: Arc<Mutex<Option<Instant>>>,
     initialized: Arc<Mutex<bool>>,
+    clock: Arc<dyn Clock>,
+    buffer_size: usize,
+    compression: Compression,
+    encryption: Option<Recipients>,
+    rotate_size: Option<u64>,
+    rotation: Arc<Mutex<Rotation>>,
+    file_id: Arc<Mutex<Option<(u64, u64)>>>,
+    // --failover-dir, and once the log has moved there, its path in it and
+    // the move as yet unreported
+    failover_dir: Option<PathBuf>,
+    failed_over: Arc<Mutex<Option<PathBuf>>>,
+    failover: Arc<Mutex<Option<StorageFailover>>>,
+    // Plain only: the output put back together into lines
+    lines: Arc<Mutex<Lines>>,
+    // Raw only, with --seek-index: how often to add a point, and the index
+    // as it is written
+    index_every: Option<Duration>,
+    index: Arc<Mutex<Option<(SeekIndex, BackgroundWriter)>>>,
+    // Advanced timing only, with --wall-clock: each record also stamped
+    // with the time of day
+    timestamps: bool,
 }
 
 impl ScriptLogger {
-    pub fn new(path: PathBuf, format: LogFormat, append: bool) -> Result<Self> {
+    pub fn new(path: PathBuf, format: LogFormat, append: bool, clock: Arc<dyn Clock>) -> Result<Self> {
         Ok(ScriptLogger {
             path,
             format,
@@ -40,190 +314,733 @@ impl ScriptLogger {
             start_time: Arc::new(Mutex::new(None)),
             last_time: Arc::new(Mutex::new(None)),
             initialized: Arc::new(Mutex::new(false)),
+            clock,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            compression: Compression::None,
+            encryption: None,
+            rotate_size: None,
+            rotation: Arc::new(Mutex::new(Rotation::default())),
+            file_id: Arc::new(Mutex::new(None)),
+            failover_dir: None,
+            failed_over: Arc::new(Mutex::new(None)),
+            failover: Arc::new(Mutex::new(None)),
+            lines: Arc::new(Mutex::new(Lines::default())),
+            index_every: None,
+            index: Arc::new(Mutex::new(None)),
+            timestamps: false,
         })
     }
 
-    pub async fn start_with_data(
-        &mut self, 
-        is_term: bool,
-        tty_type: &Option<String>,
-        tty_name: &Option<String>,
-        tty_cols: u16,
-        tty_lines: u16,
-        command_norm: &Option<String>
-    ) -> Result<()> {
+    // Capacity of the file writer, applied when the log is opened
+    pub fn with_buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size;
+        self
+    }
+
+    // Appending to a compressed log adds a gzip member, which zcat reads on
+    // as one stream
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    // Appending to an encrypted log would add a second age file, which
+    // readers never reach, so --encrypt refuses -a
+    pub fn with_encryption(mut self, recipients: Option<Recipients>) -> Self {
+        self.encryption = recipients;
+        self
+    }
+
+    // Only logs of the session's bytes rotate; a timing file stays whole, as
+    // it describes them all
+    pub fn with_rotate_size(mut self, size: Option<u64>) -> Self {
+        self.rotate_size = size.filter(|_| self.appendable());
+        self
+    }
+
+    pub fn with_failover_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.failover_dir = dir;
+        self
+    }
+
+    // The offsets only hold in a log of the session's bytes as they are
+    pub fn with_seek_index(mut self, every: Option<Duration>) -> Self {
+        self.index_every = every.filter(|_| self.format == LogFormat::Raw);
+        self
+    }
+
+    pub fn with_timestamps(mut self, on: bool) -> Self {
+        self.timestamps = on && self.format == LogFormat::TimingMulti;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The log's path, or where it went on after a failover
+    fn base_path(&self) -> PathBuf {
+        self.failed_over.lock().unwrap().clone().unwrap_or_else(|| self.path.clone())
+    }
+
+    // The file being written: the log's path, then <path>.1, <path>.2, ...
+    fn current_path(&self) -> PathBuf {
+        match self.rotation.lock().unwrap().part {
+            0 => self.base_path(),
+            part => part_path(&self.base_path(), part),
+        }
+    }
+
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    // Timing files restart with every session, like util-linux does
+    fn appendable(&self) -> bool {
+        matches!(self.format, LogFormat::Raw | LogFormat::TtyRec | LogFormat::Plain)
+    }
+
+    // Logs that start and end with the "Script started/done" lines
+    fn has_header(&self) -> bool {
+        matches!(self.format, LogFormat::Raw | LogFormat::Plain)
+    }
+
+    // Queue a chunk for the writer thread
+    fn write(&self, chunk: Vec<u8>) -> Result<()> {
+        let mut writer_guard = self.writer.lock().unwrap();
+        let writer = writer_guard.as_mut().ok_or_else(|| anyhow!("Logger not initialized"))?;
+        let chunk = match writer.send(chunk) {
+            Ok(()) => return Ok(()),
+            Err(chunk) => chunk,
+        };
+
+        // The thread has stopped; its error is the one to report, unless the
+        // disk gave out and the log can go on elsewhere
+        let result = writer.finish();
+        let unwritten = writer.take_unwritten();
+        writer_guard.take();
+        drop(writer_guard);
+        if let Some(error) = result.as_ref().err().and_then(failover::storage_error) {
+            if self.fail_over(error, unwritten)? {
+                return self.write(chunk);
+            }
+        }
+        result
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Err(anyhow!("Log writer stopped")))
+            .with_context(|| format!("Cannot write {}", self.current_path().display()))
+    }
+
+    // Go on in the --failover-dir, the first time the disk fails; the new
+    // file starts like a log of its own, then gets what the old one may have
+    // missed. When nothing reached the old file that includes its header.
+    fn fail_over(&self, error: &'static str, unwritten: Unwritten) -> Result<bool> {
+        let Some(ref dir) = self.failover_dir else {
+            return Ok(false);
+        };
+        if self.failed_over.lock().unwrap().is_some() {
+            return Ok(false);
+        }
+        let from = self.current_path();
+        *self.failed_over.lock().unwrap() = Some(failover::fallback_path(dir, &self.path));
+        // The offsets were into the old file
+        self.index.lock().unwrap().take();
+        let to = self.current_path();
+        self.open(&to, false).with_context(|| format!("Cannot fail over to {}", to.display()))?;
+        if self.has_header() && unwritten.started {
+            let session = self.rotation.lock().unwrap().session.clone();
+            self.write(self.raw_header(&session).into_bytes())?;
+        }
+        for chunk in unwritten.chunks {
+            self.write(chunk)?;
+        }
+        *self.failover.lock().unwrap() = Some(StorageFailover { from, to, error });
+        Ok(true)
+    }
+
+    // Hand the file at `path` to a new writer thread
+    fn open(&self, path: &Path, append: bool) -> Result<()> {
+        let mut options = OpenOptions::new();
+        options.create(true).write(true).append(append).truncate(!append);
+        let writer = FileWriter::open(&options, path, self.buffer_size, self.compression, self.encryption.as_ref())?;
+        *self.writer.lock().unwrap() = Some(BackgroundWriter::spawn(writer));
+        *self.file_id.lock().unwrap() = file_id(path);
+        Ok(())
+    }
+
+    fn raw_header(&self, session: &SessionInfo) -> String {
+        let now = self.clock.wall();
+        let mut fields = Vec::new();
+
+        if let Some(ref command) = session.command {
+            fields.push(format!("COMMAND=\"{}\"", command));
+        }
+
+        if session.is_term {
+            if let Some(ref tty_type) = session.tty_type {
+                fields.push(format!("TERM=\"{}\"", tty_type));
+            }
+            if let Some(ref tty_name) = session.tty_name {
+                fields.push(format!("TTY=\"{}\"", tty_name));
+            }
+            fields.push(format!("COLUMNS=\"{}\" LINES=\"{}\"", session.cols, session.lines));
+        } else {
+            fields.push("<not executed on terminal>".to_string());
+        }
+
+        format!("Script started on {} [{}]\n", now.format(TIMESTAMP_FORMAT), fields.join(" "))
+    }
+
+    // Count a chunk toward --rotate-size and move on to the next part once
+    // the current one is full
+    fn account(&self, size: usize) -> Result<()> {
+        let Some(limit) = self.rotate_size else {
+            return Ok(());
+        };
+        let (part, session) = {
+            let mut rotation = self.rotation.lock().unwrap();
+            rotation.written += size as u64;
+            if rotation.written < limit {
+                return Ok(());
+            }
+            rotation.written = 0;
+            (rotation.part + 1, rotation.session.clone())
+        };
+        let next = part_path(&self.base_path(), part);
+
+        // Each part is a log of its own, ended like a finished session
+        if self.has_header() {
+            let footer = format!(
+                "\nScript done on {} [NEXT_FILE=\"{}\"]\n",
+                self.clock.wall().format(TIMESTAMP_FORMAT),
+                next.display()
+            );
+            self.write(footer.into_bytes())?;
+        }
+        let writer = self.writer.lock().unwrap().take();
+        if let Some(mut writer) = writer {
+            writer.finish().with_context(|| format!("Cannot write {}", self.current_path().display()))?;
+        }
+
+        self.rotation.lock().unwrap().part = part;
+        self.open(&next, false)?;
+        if self.has_header() {
+            let header = self.raw_header(&session);
+            self.write(header.into_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn elapsed(&self) -> Duration {
+        let now = self.clock.now();
+        let mut last_time = self.last_time.lock().unwrap();
+        let delta = last_time.map_or(Duration::ZERO, |last| now.duration_since(last));
+        *last_time = Some(now);
+        delta
+    }
+
+    // " @2024-01-02T03:04:05.250000+01:00" at the end of a record, or nothing
+    fn stamp(&self) -> String {
+        if !self.timestamps {
+            return String::new();
+        }
+        format!(" @{}", self.clock.wall().format(WALL_CLOCK_FORMAT))
+    }
+
+    pub fn log_info(&mut self, name: &str, value: &str) -> Result<()> {
+        if self.format != LogFormat::TimingMulti {
+            return Ok(());
+        }
+
+        self.write(format!("H 0.000000 {} {}\n", name, value).into_bytes())
+    }
+
+    pub fn log_exec(&mut self, exec: &Exec) -> Result<()> {
+        if self.format != LogFormat::TimingMulti {
+            return Ok(());
+        }
+
+        let delta = self.elapsed().as_secs_f64();
+        self.write(format!("X {:.6} {}{}\n", delta, exec.fields(), self.stamp()).into_bytes())
+    }
+}
+
+impl LogSink for ScriptLogger {
+    fn start(&mut self, session: &SessionInfo) -> Result<()> {
         let mut initialized = self.initialized.lock().unwrap();
         if *initialized {
             return Ok(());
         }
 
         // Open the file
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .append(self.append && self.format == LogFormat::Raw)
-            .truncate(!self.append || self.format != LogFormat::Raw)
-            .open(&self.path)?;
-
-        let mut writer = BufWriter::new(file);
+        let append = self.append && self.appendable();
+        let existing = if append { std::fs::metadata(&self.path).map_or(0, |meta| meta.len()) } else { 0 };
+        self.open(&self.path, append)?;
+        *initialized = true;
+        self.rotation.lock().unwrap().session = session.clone();
 
         // Write header based on format
         match self.format {
-            LogFormat::Raw => {
-                let now = Local::now();
-                writeln!(writer, "Script started on {} [", now.format("%Y-%m-%d %H:%M:%S %z"))?;
-
-                if let Some(ref command) = command_norm {
-                    write!(writer, "COMMAND=\"{}\"", command)?;
-                }
-
-                if is_term {
-                    if let Some(ref tty_type) = tty_type {
-                        write!(writer, " TERM=\"{}\"", tty_type)?;
-                    }
-                    if let Some(ref tty_name) = tty_name {
-                        write!(writer, " TTY=\"{}\"", tty_name)?;
-                    }
-                    write!(writer, " COLUMNS=\"{}\" LINES=\"{}\"", tty_cols, tty_lines)?;
-                } else {
-                    write!(writer, " <not executed on terminal>")?;
+            LogFormat::Raw | LogFormat::Plain => {
+                let header = self.raw_header(session);
+                if let Some(every) = self.index_every {
+                    let path = seekindex::index_path(&self.path);
+                    let mut options = OpenOptions::new();
+                    options.create(true).write(true).truncate(true);
+                    let writer = FileWriter::open(&options, &path, self.buffer_size, Compression::None, None)?;
+                    let index = SeekIndex::new(every, existing + header.len() as u64);
+                    *self.index.lock().unwrap() = Some((index, BackgroundWriter::spawn(writer)));
+                    *self.start_time.lock().unwrap() = Some(self.clock.now());
                 }
-
-                writeln!(writer, "]")?;
+                self.write(header.into_bytes())?;
+            }
+            LogFormat::TtyRec => {
+                // Frames are self-contained, there is no header
             }
             LogFormat::TimingSimple | LogFormat::TimingMulti => {
                 // Initialize timing
-                let now = Instant::now();
+                let now = self.clock.now();
                 *self.start_time.lock().unwrap() = Some(now);
                 *self.last_time.lock().unwrap() = Some(now);
+                // Readers strip the stamps only after this
+                if self.timestamps {
+                    self.write(b"H 0.000000 TIMESTAMPS wall-clock\n".to_vec())?;
+                }
             }
         }
 
-        *self.writer.lock().unwrap() = Some(writer);
-        *initialized = true;
-
         Ok(())
     }
 
-    pub async fn log_data(&mut self, stream: LogStream, data: &[u8]) -> Result<usize> {
-        let mut writer_guard = self.writer.lock().unwrap();
-        let writer = writer_guard.as_mut().ok_or_else(|| anyhow!("Logger not initialized"))?;
-
-        match self.format {
-            LogFormat::Raw => {
-                writer.write_all(data)?;
-                writer.flush()?;
-                Ok(data.len())
+    // Only queues the record; the writer thread puts it on disk
+    fn log_data(&mut self, stream: LogStream, data: &[u8]) -> Result<usize> {
+        let chunk = match self.format {
+            LogFormat::Raw => data.to_vec(),
+            // Typed input shows in the output already, as it was echoed
+            LogFormat::Plain => match stream {
+                LogStream::Input | LogStream::Stderr => return Ok(0),
+                LogStream::Output => {
+                    let lines = self.lines.lock().unwrap().feed(data);
+                    if lines.is_empty() {
+                        return Ok(0);
+                    }
+                    lines.iter().map(|line| format!("{}\n", line)).collect::<String>().into_bytes()
+                }
+            },
+            LogFormat::TtyRec => {
+                // Frames carry absolute wall-clock time
+                let mut frame = ttyrec::frame_header(self.clock.wall(), data.len()).to_vec();
+                frame.extend_from_slice(data);
+                frame
             }
             LogFormat::TimingSimple => {
-                let now = Instant::now();
-                let mut last_time = self.last_time.lock().unwrap();
-                let delta = if let Some(last) = *last_time {
-                    now.duration_since(last)
-                } else {
-                    Duration::from_secs(0)
-                };
-
-                writeln!(writer, "{:.6} {}", 
-                    delta.as_secs_f64(), 
-                    data.len())?;
-                writer.flush()?;
-
-                *last_time = Some(now);
-                Ok(format!("{:.6} {}\n", delta.as_secs_f64(), data.len()).len())
+                format!("{:.6} {}\n", self.elapsed().as_secs_f64(), data.len()).into_bytes()
             }
             LogFormat::TimingMulti => {
-                let now = Instant::now();
-                let mut last_time = self.last_time.lock().unwrap();
-                let delta = if let Some(last) = *last_time {
-                    now.duration_since(last)
-                } else {
-                    Duration::from_secs(0)
-                };
-
                 let stream_char = match stream {
                     LogStream::Input => 'I',
                     LogStream::Output => 'O',
+                    LogStream::Stderr => 'E',
                 };
+                let delta = self.elapsed().as_secs_f64();
+                format!("{} {:.6} {}{}\n", stream_char, delta, data.len(), self.stamp()).into_bytes()
+            }
+        };
 
-                writeln!(writer, "{} {:.6} {}", 
-                    stream_char,
-                    delta.as_secs_f64(), 
-                    data.len())?;
-                writer.flush()?;
-
-                *last_time = Some(now);
-                Ok(format!("{} {:.6} {}\n", stream_char, delta.as_secs_f64(), data.len()).len())
+        let size = chunk.len();
+        if let Some((ref mut index, ref mut writer)) = *self.index.lock().unwrap() {
+            let start = *self.start_time.lock().unwrap();
+            let elapsed = start.map_or(Duration::ZERO, |start| self.clock.now().duration_since(start));
+            // A lost point only makes seeking to it slower
+            if let Some(line) = index.record(elapsed, size) {
+                let _ = writer.send(line.into_bytes());
             }
         }
+        self.write(chunk)?;
+        self.account(size)?;
+        Ok(size)
     }
 
-    pub async fn log_signal(&mut self, signal_name: &str, message: Option<&str>) -> Result<()> {
+    fn log_event(&mut self, name: &str, message: Option<&str>) -> Result<()> {
         if self.format != LogFormat::TimingMulti {
             return Ok(());
         }
 
-        let mut writer_guard = self.writer.lock().unwrap();
-        let writer = writer_guard.as_mut().ok_or_else(|| anyhow!("Logger not initialized"))?;
-
-        let now = Instant::now();
-        let mut last_time = self.last_time.lock().unwrap();
-        let delta = if let Some(last) = *last_time {
-            now.duration_since(last)
-        } else {
-            Duration::from_secs(0)
+        let delta = self.elapsed().as_secs_f64();
+        let record = match message {
+            Some(msg) => format!("S {:.6} {} {}{}\n", delta, name, msg, self.stamp()),
+            None => format!("S {:.6} {}{}\n", delta, name, self.stamp()),
         };
+        self.write(record.into_bytes())
+    }
 
-        if let Some(msg) = message {
-            writeln!(writer, "S {:.6} {} {}", delta.as_secs_f64(), signal_name, msg)?;
-        } else {
-            writeln!(writer, "S {:.6} {}", delta.as_secs_f64(), signal_name)?;
+    // Waits until everything logged is on disk
+    fn close(&mut self, exit_status: i32) -> Result<()> {
+        if self.writer.lock().unwrap().is_none() {
+            return Ok(());
         }
-        writer.flush()?;
 
-        *last_time = Some(now);
+        match self.format {
+            LogFormat::Raw => {
+                let now = self.clock.wall();
+                let footer = format!(
+                    "\nScript done on {} [COMMAND_EXIT_CODE=\"{}\"]\n",
+                    now.format(TIMESTAMP_FORMAT),
+                    exit_status
+                );
+                self.write(footer.into_bytes())?;
+            }
+            LogFormat::Plain => {
+                let mut footer = self.lines.lock().unwrap().take();
+                if !footer.is_empty() {
+                    footer.push('\n');
+                }
+                footer.push_str(&format!(
+                    "Script done on {} [COMMAND_EXIT_CODE=\"{}\"]\n",
+                    self.clock.wall().format(TIMESTAMP_FORMAT),
+                    exit_status
+                ));
+                self.write(footer.into_bytes())?;
+            }
+            LogFormat::TimingMulti => {
+                let now = self.clock.now();
+                let start_time = *self.start_time.lock().unwrap();
+                if let Some(start) = start_time {
+                    let duration = now.duration_since(start);
+                    let footer = format!(
+                        "H 0.000000 DURATION {:.6}\nH 0.000000 EXIT_CODE {}\n",
+                        duration.as_secs_f64(),
+                        exit_status
+                    );
+                    self.write(footer.into_bytes())?;
+                }
+            }
+            LogFormat::TimingSimple | LogFormat::TtyRec => {
+                // Nothing to add at the end of these formats
+            }
+        }
+
+        let writer = self.writer.lock().unwrap().take();
+        if let Some(mut writer) = writer {
+            writer.finish().with_context(|| format!("Cannot write {}", self.current_path().display()))?;
+        }
+        let index = self.index.lock().unwrap().take();
+        if let Some((_, mut writer)) = index {
+            writer.finish().with_context(|| format!("Cannot write {}", seekindex::index_path(&self.path).display()))?;
+        }
         Ok(())
     }
 
-    pub async fn log_info(&mut self, name: &str, value: &str) -> Result<()> {
-        if self.format != LogFormat::TimingMulti {
+    // Clones share the file, so the second of a log's two sinks finds it
+    // reopened already
+    fn reopen(&mut self) -> Result<()> {
+        let path = self.current_path();
+        if self.writer.lock().unwrap().is_none() || file_id(&path) == *self.file_id.lock().unwrap() {
             return Ok(());
         }
+        let writer = self.writer.lock().unwrap().take();
+        if let Some(mut writer) = writer {
+            writer.finish().with_context(|| format!("Cannot write {}", path.display()))?;
+        }
+        self.open(&path, true)
+    }
 
-        let mut writer_guard = self.writer.lock().unwrap();
-        let writer = writer_guard.as_mut().ok_or_else(|| anyhow!("Logger not initialized"))?;
+    fn take_failover(&mut self) -> Option<StorageFailover> {
+        self.failover.lock().unwrap().take()
+    }
+}
 
-        writeln!(writer, "H 0.0 {} {}", name, value)?;
-        writer.flush()?;
+// The metadata sidecar of a log, for --analyzer and --meta
+#[cfg(any(feature = "analyzers", feature = "timeline"))]
+pub(crate) fn sidecar_path(log: &Path) -> PathBuf {
+    let mut name = log.as_os_str().to_owned();
+    name.push(".meta.json");
+    PathBuf::from(name)
+}
 
-        Ok(())
+// typescript -> typescript.1
+fn part_path(path: &Path, part: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", part));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::timing::{parse_timing, EntryKind};
+    use chrono::DateTime;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rust_script-{}-{}", std::process::id(), name))
     }
 
-    pub async fn close(&mut self, exit_status: i32) -> Result<()> {
-        let mut writer_guard = self.writer.lock().unwrap();
-        if let Some(mut writer) = writer_guard.take() {
-            match self.format {
-                LogFormat::Raw => {
-                    let now = Local::now();
-                    writeln!(writer, "\nScript done on {} [COMMAND_EXIT_CODE=\"{}\"]", 
-                        now.format("%Y-%m-%d %H:%M:%S %z"), 
-                        exit_status)?;
-                }
-                LogFormat::TimingMulti => {
-                    let now = Instant::now();
-                    let start_time = self.start_time.lock().unwrap();
-                    if let Some(start) = *start_time {
-                        let duration = now.duration_since(start);
-                        writeln!(writer, "H 0.0 DURATION {:.6}", duration.as_secs_f64())?;
-                        writeln!(writer, "H 0.0 EXIT_CODE {}", exit_status)?;
-                    }
-                }
-                LogFormat::TimingSimple => {
-                    // No special closing for simple timing format
-                }
+    fn manual_clock() -> Arc<ManualClock> {
+        let start = DateTime::parse_from_rfc3339("2024-01-02T03:04:05+00:00").unwrap();
+        Arc::new(ManualClock::new(start))
+    }
+
+    #[test]
+    fn test_multi_timing_is_deterministic() {
+        let path = temp_path("multi.tm");
+        let clock = manual_clock();
+        let mut logger = ScriptLogger::new(path.clone(), LogFormat::TimingMulti, false, clock.clone()).unwrap();
+
+        logger.start(&SessionInfo::default()).unwrap();
+        clock.advance(Duration::from_millis(250));
+        logger.log_data(LogStream::Output, b"hello").unwrap();
+        clock.advance(Duration::from_micros(1500));
+        logger.log_data(LogStream::Input, b"x").unwrap();
+        logger.log_data(LogStream::Stderr, b"oops\n").unwrap();
+        clock.advance(Duration::from_secs(2));
+        logger.log_event("SIGWINCH", Some("ROWS=24 COLS=80")).unwrap();
+        logger.close(0).unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            written,
+            "O 0.250000 5\n\
+             I 0.001500 1\n\
+             E 0.000000 5\n\
+             S 2.000000 SIGWINCH ROWS=24 COLS=80\n\
+             H 0.000000 DURATION 2.251500\n\
+             H 0.000000 EXIT_CODE 0\n"
+        );
+    }
+
+    #[test]
+    fn test_wall_clock_stamps() {
+        let path = temp_path("stamped.tm");
+        let clock = manual_clock();
+        let logger = ScriptLogger::new(path.clone(), LogFormat::TimingMulti, false, clock.clone()).unwrap();
+        let mut logger = logger.with_timestamps(true);
+
+        logger.start(&SessionInfo::default()).unwrap();
+        clock.advance(Duration::from_micros(250_001));
+        logger.log_data(LogStream::Output, b"hello").unwrap();
+        logger.log_event("SUDO", Some("USER=root COMMAND=mail @ops")).unwrap();
+        logger.close(0).unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(written.starts_with(
+            "H 0.000000 TIMESTAMPS wall-clock\n\
+             O 0.250001 5 @2024-01-02T03:04:05.250001+00:00\n\
+             S 0.000000 SUDO USER=root COMMAND=mail @ops @2024-01-02T03:04:05.250001+00:00\n"
+        ));
+        let entries = parse_timing(&written).unwrap();
+        assert_eq!(entries[1].kind, EntryKind::Output(5));
+        assert_eq!(entries[2].kind, EntryKind::Signal("SUDO".into(), Some("USER=root COMMAND=mail @ops".into())));
+    }
+
+    #[test]
+    fn test_seek_index_points_into_the_log() {
+        let path = temp_path("indexed.log");
+        let clock = manual_clock();
+        let mut logger = ScriptLogger::new(path.clone(), LogFormat::Raw, false, clock.clone())
+            .unwrap()
+            .with_seek_index(Some(Duration::from_secs(10)));
+
+        logger.start(&SessionInfo::default()).unwrap();
+        for chunk in [&b"one "[..], b"two ", b"three"] {
+            clock.advance(Duration::from_secs(6));
+            logger.log_data(LogStream::Output, chunk).unwrap();
+        }
+        logger.close(0).unwrap();
+
+        let index = std::fs::read_to_string(seekindex::index_path(&path)).unwrap();
+        let log = std::fs::read(&path).unwrap();
+        let points = seekindex::parse(&index);
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].0, Duration::from_secs(12));
+        assert!(log[points[0].1 as usize..].starts_with(b"two three"));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(seekindex::index_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn test_raw_header_uses_clock() {
+        let path = temp_path("raw");
+        let clock = manual_clock();
+        let mut logger = ScriptLogger::new(path.clone(), LogFormat::Raw, false, clock.clone()).unwrap();
+
+        logger.start(&SessionInfo { command: Some("true".to_string()), ..SessionInfo::default() }).unwrap();
+        logger.log_data(LogStream::Output, b"out").unwrap();
+        clock.advance(Duration::from_secs(61));
+        logger.close(3).unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            written,
+            "Script started on 2024-01-02 03:04:05+00:00 [COMMAND=\"true\" <not executed on terminal>]\n\
+             out\n\
+             Script done on 2024-01-02 03:05:06+00:00 [COMMAND_EXIT_CODE=\"3\"]\n"
+        );
+    }
+
+    #[test]
+    fn test_plain_log_resolves_overwrites() {
+        let path = temp_path("plain");
+        let clock = manual_clock();
+        let mut logger = ScriptLogger::new(path.clone(), LogFormat::Plain, false, clock.clone()).unwrap();
+
+        logger.start(&SessionInfo::default()).unwrap();
+        logger.log_data(LogStream::Output, b"\x1b[1;32m$ \x1b[0mmkae\x08\x08\x08\x1b[Kake\r\n").unwrap();
+        logger.log_data(LogStream::Input, b"ignored\r").unwrap();
+        logger.log_data(LogStream::Output, b"10%\r50%\r100%\r\n$ ").unwrap();
+        clock.advance(Duration::from_secs(1));
+        logger.close(0).unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            written,
+            "Script started on 2024-01-02 03:04:05+00:00 [<not executed on terminal>]\n\
+             $ make\n\
+             100%\n\
+             $\n\
+             Script done on 2024-01-02 03:04:06+00:00 [COMMAND_EXIT_CODE=\"0\"]\n"
+        );
+    }
+
+    #[test]
+    fn test_rotated_parts_are_complete_logs() {
+        let path = temp_path("rotated");
+        let clock = manual_clock();
+        let mut logger = ScriptLogger::new(path.clone(), LogFormat::Raw, false, clock.clone())
+            .unwrap()
+            .with_rotate_size(Some(6));
+
+        logger.start(&SessionInfo::default()).unwrap();
+        logger.log_data(LogStream::Output, b"abcd").unwrap();
+        logger.log_data(LogStream::Output, b"efgh").unwrap();
+        clock.advance(Duration::from_secs(1));
+        logger.log_data(LogStream::Output, b"ij").unwrap();
+        logger.close(0).unwrap();
+
+        let first = std::fs::read_to_string(&path).unwrap();
+        let second = std::fs::read_to_string(part_path(&path, 1)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(part_path(&path, 1)).unwrap();
+        assert_eq!(
+            first,
+            format!(
+                "Script started on 2024-01-02 03:04:05+00:00 [<not executed on terminal>]\n\
+                 abcdefgh\n\
+                 Script done on 2024-01-02 03:04:05+00:00 [NEXT_FILE=\"{}\"]\n",
+                part_path(&path, 1).display()
+            )
+        );
+        assert_eq!(
+            second,
+            "Script started on 2024-01-02 03:04:05+00:00 [<not executed on terminal>]\n\
+             ij\n\
+             Script done on 2024-01-02 03:04:06+00:00 [COMMAND_EXIT_CODE=\"0\"]\n"
+        );
+    }
+
+    #[test]
+    fn test_reopen_follows_a_moved_log() {
+        let path = temp_path("reopened");
+        let moved = temp_path("reopened.1");
+        let mut logger = ScriptLogger::new(path.clone(), LogFormat::Raw, false, manual_clock()).unwrap();
+
+        logger.start(&SessionInfo::default()).unwrap();
+        logger.log_data(LogStream::Output, b"before").unwrap();
+        logger.reopen().unwrap();
+        std::fs::rename(&path, &moved).unwrap();
+        logger.log_data(LogStream::Output, b" moved").unwrap();
+        logger.reopen().unwrap();
+        logger.log_data(LogStream::Output, b"after").unwrap();
+        logger.close(0).unwrap();
+
+        let old = std::fs::read_to_string(&moved).unwrap();
+        let new = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&moved).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(old.ends_with("]\nbefore moved"), "{}", old);
+        assert!(new.starts_with("after\nScript done on"), "{}", new);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip_log_is_finished_on_close() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let path = temp_path("raw.gz");
+        let mut logger = ScriptLogger::new(path.clone(), LogFormat::Raw, false, manual_clock())
+            .unwrap()
+            .with_compression(Compression::Gzip);
+        logger.start(&SessionInfo::default()).unwrap();
+        for _ in 0..1000 {
+            logger.log_data(LogStream::Output, b"the same line again\r\n").unwrap();
+        }
+        logger.close(0).unwrap();
+
+        let compressed = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(compressed.len() < 1000);
+        let mut written = String::new();
+        GzDecoder::new(&compressed[..]).read_to_string(&mut written).unwrap();
+        assert!(written.starts_with("Script started on 2024-01-02 03:04:05+00:00"));
+        assert_eq!(written.matches("the same line again").count(), 1000);
+        assert!(written.ends_with("[COMMAND_EXIT_CODE=\"0\"]\n"));
+    }
+
+    #[test]
+    fn test_write_errors_surface_on_close() {
+        let mut logger = ScriptLogger::new(PathBuf::from("/dev/full"), LogFormat::Raw, false, manual_clock()).unwrap();
+        logger.start(&SessionInfo::default()).unwrap();
+        // Queued without waiting for the disk, unless the writer thread has
+        // failed on the header already
+        let e = match logger.log_data(LogStream::Output, b"out") {
+            Ok(_) => logger.close(0).unwrap_err(),
+            Err(e) => e,
+        };
+        assert_eq!(format!("{:#}", e).split(": ").next(), Some("Cannot write /dev/full"));
+        assert!(format!("{:#}", e).contains("No space left on device"), "{:#}", e);
+    }
+
+    #[test]
+    fn test_full_disk_fails_over() {
+        let dir = temp_path("failover");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut logger = ScriptLogger::new(PathBuf::from("/dev/full"), LogFormat::Raw, false, manual_clock())
+            .unwrap()
+            .with_failover_dir(Some(dir.clone()));
+        logger.start(&SessionInfo::default()).unwrap();
+
+        // The failure shows on a write after the writer thread hit it
+        let mut failover = None;
+        let mut sent = 0;
+        for _ in 0..500 {
+            logger.log_data(LogStream::Output, b"out").unwrap();
+            sent += 1;
+            failover = logger.take_failover();
+            if failover.is_some() {
+                break;
             }
-            writer.flush()?;
+            std::thread::sleep(Duration::from_millis(10));
         }
-        Ok(())
+        logger.log_data(LogStream::Output, b"after").unwrap();
+        logger.close(0).unwrap();
+
+        let written = std::fs::read_to_string(dir.join("full")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            failover,
+            Some(StorageFailover { from: PathBuf::from("/dev/full"), to: dir.join("full"), error: "ENOSPC" })
+        );
+        assert!(written.starts_with("Script started on 2024-01-02 03:04:05+00:00 [<not executed on terminal>]\nout"));
+        assert!(written.ends_with("outafter\nScript done on 2024-01-02 03:04:05+00:00 [COMMAND_EXIT_CODE=\"0\"]\n"));
+        // Nothing reached /dev/full, so everything queued for it went on in
+        // the fallback, once, under the one header
+        assert_eq!(written.matches("out").count(), sent);
+        assert_eq!(written.matches("Script started").count(), 1);
+        assert!(logger.take_failover().is_none());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+
+// Ctrl+A, as in screen
+pub const DEFAULT_PREFIX: u8 = 0x01;
+
+// Canned input bound to "<prefix> <key>"
+#[derive(Debug, Clone, Default)]
+pub struct MacroTable {
+    pub prefix: Option<u8>,
+    pub macros: BTreeMap<u8, Vec<u8>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InputChunk {
+    Typed(Vec<u8>),
+    Macro(u8, Vec<u8>),
+}
+
+// Parse a key such as "^A", "Ctrl+A" or a single character
+pub fn parse_key(key: &str) -> Result<u8> {
+    let ctrl = key
+        .strip_prefix('^')
+        .or_else(|| key.strip_prefix("Ctrl+"))
+        .or_else(|| key.strip_prefix("C-"));
+    match (ctrl, key.as_bytes()) {
+        (Some(c), _) if c.len() == 1 && c.as_bytes()[0].is_ascii_alphabetic() => {
+            Ok(c.as_bytes()[0].to_ascii_uppercase() - b'@')
+        }
+        (None, [c]) if c.is_ascii_graphic() => Ok(*c),
+        _ => Err(anyhow!("Invalid key: {}", key)),
+    }
+}
+
+pub fn key_name(key: u8) -> String {
+    match key {
+        0..=0x1f => format!("^{}", (key + b'@') as char),
+        _ => (key as char).to_string(),
+    }
+}
+
+// Expand \r, \n, \t, \e and \\ in a macro body
+pub fn unescape(text: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('r') => b'\r',
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('e') => 0x1b,
+            Some('\\') => b'\\',
+            other => return Err(anyhow!("Invalid escape \\{} in: {}", other.unwrap_or(' '), text)),
+        });
+    }
+    Ok(out)
+}
+
+// Splits typed input into plain keystrokes and macro expansions. The
+// prefix may arrive at the end of one read and the key in the next.
+pub struct MacroExpander {
+    table: MacroTable,
+    pending: bool,
+}
+
+impl MacroExpander {
+    pub fn new(table: MacroTable) -> Self {
+        MacroExpander {
+            table,
+            pending: false,
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) -> Vec<InputChunk> {
+        let prefix = self.table.prefix.unwrap_or(DEFAULT_PREFIX);
+        let mut chunks = Vec::new();
+        let mut typed = Vec::new();
+
+        for &byte in data {
+            if self.pending {
+                self.pending = false;
+                match self.table.macros.get(&byte) {
+                    Some(text) => {
+                        if !typed.is_empty() {
+                            chunks.push(InputChunk::Typed(std::mem::take(&mut typed)));
+                        }
+                        chunks.push(InputChunk::Macro(byte, text.clone()));
+                    }
+                    // Prefix twice sends it once
+                    None if byte == prefix => typed.push(prefix),
+                    // Not a macro; pass both keys through
+                    None => typed.extend_from_slice(&[prefix, byte]),
+                }
+            } else if byte == prefix {
+                self.pending = true;
+            } else {
+                typed.push(byte);
+            }
+        }
+
+        if !typed.is_empty() {
+            chunks.push(InputChunk::Typed(typed));
+        }
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_and_unescape() {
+        assert_eq!(parse_key("^A").unwrap(), 0x01);
+        assert_eq!(parse_key("Ctrl+b").unwrap(), 0x02);
+        assert_eq!(parse_key("1").unwrap(), b'1');
+        assert!(parse_key("ab").is_err());
+        assert_eq!(key_name(0x01), "^A");
+        assert_eq!(unescape("make\\ttest\\r").unwrap(), b"make\ttest\r");
+        assert!(unescape("bad\\q").is_err());
+    }
+
+    #[test]
+    fn test_expander_splits_macros() {
+        let mut table = MacroTable::default();
+        table.macros.insert(b'1', b"make test\r".to_vec());
+        let mut expander = MacroExpander::new(table);
+
+        assert_eq!(
+            expander.feed(b"ls\x011"),
+            vec![InputChunk::Typed(b"ls".to_vec()), InputChunk::Macro(b'1', b"make test\r".to_vec())]
+        );
+        // Prefix split across reads, unbound key, doubled prefix
+        assert_eq!(expander.feed(b"\x01"), vec![]);
+        assert_eq!(expander.feed(b"x\x01\x01"), vec![InputChunk::Typed(b"\x01x\x01".to_vec())]);
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::{anyhow, Result};
+use std::time::{Duration, Instant};
+
+use crate::utils;
+
+// --auto-marker: chapters put in the recording by themselves, as `S MARKER`
+// events in the advanced timing log, for `script replay --interactive`, the
+// exported player and asciicast to jump between. `every-command` marks each
+// command line typed (not a password: the terminal must be echoing), no
+// closer together than --auto-marker-gap; `every-5m` marks every five
+// minutes the session wrote anything in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AutoMarker {
+    EveryCommand,
+    Every(Duration),
+}
+
+impl AutoMarker {
+    pub fn parse(spec: &str) -> Result<AutoMarker> {
+        let invalid = || anyhow!("Invalid --auto-marker: '{}' (every-command, or an interval such as every-5m)", spec);
+        match spec.strip_prefix("every-").ok_or_else(invalid)? {
+            "command" => Ok(AutoMarker::EveryCommand),
+            interval => match utils::parse_duration(interval) {
+                Ok(interval) if interval > Duration::ZERO => Ok(AutoMarker::Every(interval)),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+pub struct AutoMarkers {
+    mode: AutoMarker,
+    gap: Duration,
+    last: Option<Instant>,
+    // The session wrote something since the last marker
+    active: bool,
+}
+
+impl AutoMarkers {
+    pub fn new(mode: AutoMarker, gap: Duration) -> Self {
+        AutoMarkers { mode, gap, last: None, active: false }
+    }
+
+    // How often `tick` wants calling
+    pub fn interval(&self) -> Option<Duration> {
+        match self.mode {
+            AutoMarker::Every(interval) => Some(interval),
+            AutoMarker::EveryCommand => None,
+        }
+    }
+
+    pub fn activity(&mut self) {
+        self.active = true;
+    }
+
+    // Whether a command line typed now gets a marker
+    pub fn command(&mut self, now: Instant) -> bool {
+        if self.mode != AutoMarker::EveryCommand || self.last.is_some_and(|last| now - last < self.gap) {
+            return false;
+        }
+        self.last = Some(now);
+        true
+    }
+
+    // Whether the interval just up gets a marker
+    pub fn tick(&mut self, now: Instant) -> bool {
+        if !std::mem::take(&mut self.active) {
+            return false;
+        }
+        self.last = Some(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markers_are_throttled() {
+        assert_eq!(AutoMarker::parse("every-5m").unwrap(), AutoMarker::Every(Duration::from_secs(300)));
+        assert!(AutoMarker::parse("every-0s").is_err() && AutoMarker::parse("5m").is_err());
+
+        let start = Instant::now();
+        let mut markers = AutoMarkers::new(AutoMarker::parse("every-command").unwrap(), Duration::from_secs(30));
+        assert_eq!(markers.interval(), None);
+        assert!(markers.command(start));
+        assert!(!markers.command(start + Duration::from_secs(10)));
+        assert!(markers.command(start + Duration::from_secs(40)));
+
+        // An interval nothing happened in has nothing to mark
+        let mut markers = AutoMarkers::new(AutoMarker::Every(Duration::from_secs(60)), Duration::ZERO);
+        assert!(!markers.tick(start));
+        markers.activity();
+        assert!(markers.tick(start) && !markers.tick(start));
+        assert!(!markers.command(start));
+    }
+}
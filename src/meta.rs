@@ -0,0 +1,177 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::altscreen::AltScreenTracker;
+use crate::clock::Clock;
+use crate::logging::{LogSink, LogStream, SessionInfo};
+use crate::schema::SCHEMA_VERSION;
+use crate::typed::TypedLines;
+
+// START_TIME with the microseconds, so sessions recorded side by side keep
+// their order when merged
+pub const PRECISE_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.6f%:z";
+
+// Events that say nothing about what happened in the session
+const ROUTINE_EVENTS: &[&str] = &[
+    "HEARTBEAT", "SIGWINCH", "PROMPT_START", "COMMAND_START", "OUTPUT_START", "ALTSCREEN_ENTER", "ALTSCREEN_LEAVE",
+    "FILE_OPEN", "MARKER",
+];
+
+// --meta: who ran the session where and when, and its timeline of command
+// lines typed and notable events, written to the log's metadata sidecar
+// once it is over:
+//
+//   {"schema_version": 1,
+//    "session": {"start_time": "2024-01-02 03:04:05.250000+00:00", "host": "web1",
+//                "user": "alice", "command": null, "log": "/var/log/s.log", "exit_code": 0},
+//    "timeline": [{"t": 1.5, "type": "command", "text": "systemctl restart nginx"},
+//                 {"t": 1.6, "type": "event", "name": "SUDO", "message": "USER=root ..."}]}
+//
+// "degraded" says why the terminal was left in cooked mode, if it was. A
+// command that ended in failure (COMMAND_END with a non-zero EXIT_CODE)
+// is kept; the routine events are not. `script timeline-merge` reads the
+// sidecars of several sessions into one report.
+pub struct MetaSink {
+    sidecar: PathBuf,
+    log: PathBuf,
+    clock: Arc<dyn Clock>,
+    start: Option<Instant>,
+    start_time: Option<DateTime<FixedOffset>>,
+    command: Option<String>,
+    degraded: Option<String>,
+    typed: TypedLines,
+    alt_screen: AltScreenTracker,
+    timeline: Vec<Value>,
+}
+
+impl MetaSink {
+    pub fn new(sidecar: PathBuf, log: PathBuf, clock: Arc<dyn Clock>) -> Self {
+        MetaSink {
+            sidecar,
+            log,
+            clock,
+            start: None,
+            start_time: None,
+            command: None,
+            degraded: None,
+            typed: TypedLines::default(),
+            alt_screen: AltScreenTracker::new(),
+            timeline: Vec::new(),
+        }
+    }
+
+    fn elapsed(&self) -> f64 {
+        let secs = self.start.map_or(0.0, |start| self.clock.now().duration_since(start).as_secs_f64());
+        (secs * 1e6).round() / 1e6
+    }
+}
+
+fn notable(name: &str, message: Option<&str>) -> bool {
+    match name {
+        "COMMAND_END" => message.is_some_and(|message| message != "EXIT_CODE=0"),
+        name => !ROUTINE_EVENTS.contains(&name),
+    }
+}
+
+impl LogSink for MetaSink {
+    fn start(&mut self, session: &SessionInfo) -> Result<()> {
+        self.start = Some(self.clock.now());
+        self.start_time = Some(self.clock.wall());
+        self.command = session.command.clone();
+        self.degraded = session.degraded.clone();
+        Ok(())
+    }
+
+    // Not counted toward -o; the sidecar isn't one of the logs
+    fn log_data(&mut self, stream: LogStream, data: &[u8]) -> Result<usize> {
+        match stream {
+            LogStream::Output => {
+                self.alt_screen.feed(data);
+            }
+            // Keys pressed in a full-screen program aren't command lines
+            LogStream::Input if !self.alt_screen.is_active() => {
+                let t = self.elapsed();
+                for line in self.typed.feed(data) {
+                    self.timeline.push(json!({ "t": t, "type": "command", "text": line }));
+                }
+            }
+            LogStream::Input | LogStream::Stderr => {}
+        }
+        Ok(0)
+    }
+
+    fn log_event(&mut self, name: &str, message: Option<&str>) -> Result<()> {
+        if notable(name, message) {
+            let t = self.elapsed();
+            self.timeline.push(json!({ "t": t, "type": "event", "name": name, "message": message }));
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, exit_status: i32) -> Result<()> {
+        let host = std::fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+        let user = nix::unistd::User::from_uid(nix::unistd::getuid()).ok().flatten().map(|user| user.name);
+        let metadata = json!({
+            "schema_version": SCHEMA_VERSION,
+            "session": {
+                "start_time": self.start_time.map(|time| time.format(PRECISE_TIMESTAMP_FORMAT).to_string()),
+                "host": host.trim(),
+                "user": user,
+                "command": self.command,
+                "log": self.log,
+                "exit_code": exit_status,
+                "degraded": self.degraded,
+            },
+            "timeline": std::mem::take(&mut self.timeline),
+        });
+        std::fs::write(&self.sidecar, serde_json::to_string_pretty(&metadata)? + "\n")
+            .with_context(|| format!("Cannot write {}", self.sidecar.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::time::Duration;
+
+    #[test]
+    fn test_meta_keeps_commands_and_notable_events() {
+        let sidecar = std::env::temp_dir().join(format!("rust_script-{}-session.meta.json", std::process::id()));
+        let start = DateTime::parse_from_rfc3339("2024-01-02T03:04:05.25+00:00").unwrap();
+        let clock = Arc::new(ManualClock::new(start));
+        let mut sink = MetaSink::new(sidecar.clone(), PathBuf::from("s.log"), clock.clone());
+
+        sink.start(&SessionInfo { degraded: Some("TERM=dumb".to_string()), ..SessionInfo::default() }).unwrap();
+        clock.advance(Duration::from_millis(1500));
+        sink.log_data(LogStream::Input, b"sudo ls\r").unwrap();
+        sink.log_event("SUDO", Some("USER=root COMMAND=ls")).unwrap();
+        sink.log_event("HEARTBEAT", Some("BYTES_IN=8")).unwrap();
+        sink.log_event("COMMAND_END", Some("EXIT_CODE=0")).unwrap();
+        sink.log_data(LogStream::Output, b"\x1b[?1049h").unwrap();
+        sink.log_data(LogStream::Input, b":wq\r").unwrap();
+        sink.log_event("COMMAND_END", Some("EXIT_CODE=1")).unwrap();
+        sink.close(1).unwrap();
+
+        let metadata: Value = serde_json::from_str(&std::fs::read_to_string(&sidecar).unwrap()).unwrap();
+        std::fs::remove_file(&sidecar).unwrap();
+        assert_eq!(metadata["session"]["start_time"], "2024-01-02 03:04:05.250000+00:00");
+        assert_eq!(metadata["session"]["exit_code"], 1);
+        assert_eq!(metadata["session"]["degraded"], "TERM=dumb");
+        assert_eq!(metadata["schema_version"], 1);
+        assert_eq!(
+            metadata["timeline"],
+            json!([
+                {"t": 1.5, "type": "command", "text": "sudo ls"},
+                {"t": 1.5, "type": "event", "name": "SUDO", "message": "USER=root COMMAND=ls"},
+                {"t": 1.5, "type": "event", "name": "COMMAND_END", "message": "EXIT_CODE=1"},
+            ])
+        );
+        #[cfg(feature = "schemas")]
+        serde_json::from_value::<crate::schema::Metadata>(metadata).unwrap();
+    }
+}
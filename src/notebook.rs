@@ -0,0 +1,99 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+use std::path::Path;
+
+use crate::convert;
+use crate::split::{self, Command};
+
+// nbformat 4: a bash notebook with a code cell per command and its output as
+// the cell's stdout. The duration and exit code go in the cell metadata.
+pub fn notebook(commands: &[Command]) -> Value {
+    let cells: Vec<Value> = commands
+        .iter()
+        .enumerate()
+        .map(|(i, cell)| {
+            let text: String = cell.output.iter().map(|line| format!("{}\n", line)).collect();
+            let outputs = if text.is_empty() {
+                json!([])
+            } else {
+                json!([{ "output_type": "stream", "name": "stdout", "text": text }])
+            };
+            json!({
+                "cell_type": "code",
+                "id": format!("command-{}", i + 1),
+                "execution_count": i + 1,
+                "metadata": {
+                    "rust_script": {
+                        "duration": cell.duration.as_secs_f64(),
+                        "exit_code": cell.exit_code,
+                    }
+                },
+                "source": cell.command,
+                "outputs": outputs,
+            })
+        })
+        .collect();
+
+    json!({
+        "cells": cells,
+        "metadata": {
+            "kernelspec": { "name": "bash", "display_name": "Bash", "language": "bash" },
+            "language_info": { "name": "bash" },
+        },
+        "nbformat": 4,
+        "nbformat_minor": 5,
+    })
+}
+
+pub fn run(timing: Option<&Path>, recording: &Path, output: Option<&Path>) -> Result<()> {
+    let (entries, data) = convert::load_recording(timing, recording)?;
+    let slices = split::split(&entries, &data)?;
+    if slices.is_empty() {
+        return Err(anyhow!(
+            "No commands found in {}: it has no typed input (record with -B, or an asciicast with input events)",
+            recording.display()
+        ));
+    }
+
+    let json = serde_json::to_string_pretty(&notebook(&split::commands(&slices)))? + "\n";
+    match output {
+        Some(path) => std::fs::write(path, json).with_context(|| format!("Cannot write {}", path.display())),
+        None => {
+            print!("{}", json);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::parse_timing;
+    use std::time::Duration;
+
+    #[test]
+    fn test_commands_become_cells() {
+        let data = b"$ llss\r\r\na  b\r\n$ makemake\r\r\n\x1b[31mError\x1b[0m\r\n$ ";
+        let entries = parse_timing(
+            "O 0.1 2\nI 1.0 1\nO 0.0 1\nI 0.1 1\nO 0.0 1\nI 0.1 1\nO 0.0 2\nO 0.5 6\nO 0.0 2\n\
+             I 2.0 4\nO 0.0 4\nI 0.2 1\nO 0.0 2\nO 1.5 16\nS 0.0 COMMAND_END EXIT_CODE=2\nO 0.0 2\n",
+        )
+        .unwrap();
+
+        let slices = split::split(&entries, data).unwrap();
+        let commands = split::commands(&slices);
+        assert_eq!(
+            commands,
+            vec![
+                Command { command: "ls".into(), output: vec!["a  b".into()], duration: Duration::from_millis(500), exit_code: None },
+                Command { command: "make".into(), output: vec!["Error".into()], duration: Duration::from_millis(1500), exit_code: Some(2) },
+            ]
+        );
+
+        let notebook = notebook(&commands);
+        assert_eq!(notebook["nbformat"], 4);
+        assert_eq!(notebook["cells"][1]["source"], "make");
+        assert_eq!(notebook["cells"][1]["outputs"][0]["text"], "Error\n");
+        assert_eq!(notebook["cells"][0]["metadata"]["rust_script"]["duration"], 0.5);
+    }
+}
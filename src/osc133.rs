@@ -0,0 +1,141 @@
+// Shell integration marks (OSC 133), as written by shells set up for
+// terminals such as WezTerm, kitty or VS Code:
+//
+//   ESC ] 133 ; A ST   prompt starts
+//   ESC ] 133 ; B ST   prompt ends, the command is typed
+//   ESC ] 133 ; C ST   the command runs, its output starts
+//   ESC ] 133 ; D ; 0 ST   the command finished, with its exit status
+//
+// ST is BEL or ESC \. Other fields after the mark, such as aid=..., are
+// ignored.
+
+// Longest OSC 133 sequence we keep around when a read splits it
+const MAX_PENDING: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mark {
+    PromptStart,
+    CommandStart,
+    OutputStart,
+    CommandEnd(Option<i32>),
+}
+
+impl Mark {
+    // The event the mark is logged as
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mark::PromptStart => "PROMPT_START",
+            Mark::CommandStart => "COMMAND_START",
+            Mark::OutputStart => "OUTPUT_START",
+            Mark::CommandEnd(_) => "COMMAND_END",
+        }
+    }
+
+    pub fn message(&self) -> Option<String> {
+        match self {
+            Mark::CommandEnd(Some(status)) => Some(format!("EXIT_CODE={}", status)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MarkTracker {
+    pending: Vec<u8>,
+}
+
+impl MarkTracker {
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Mark> {
+        let mut marks = Vec::new();
+        let mut buf = std::mem::take(&mut self.pending);
+        buf.extend_from_slice(data);
+
+        let mut i = 0;
+        while i < buf.len() {
+            if buf[i] != 0x1b {
+                i += 1;
+                continue;
+            }
+            match parse_mark(&buf[i..]) {
+                Parse::Incomplete => {
+                    if buf.len() - i <= MAX_PENDING {
+                        self.pending = buf[i..].to_vec();
+                    }
+                    break;
+                }
+                Parse::NotMark => i += 1,
+                Parse::Mark { len, mark } => {
+                    i += len;
+                    marks.extend(mark);
+                }
+            }
+        }
+
+        marks
+    }
+}
+
+enum Parse {
+    Incomplete,
+    NotMark,
+    // An OSC 133 with a kind we don't know is skipped whole
+    Mark { len: usize, mark: Option<Mark> },
+}
+
+// Parses `ESC ] 133 ; <kind> [; fields] ST` at the start of `buf`
+fn parse_mark(buf: &[u8]) -> Parse {
+    let prefix = b"\x1b]133;";
+    if buf.len() < prefix.len() {
+        return if prefix.starts_with(buf) { Parse::Incomplete } else { Parse::NotMark };
+    }
+    if !buf.starts_with(prefix) {
+        return Parse::NotMark;
+    }
+
+    let body = &buf[prefix.len()..];
+    let (end, len) = match body.iter().position(|&b| b == 0x07 || b == 0x1b) {
+        Some(end) if body[end] == 0x07 => (end, end + 1),
+        Some(end) if body.get(end + 1) == Some(&b'\\') => (end, end + 2),
+        Some(end) if end + 1 == body.len() => return Parse::Incomplete,
+        Some(_) => return Parse::NotMark,
+        None => return Parse::Incomplete,
+    };
+
+    let text = String::from_utf8_lossy(&body[..end]);
+    let mut fields = text.split(';');
+    let mark = match fields.next() {
+        Some("A") => Some(Mark::PromptStart),
+        Some("B") => Some(Mark::CommandStart),
+        Some("C") => Some(Mark::OutputStart),
+        Some("D") => Some(Mark::CommandEnd(fields.next().and_then(|status| status.parse().ok()))),
+        _ => None,
+    };
+    Parse::Mark { len: prefix.len() + len, mark }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marks_of_a_command() {
+        let mut tracker = MarkTracker::default();
+        let marks = tracker.feed(b"\x1b]133;A\x07$ \x1b]133;B\x07ls\r\n\x1b]133;C\x1b\\a b\r\n\x1b]133;D;2;aid=7\x07");
+        assert_eq!(marks, vec![Mark::PromptStart, Mark::CommandStart, Mark::OutputStart, Mark::CommandEnd(Some(2))]);
+        assert_eq!(marks[3].message().as_deref(), Some("EXIT_CODE=2"));
+    }
+
+    #[test]
+    fn test_mark_split_across_reads() {
+        let mut tracker = MarkTracker::default();
+        assert!(tracker.feed(b"done\x1b]13").is_empty());
+        assert!(tracker.feed(b"3;D").is_empty());
+        assert_eq!(tracker.feed(b"\x1b\\$ "), vec![Mark::CommandEnd(None)]);
+    }
+
+    #[test]
+    fn test_other_sequences_ignored() {
+        let mut tracker = MarkTracker::default();
+        assert!(tracker.feed(b"\x1b]0;title\x07\x1b[31m\x1b]133;P;k=i\x07").is_empty());
+    }
+}
@@ -0,0 +1,375 @@
+use anyhow::{anyhow, Result};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use std::io::Write;
+use std::os::fd::BorrowedFd;
+use std::time::{Duration, Instant};
+use termios::{tcsetattr, Termios, TCSANOW};
+
+use crate::replay::Chunk;
+use crate::utils;
+use crate::vt::Screen;
+
+// `script replay --interactive`: the recording plays in all but the last row
+// of the terminal, kept out of its way with a scroll region, and the last
+// row shows where the replay is and takes the keys:
+//
+//   space    pause or go on (from the start again, once at the end)
+//   ← →      seek 10 seconds back or ahead
+//   + -      double or halve the speed
+//   .        pause and show the next output record
+//   n p      jump to the next or previous marker (--auto-marker)
+//   q        quit
+//
+// Seeking back clears the screen and writes everything up to the new point
+// again at once, as the terminal can't be asked to undo output. The output
+// also goes through the terminal emulator, so the cursor and colors can be
+// put back after drawing the bar without touching the cursor the recording
+// may have saved.
+const SEEK_STEP: Duration = Duration::from_secs(10);
+const MIN_SPEED: f64 = 1.0 / 16.0;
+const MAX_SPEED: f64 = 16.0;
+
+// How often the clock in the bar moves on while nothing is written
+const TICK: Duration = Duration::from_millis(200);
+
+// Leave an alternate screen and clear the attributes and the screen, to
+// play from the start again
+const RESET: &[u8] = b"\x1b[?1049l\x1b[0m\x1b[H\x1b[2J";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Pause,
+    Back,
+    Forward,
+    Faster,
+    Slower,
+    Step,
+    NextMarker,
+    PreviousMarker,
+    Quit,
+}
+
+// The keys in some input; anything else typed is ignored
+pub fn keys(input: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        let key = match input[i] {
+            b' ' => Some(Key::Pause),
+            b'+' | b'=' => Some(Key::Faster),
+            b'-' | b'_' => Some(Key::Slower),
+            b'.' => Some(Key::Step),
+            b'n' | b'N' => Some(Key::NextMarker),
+            b'p' | b'P' => Some(Key::PreviousMarker),
+            b'q' | b'Q' | 0x03 | 0x04 => Some(Key::Quit),
+            // Arrows, in normal and application cursor mode
+            0x1b if matches!(input.get(i + 1), Some(b'[' | b'O')) => {
+                i += 2;
+                match input.get(i) {
+                    Some(b'C') => Some(Key::Forward),
+                    Some(b'D') => Some(Key::Back),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        keys.extend(key);
+        i += 1;
+    }
+    keys
+}
+
+// Where the replay is: the chunks shown so far and how far into the
+// recording they go
+pub struct Player<'a> {
+    chunks: &'a [Chunk],
+    shown: usize,
+    offset: Duration,
+}
+
+impl<'a> Player<'a> {
+    pub fn new(chunks: &'a [Chunk]) -> Self {
+        Player { chunks, shown: 0, offset: Duration::ZERO }
+    }
+
+    pub fn offset(&self) -> Duration {
+        self.offset
+    }
+
+    pub fn length(&self) -> Duration {
+        self.chunks.last().map_or(Duration::ZERO, |chunk| chunk.offset)
+    }
+
+    // When the next chunk is due
+    pub fn next(&self) -> Option<Duration> {
+        self.chunks.get(self.shown).map(|chunk| chunk.offset)
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.shown == self.chunks.len()
+    }
+
+    // What to write for the screen to show the recording at `target`
+    pub fn seek(&mut self, target: Duration) -> Vec<u8> {
+        let target = target.min(self.length());
+        let mut out = Vec::new();
+        if target < self.offset {
+            out.extend_from_slice(RESET);
+            self.shown = 0;
+        }
+        while let Some(chunk) = self.chunks.get(self.shown).filter(|chunk| chunk.offset <= target) {
+            out.extend_from_slice(&chunk.data);
+            self.shown += 1;
+        }
+        self.offset = target;
+        out
+    }
+
+    // The next chunk, however far off; markers have nothing to show
+    pub fn step(&mut self) -> Vec<u8> {
+        match self.chunks[self.shown..].iter().find(|chunk| !chunk.data.is_empty()) {
+            Some(chunk) => self.seek(chunk.offset),
+            None => Vec::new(),
+        }
+    }
+
+    fn markers(&self) -> impl DoubleEndedIterator<Item = Duration> + '_ {
+        self.chunks.iter().filter(|chunk| chunk.data.is_empty()).map(|chunk| chunk.offset)
+    }
+
+    pub fn next_marker(&self) -> Option<Duration> {
+        self.markers().find(|&offset| offset > self.offset)
+    }
+
+    // The start of the part playing, like a chapter's
+    pub fn previous_marker(&self) -> Option<Duration> {
+        self.markers().rev().find(|&offset| offset < self.offset)
+    }
+}
+
+// 01:02:03
+fn clock(offset: Duration) -> String {
+    let secs = offset.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn bar(player: &Player, speed: f64, paused: bool, cols: usize) -> String {
+    let state = if player.at_end() {
+        "end"
+    } else if paused {
+        "paused"
+    } else {
+        "playing"
+    };
+    let markers = if player.markers().next().is_some() { "  n/p marker" } else { "" };
+    let text = format!(
+        " {} / {}  {}x  {}   space pause  \u{2190}/\u{2192} 10s  +/- speed  . step{}  q quit",
+        clock(player.offset()),
+        clock(player.length()),
+        speed,
+        state,
+        markers
+    );
+    let mut text: String = text.chars().take(cols).collect();
+    let len = text.chars().count();
+    text.extend(std::iter::repeat_n(' ', cols - len));
+    text
+}
+
+// The terminal as it was, put back however the replay ends
+struct RawMode(Termios);
+
+impl RawMode {
+    fn enter() -> Result<RawMode> {
+        let original = Termios::from_fd(libc::STDIN_FILENO)?;
+        let mut raw = original;
+        termios::cfmakeraw(&mut raw);
+        tcsetattr(libc::STDIN_FILENO, TCSANOW, &raw)?;
+        Ok(RawMode(original))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = tcsetattr(libc::STDIN_FILENO, TCSANOW, &self.0);
+    }
+}
+
+pub fn run(chunks: &[Chunk], start: Duration) -> Result<()> {
+    if !utils::is_stdin_tty() {
+        return Err(anyhow!("The replay controls need a terminal to read keys from (--interactive)"));
+    }
+    let (cols, rows) = utils::get_terminal_size()?;
+    let (cols, rows) = (cols.max(1) as usize, rows.max(2) as usize);
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+
+    let result = {
+        let _raw = RawMode::enter()?;
+        write!(out, "\x1b[1;{}r\x1b[H\x1b[2J", rows - 1)?;
+        let result = control(chunks, start, &mut out, cols, rows);
+        // The whole screen back, with the cursor on a fresh bottom row
+        write!(out, "\x1b[r\x1b[0m\x1b[{};1H\x1b[2K", rows)?;
+        out.flush()?;
+        result
+    };
+    writeln!(out)?;
+    result
+}
+
+// The terminal as the replay left it
+struct View<W: Write> {
+    out: W,
+    screen: Screen,
+    rows: usize,
+    // The bar as last drawn, until output may have moved past it
+    bar: Option<String>,
+}
+
+impl<W: Write> View<W> {
+    fn show(&mut self, data: Vec<u8>) -> std::io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.screen.feed(&data, 0);
+        self.bar = None;
+        self.out.write_all(&data)
+    }
+
+    // Never in the middle of a sequence the next chunk finishes
+    fn draw(&mut self, bar: String) -> std::io::Result<()> {
+        if self.screen.in_sequence() || self.bar.as_ref() == Some(&bar) {
+            return self.out.flush();
+        }
+        let (row, col) = self.screen.cursor();
+        let style = self.screen.style().sgr();
+        write!(self.out, "\x1b[{};1H\x1b[0;7m{}{}\x1b[{};{}H", self.rows, bar, style, row + 1, col + 1)?;
+        self.bar = Some(bar);
+        self.out.flush()
+    }
+}
+
+fn control(chunks: &[Chunk], start: Duration, out: &mut impl Write, cols: usize, rows: usize) -> Result<()> {
+    let stdin = unsafe { BorrowedFd::borrow_raw(libc::STDIN_FILENO) };
+    let mut view = View { out, screen: Screen::new(cols, rows - 1), rows, bar: None };
+    let mut player = Player::new(chunks);
+    let mut speed = 1.0;
+    let mut paused = false;
+    // The point of the recording playing went on from, and when
+    let mut anchor = (start, Instant::now());
+    let position = |anchor: (Duration, Instant), speed: f64| anchor.0 + anchor.1.elapsed().mul_f64(speed);
+    let mut buf = [0u8; 64];
+
+    loop {
+        if !paused {
+            view.show(player.seek(position(anchor, speed)))?;
+            paused = player.at_end();
+        }
+        view.draw(bar(&player, speed, paused, cols))?;
+
+        let wait = match player.next() {
+            Some(next) if !paused => next.saturating_sub(player.offset()).min(TICK.mul_f64(speed)).div_f64(speed),
+            _ => TICK,
+        };
+        let mut fds = [PollFd::new(&stdin, PollFlags::POLLIN)];
+        match poll(&mut fds, wait.as_millis().max(1) as i32) {
+            Ok(0) | Err(Errno::EINTR) => continue,
+            Ok(_) => {}
+            Err(e) => return Err(e.into()),
+        }
+        let n = match nix::unistd::read(libc::STDIN_FILENO, &mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(Errno::EINTR | Errno::EAGAIN) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        // Catch up before acting from where the replay is now
+        if !paused {
+            view.show(player.seek(position(anchor, speed)))?;
+        }
+        for key in keys(&buf[..n]) {
+            match key {
+                Key::Quit => return Ok(()),
+                Key::Pause if player.at_end() => {
+                    view.show(player.seek(Duration::ZERO))?;
+                    paused = false;
+                }
+                Key::Pause => paused = !paused,
+                Key::Back => view.show(player.seek(player.offset().saturating_sub(SEEK_STEP)))?,
+                Key::Forward => view.show(player.seek(player.offset() + SEEK_STEP))?,
+                Key::Faster => speed = (speed * 2.0).min(MAX_SPEED),
+                Key::Slower => speed = (speed / 2.0).max(MIN_SPEED),
+                Key::Step => {
+                    paused = true;
+                    view.show(player.step())?;
+                }
+                Key::NextMarker => {
+                    if let Some(marker) = player.next_marker() {
+                        view.show(player.seek(marker))?;
+                    }
+                }
+                Key::PreviousMarker => {
+                    if let Some(marker) = player.previous_marker() {
+                        view.show(player.seek(marker))?;
+                    }
+                }
+            }
+        }
+        anchor = (player.offset(), Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(
+            keys(b" \x1b[C\x1bOD+-.x\x1b[Anpq"),
+            [
+                Key::Pause, Key::Forward, Key::Back, Key::Faster, Key::Slower, Key::Step, Key::NextMarker,
+                Key::PreviousMarker, Key::Quit,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_seek_and_step() {
+        let chunk = |secs, data: &[u8]| Chunk { offset: Duration::from_secs(secs), data: data.to_vec() };
+        let chunks = [chunk(1, b"a"), chunk(5, b"b"), chunk(20, b"c")];
+        let mut player = Player::new(&chunks);
+
+        assert_eq!(player.seek(Duration::from_secs(10)), b"ab");
+        assert_eq!(player.next(), Some(Duration::from_secs(20)));
+        assert_eq!(player.step(), b"c");
+        assert!(player.at_end());
+        assert_eq!(player.seek(Duration::from_secs(60)), b"");
+        assert_eq!(player.offset(), Duration::from_secs(20));
+
+        // Back means again from a cleared screen
+        let mut back = RESET.to_vec();
+        back.extend_from_slice(b"a");
+        assert_eq!(player.seek(Duration::from_secs(2)), back);
+        assert!(bar(&player, 0.5, true, 80).starts_with(" 00:00:02 / 00:00:20  0.5x  paused"));
+        assert_eq!(bar(&player, 1.0, false, 30).chars().count(), 30);
+    }
+
+    #[test]
+    fn test_markers_are_chapters() {
+        let chunk = |secs, data: &[u8]| Chunk { offset: Duration::from_secs(secs), data: data.to_vec() };
+        let chunks = [chunk(1, b"a"), chunk(4, b""), chunk(5, b"b"), chunk(20, b""), chunk(21, b"c")];
+        let mut player = Player::new(&chunks);
+
+        assert_eq!(player.step(), b"a");
+        assert_eq!(player.step(), b"b");
+        assert_eq!(player.previous_marker(), Some(Duration::from_secs(4)));
+        assert_eq!(player.next_marker(), Some(Duration::from_secs(20)));
+        player.seek(Duration::from_secs(20));
+        assert_eq!(player.previous_marker(), Some(Duration::from_secs(4)));
+        assert_eq!(player.next_marker(), None);
+        assert!(bar(&player, 1.0, true, 120).contains("n/p marker"));
+    }
+}
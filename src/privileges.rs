@@ -0,0 +1,127 @@
+use anyhow::{anyhow, Result};
+
+#[cfg(target_os = "linux")]
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+// The user a recorder started as root was started for: the one sudo ran it
+// for, or else the owner of the terminal. None when that is root as well.
+#[cfg(target_os = "linux")]
+fn invoking_user() -> Option<(nix::unistd::Uid, nix::unistd::Gid)> {
+    use nix::unistd::{Gid, Uid, User};
+    use std::os::unix::fs::MetadataExt;
+
+    let id = |name: &str| std::env::var(name).ok()?.parse::<u32>().ok();
+    let (uid, gid) = match (id("SUDO_UID"), id("SUDO_GID")) {
+        (Some(uid), Some(gid)) => (Uid::from_raw(uid), Gid::from_raw(gid)),
+        _ => {
+            // The terminal's group is tty, so the gid is the owner's own
+            let tty = nix::unistd::ttyname(libc::STDIN_FILENO).ok()?;
+            let uid = Uid::from_raw(std::fs::metadata(tty).ok()?.uid());
+            (uid, User::from_uid(uid).ok()??.gid)
+        }
+    };
+    (!uid.is_root()).then_some((uid, gid))
+}
+
+// Root's uid and gid give up to the invoking user, for good: real, effective
+// and saved ids all change, and the supplementary groups go
+#[cfg(target_os = "linux")]
+fn leave_root() -> Result<()> {
+    use nix::unistd::{setgroups, setresgid, setresuid};
+
+    if !nix::unistd::geteuid().is_root() {
+        return Ok(());
+    }
+    let Some((uid, gid)) = invoking_user() else {
+        return Ok(());
+    };
+    setgroups(&[gid]).map_err(|e| anyhow!("Failed to set the groups: {}", e))?;
+    setresgid(gid, gid, gid).map_err(|e| anyhow!("Failed to switch to gid {}: {}", gid, e))?;
+    setresuid(uid, uid, uid).map_err(|e| anyhow!("Failed to switch to uid {}: {}", uid, e))?;
+    Ok(())
+}
+
+// A user namespace of its own, where only the recorder's uid and gid are
+// mapped, and a mount namespace whose mounts no longer propagate to or from
+// the host's: whatever the recorder could still do stays inside them
+#[cfg(target_os = "linux")]
+fn enter_namespaces() -> Result<()> {
+    use nix::mount::{mount, MsFlags};
+    use nix::sched::{unshare, CloneFlags};
+
+    let (uid, gid) = (nix::unistd::geteuid(), nix::unistd::getegid());
+    unshare(CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNS)
+        .map_err(|e| anyhow!("Failed to enter a user and mount namespace: {}", e))?;
+
+    // The gid map can only be written once setgroups is denied
+    let write = |path: &str, content: String| {
+        std::fs::write(path, content).map_err(|e| anyhow!("Failed to write {}: {}", path, e))
+    };
+    write("/proc/self/setgroups", "deny".to_string())?;
+    write("/proc/self/uid_map", format!("{} {} 1\n", uid, uid))?;
+    write("/proc/self/gid_map", format!("{} {} 1\n", gid, gid))?;
+
+    mount(None::<&str>, "/", None::<&str>, MsFlags::MS_REC | MsFlags::MS_PRIVATE, None::<&str>)
+        .map_err(|e| anyhow!("Failed to make the mounts private: {}", e))?;
+    Ok(())
+}
+
+// Capabilities are per thread, so this must run before the runtime spawns
+// any helper threads for the session; unshare() also refuses a process with
+// more than one thread
+#[cfg(target_os = "linux")]
+pub fn drop_privileges(namespaces: bool) -> Result<()> {
+    // First, while the ids can still be changed; the namespace then maps
+    // the user's ids rather than root's
+    leave_root()?;
+    if namespaces {
+        enter_namespaces()?;
+    }
+
+    unsafe {
+        // Older kernels don't know about ambient capabilities, which is fine
+        libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_CLEAR_ALL, 0, 0, 0);
+
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) == -1 {
+            return Err(anyhow!(
+                "Failed to set no_new_privs: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        let header = CapHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let data = [CapData::default(); 2];
+        if libc::syscall(libc::SYS_capset, &header as *const CapHeader, data.as_ptr()) == -1 {
+            return Err(anyhow!(
+                "Failed to clear capabilities: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn drop_privileges(_namespaces: bool) -> Result<()> {
+    Err(anyhow!("Dropping privileges is only supported on Linux"))
+}
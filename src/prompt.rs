@@ -0,0 +1,96 @@
+use anyhow::Result;
+#[cfg(not(feature = "prompts"))]
+use anyhow::anyhow;
+#[cfg(feature = "prompts")]
+use anyhow::Context;
+
+#[cfg(feature = "prompts")]
+use regex::Regex;
+
+use crate::osc133::Mark;
+use crate::vt::Lines;
+
+// Guesses the command boundaries of shells without OSC 133 integration:
+// whenever the unfinished last line of output (colors and cursor movement
+// taken out) matches the prompt regex, a prompt is showing; Enter at a
+// prompt runs a command. The marks are the ones the shell would have sent,
+// less the exit status.
+pub struct PromptDetector {
+    #[cfg(feature = "prompts")]
+    regex: Regex,
+    lines: Lines,
+    at_prompt: bool,
+    // Enter was pressed at a prompt, and no prompt has shown since
+    running: bool,
+}
+
+impl PromptDetector {
+    #[cfg(feature = "prompts")]
+    pub fn new(pattern: &str) -> Result<PromptDetector> {
+        let regex = Regex::new(pattern).with_context(|| format!("Invalid prompt regex '{}'", pattern))?;
+        Ok(PromptDetector { regex, lines: Lines::default(), at_prompt: false, running: false })
+    }
+
+    #[cfg(not(feature = "prompts"))]
+    pub fn new(_pattern: &str) -> Result<PromptDetector> {
+        Err(anyhow!("This build cannot detect prompts (feature `prompts`)"))
+    }
+
+    pub fn input(&mut self, data: &[u8]) -> Option<Mark> {
+        if !self.at_prompt || !data.iter().any(|&b| b == b'\r' || b == b'\n') {
+            return None;
+        }
+        self.at_prompt = false;
+        self.running = true;
+        Some(Mark::OutputStart)
+    }
+
+    pub fn output(&mut self, data: &[u8]) -> Vec<Mark> {
+        self.lines.feed(data);
+        // What is typed at a prompt echoes onto its line
+        if self.at_prompt || !self.is_prompt(&self.lines.current()) {
+            return Vec::new();
+        }
+
+        self.at_prompt = true;
+        let mut marks = Vec::new();
+        if std::mem::take(&mut self.running) {
+            marks.push(Mark::CommandEnd(None));
+        }
+        marks.extend([Mark::PromptStart, Mark::CommandStart]);
+        marks
+    }
+
+    #[cfg(feature = "prompts")]
+    fn is_prompt(&self, line: &str) -> bool {
+        !line.is_empty() && self.regex.is_match(line)
+    }
+
+    #[cfg(not(feature = "prompts"))]
+    fn is_prompt(&self, _line: &str) -> bool {
+        false
+    }
+}
+
+#[cfg(all(test, feature = "prompts"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompts_mark_commands() {
+        let mut detector = PromptDetector::new(r"[$#] $").unwrap();
+        assert!(detector.input(b"\r").is_none());
+        assert_eq!(detector.output(b"\x1b[32muser@host\x1b[0m:~$ "), vec![Mark::PromptStart, Mark::CommandStart]);
+
+        // Typing at the prompt, then running the command
+        assert!(detector.output(b"l").is_empty());
+        assert!(detector.input(b"s").is_none());
+        assert_eq!(detector.input(b"\r"), Some(Mark::OutputStart));
+        assert!(detector.output(b"s\r\na  b\r\n").is_empty());
+        assert!(detector.output(b"cost: 5$").is_empty());
+        assert_eq!(
+            detector.output(b"\r\nuser@host:~$ "),
+            vec![Mark::CommandEnd(None), Mark::PromptStart, Mark::CommandStart]
+        );
+    }
+}
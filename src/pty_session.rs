@@ -10,6 +10,9 @@ pub struct PtySession {
     pub is_term: bool,
     pub original_termios: Option<Termios>,
     pub window_size: Winsize,
+    // The child's terminal echoes what it is sent, unless the recorder's
+    // terminal is left in cooked mode and echoes it already
+    pub echo: bool,
 }
 
 impl PtySession {
@@ -26,10 +29,10 @@ impl PtySession {
             }
         };
 
-        // Save original terminal settings
+        // Save original terminal settings; a terminal whose settings can't
+        // be read is left as it is (see setup)
         let original_termios = if is_term {
-            let termios = Termios::from_fd(libc::STDIN_FILENO)?;
-            Some(termios)
+            Termios::from_fd(libc::STDIN_FILENO).ok()
         } else {
             None
         };
@@ -47,13 +50,16 @@ impl PtySession {
             is_term,
             original_termios,
             window_size,
+            echo: true,
         })
     }
 
     pub fn setup(&mut self) -> Result<()> {
         if self.is_term {
             // Set terminal to raw mode
-            let mut termios = Termios::from_fd(libc::STDIN_FILENO)?;
+            let Some(mut termios) = self.original_termios else {
+                return Err(anyhow!("cannot read the terminal's settings"));
+            };
             
             // Make terminal raw
             termios::cfmakeraw(&mut termios);
@@ -99,6 +105,9 @@ impl PtySession {
         termios.c_oflag = libc::OPOST | libc::ONLCR;
         termios.c_cflag = libc::CS8 | libc::CREAD | libc::CLOCAL;
         termios.c_lflag = libc::ISIG | libc::ICANON | libc::ECHO | libc::ECHOE | libc::ECHOK | libc::ECHOCTL | libc::ECHOKE;
+        if !self.echo {
+            termios.c_lflag &= !(libc::ECHO | libc::ECHOE | libc::ECHOK | libc::ECHOCTL | libc::ECHOKE);
+        }
         
         // Set control characters
         termios.c_cc[libc::VINTR] = 3;    // Ctrl+C
@@ -141,6 +150,45 @@ impl PtySession {
     pub fn get_slave_fd(&self) -> RawFd {
         self.slave_fd
     }
+
+    // Closed in the parent once the child has its copy, so that reading the
+    // master fails with EIO when the last process using the terminal is gone
+    pub fn close_slave(&mut self) {
+        if self.slave_fd >= 0 {
+            let _ = close(self.slave_fd);
+            self.slave_fd = -1;
+        }
+    }
+
+    // Input the child has yet to read; a line still being typed in canonical
+    // mode isn't counted until it is complete
+    pub fn pending_input(&self) -> Result<usize> {
+        // With the slave closed, it is opened just for the question; closing
+        // it again ends the session the same way if nothing else has it open
+        let peer = match self.slave_fd {
+            -1 => match unsafe { libc::ioctl(self.master_fd, libc::TIOCGPTPEER, libc::O_RDWR | libc::O_NOCTTY) } {
+                -1 => return Err(std::io::Error::last_os_error().into()),
+                fd => Some(fd),
+            },
+            _ => None,
+        };
+        let mut count: libc::c_int = 0;
+        let ret = unsafe { libc::ioctl(peer.unwrap_or(self.slave_fd), libc::FIONREAD, &mut count) };
+        let error = std::io::Error::last_os_error();
+        if let Some(fd) = peer {
+            let _ = close(fd);
+        }
+        if ret == -1 {
+            return Err(error.into());
+        }
+        Ok(count as usize)
+    }
+
+    pub fn get_termios(&self) -> Result<Termios> {
+        // Asked of the master, this is about the slave: whatever modes the
+        // child left the terminal in, whether or not the slave is still open
+        Ok(Termios::from_fd(self.master_fd)?)
+    }
 }
 
 impl Drop for PtySession {
@@ -152,6 +200,6 @@ impl Drop for PtySession {
 
         // Close file descriptors
         let _ = close(self.master_fd);
-        let _ = close(self.slave_fd);
+        self.close_slave();
     }
 }
\ No newline at end of file
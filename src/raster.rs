@@ -0,0 +1,334 @@
+use anyhow::{anyhow, Context, Result};
+use embedded_graphics::mono_font::iso_8859_1::{FONT_8X13, FONT_8X13_BOLD};
+use embedded_graphics::mono_font::MonoTextStyleBuilder;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::{Baseline, Text};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::time::Duration;
+
+use crate::export::{Mark, Position, Watermark};
+use crate::frames::Frame;
+use crate::vt::Color;
+
+// Cell size of the bundled font, and the margin around the screen
+const CELL: (u32, u32) = (8, 13);
+const MARGIN: u32 = 8;
+
+// The screen's own background and text, as entries of the xterm 256-color
+// palette the GIF uses
+const BACKGROUND: Ink = Ink(234);
+const FOREGROUND: Ink = Ink(252);
+
+// How long the last screen stays before the animation starts over
+const HOLD: Duration = Duration::from_secs(2);
+
+// GIF delays count hundredths of a second in 16 bits
+const MAX_DELAY: u16 = u16::MAX;
+
+// An entry of the palette
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Ink(u8);
+
+impl PixelColor for Ink {
+    type Raw = ();
+}
+
+impl Ink {
+    // True color goes to the nearest entry of the cube or the grays
+    fn of(color: Color, default: Ink) -> Ink {
+        let (r, g, b) = match color {
+            Color::Default => return default,
+            Color::Indexed(n) => return Ink(n),
+            Color::Rgb(r, g, b) => (r, g, b),
+        };
+        Ink::nearest((r, g, b))
+    }
+
+    fn nearest((r, g, b): (u8, u8, u8)) -> Ink {
+        let distance = |n: u8| {
+            let (r2, g2, b2) = Color::Indexed(n).rgb().unwrap_or_default();
+            [(r, r2), (g, g2), (b, b2)].iter().map(|&(a, b)| (a as i32 - b as i32).pow(2)).sum::<i32>()
+        };
+        Ink((16..=255).min_by_key(|&n| distance(n)).unwrap_or(FOREGROUND.0))
+    }
+}
+
+fn palette() -> Vec<u8> {
+    (0..=255)
+        .flat_map(|n| {
+            let (r, g, b) = Color::Indexed(n).rgb().unwrap_or_default();
+            [r, g, b]
+        })
+        .collect()
+}
+
+// One frame of pixels, each an entry of the palette
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl OriginDimensions for Canvas {
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+impl DrawTarget for Canvas {
+    type Color = Ink;
+    type Error = Infallible;
+
+    fn draw_iter<I: IntoIterator<Item = Pixel<Ink>>>(&mut self, pixels: I) -> Result<(), Infallible> {
+        for Pixel(point, Ink(color)) in pixels {
+            let (x, y) = (point.x as u32, point.y as u32);
+            if point.x >= 0 && point.y >= 0 && x < self.width && y < self.height {
+                self.pixels[(y * self.width + x) as usize] = color;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Bit n of the attributes is SGR n; the font has no italic
+fn draw(frame: &Frame, width: u32, height: u32) -> Vec<u8> {
+    let mut canvas = Canvas { width, height, pixels: vec![BACKGROUND.0; (width * height) as usize] };
+    for (row, runs) in frame.runs.iter().enumerate() {
+        let mut col = 0;
+        for run in runs {
+            let attr = |n: u16| run.style.attrs & (1 << n) != 0;
+            let (mut fg, mut bg) = (Ink::of(run.style.fg, FOREGROUND), Ink::of(run.style.bg, BACKGROUND));
+            if attr(7) {
+                (fg, bg) = (bg, fg);
+            }
+            let mut style = MonoTextStyleBuilder::new()
+                .font(if attr(1) { &FONT_8X13_BOLD } else { &FONT_8X13 })
+                .text_color(fg)
+                .background_color(bg);
+            if attr(4) {
+                style = style.underline();
+            }
+            if attr(9) {
+                style = style.strikethrough();
+            }
+            let at = Point::new((MARGIN + col * CELL.0) as i32, (MARGIN + row as u32 * CELL.1) as i32);
+            let _ = Text::with_baseline(&run.text, at, style.build(), Baseline::Top).draw(&mut canvas);
+            col += run.text.chars().count() as u32;
+        }
+    }
+    canvas.pixels
+}
+
+// A watermark as RGBA pixels at its place on the frame, blended into each
+// frame at its opacity
+struct Overlay {
+    x: u32,
+    y: u32,
+    width: u32,
+    rgba: Vec<u8>,
+    opacity: f64,
+    // What each palette entry under each pixel color turns into
+    blends: HashMap<(u8, [u8; 4]), u8>,
+}
+
+impl Overlay {
+    fn new(watermark: &Watermark, width: u32, height: u32) -> Result<Overlay> {
+        let (mark_width, rgba) = match watermark.mark {
+            Mark::Text(ref text) => {
+                let cols = text.chars().count() as u32;
+                let mut canvas = Canvas { width: cols * CELL.0, height: CELL.1, pixels: vec![0; (cols * CELL.0 * CELL.1) as usize] };
+                let style = MonoTextStyleBuilder::new().font(&FONT_8X13_BOLD).text_color(Ink(1)).build();
+                let _ = Text::with_baseline(text, Point::zero(), style, Baseline::Top).draw(&mut canvas);
+                let rgba = canvas.pixels.iter().flat_map(|&p| if p == 1 { [255, 255, 255, 255] } else { [0; 4] }).collect();
+                (canvas.width, rgba)
+            }
+            Mark::Image(ref path) => {
+                let file = std::fs::File::open(path).with_context(|| format!("Failed to read watermark image: {}", path.display()))?;
+                let mut options = gif::DecodeOptions::new();
+                options.set_color_output(gif::ColorOutput::RGBA);
+                let image = options
+                    .read_info(file)
+                    .and_then(|mut decoder| decoder.read_next_frame().map(|frame| frame.map(|frame| (frame.width, frame.buffer.to_vec()))))
+                    .ok()
+                    .flatten()
+                    .ok_or_else(|| anyhow!("A GIF's watermark image has to be a GIF: {}", path.display()))?;
+                (image.0 as u32, image.1)
+            }
+        };
+        let mark_height = (rgba.len() / 4) as u32 / mark_width.max(1);
+        let right = width.saturating_sub(MARGIN + mark_width);
+        let bottom = height.saturating_sub(MARGIN + mark_height);
+        let (x, y) = match watermark.position {
+            Position::TopLeft => (MARGIN, MARGIN),
+            Position::TopRight => (right, MARGIN),
+            Position::BottomLeft => (MARGIN, bottom),
+            Position::BottomRight => (right, bottom),
+            Position::Bottom => (width.saturating_sub(mark_width) / 2, bottom),
+        };
+        Ok(Overlay { x, y, width: mark_width, rgba, opacity: watermark.opacity, blends: HashMap::new() })
+    }
+
+    // Whatever falls outside the frame is cut off
+    fn apply(&mut self, pixels: &mut [u8], width: u32) {
+        let height = pixels.len() as u32 / width;
+        for (i, rgba) in self.rgba.chunks_exact(4).enumerate() {
+            let (x, y) = (self.x + i as u32 % self.width.max(1), self.y + i as u32 / self.width.max(1));
+            let alpha = rgba[3] as f64 / 255.0 * self.opacity;
+            if x >= width || y >= height || alpha == 0.0 {
+                continue;
+            }
+            let pixel = &mut pixels[(y * width + x) as usize];
+            *pixel = *self.blends.entry((*pixel, [rgba[0], rgba[1], rgba[2], rgba[3]])).or_insert_with(|| {
+                let under = Color::Indexed(*pixel).rgb().unwrap_or_default();
+                let mix = |a: u8, b: u8| (a as f64 * (1.0 - alpha) + b as f64 * alpha).round() as u8;
+                Ink::nearest((mix(under.0, rgba[0]), mix(under.1, rgba[1]), mix(under.2, rgba[2]))).0
+            });
+        }
+    }
+}
+
+// Which frames to show, and for how long: a recording is sampled `fps`
+// times a second up to `max_duration`, and a screen that doesn't change
+// from one sample to the next simply stays
+pub fn timeline(frames: &[Frame], fps: u32, max_duration: Option<Duration>) -> Vec<(&Frame, Duration)> {
+    let tick = Duration::from_secs(1) / fps.max(1);
+    let end = max_duration.unwrap_or(Duration::MAX);
+
+    // The last frame of each tick, shown from the start of the tick
+    let mut shown: Vec<(&Frame, u32)> = Vec::new();
+    for frame in frames.iter().take_while(|frame| frame.offset <= end) {
+        let slot = (frame.offset.as_nanos() / tick.as_nanos()) as u32;
+        match shown.last_mut() {
+            Some(last) if last.1 == slot => last.0 = frame,
+            _ => shown.push((frame, slot)),
+        }
+    }
+
+    (0..shown.len())
+        .map(|i| {
+            let start = shown[i].1 * tick;
+            let until = shown.get(i + 1).map_or(start + HOLD, |next| next.1 * tick);
+            (shown[i].0, until.min(end).max(start + tick) - start)
+        })
+        .collect()
+}
+
+pub fn render(frames: &[Frame], fps: u32, max_duration: Option<Duration>, watermark: Option<&Watermark>) -> Result<Vec<u8>> {
+    let timeline = timeline(frames, fps, max_duration);
+    let cols = timeline.iter().map(|(frame, _)| frame.cols).max().unwrap_or(80) as u32;
+    let rows = timeline.iter().map(|(frame, _)| frame.lines.len()).max().unwrap_or(24) as u32;
+    let (width, height) = (cols * CELL.0 + 2 * MARGIN, rows * CELL.1 + 2 * MARGIN);
+    let size = |value: u32| u16::try_from(value).map_err(|_| anyhow!("The screen is too large for a GIF: {}x{}", cols, rows));
+
+    let mut overlay = watermark.map(|watermark| Overlay::new(watermark, width, height)).transpose()?;
+
+    let mut encoder = gif::Encoder::new(Vec::new(), size(width)?, size(height)?, &palette())?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+    for (frame, shown_for) in timeline {
+        let mut pixels = draw(frame, width, height);
+        if let Some(ref mut overlay) = overlay {
+            overlay.apply(&mut pixels, width);
+        }
+        // Pauses longer than a GIF delay are split up
+        let mut left = (shown_for.as_millis() / 10).max(1);
+        while left > 0 {
+            let delay = left.min(MAX_DELAY as u128) as u16;
+            left -= delay as u128;
+            encoder.write_frame(&gif::Frame {
+                width: width as u16,
+                height: height as u16,
+                buffer: Cow::Borrowed(&pixels),
+                delay,
+                ..gif::Frame::default()
+            })?;
+        }
+    }
+    Ok(encoder.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(ms: u64, text: &str) -> Frame {
+        Frame::plain(Duration::from_millis(ms), 10, &[text, ""])
+    }
+
+    #[test]
+    fn test_sampled_timeline() {
+        let frames = [frame(0, ""), frame(120, "$"), frame(150, "$ l"), frame(400, "$ ls"), frame(5000, "a")];
+        let shown: Vec<(&str, u128)> = timeline(&frames, 10, None)
+            .into_iter()
+            .map(|(frame, duration)| (frame.lines[0].as_str(), duration.as_millis()))
+            .collect();
+        assert_eq!(shown, vec![("", 100), ("$ l", 300), ("$ ls", 4600), ("a", 2000)]);
+
+        // Cut at one second, the last screen stays until then
+        let cut = timeline(&frames, 10, Some(Duration::from_secs(1)));
+        assert_eq!(cut.last().map(|(frame, duration)| (frame.lines[0].as_str(), duration.as_millis())), Some(("$ ls", 600)));
+    }
+
+    #[test]
+    fn test_gif_of_frames() {
+        let gif = render(&[frame(0, "hi"), frame(500, "hi there")], 10, None, None).unwrap();
+        assert!(gif.starts_with(b"GIF89a"));
+        // 10x2 cells of 8x13 pixels, with the margin
+        assert_eq!(&gif[6..10], &[96, 0, 42, 0]);
+
+        let pixels = draw(&frame(0, "#"), 96, 42);
+        assert!(pixels.contains(&FOREGROUND.0));
+        assert!(!draw(&frame(0, " "), 96, 42).contains(&FOREGROUND.0));
+    }
+
+    #[test]
+    fn test_gif_colors() {
+        use crate::frames::Run;
+        use crate::vt::Style;
+
+        let mut inverse = frame(0, "");
+        let style = Style { fg: Color::Indexed(1), bg: Color::Rgb(0, 0, 250), attrs: 1 << 7 };
+        inverse.runs[0] = vec![Run { text: "#".to_string(), style, link: None, image: None }];
+        let pixels = draw(&inverse, 96, 42);
+        // Blue, the nearest of the cube, on red
+        assert!(pixels.contains(&1));
+        assert!(pixels.contains(&21));
+        assert_eq!(Ink::of(Color::Rgb(0xd0, 0xd0, 0xd0), BACKGROUND), FOREGROUND);
+        assert_eq!(&palette()[234 * 3..235 * 3], &[28, 28, 28]);
+    }
+
+    #[test]
+    fn test_gif_watermark() {
+        let text = Watermark { mark: Mark::Text("ab".to_string()), position: Position::BottomRight, opacity: 1.0 };
+        let mut overlay = Overlay::new(&text, 96, 42).unwrap();
+        assert_eq!((overlay.x, overlay.y, overlay.width), (72, 21, 16));
+        let mut pixels = draw(&frame(0, ""), 96, 42);
+        overlay.apply(&mut pixels, 96);
+        assert!(pixels.contains(&231));
+
+        // Half way between the background and white
+        let mut faint = Overlay::new(&Watermark { opacity: 0.5, ..text.clone() }, 96, 42).unwrap();
+        let mut pixels = draw(&frame(0, ""), 96, 42);
+        faint.apply(&mut pixels, 96);
+        assert!(!pixels.contains(&231));
+        assert!(pixels.contains(&Ink::nearest((142, 142, 142)).0));
+
+        // An image from a GIF: one opaque red pixel, one clear
+        let mut encoder = gif::Encoder::new(Vec::new(), 2, 1, &[255, 0, 0, 0, 0, 0]).unwrap();
+        let image = gif::Frame { width: 2, height: 1, buffer: Cow::Borrowed(&[0, 1]), transparent: Some(1), ..gif::Frame::default() };
+        encoder.write_frame(&image).unwrap();
+        let path = std::env::temp_dir().join(format!("rust_script-{}-mark.gif", std::process::id()));
+        std::fs::write(&path, encoder.into_inner().unwrap()).unwrap();
+        let image = Watermark { mark: Mark::Image(path.clone()), position: Position::TopLeft, opacity: 1.0 };
+        let overlay = Overlay::new(&image, 96, 42);
+        std::fs::write(&path, b"\x89PNG").unwrap();
+        let png = Overlay::new(&image, 96, 42);
+        std::fs::remove_file(&path).unwrap();
+        let mut overlay = overlay.unwrap();
+        let mut pixels = draw(&frame(0, ""), 96, 42);
+        overlay.apply(&mut pixels, 96);
+        assert_eq!(&pixels[8 * 96 + 8..8 * 96 + 10], &[196, BACKGROUND.0]);
+        assert!(png.is_err());
+    }
+}
@@ -0,0 +1,578 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "asciicast")]
+use crate::asciicast;
+use crate::clock::{Clock, SystemClock};
+use crate::compression;
+use crate::config;
+#[cfg(feature = "screenshot")]
+use crate::exectrace;
+use crate::images::ImageScanner;
+#[cfg(feature = "player")]
+use crate::player;
+#[cfg(feature = "screenshot")]
+use crate::screenshot;
+use crate::seekindex;
+use crate::timing::{self, parse_offset, parse_timing, skip_header, EntryKind, TimingEntry};
+use crate::ttyrec;
+use crate::utils;
+#[cfg(feature = "screenshot")]
+use crate::vt::Screen;
+
+// An output record of a recording: its bytes and when they were written,
+// counted from the start. A marker (--auto-marker) is one without bytes,
+// for the interactive player to jump to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub offset: Duration,
+    pub data: Vec<u8>,
+}
+
+// Read the output stream of `log` as the timing entries cut it up. When the
+// log also holds the input stream, input records consume their bytes
+// without showing them.
+pub fn decode<R: Read>(entries: &[TimingEntry], mut log: R, input_in_log: bool) -> Result<Vec<Chunk>> {
+    let pending = skip_header(&mut log)?;
+    decode_from(entries, pending.as_slice().chain(log), input_in_log, Duration::ZERO)
+}
+
+// The same from a point inside the log, the entries starting there at `offset`
+fn decode_from<R: Read>(
+    entries: &[TimingEntry],
+    mut log: R,
+    input_in_log: bool,
+    mut offset: Duration,
+) -> Result<Vec<Chunk>> {
+    let mut chunks = Vec::new();
+    for entry in entries {
+        offset += entry.delay;
+        let size = match entry.kind {
+            EntryKind::Output(size) => size,
+            EntryKind::Input(size) if input_in_log => size,
+            EntryKind::Signal(ref name, _) if name == "MARKER" => {
+                chunks.push(Chunk { offset, data: Vec::new() });
+                continue;
+            }
+            _ => continue,
+        };
+        // Only as much as the log holds, whatever size the timing file claims
+        let mut data = Vec::new();
+        (&mut log).take(size as u64).read_to_end(&mut data)?;
+        if data.len() < size {
+            return Err(anyhow!("log file is shorter than its timing file"));
+        }
+        if matches!(entry.kind, EntryKind::Output(_)) {
+            chunks.push(Chunk { offset, data });
+        }
+    }
+    Ok(chunks)
+}
+
+// Write the chunks to `out`, sleeping between them as they were written
+pub fn replay<W: Write>(chunks: &[Chunk], out: &mut W, clock: &dyn Clock) -> Result<()> {
+    // Deadlines are cumulative so rounding in each sleep doesn't add up
+    let start = clock.now();
+    for chunk in chunks {
+        // Output later than the clock can count is never reached
+        let Some(deadline) = start.checked_add(chunk.offset) else {
+            break;
+        };
+        clock.sleep_until(deadline);
+        out.write_all(&chunk.data)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+// Output no faster than `rate` bytes a second, as over a slow line. It goes
+// out a hundredth of a second's worth at a time, each piece once the line
+// is done with the one before, so output recorded in a burst trickles out
+// and what comes after it waits its turn.
+pub struct Throttled<'a, W: Write> {
+    out: W,
+    clock: &'a dyn Clock,
+    rate: f64,
+    // When the line is done with what was written last
+    free_at: Option<Instant>,
+}
+
+impl<'a, W: Write> Throttled<'a, W> {
+    pub fn new(out: W, clock: &'a dyn Clock, rate: f64) -> Self {
+        Throttled { out, clock, rate, free_at: None }
+    }
+}
+
+impl<W: Write> Write for Throttled<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let piece = ((self.rate / 100.0) as usize).clamp(1, buf.len().max(1));
+        let now = self.clock.now();
+        let start = match self.free_at {
+            Some(free_at) if free_at > now => {
+                self.clock.sleep_until(free_at);
+                free_at
+            }
+            _ => now,
+        };
+        let written = self.out.write(&buf[..piece.min(buf.len())])?;
+        self.out.flush()?;
+        let busy = utils::secs_to_duration(written as f64 / self.rate).unwrap_or(Duration::MAX);
+        self.free_at = start.checked_add(busy);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// Output for a terminal running tmux: each record is passed through to the
+// terminal tmux itself runs in, as `ESC P tmux; ... ESC \` with every ESC
+// doubled, so graphics and colors tmux would redo its own way arrive as
+// recorded. tmux only lets it through with `allow-passthrough on`.
+pub struct TmuxPassthrough<W: Write>(pub W);
+
+impl<W: Write> Write for TmuxPassthrough<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut wrapped = Vec::with_capacity(buf.len() + 16);
+        wrapped.extend_from_slice(b"\x1bPtmux;");
+        for &byte in buf {
+            if byte == 0x1b {
+                wrapped.push(0x1b);
+            }
+            wrapped.push(byte);
+        }
+        wrapped.extend_from_slice(b"\x1b\\");
+        self.0.write_all(&wrapped)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+// "auto" passes output through when running inside tmux
+fn passthrough(mode: &str) -> Result<bool> {
+    match mode {
+        "auto" => Ok(config::tmux()),
+        "always" => Ok(true),
+        "never" => Ok(false),
+        _ => Err(anyhow!("Invalid --tmux-passthrough: '{}' (auto, always or never)", mode)),
+    }
+}
+
+// "auto" shows inline images on terminals known to show them
+fn show_images(mode: &str) -> Result<bool> {
+    match mode {
+        "auto" => Ok(config::inline_images()),
+        "always" => Ok(true),
+        "never" => Ok(false),
+        _ => Err(anyhow!("Invalid --images: '{}' (auto, always or never)", mode)),
+    }
+}
+
+// Inline images taken out, each leaving [image], for a terminal that would
+// show their data as text
+fn strip_images(chunks: Vec<Chunk>) -> Vec<Chunk> {
+    let mut scanner = ImageScanner::default();
+    chunks
+        .into_iter()
+        .map(|chunk| Chunk { offset: chunk.offset, data: scanner.strip(&chunk.data) })
+        .collect()
+}
+
+// "screen" leaves the viewer looking at the screen the session ended with,
+// "newline" ends as scriptreplay does
+fn resume_screen(style: &str) -> Result<bool> {
+    match style {
+        "newline" => Ok(false),
+        "screen" if cfg!(feature = "screenshot") => Ok(true),
+        "screen" => Err(anyhow!("This build cannot redraw the screen a recording ends on (feature `screenshot`)")),
+        _ => Err(anyhow!("Invalid --resume-style: '{}' (newline or screen)", style)),
+    }
+}
+
+// What --resume-style screen writes once the output has played: the screen
+// and cursor the recorder saw at the end (EXIT_SCREEN and EXIT_CURSOR), or
+// for a recording without them, what its output leaves on an emulated
+// screen. It is drawn from the top left of a cleared screen.
+#[cfg(feature = "screenshot")]
+fn ending(entries: &[TimingEntry], chunks: &[Chunk]) -> Vec<u8> {
+    let cursor = timing::info(entries, "EXIT_CURSOR").and_then(|message| {
+        let field = |key: &str| message.split(' ').find_map(|f| f.strip_prefix(key)?.parse::<usize>().ok());
+        Some((field("ROW=")?, field("COL=")?))
+    });
+    let recorded = timing::info(entries, "EXIT_SCREEN").and_then(exectrace::unescape).zip(cursor);
+    let (text, (row, col)) = recorded.unwrap_or_else(|| {
+        let size = |name| timing::info(entries, name).and_then(|v| v.parse().ok()).filter(|&v| v > 0);
+        let mut screen = Screen::new(size("COLUMNS").unwrap_or(80), size("LINES").unwrap_or(24));
+        for chunk in chunks {
+            screen.feed(&chunk.data, 0);
+        }
+        let (row, col) = screen.cursor();
+        (screenshot::render(&screen, true), (row + 1, col + 1))
+    });
+
+    let mut out = b"\x1b[0m\x1b[H\x1b[2J".to_vec();
+    out.extend_from_slice(text.trim_end_matches('\n').replace('\n', "\r\n").as_bytes());
+    out.extend_from_slice(format!("\x1b[{};{}H", row, col).as_bytes());
+    out
+}
+
+// The log read from the last point of its seek index at or before `start`:
+// how many timing entries that leaves out, the time they take, and the log
+// positioned after their bytes. None without a usable index.
+fn seek_log(
+    entries: &[TimingEntry],
+    log_path: &Path,
+    input_in_log: bool,
+    start: Duration,
+) -> Result<Option<(usize, Duration, BufReader<File>)>> {
+    let Ok(index) = std::fs::read_to_string(seekindex::index_path(log_path)) else {
+        return Ok(None);
+    };
+    let Some((_, offset)) = seekindex::point(&seekindex::parse(&index), start) else {
+        return Ok(None);
+    };
+    let mut log = File::open(log_path)?;
+    let header = match skip_header(&mut log)?.as_slice() {
+        [] => log.stream_position()?,
+        _ => 0,
+    };
+    let located = offset.checked_sub(header).and_then(|bytes| seekindex::locate(entries, bytes, input_in_log));
+    let Some((skipped, time)) = located else {
+        return Ok(None);
+    };
+    log.seek(SeekFrom::Start(offset))?;
+    Ok(Some((skipped, time, BufReader::new(log))))
+}
+
+// Pauses longer than `max` cut down to it, as scriptreplay --maxdelay does,
+// and `start` moved with the output around it
+pub fn cap_pauses(chunks: Vec<Chunk>, max: Duration, start: Duration) -> (Vec<Chunk>, Duration) {
+    let (mut original, mut capped) = (Duration::ZERO, Duration::ZERO);
+    let mut new_start = None;
+    let mut out = Vec::with_capacity(chunks.len());
+    for chunk in chunks {
+        if new_start.is_none() && start <= chunk.offset {
+            new_start = Some(capped + start.saturating_sub(original).min(max));
+        }
+        capped += chunk.offset.saturating_sub(original).min(max);
+        original = chunk.offset;
+        out.push(Chunk { offset: capped, data: chunk.data });
+    }
+    let start = new_start.unwrap_or_else(|| capped + start.saturating_sub(original).min(max));
+    (out, start)
+}
+
+// --speed: x2, 2x or 2; 0.5 for half speed
+fn parse_speed(speed: &str) -> Result<f64> {
+    let number = speed.strip_prefix('x').or_else(|| speed.strip_suffix('x')).unwrap_or(speed);
+    number
+        .parse()
+        .ok()
+        .filter(|n: &f64| n.is_normal() && *n > 0.0)
+        .ok_or_else(|| anyhow!("Invalid --speed: '{}' (e.g. x2 or x0.5)", speed))
+}
+
+// --throttle: the line's speed in bits a second, as 9600bps, 56k or 1.5M;
+// each byte takes eight. Returns bytes a second.
+fn parse_throttle(rate: &str) -> Result<f64> {
+    let number = rate.strip_suffix("bps").unwrap_or(rate);
+    let (number, scale) = match number.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&number[..i], 1e3),
+        Some((i, 'M')) => (&number[..i], 1e6),
+        Some((i, 'G')) => (&number[..i], 1e9),
+        _ => (number, 1.0),
+    };
+    number
+        .parse()
+        .ok()
+        .map(|n: f64| n * scale)
+        .filter(|bits| bits.is_finite() && *bits >= 1.0)
+        .map(|bits| bits / 8.0)
+        .ok_or_else(|| anyhow!("Invalid --throttle: '{}' (e.g. 9600bps or 56k)", rate))
+}
+
+// Every delay divided by `divisor`, `start` with them. A tiny divisor
+// stretches them no further than a Duration goes.
+fn speed_up(chunks: Vec<Chunk>, divisor: f64, start: Duration) -> (Vec<Chunk>, Duration) {
+    let divide = |offset: Duration| utils::secs_to_duration(offset.as_secs_f64() / divisor).unwrap_or(Duration::MAX);
+    let chunks = chunks
+        .into_iter()
+        .map(|chunk| Chunk { offset: divide(chunk.offset), data: chunk.data })
+        .collect();
+    (chunks, divide(start))
+}
+
+pub struct ReplayOptions<'a> {
+    // auto, always or never
+    pub tmux: &'a str,
+    // Under the control bar of player.rs
+    pub interactive: bool,
+    // A time to skip ahead to, as `blame --at` takes it
+    pub start: Option<&'a str>,
+    // Seconds, of the pauses as played: after the divisor
+    pub max_delay: Option<f64>,
+    // --divisor N, or --speed as x2
+    pub divisor: Option<f64>,
+    pub speed: Option<&'a str>,
+    // The line speed to play at, as 9600bps
+    pub throttle: Option<&'a str>,
+    // auto, always or never
+    pub images: &'a str,
+    // newline or screen
+    pub resume_style: &'a str,
+}
+
+pub fn run(
+    timing: Option<&Path>,
+    log_io: Option<&Path>,
+    identity: Option<&Path>,
+    typescript: Option<&Path>,
+    options: &ReplayOptions,
+) -> Result<()> {
+    let interactive = options.interactive;
+    let start = options.start.map(parse_offset).transpose()?.unwrap_or(Duration::ZERO);
+    let max_delay = options
+        .max_delay
+        .map(|seconds| utils::secs_to_duration(seconds).ok_or_else(|| anyhow!("Invalid --maxdelay: {:?}", seconds)))
+        .transpose()?;
+    let divisor = match (options.divisor, options.speed) {
+        (Some(divisor), _) if divisor.is_normal() && divisor > 0.0 => divisor,
+        (Some(divisor), _) => return Err(anyhow!("Invalid --divisor: {:?}", divisor)),
+        (None, Some(speed)) => parse_speed(speed)?,
+        (None, None) => 1.0,
+    };
+    let throttle = options.throttle.map(parse_throttle).transpose()?;
+    // The control bar is drawn on the terminal tmux gives us
+    let passthrough = passthrough(options.tmux)? && !interactive;
+    let resume = resume_screen(options.resume_style)?;
+    let images = show_images(options.images)?;
+    #[cfg_attr(not(feature = "screenshot"), allow(unused_variables))]
+    let play = |entries: &[TimingEntry], chunks: Vec<Chunk>| {
+        #[cfg(feature = "screenshot")]
+        let ending = resume.then(|| ending(entries, &chunks));
+        #[cfg(not(feature = "screenshot"))]
+        let ending = None;
+        let chunks = if images { chunks } else { strip_images(chunks) };
+        // As scriptreplay: --maxdelay caps the pauses once they are divided
+        let (chunks, start) = speed_up(chunks, divisor, start);
+        let (chunks, start) = match max_delay {
+            Some(max) => cap_pauses(chunks, max, start),
+            None => (chunks, start),
+        };
+        show(chunks, passthrough, throttle, interactive, start, ending)
+    };
+    let log_path = log_io.or(typescript).unwrap_or(Path::new("typescript"));
+    let mut log = compression::open_with(log_path, identity)?;
+
+    // asciicast and ttyrec files carry their own timing
+    let is_ttyrec = log_path.extension().is_some_and(|ext| ext == "ttyrec")
+        || ttyrec::looks_like_ttyrec(log.fill_buf()?);
+    if is_ttyrec && timing.is_none() {
+        let mut content = Vec::new();
+        log.read_to_end(&mut content)?;
+        let (entries, data) = ttyrec::parse(&content)?;
+        return play(&entries, decode(&entries, data.as_slice(), false)?);
+    }
+
+    #[cfg(feature = "asciicast")]
+    if log_path.extension().is_some_and(|ext| ext == "cast") || asciicast::is_asciicast(log.fill_buf()?) {
+        let mut content = String::new();
+        log.read_to_string(&mut content)?;
+        let (entries, data) = asciicast::parse(&content)?;
+        return play(&entries, decode(&entries, data.as_slice(), true)?);
+    }
+    #[cfg(not(feature = "asciicast"))]
+    if log_path.extension().is_some_and(|ext| ext == "cast") {
+        return Err(anyhow!("This build cannot replay asciicast files (feature `asciicast`)"));
+    }
+
+    let timing = timing.ok_or_else(|| anyhow!("--timing is required unless replaying an asciicast or ttyrec file"))?;
+    let mut content = String::new();
+    compression::open_with(timing, identity)
+        .and_then(|mut file| Ok(file.read_to_string(&mut content)?))
+        .context("Cannot read timing file")?;
+    let entries = parse_timing(&content)?;
+
+    // Only the part of a long recording from its seek point on is read,
+    // unless the screen it ends with has to be worked out from all of it
+    let input_in_log = log_io.is_some();
+    let recorded_ending = timing::info(&entries, "EXIT_SCREEN").is_some();
+    if start > Duration::ZERO && (!resume || recorded_ending) {
+        if let Some((skipped, time, log)) = seek_log(&entries, log_path, input_in_log, start)? {
+            return play(&entries, decode_from(&entries[skipped..], log, input_in_log, time)?);
+        }
+    }
+    play(&entries, decode(&entries, log, input_in_log)?)
+}
+
+// The output before `start` is written at once, the rest as it was, but no
+// faster than `throttle` bytes a second
+fn show(
+    chunks: Vec<Chunk>,
+    passthrough: bool,
+    throttle: Option<f64>,
+    interactive: bool,
+    start: Duration,
+    ending: Option<Vec<u8>>,
+) -> Result<()> {
+    #[cfg(feature = "player")]
+    if interactive {
+        return player::run(&chunks, start);
+    }
+    #[cfg(not(feature = "player"))]
+    if interactive {
+        return Err(anyhow!("This build has no interactive player (feature `player`)"));
+    }
+    let chunks: Vec<Chunk> = chunks
+        .into_iter()
+        .map(|chunk| Chunk { offset: chunk.offset.saturating_sub(start), data: chunk.data })
+        .collect();
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    {
+        let mut played: Box<dyn Write> = match passthrough {
+            true => Box::new(TmuxPassthrough(&mut out)),
+            false => Box::new(&mut out),
+        };
+        // Counted in the recording's bytes, before tmux wraps them
+        if let Some(rate) = throttle {
+            played = Box::new(Throttled::new(played, &SystemClock, rate));
+        }
+        replay(&chunks, &mut played, &SystemClock)?;
+    }
+
+    match ending {
+        Some(ending) => out.write_all(&ending)?,
+        // scriptreplay ends with a newline too
+        None => writeln!(out)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use chrono::DateTime;
+
+    fn manual_clock() -> ManualClock {
+        ManualClock::new(DateTime::parse_from_rfc3339("2024-01-02T03:04:05+00:00").unwrap())
+    }
+
+    #[test]
+    fn test_replay_skips_input_and_keeps_time() {
+        let log = b"Script started on 2024-01-02 [x]\nlsls\r\nfile\r\n";
+        let entries = parse_timing("H 0.000000 START_TIME x\nI 1.0 2\nO 0.5 4\nO 0.5 6\n").unwrap();
+        let clock = manual_clock();
+        let start = clock.now();
+        let mut out = Vec::new();
+
+        let chunks = decode(&entries, &log[..], true).unwrap();
+        assert_eq!(chunks[1], Chunk { offset: Duration::from_secs(2), data: b"file\r\n".to_vec() });
+        replay(&chunks, &mut out, &clock).unwrap();
+
+        assert_eq!(out, b"ls\r\nfile\r\n");
+        assert_eq!(clock.now() - start, Duration::from_secs(2));
+
+        // A size the log can't back is an error, not an allocation
+        let entries = parse_timing("0.1 100000000000000\n").unwrap();
+        assert!(decode(&entries, &b"short"[..], false).is_err());
+    }
+
+    #[test]
+    fn test_cap_pauses() {
+        let chunk = |secs: f64| Chunk { offset: Duration::from_secs_f64(secs), data: b"x".to_vec() };
+        let chunks = vec![chunk(0.5), chunk(30.0), chunk(31.0), chunk(100.0)];
+        let (capped, start) = cap_pauses(chunks.clone(), Duration::from_secs(2), Duration::from_secs(60));
+        let offsets: Vec<_> = capped.iter().map(|chunk| chunk.offset.as_millis()).collect();
+        assert_eq!(offsets, [500, 2500, 3500, 5500]);
+        // Halfway through the long pause, which is cut to 2s
+        assert_eq!(start, Duration::from_millis(5500));
+        assert_eq!(cap_pauses(chunks, Duration::from_secs(2), Duration::from_secs(200)).1, Duration::from_millis(7500));
+    }
+
+    #[test]
+    fn test_speed_then_cap() {
+        let chunk = |secs: u64| Chunk { offset: Duration::from_secs(secs), data: b"x".to_vec() };
+        let (chunks, start) = speed_up(vec![chunk(1), chunk(3), chunk(13)], 2.0, Duration::from_secs(8));
+        let (chunks, start) = cap_pauses(chunks, Duration::from_secs(2), start);
+        let offsets: Vec<_> = chunks.iter().map(|chunk| chunk.offset.as_millis()).collect();
+        assert_eq!(offsets, [500, 1500, 3500]);
+        assert_eq!(start, Duration::from_millis(3500));
+
+        assert_eq!(parse_speed("x2").unwrap(), 2.0);
+        assert_eq!(parse_speed("0.5x").unwrap(), 0.5);
+        assert_eq!(parse_speed("3").unwrap(), 3.0);
+        assert!(parse_speed("x0").is_err());
+        assert!(parse_speed("x1e-310").is_err());
+        let (chunks, start) = speed_up(vec![chunk(1)], 1e-300, Duration::ZERO);
+        assert_eq!((chunks[0].offset, start), (Duration::MAX, Duration::ZERO));
+        assert!(parse_speed("fast").is_err());
+    }
+
+    #[cfg(feature = "screenshot")]
+    #[test]
+    fn test_ending_redraws_the_last_screen() {
+        let chunks = [Chunk { offset: Duration::ZERO, data: b"\x1b[31mred\x1b[0m line\r\nsecond".to_vec() }];
+        let entries = parse_timing("H 0.000000 COLUMNS 20\nO 0.5 28\n").unwrap();
+        let worked_out = ending(&entries, &chunks);
+        assert_eq!(worked_out, b"\x1b[0m\x1b[H\x1b[2J\x1b[0;31mred\x1b[0m line\r\nsecond\x1b[2;7H");
+
+        // What the recorder saw wins over the output, its escapes decoded
+        let recorded = "H 0.000000 EXIT_CURSOR ROW=2 COL=4\nH 0.000000 EXIT_SCREEN ok\\x20go\\x0atwo\\x0a\n";
+        let entries = parse_timing(recorded).unwrap();
+        assert_eq!(ending(&entries, &chunks), b"\x1b[0m\x1b[H\x1b[2Jok go\r\ntwo\x1b[2;4H");
+        assert!(resume_screen("screen").unwrap());
+        assert!(resume_screen("exact").is_err());
+    }
+
+    #[test]
+    fn test_throttle() {
+        assert_eq!(parse_throttle("9600bps").unwrap(), 1200.0);
+        assert_eq!(parse_throttle("56k").unwrap(), 7000.0);
+        assert_eq!(parse_throttle("1.5M").unwrap(), 187500.0);
+        assert!(parse_throttle("0").is_err());
+        assert!(parse_throttle("0.5bps").is_err());
+        assert!(parse_throttle("fast").is_err());
+
+        // At 80 bits a second, ten bytes; the second chunk waits for the
+        // line to be done with the first
+        let clock = manual_clock();
+        let start = clock.now();
+        let chunks = [
+            Chunk { offset: Duration::ZERO, data: vec![b'x'; 30] },
+            Chunk { offset: Duration::from_secs(1), data: b"ok".to_vec() },
+        ];
+        let mut out = Vec::new();
+        replay(&chunks, &mut Throttled::new(&mut out, &clock, parse_throttle("80").unwrap()), &clock).unwrap();
+        assert_eq!(out.len(), 32);
+        assert_eq!(clock.now() - start, Duration::from_millis(3100));
+    }
+
+    #[test]
+    fn test_tmux_passthrough() {
+        let mut out = Vec::new();
+        TmuxPassthrough(&mut out).write_all(b"\x1bPq#0;2;0;0;0\x1b\\ok").unwrap();
+        assert_eq!(out, b"\x1bPtmux;\x1b\x1bPq#0;2;0;0;0\x1b\x1b\\ok\x1b\\");
+        assert!(passthrough("always").unwrap());
+        assert!(passthrough("sometimes").is_err());
+    }
+
+    #[test]
+    fn test_images_stripped() {
+        let chunk = |ms, data: &[u8]| Chunk { offset: Duration::from_millis(ms), data: data.to_vec() };
+        let chunks = vec![chunk(0, b"$ cat\r\n\x1bP0;1"), chunk(100, b"q#0~~-~~"), chunk(200, b"~\x1b\\$ \x1b[K")];
+        let stripped = strip_images(chunks);
+        assert_eq!(stripped, vec![chunk(0, b"$ cat\r\n"), chunk(100, b"[image]"), chunk(200, b"$ \x1b[K")]);
+        assert!(!show_images("never").unwrap());
+        assert!(show_images("maybe").is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// --reserve: the space to leave free where the logs are written. It is
+// checked before the session starts, which refuses to (or with
+// --reserve-warn only warns), and again every CHECK_INTERVAL while it runs,
+// with an event each time a directory runs short:
+//
+//   S 0.000000 SPACE_LOW DIR=/var/log/sessions FREE=104857600 RESERVE=524288000
+//
+// The free space is what statvfs leaves to unprivileged users: the blocks
+// kept for root don't count, and a quota the filesystem reports for the
+// directory (NFS, XFS project quotas) is taken into account.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpaceLow {
+    pub dir: PathBuf,
+    pub free: u64,
+    pub reserve: u64,
+}
+
+impl SpaceLow {
+    pub fn message(&self) -> String {
+        format!("DIR={} FREE={} RESERVE={}", self.dir.display(), self.free, self.reserve)
+    }
+}
+
+// 1.5 MiB
+pub fn mib(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+pub fn free_space(dir: &Path) -> Option<u64> {
+    let stat = nix::sys::statvfs::statvfs(dir).ok()?;
+    Some(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
+
+pub struct Reserve {
+    size: u64,
+    dirs: Vec<PathBuf>,
+    // The directories short of space at the last check
+    low: Vec<PathBuf>,
+}
+
+impl Reserve {
+    // Each directory once, however many logs are in it
+    pub fn new<'a>(size: u64, logs: impl IntoIterator<Item = &'a Path>) -> Self {
+        let mut dirs = Vec::new();
+        for log in logs {
+            let dir = match log.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        Reserve { size, dirs, low: Vec::new() }
+    }
+
+    // The directories that have run short since the last check; one that
+    // has room again is warned about again when it next runs short. A
+    // directory that can't be looked at counts as having room.
+    pub fn check(&mut self) -> Vec<SpaceLow> {
+        self.check_with(free_space)
+    }
+
+    fn check_with(&mut self, free: impl Fn(&Path) -> Option<u64>) -> Vec<SpaceLow> {
+        let mut short = Vec::new();
+        let mut low = Vec::new();
+        for dir in &self.dirs {
+            match free(dir) {
+                Some(free) if free < self.size => {
+                    if !self.low.contains(dir) {
+                        short.push(SpaceLow { dir: dir.clone(), free, reserve: self.size });
+                    }
+                    low.push(dir.clone());
+                }
+                _ => {}
+            }
+        }
+        self.low = low;
+        short
+    }
+
+    // For `script ctl NAME status`
+    pub fn status(&self) -> String {
+        if self.low.is_empty() {
+            return "SPACE=ok".to_string();
+        }
+        let dirs: Vec<String> = self.low.iter().map(|dir| dir.display().to_string()).collect();
+        format!("SPACE=low DIRS={}", dirs.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_warns_once_per_shortage() {
+        let logs = [Path::new("/var/log/a.log"), Path::new("/var/log/a.tm"), Path::new("b.log")];
+        let mut reserve = Reserve::new(100, logs);
+        assert_eq!(reserve.dirs, [PathBuf::from("/var/log"), PathBuf::from(".")]);
+
+        let free = |space: u64| move |dir: &Path| if dir == Path::new(".") { Some(1000) } else { Some(space) };
+        let low = reserve.check_with(free(40));
+        assert_eq!(low, [SpaceLow { dir: PathBuf::from("/var/log"), free: 40, reserve: 100 }]);
+        assert_eq!(low[0].message(), "DIR=/var/log FREE=40 RESERVE=100");
+        assert_eq!(reserve.status(), "SPACE=low DIRS=/var/log");
+
+        // Still short: nothing new to tell
+        assert!(reserve.check_with(free(30)).is_empty());
+        assert!(reserve.check_with(free(500)).is_empty());
+        assert_eq!(reserve.status(), "SPACE=ok");
+        assert_eq!(reserve.check_with(free(10)).len(), 1);
+        assert!(reserve.check_with(|_| None).is_empty());
+    }
+}
@@ -0,0 +1,361 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::{BufRead, IsTerminal, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::altscreen::AltScreenTracker;
+use crate::secrets;
+use crate::sensitive::SecretBytes;
+
+// Longest stretch of a span shown when asking about it
+const PREVIEW_LEN: usize = 160;
+
+// Context shown on either side of a span
+const CONTEXT_LEN: usize = 40;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub range: Range<usize>,
+    pub label: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Approve,
+    Mask,
+    Cut,
+}
+
+// The part of a typescript between our header and footer lines; those are
+// not counted in the timing file and are never touched
+pub fn session_body(log: &[u8]) -> Range<usize> {
+    let start = if log.starts_with(b"Script started on ") {
+        log.iter().position(|&b| b == b'\n').map_or(log.len(), |i| i + 1)
+    } else {
+        0
+    };
+    let footer = b"\nScript done on ";
+    let end = log[start..]
+        .windows(footer.len())
+        .rposition(|w| w == footer)
+        .map_or(log.len(), |i| start + i);
+    start..end
+}
+
+// Secret-scanner hits and full-screen programs, in order. Spans are
+// relative to `body`.
+pub fn find_spans(body: &[u8]) -> Vec<Span> {
+    let mut spans: Vec<Span> = secrets::scan(body)
+        .into_iter()
+        .map(|finding| Span {
+            range: finding.range,
+            label: finding.kind.to_string(),
+        })
+        .collect();
+
+    let mut entered = None;
+    for transition in AltScreenTracker::new().feed(body) {
+        match (transition.active, entered) {
+            (true, _) => entered = Some(transition.start),
+            (false, Some(start)) => {
+                spans.push(Span {
+                    range: start..transition.offset,
+                    label: "alternate screen".to_string(),
+                });
+                entered = None;
+            }
+            _ => {}
+        }
+    }
+    // A program still on the alternate screen when the session ended
+    if let Some(start) = entered {
+        spans.push(Span {
+            range: start..body.len(),
+            label: "alternate screen".to_string(),
+        });
+    }
+
+    spans.sort_by_key(|span| span.range.start);
+    spans
+}
+
+// Make terminal data readable on one line. What it shows may be a secret,
+// so it is sized up front, leaving no copies behind as it grows, and wiped.
+fn printable(data: &[u8]) -> Zeroizing<String> {
+    // No byte takes more than three: an escape, or U+FFFD for a bad one
+    let mut out = Zeroizing::new(String::with_capacity(data.len() * 3));
+    for chunk in data.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\x1b' => out.push_str("\\e"),
+                '\r' => out.push_str("\\r"),
+                '\n' => out.push_str("\\n"),
+                c if c.is_control() => {
+                    out.push('^');
+                    out.push(((c as u8) ^ 0x40) as char);
+                }
+                c => out.push(c),
+            }
+        }
+        if !chunk.invalid().is_empty() {
+            out.push(char::REPLACEMENT_CHARACTER);
+        }
+    }
+    out
+}
+
+fn preview(body: &[u8], span: &Span, highlight: bool) -> Zeroizing<String> {
+    let before = &body[span.range.start.saturating_sub(CONTEXT_LEN)..span.range.start];
+    let before = printable(before.rsplit(|&b| b == b'\n').next().unwrap_or_default());
+    let after = &body[span.range.end..(span.range.end + CONTEXT_LEN).min(body.len())];
+    let after = printable(after.split(|&b| b == b'\r' || b == b'\n').next().unwrap_or_default());
+
+    let inside = &body[span.range.clone()];
+    let shown = printable(&inside[..inside.len().min(PREVIEW_LEN)]);
+    let (on, off) = if highlight { ("\x1b[7m", "\x1b[0m") } else { ("[[", "]]") };
+    let mut line = Zeroizing::new(String::with_capacity(before.len() + shown.len() + after.len() + 16));
+    line.push_str(&before);
+    line.push_str(on);
+    line.push_str(&shown);
+    if inside.len() > PREVIEW_LEN {
+        line.push_str("...");
+    }
+    line.push_str(off);
+    line.push_str(&after);
+    line
+}
+
+// Ask about each span in turn. Returns None when the user quits.
+pub fn ask<R: BufRead, W: Write>(
+    body: &[u8],
+    spans: &[Span],
+    input: &mut R,
+    out: &mut W,
+    highlight: bool,
+) -> Result<Option<Vec<Action>>> {
+    let mut actions = Vec::new();
+
+    for (i, span) in spans.iter().enumerate() {
+        writeln!(
+            out,
+            "\n[{}/{}] {} ({} bytes at offset {})",
+            i + 1,
+            spans.len(),
+            span.label,
+            span.range.len(),
+            span.range.start
+        )?;
+        writeln!(out, "  {}", preview(body, span, highlight).as_str())?;
+
+        loop {
+            write!(out, "[a]pprove, [m]ask, [c]ut or [q]uit? [m] ")?;
+            out.flush()?;
+            let mut answer = String::new();
+            if input.read_line(&mut answer)? == 0 {
+                return Err(anyhow!("Review ended before every span was decided"));
+            }
+            let action = match answer.trim() {
+                "a" | "approve" => Action::Approve,
+                "" | "m" | "mask" => Action::Mask,
+                "c" | "cut" => Action::Cut,
+                "q" | "quit" => return Ok(None),
+                _ => continue,
+            };
+            actions.push(action);
+            break;
+        }
+    }
+
+    Ok(Some(actions))
+}
+
+// Masking keeps line breaks and the byte count, so the timing file still
+// fits; cutting drops the bytes. Spans may overlap.
+pub fn sanitize(body: &[u8], spans: &[Span], actions: &[Action]) -> (Vec<u8>, Vec<Range<usize>>) {
+    // Holds the spans, masked or not, until it is wiped
+    let mut masked = Zeroizing::new(body.to_vec());
+    let mut cut = vec![false; body.len()];
+    for (span, action) in spans.iter().zip(actions) {
+        match action {
+            Action::Approve => {}
+            Action::Mask => {
+                for byte in &mut masked[span.range.clone()] {
+                    if *byte != b'\r' && *byte != b'\n' {
+                        *byte = b'*';
+                    }
+                }
+            }
+            Action::Cut => cut[span.range.clone()].iter_mut().for_each(|c| *c = true),
+        }
+    }
+
+    let mut cuts: Vec<Range<usize>> = Vec::new();
+    for (i, _) in cut.iter().enumerate().filter(|(_, &c)| c) {
+        match cuts.last_mut() {
+            Some(last) if last.end == i => last.end = i + 1,
+            _ => cuts.push(i..i + 1),
+        }
+    }
+
+    let kept = masked
+        .iter()
+        .zip(cut)
+        .filter(|(_, cut)| !cut)
+        .map(|(&byte, _)| byte)
+        .collect();
+    (kept, cuts)
+}
+
+fn overlap(range: &Range<usize>, cuts: &[Range<usize>]) -> usize {
+    cuts.iter()
+        .map(|cut| cut.end.min(range.end).saturating_sub(cut.start.max(range.start)))
+        .sum()
+}
+
+// Shrink the records whose bytes were cut. Records left empty are dropped
+// and their delay moves to the next record, so the session keeps its length.
+pub fn cut_timing(content: &str, cuts: &[Range<usize>], input_in_log: bool) -> Result<String> {
+    let mut out = String::with_capacity(content.len());
+    let mut pos = 0;
+    let mut carry = 0.0f64;
+
+    for line in content.split_inclusive('\n') {
+        let fields: Vec<&str> = line.trim_end_matches('\n').splitn(3, ' ').collect();
+        let invalid = || anyhow!("Invalid timing record: {}", line.trim_end());
+        let (tag, delay, size) = match fields.as_slice() {
+            [delay, size] => (None, *delay, Some(*size)),
+            ["O", delay, size] => (Some("O"), *delay, Some(*size)),
+            ["I", delay, size] if input_in_log => (Some("I"), *delay, Some(*size)),
+            ["I", delay, _] | ["S", delay, _] => (None, *delay, None),
+            _ => {
+                out.push_str(line);
+                continue;
+            }
+        };
+        let delay: f64 = delay.parse().map_err(|_| invalid())?;
+
+        let Some(size) = size else {
+            // Records without log data only need the carried delay
+            if carry > 0.0 {
+                out.push_str(&format!("{} {:.6} {}\n", fields[0], delay + carry, fields[2]));
+                carry = 0.0;
+            } else {
+                out.push_str(line);
+            }
+            continue;
+        };
+
+        let size: usize = size.parse().map_err(|_| invalid())?;
+        let removed = overlap(&(pos..pos + size), cuts);
+        pos += size;
+
+        if removed == size {
+            carry += delay;
+        } else if removed > 0 || carry > 0.0 {
+            let prefix = tag.map(|t| format!("{} ", t)).unwrap_or_default();
+            out.push_str(&format!("{}{:.6} {}\n", prefix, delay + carry, size - removed));
+            carry = 0.0;
+        } else {
+            out.push_str(line);
+        }
+    }
+
+    Ok(out)
+}
+
+fn sanitized_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".sanitized");
+    PathBuf::from(name)
+}
+
+pub fn run(timing: Option<&Path>, log_io: Option<&Path>, typescript: Option<&Path>, output: Option<&Path>) -> Result<()> {
+    let log_path = log_io.or(typescript).unwrap_or(Path::new("typescript"));
+    // The log is full of what the review is there to find
+    let mut log = SecretBytes::read(log_path).with_context(|| format!("Cannot open {}", log_path.display()))?;
+    let body_range = session_body(&log);
+    let body = &log[body_range.clone()];
+
+    let spans = find_spans(body);
+    if spans.is_empty() {
+        println!("Nothing sensitive found in {}", log_path.display());
+        return Ok(());
+    }
+
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    let highlight = stdout.is_terminal();
+    let actions = match ask(body, &spans, &mut stdin.lock(), &mut stdout.lock(), highlight)? {
+        Some(actions) => actions,
+        None => {
+            println!("Review abandoned; nothing written");
+            return Ok(());
+        }
+    };
+
+    let (sanitized, cuts) = sanitize(body, &spans, &actions);
+    // Redacted spans are wiped now rather than with the rest of the log
+    for (span, action) in spans.iter().zip(&actions) {
+        if *action != Action::Approve {
+            log[body_range.start + span.range.start..body_range.start + span.range.end].zeroize();
+        }
+    }
+    let mut out = log[..body_range.start].to_vec();
+    out.extend_from_slice(&sanitized);
+    out.extend_from_slice(&log[body_range.end..]);
+
+    let output = output.map(Path::to_path_buf).unwrap_or_else(|| sanitized_path(log_path));
+    std::fs::write(&output, out).with_context(|| format!("Cannot write {}", output.display()))?;
+    println!("\nWrote {}", output.display());
+
+    match timing {
+        Some(timing) => {
+            let content = std::fs::read_to_string(timing)
+                .with_context(|| format!("Cannot read timing file {}", timing.display()))?;
+            let timing_out = sanitized_path(timing);
+            std::fs::write(&timing_out, cut_timing(&content, &cuts, log_io.is_some())?)?;
+            println!("Wrote {}", timing_out.display());
+        }
+        None if !cuts.is_empty() => {
+            eprintln!("Warning: spans were cut but no --timing file was given to adjust");
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_review_masks_and_cuts() {
+        let log = b"Script started on x\n$ echo password=hunter22\r\n\x1b[?1049hsecret screen\x1b[?1049l$ \nScript done on y\n";
+        let range = session_body(log);
+        let body = &log[range];
+        assert!(body.starts_with(b"$ echo") && body.ends_with(b"l$ "));
+
+        let spans = find_spans(body);
+        assert_eq!(spans.iter().map(|s| s.label.as_str()).collect::<Vec<_>>(), ["password", "alternate screen"]);
+
+        let mut answers = &b"x\nm\nc\n"[..];
+        let mut out = Vec::new();
+        let actions = ask(body, &spans, &mut answers, &mut out, false).unwrap().unwrap();
+        assert_eq!(actions, [Action::Mask, Action::Cut]);
+        assert!(String::from_utf8(out).unwrap().contains("  $ echo password=[[hunter22]]\n"));
+
+        let (sanitized, cuts) = sanitize(body, &spans, &actions);
+        assert_eq!(sanitized, b"$ echo password=********\r\n$ ");
+        assert_eq!(cuts, [spans[1].range.clone()]);
+
+        // The cut swallows the third record and part of the fourth
+        let timing = "O 0.100000 26\nO 0.200000 10\nS 0.300000 SIGWINCH ROWS=24 COLS=80\nO 0.400000 21\n";
+        assert_eq!(
+            cut_timing(timing, &cuts, false).unwrap(),
+            "O 0.100000 26\nS 0.500000 SIGWINCH ROWS=24 COLS=80\nO 0.400000 2\n"
+        );
+        assert!(ask(body, &spans, &mut &b"q\n"[..], &mut Vec::new(), false).unwrap().is_none());
+    }
+}
@@ -0,0 +1,78 @@
+use crate::split::Command;
+
+// Output lines per step kept as its assertions
+const EXPECT_LINES: usize = 3;
+
+// A recorded procedure as a runbook: one step per command, typed with
+// `send`, and the first lines of what it showed as `expect`, each to be
+// found in the step's output when it is run again. Output that changes
+// from run to run (times, ids) has to be taken out by hand.
+pub fn render(source: &str, commands: &[Command]) -> String {
+    let mut yaml = String::new();
+    yaml.push_str(&format!("# Runbook recorded from {}\n", source));
+    yaml.push_str("steps:\n");
+    for command in commands {
+        yaml.push_str(&format!("  - send: {}\n", quote(&command.command)));
+        let expect: Vec<&str> = command
+            .output
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .take(EXPECT_LINES)
+            .collect();
+        if !expect.is_empty() {
+            yaml.push_str("    expect:\n");
+            for line in expect {
+                yaml.push_str(&format!("      - {}\n", quote(line)));
+            }
+        }
+        if let Some(code) = command.exit_code {
+            yaml.push_str(&format!("    exit_code: {}\n", code));
+        }
+    }
+    yaml
+}
+
+// A YAML double-quoted scalar, which any text fits in
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\t' => quoted.push_str("\\t"),
+            ch if ch.is_control() => quoted.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_runbook_steps() {
+        let command = |command: &str, output: &[&str], exit_code| Command {
+            command: command.to_string(),
+            output: output.iter().map(|line| line.to_string()).collect(),
+            duration: Duration::ZERO,
+            exit_code,
+        };
+        let commands = [
+            command("grep -c \"x\" a\\b", &["", "3  ", "4", "5", "6"], Some(0)),
+            command("clear", &[], None),
+        ];
+
+        assert_eq!(
+            render("session.log", &commands),
+            "# Runbook recorded from session.log\nsteps:\n\
+             \x20 - send: \"grep -c \\\"x\\\" a\\\\b\"\n    expect:\n      - \"3\"\n      - \"4\"\n      - \"5\"\n    exit_code: 0\n\
+             \x20 - send: \"clear\"\n"
+        );
+        assert_eq!(quote("a\u{1b}b"), "\"a\\u001bb\"");
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::{anyhow, Result};
+#[cfg(target_os = "linux")]
+use anyhow::Context;
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+
+use crate::utils;
+
+// cgroup v2 CPU quotas are given per period, in microseconds
+#[cfg(target_os = "linux")]
+const CPU_PERIOD: u64 = 100_000;
+
+// Bounds on the recorder's own footprint, from --self-limit
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SelfLimit {
+    // Bytes of session data waiting for the log writers
+    pub mem: Option<u64>,
+    // Percent of one CPU
+    pub cpu: Option<u32>,
+}
+
+impl SelfLimit {
+    // "mem=64M,cpu=10%", either part optional
+    pub fn parse(spec: &str) -> Result<SelfLimit> {
+        let invalid = || anyhow!("Invalid self limit: '{}' (mem=SIZE,cpu=PERCENT%)", spec);
+        let mut limit = SelfLimit::default();
+        for part in spec.split(',') {
+            match part.trim().split_once('=') {
+                Some(("mem", size)) => match utils::parse_size(size)? {
+                    0 => return Err(invalid()),
+                    size => limit.mem = Some(size),
+                },
+                Some(("cpu", percent)) => match percent.trim_end_matches('%').parse::<u32>() {
+                    Ok(percent) if percent > 0 => limit.cpu = Some(percent),
+                    _ => return Err(invalid()),
+                },
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(limit)
+    }
+}
+
+// Parse a CPU list such as "0,2-3" into the CPU numbers it names
+pub fn parse_cpu_list(list: &str) -> Result<Vec<usize>> {
+    let invalid = || anyhow!("Invalid CPU list: {}", list);
+    let mut cpus = Vec::new();
+
+    for part in list.split(',') {
+        let (first, last) = match part.trim().split_once('-') {
+            Some((first, last)) => (first.parse::<usize>(), last.parse::<usize>()),
+            None => (part.trim().parse::<usize>(), part.trim().parse::<usize>()),
+        };
+        let (first, last) = (first.map_err(|_| invalid())?, last.map_err(|_| invalid())?);
+        if first > last {
+            return Err(invalid());
+        }
+        cpus.extend(first..=last);
+    }
+
+    cpus.sort_unstable();
+    cpus.dedup();
+    Ok(cpus)
+}
+
+// Scheduling attributes are per thread but inherited by threads created
+// later, so this must run before the runtime spawns helpers for the session
+#[cfg(target_os = "linux")]
+pub fn set_rt_priority(priority: i32) -> Result<()> {
+    let param = libc::sched_param {
+        sched_priority: priority,
+    };
+    if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } == -1 {
+        return Err(anyhow!(
+            "Failed to set real-time priority {}: {}",
+            priority,
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_cpu_affinity(cpus: &[usize]) -> Result<()> {
+    use nix::sched::{sched_setaffinity, CpuSet};
+    use nix::unistd::Pid;
+
+    let mut set = CpuSet::new();
+    for &cpu in cpus {
+        set.set(cpu).map_err(|_| anyhow!("CPU {} is out of range", cpu))?;
+    }
+    sched_setaffinity(Pid::from_raw(0), &set)
+        .map_err(|e| anyhow!("Failed to set CPU affinity: {}", e))
+}
+
+// A cgroup of the recorder's own below the one it was started in, with a CPU
+// quota; the session's processes stay where they were. It needs a delegated
+// cgroup v2 hierarchy with the cpu controller enabled. The recorder moves
+// back out and removes it when dropped.
+#[cfg(target_os = "linux")]
+pub struct CpuLimit {
+    parent: PathBuf,
+    dir: PathBuf,
+}
+
+#[cfg(target_os = "linux")]
+pub fn limit_cpu(percent: u32) -> Result<CpuLimit> {
+    let cgroups = std::fs::read_to_string("/proc/self/cgroup")?;
+    let path = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| anyhow!("not in a cgroup v2 hierarchy"))?;
+    let parent = cgroup2_mount()?.join(path.trim_start_matches('/'));
+    let dir = parent.join(format!("rust_script-{}", std::process::id()));
+    std::fs::create_dir(&dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    let limit = CpuLimit { parent, dir };
+
+    let quota = percent as u64 * CPU_PERIOD / 100;
+    std::fs::write(limit.dir.join("cpu.max"), format!("{} {}", quota, CPU_PERIOD))
+        .with_context(|| format!("The cpu controller is not enabled in {}", limit.parent.display()))?;
+    std::fs::write(limit.dir.join("cgroup.procs"), std::process::id().to_string())
+        .with_context(|| format!("Cannot move into {}", limit.dir.display()))?;
+    Ok(limit)
+}
+
+// Where the cgroup v2 hierarchy is mounted: /sys/fs/cgroup on most systems,
+// /sys/fs/cgroup/unified on hybrid ones
+#[cfg(target_os = "linux")]
+fn cgroup2_mount() -> Result<PathBuf> {
+    // "36 25 0:30 / /sys/fs/cgroup rw,nosuid - cgroup2 cgroup2 rw"
+    let mounts = std::fs::read_to_string("/proc/self/mountinfo")?;
+    mounts
+        .lines()
+        .find(|line| line.split(" - ").nth(1).is_some_and(|fs| fs.starts_with("cgroup2 ")))
+        .and_then(|line| line.split(' ').nth(4))
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("no cgroup v2 hierarchy is mounted"))
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for CpuLimit {
+    fn drop(&mut self) {
+        let _ = std::fs::write(self.parent.join("cgroup.procs"), std::process::id().to_string());
+        let _ = std::fs::remove_dir(&self.dir);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub struct CpuLimit;
+
+#[cfg(not(target_os = "linux"))]
+pub fn limit_cpu(_percent: u32) -> Result<CpuLimit> {
+    Err(anyhow!("CPU limits are only supported on Linux"))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_rt_priority(_priority: i32) -> Result<()> {
+    Err(anyhow!("Real-time priority is only supported on Linux"))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_cpu_affinity(_cpus: &[usize]) -> Result<()> {
+    Err(anyhow!("CPU affinity is only supported on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("3").unwrap(), vec![3]);
+        assert_eq!(parse_cpu_list("0,2-4").unwrap(), vec![0, 2, 3, 4]);
+        assert_eq!(parse_cpu_list("2-3,1,3").unwrap(), vec![1, 2, 3]);
+        assert!(parse_cpu_list("").is_err());
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+    }
+
+    #[test]
+    fn test_parse_self_limit() {
+        assert_eq!(SelfLimit::parse("mem=64M,cpu=10%").unwrap(), SelfLimit { mem: Some(64 << 20), cpu: Some(10) });
+        assert_eq!(SelfLimit::parse("cpu=150").unwrap(), SelfLimit { mem: None, cpu: Some(150) });
+        assert!(SelfLimit::parse("mem=0").is_err());
+        assert!(SelfLimit::parse("cpu=0%").is_err());
+        assert!(SelfLimit::parse("disk=1G").is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use anyhow::{anyhow, Result};
+
+// The JSON the recorder hands to other programs, described for those who
+// validate it or generate code from it: the session JSON lines --analyzer
+// commands read, the --meta/--analyzer sidecar and the --failover-webhook
+// alert. Each says which version of its schema it follows in
+// `schema_version`; a change that could break a reader bumps it, adding an
+// optional field doesn't.
+pub const SCHEMA_VERSION: u32 = 1;
+
+// `script schema NAME` prints these, as shipped in schemas/
+pub(crate) const SCHEMAS: &[(&str, &str)] = &[
+    ("event", include_str!("../schemas/event.schema.json")),
+    ("metadata", include_str!("../schemas/metadata.schema.json")),
+    ("webhook", include_str!("../schemas/webhook.schema.json")),
+];
+
+pub(crate) fn get(name: &str) -> Result<&'static str> {
+    SCHEMAS.iter().find(|(schema, _)| *schema == name).map(|(_, content)| *content).ok_or_else(|| {
+        let names: Vec<&str> = SCHEMAS.iter().map(|(name, _)| *name).collect();
+        anyhow!("No schema '{}' (there are {})", name, names.join(", "))
+    })
+}
+
+pub(crate) fn run(name: &str) -> Result<()> {
+    print!("{}", get(name)?);
+    Ok(())
+}
+
+// The same as Rust types, for programs reading the JSON with serde
+#[cfg(feature = "schemas")]
+pub use types::*;
+
+#[cfg(feature = "schemas")]
+mod types {
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
+
+    // One line of the session JSON lines (event.schema.json)
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    pub enum Event {
+        #[serde(rename = "start")]
+        Start {
+            schema_version: u32,
+            time: String,
+            command: Option<String>,
+            term: Option<String>,
+            cols: u16,
+            lines: u16,
+        },
+        #[serde(rename = "o")]
+        Output { t: f64, data: String },
+        #[serde(rename = "i")]
+        Input { t: f64, data: String },
+        #[serde(rename = "e")]
+        Stderr { t: f64, data: String },
+        #[serde(rename = "event")]
+        Event { t: f64, name: String, message: Option<String> },
+        #[serde(rename = "close")]
+        Close { t: f64, exit_code: i32 },
+    }
+
+    // <log>.meta.json (metadata.schema.json); --meta writes the session and
+    // its timeline, --analyzer the analyzers
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Metadata {
+        pub schema_version: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub session: Option<Session>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub timeline: Option<Vec<TimelineEntry>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub analyzers: Option<Vec<AnalyzerResult>>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Session {
+        pub start_time: Option<String>,
+        pub host: String,
+        pub user: Option<String>,
+        pub command: Option<String>,
+        pub log: String,
+        pub exit_code: i32,
+        #[serde(default)]
+        pub degraded: Option<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    pub enum TimelineEntry {
+        #[serde(rename = "command")]
+        Command { t: f64, text: String },
+        #[serde(rename = "event")]
+        Event { t: f64, name: String, message: Option<String> },
+    }
+
+    // Either annotations or the error the analyzer failed with
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct AnalyzerResult {
+        pub command: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub annotations: Option<Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub error: Option<String>,
+    }
+
+    // The --failover-webhook body (webhook.schema.json)
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct FailoverAlert {
+        pub schema_version: u32,
+        pub event: String,
+        pub host: String,
+        pub from: String,
+        pub to: String,
+        pub error: String,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schemas_are_versioned() {
+        for (name, _) in SCHEMAS {
+            let schema = get(name).unwrap();
+            let id = format!("\"$id\": \"rust_script/schemas/{}/{}.schema.json\"", SCHEMA_VERSION, name);
+            assert!(schema.contains(&id));
+            assert!(schema.contains(&format!("\"schema_version\": {{ \"const\": {} }}", SCHEMA_VERSION)));
+        }
+        assert_eq!(get("session").unwrap_err().to_string(), "No schema 'session' (there are event, metadata, webhook)");
+    }
+
+    #[cfg(feature = "schemas")]
+    #[test]
+    fn test_types_read_what_is_written() {
+        let line = r#"{"type":"start","schema_version":1,"time":"2024-01-02 03:04:05+00:00","command":null,
+                      "term":"xterm","cols":80,"lines":24}"#;
+        let start: Event = serde_json::from_str(line).unwrap();
+        assert!(matches!(start, Event::Start { cols: 80, ref term, .. } if term.as_deref() == Some("xterm")));
+        let event: Event = serde_json::from_str(r#"{"data":"$ ","t":0.25,"type":"o"}"#).unwrap();
+        assert_eq!(event, Event::Output { t: 0.25, data: "$ ".into() });
+
+        let metadata: Metadata = serde_json::from_str(
+            r#"{"schema_version": 1, "analyzers": [{"command": "wc", "annotations": {"lines": 3}},
+                {"command": "false", "error": "exit status: 1"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(metadata.session, None);
+        let analyzers = metadata.analyzers.unwrap();
+        assert_eq!(analyzers[0].annotations, Some(serde_json::json!({"lines": 3})));
+        assert_eq!(analyzers[1].error.as_deref(), Some("exit status: 1"));
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::Result;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::convert;
+use crate::timing::{self, parse_offset, window_size, EntryKind, TimingEntry};
+use crate::vt::{Cell, Screen, Style};
+
+// Play the output up to `at` through the terminal emulator
+pub fn screen_at(entries: &[TimingEntry], data: &[u8], at: Duration) -> Screen {
+    // A size of 0 means the recording didn't know
+    let size = |name| timing::info(entries, name).and_then(|v| v.parse().ok()).filter(|&v| v > 0);
+    let mut screen = Screen::new(size("COLUMNS").unwrap_or(80), size("LINES").unwrap_or(24));
+
+    let mut offset = Duration::ZERO;
+    let mut pos = 0;
+    for entry in entries {
+        offset += entry.delay;
+        if offset > at {
+            break;
+        }
+        match entry.kind {
+            EntryKind::Output(size) => {
+                let end = (pos + size).min(data.len());
+                screen.feed(&data[pos..end], 0);
+                pos = end;
+            }
+            EntryKind::Input(size) => pos = (pos + size).min(data.len()),
+            EntryKind::Signal(ref name, Some(ref message)) if name == "SIGWINCH" => {
+                if let Some((cols, rows)) = window_size(message) {
+                    screen.resize(cols, rows);
+                }
+            }
+            _ => {}
+        }
+    }
+    screen
+}
+
+// The screen's rows, without the blank cells and rows at their ends; with
+// `ansi` each change of colors or attributes is set with SGR, and a row
+// ends with them reset
+pub fn render(screen: &Screen, ansi: bool) -> String {
+    let (cols, rows) = screen.size();
+    let mut lines = Vec::new();
+    for row in 0..rows {
+        let cells: Vec<_> = (0..cols).filter_map(|col| screen.cell(row, col)).collect();
+        // A blank with a background color still shows
+        let shows = |cell: &Cell| cell.ch != ' ' || (ansi && cell.style != Style::DEFAULT);
+        let end = cells.iter().rposition(|&cell| shows(cell)).map_or(0, |last| last + 1);
+
+        let mut line = String::new();
+        let mut style = Style::DEFAULT;
+        for cell in &cells[..end] {
+            if ansi && cell.style != style {
+                style = cell.style;
+                line.push_str(&style.sgr());
+            }
+            line.push(cell.ch);
+        }
+        if style != Style::DEFAULT {
+            line.push_str("\x1b[0m");
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(String::is_empty) {
+        lines.pop();
+    }
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+pub fn run(timing: Option<&Path>, recording: &Path, at: Option<&str>, ansi: bool) -> Result<()> {
+    let at = at.map(parse_offset).transpose()?.unwrap_or(Duration::MAX);
+    let (entries, data) = convert::load_recording(timing, recording)?;
+    print!("{}", render(&screen_at(&entries, &data, at), ansi));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::parse_timing;
+
+    #[test]
+    fn test_screen_at_a_time() {
+        let entries =
+            parse_timing("H 0.000000 COLUMNS 20\nH 0.000000 LINES 5\nO 1.0 7\nI 0.5 2\nO 60.0 44\nO 1.0 8\n").unwrap();
+        let data = b"$ top\r\nq\r\x1b[?1049h\x1b[H\x1b[7mPID  CPU\x1b[m\r\n1    \x1b[31m99%\x1b[K\x1b[?1049l";
+        let at = |secs| screen_at(&entries, data, Duration::from_secs(secs));
+
+        assert_eq!(render(&at(1), false), "$ top\n");
+        assert_eq!(render(&at(62), false), "PID  CPU\n1    99%\n");
+        assert_eq!(render(&at(62), true), "\x1b[0;7mPID  CPU\x1b[0m\n1    \x1b[0;31m99%\x1b[0m\n");
+        assert_eq!(render(&at(1000), true), "$ top\n");
+    }
+}
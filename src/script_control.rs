@@ -1,31 +1,127 @@
-use anyhow::{anyhow, Result};
-use chrono::Local;
+use anyhow::{anyhow, Context, Result};
 use nix::unistd::{fork, ForkResult};
-use std::os::unix::io::RawFd;
-use std::path::PathBuf;
+use std::io::{IsTerminal, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
 use tokio::signal;
 
-use crate::logging::{LogFormat, ScriptLogger};
+use crate::altscreen::AltScreenTracker;
+#[cfg(feature = "analyzers")]
+use crate::analyzer::Analyzers;
+use crate::clock::{Clock, SystemClock};
+use crate::compression::Compression;
+use crate::encryption::{Recipients, NOT_FIPS};
+use crate::expect::Expect;
+#[cfg(feature = "screenshot")]
+use crate::exectrace;
+use crate::exectrace::{Exec, ExecTracer};
+use crate::filetrace::{FileEvent, FileTracer};
+use crate::config::{self, Config, Shell};
+use crate::control::ControlSocket;
+use crate::failover;
+use crate::failure::{self, Failure};
+use crate::feed::Feed;
+use crate::hash::HashAlgorithm;
+use crate::i18n::{tr, Message};
+use crate::images::ImageScanner;
+use crate::logging::{self, LogFormat, LogSink, LogStream, ScriptLogger, SessionInfo, DEFAULT_BUFFER_SIZE, TIMESTAMP_FORMAT};
+use crate::macros::{self, InputChunk, MacroExpander};
+use crate::marker::{AutoMarker, AutoMarkers};
+#[cfg(feature = "timeline")]
+use crate::meta::MetaSink;
+use crate::privileges;
 use crate::pty_session::PtySession;
+use crate::reserve::{self, Reserve};
+use crate::osc133::{Mark, MarkTracker};
+use crate::prompt::PromptDetector;
+use crate::scheduling::{self, CpuLimit, SelfLimit};
+#[cfg(feature = "screenshot")]
+use crate::screenshot;
+#[cfg(feature = "snapshot")]
+use crate::snapshot::{self, SnapshotMode, Snapshots};
+use crate::spool::Spool;
+use crate::sudo;
+use crate::transcript::TranscriptSink;
+use crate::typed::TypedLines;
+#[cfg(feature = "screenshot")]
+use crate::vt::Screen;
 use crate::utils;
-use crate::Args;
+use crate::watchdog::{self, Watchdog};
+use crate::childenv::ChildEnv;
+use crate::cli::Args;
 
 const DEFAULT_TYPESCRIPT_FILENAME: &str = "typescript";
 
+// Read buffer autotuning: grow after this many consecutive full reads, up to
+// the maximum size
+const AUTOTUNE_FULL_READS: u32 = 4;
+const MAX_READ_BUFFER: usize = 1024 * 1024;
+
+// Largest --read-buffer or --write-buffer; each is allocated whole
+const MAX_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+// How often to check whether the child has read all of a piped stdin
+const EOF_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// A log and the records it is sent
+struct Sink {
+    log: Box<dyn LogSink>,
+    input: bool,
+    output: bool,
+    stderr: bool,
+    events: bool,
+}
+
+// What ends the session (--wait-for)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaitFor {
+    // The terminal, once no process has it open: a wrapper that execs
+    // something and exits, or a command that leaves a job writing in the
+    // background, is recorded to the end
+    Pty,
+    // The command; whatever it leaves running is cut off
+    Child,
+}
+
+impl WaitFor {
+    pub fn parse(name: &str) -> Result<WaitFor> {
+        match name.to_lowercase().as_str() {
+            "pty" => Ok(WaitFor::Pty),
+            "child" => Ok(WaitFor::Child),
+            _ => Err(anyhow!("Unsupported --wait-for: '{}' (pty or child)", name)),
+        }
+    }
+}
+
 pub struct ScriptControl {
     // Output and input streams
     pub out_logs: Vec<ScriptLogger>,
     pub in_logs: Vec<ScriptLogger>,
     
-    // Signal and info logs
-    pub sig_log: Option<ScriptLogger>,
+    // Log for the header records of the advanced timing format
     pub info_log: Option<ScriptLogger>,
+    // --log-err: the child's stderr, read from a pipe of its own (read and
+    // write ends) rather than the terminal
+    pub err_log: Option<ScriptLogger>,
+    stderr_pipe: Option<(RawFd, RawFd)>,
+
+    // Everything the session is recorded to: the logs above and added sinks
+    sinks: Vec<Sink>,
     
     // Terminal information
     pub tty_name: Option<String>,
     pub tty_type: Option<String>,
     pub command: Option<String>,
     pub command_norm: Option<String>,
+    // The program and its arguments, run without a shell; `command` shows
+    // them quoted
+    exec: Vec<String>,
+    // What --env, --env-file and --clear-env make of the child's environment
+    child_env: ChildEnv,
     pub tty_cols: u16,
     pub tty_lines: u16,
     
@@ -33,55 +129,282 @@ pub struct ScriptControl {
     pub pty: Option<PtySession>,
     pub child_pid: Option<nix::unistd::Pid>,
     pub child_status: Option<i32>,
+    pub wait_for: WaitFor,
+    // Signal that made the recorder end the session
+    pub terminated_by: Option<i32>,
     
     // Configuration flags
     pub append: bool,
-    pub rc_wanted: bool,
+    #[allow(dead_code)]
     pub flush: bool,
     pub quiet: bool,
     pub force: bool,
+    pub drop_privs: bool,
+    // --drop-privs-namespace
+    pub privs_namespace: bool,
+    pub debug: bool,
     pub is_term: bool,
+    // The recorder's stdin is the terminal of its session, whose hangup
+    // sends it SIGHUP
+    controlling_tty: bool,
+    // Why the terminal is left in cooked mode rather than made raw, when it
+    // is: a dumb or unknown terminal, or one that can't be set up
+    pub degraded: Option<String>,
+
+    // Scheduling of the recorder process (the child keeps the defaults)
+    pub rt_priority: Option<i32>,
+    pub cpu_affinity: Option<Vec<usize>>,
+
+    // --self-limit; over the memory budget session data is dropped from
+    // the logs, and the amount recorded once there is room again
+    pub self_limit: SelfLimit,
+    pub cpu_cgroup: Option<CpuLimit>,
+    pub dropped: u64,
+
+    // I/O buffer sizes; the read buffer grows unless set explicitly
+    pub read_buffer: usize,
+    pub read_autotune: bool,
+    pub write_buffer: usize,
+
+    // From --compress; otherwise each log's name decides
+    pub compression: Option<Compression>,
+
+    // From --encrypt; every log and the transcript are encrypted to them
+    pub encryption: Option<Recipients>,
     
     // Output size tracking
     pub out_size: u64,
     pub max_size: u64,
+    pub rotate_size: Option<u64>,
+    pub seek_index: Option<Duration>,
+    // --wall-clock, for the advanced timing log
+    pub wall_clock: bool,
+    // --label KEY=VALUE, written as LABEL headers of the advanced timing log
+    pub labels: Vec<String>,
+
+    // Where a log goes on when its disk fills up or fails, and whom to tell
+    pub failover_dir: Option<PathBuf>,
+    pub failover_webhook: Option<String>,
+
+    // --reserve; the log directories short of space are warned about
+    pub reserve: Option<Reserve>,
+
+    // Bytes passed to and from the child
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+
+    // Forward keystrokes from stdin; off for sessions run unattended
+    pub read_stdin: bool,
+    // --stdin-file, typed into the session in place of stdin
+    feed: Option<Feed>,
+
+    // Alternate screen state of the session output
+    pub alt_screen: AltScreenTracker,
+    pub skip_alt_screen: bool,
+    pub alt_omitted: u64,
+    pub alt_entered: Option<Instant>,
+
+    // The session's screen, for the EXIT_SCREEN record of the advanced
+    // timing log
+    #[cfg(feature = "screenshot")]
+    exit_screen: Option<Screen>,
+
+    // Lines typed at the shell, watched for sudo, and the session's own
+    // terminal as sudo names it
+    pub typed: TypedLines,
+    pub session_tty: Option<String>,
+
+    // Command boundaries from shells with OSC 133 integration
+    pub shell_marks: MarkTracker,
+    // and guessed from --prompt-regex for those without
+    pub prompts: Option<PromptDetector>,
+    // Sixel and iTerm2 inline images, each logged as it ends
+    images: ImageScanner,
+
+    // --expect: answers to what the session asks
+    expect: Option<Expect>,
+    // --auto-marker: chapters dropped as the session goes
+    auto_markers: Option<AutoMarkers>,
+
+    // Stall detection
+    pub watchdog: Option<Watchdog>,
+
+    // Interval of the HEARTBEAT records, so a collector can tell an idle
+    // session from a recorder that died
+    pub heartbeat: Option<Duration>,
+
+    // Commands from `script ctl`
+    pub control: Option<ControlSocket>,
+    // Listened for from before the fork
+    signals: Option<Signals>,
+    // Restores the signals' dispositions once the session is over
+    dispositions: Option<Dispositions>,
+    pub input_locked: bool,
+
+    // Keystroke macros from the config file
+    pub macros: Option<MacroExpander>,
+
+    // Time source shared by all loggers
+    pub clock: Arc<dyn Clock>,
+
+    // Files looked at before and after the session
+    #[cfg(feature = "snapshot")]
+    pub snapshots: Option<Snapshots>,
+    // --spool: the session's directory, handed over with a manifest in this
+    // digest once it is over
+    spool: Option<(Spool, HashAlgorithm)>,
+
+    // --trace-exec; the programs run are written to info_log. --trace-files
+    // follows the session's processes with it too.
+    pub exec_tracer: Option<ExecTracer>,
+    pub trace_exec: bool,
+    pub file_tracer: Option<FileTracer>,
+
+    // Commands run on the session once it is over
+    #[cfg(feature = "analyzers")]
+    analyzers: Option<Analyzers>,
+
+    // --meta writes the sidecar the analyzers add to
+    #[cfg(feature = "analyzers")]
+    pub meta: bool,
 }
 
 impl ScriptControl {
-    pub fn new(args: Args) -> Result<Self> {
-        let is_term = utils::is_stdin_tty();
+    pub fn new(mut args: Args) -> Result<Self> {
+        let config = Config::load(args.config.as_deref())?;
+        // --no-stdin leaves stdin alone, whatever it is
+        let is_term = utils::is_stdin_tty() && !args.no_stdin;
         let (tty_cols, tty_lines) = if is_term {
             utils::get_terminal_size()?
         } else {
             (80, 24)
         };
+        let command = match args.exec.as_slice() {
+            [] => args.command.clone(),
+            argv => Some(utils::shell_join(argv)),
+        };
 
         let mut control = ScriptControl {
             out_logs: Vec::new(),
             in_logs: Vec::new(),
-            sig_log: None,
             info_log: None,
+            err_log: None,
+            stderr_pipe: None,
+            sinks: Vec::new(),
             tty_name: None,
             tty_type: None,
-            command: args.command.clone(),
-            command_norm: args.command.as_ref().map(|c| c.replace('\n', " ")),
+            command: command.clone(),
+            command_norm: command.as_ref().map(|c| c.replace('\n', " ")),
+            exec: args.exec.clone(),
+            child_env: ChildEnv::new(args.clear_env, &args.env, args.env_file.as_deref())?,
             tty_cols,
             tty_lines,
             pty: None,
             child_pid: None,
             child_status: None,
+            wait_for: WaitFor::parse(&args.wait_for)?,
+            terminated_by: None,
             append: args.append,
-            rc_wanted: args.return_exit_code,
             flush: args.flush,
             quiet: args.quiet,
             force: args.force,
+            drop_privs: args.drop_privs,
+            privs_namespace: args.drop_privs_namespace,
+            debug: args.debug,
             is_term,
+            controlling_tty: utils::is_stdin_tty()
+                && nix::sys::termios::tcgetsid(std::io::stdin()).ok() == nix::unistd::getsid(None).ok(),
+            degraded: None,
+            rt_priority: args.rt_priority,
+            cpu_affinity: args.cpu_affinity.as_deref().map(scheduling::parse_cpu_list).transpose()?,
+            self_limit: args.self_limit.as_deref().map(SelfLimit::parse).transpose()?.unwrap_or_default(),
+            cpu_cgroup: None,
+            dropped: 0,
+            read_buffer: match args.read_buffer {
+                Some(ref size) => parse_buffer_size(size)?,
+                None => DEFAULT_BUFFER_SIZE,
+            },
+            read_autotune: args.read_buffer.is_none(),
+            write_buffer: match args.write_buffer {
+                Some(ref size) => parse_buffer_size(size)?,
+                None => DEFAULT_BUFFER_SIZE,
+            },
+            compression: args.compress.as_deref().map(Compression::parse).transpose()?,
+            encryption: match args.encrypt.as_slice() {
+                [] => None,
+                _ if args.fips || config::fips() => return Err(anyhow!("Cannot --encrypt with --fips: {}", NOT_FIPS)),
+                _ if args.append => return Err(anyhow!("Cannot append to an encrypted log")),
+                recipients => Some(Recipients::parse(recipients)?),
+            },
             out_size: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            read_stdin: !args.no_stdin && args.stdin_file.is_none(),
+            feed: args.stdin_file.as_deref().map(|path| Feed::load(path, args.cps, args.wait_prompt)).transpose()?,
             max_size: if let Some(ref limit) = args.output_limit {
-                utils::parse_size(&limit)?
+                utils::parse_size(limit)?
             } else {
                 0
             },
+            rotate_size: match args.rotate_size.as_deref().map(utils::parse_size).transpose()? {
+                Some(0) => return Err(anyhow!("Invalid rotation size: 0")),
+                size => size,
+            },
+            seek_index: match args.seek_index {
+                Some(0) => return Err(anyhow!("Invalid seek index interval: 0")),
+                secs => secs.map(Duration::from_secs),
+            },
+            wall_clock: args.wall_clock,
+            labels: args.label.clone(),
+            failover_dir: args.failover_dir.clone(),
+            failover_webhook: args.failover_webhook.clone(),
+            reserve: None,
+            alt_screen: AltScreenTracker::new(),
+            skip_alt_screen: args.skip_alt_screen,
+            alt_omitted: 0,
+            alt_entered: None,
+            #[cfg(feature = "screenshot")]
+            exit_screen: None,
+            typed: TypedLines::default(),
+            session_tty: None,
+            shell_marks: MarkTracker::default(),
+            images: ImageScanner::default(),
+            prompts: args.prompt_regex.as_deref().map(PromptDetector::new).transpose()?,
+            expect: match (&args.expect[..], &args.expect_file) {
+                ([], None) => None,
+                (rules, file) => Some(Expect::new(rules, file.as_deref())?),
+            },
+            auto_markers: match args.auto_marker.as_deref() {
+                Some(spec) => {
+                    let gap = args.auto_marker_gap.as_deref().map(utils::parse_duration).transpose()?;
+                    Some(AutoMarkers::new(AutoMarker::parse(spec)?, gap.unwrap_or(Duration::ZERO)))
+                }
+                None => None,
+            },
+            watchdog: args.watchdog.map(|secs| {
+                Watchdog::new(Duration::from_secs(secs), args.watchdog_hook.clone())
+            }),
+            heartbeat: args.heartbeat.map(Duration::from_secs),
+            control: args.name.as_deref().map(|name| ControlSocket::bind(name, args.auth_cmd.as_deref())).transpose()?,
+            signals: None,
+            dispositions: None,
+            input_locked: false,
+            macros: if config.macros.macros.is_empty() {
+                None
+            } else {
+                Some(MacroExpander::new(config.macros))
+            },
+            clock: Arc::new(SystemClock),
+            #[cfg(feature = "snapshot")]
+            snapshots: None,
+            spool: None,
+            exec_tracer: None,
+            trace_exec: args.trace_exec,
+            file_tracer: None,
+            #[cfg(feature = "analyzers")]
+            analyzers: None,
+            #[cfg(feature = "analyzers")]
+            meta: false,
         };
 
         // Initialize terminal info if we're on a terminal
@@ -89,15 +412,124 @@ impl ScriptControl {
             control.init_terminal_info()?;
         }
 
+        let hash = args.hash.as_deref().map_or(Ok(HashAlgorithm::Sha256), HashAlgorithm::parse)?;
+        let hash = hash.check(args.fips || config::fips())?;
+        if let Some(root) = args.spool.clone() {
+            let spool = Spool::create(&root, control.clock.wall())?;
+            spool.place(&mut args, DEFAULT_TYPESCRIPT_FILENAME);
+            control.spool = Some((spool, hash));
+        }
+
         // Set up logging based on arguments
+        #[cfg(feature = "analyzers")]
+        let analyzers = args.analyzer.clone();
+        #[cfg(feature = "timeline")]
+        let meta = args.meta;
+        let transcript = args.transcript.clone();
+        #[cfg(feature = "snapshot")]
+        let snapshots = args.snapshot_paths.clone().map(|paths| (paths, args.snapshot_mode.clone()));
+        let reserve = args.reserve.as_deref().map(utils::parse_size).transpose()?;
+        let reserve_warn = args.reserve_warn;
+        let trace_files = args.trace_files.clone();
         control.setup_logging(args)?;
 
+        // The snapshots sit next to the main log
+        #[cfg(feature = "snapshot")]
+        if let Some((paths, mode)) = snapshots {
+            let dir = control
+                .out_logs
+                .first()
+                .or(control.in_logs.first())
+                .map(|log| snapshot::bundle_path(log.path()))
+                .ok_or_else(|| anyhow!("--snapshot-paths needs a log to keep the snapshots beside"))?;
+            control.snapshots = Some(Snapshots::new(&paths, SnapshotMode::parse(&mode)?, hash, dir)?);
+        }
+
+        if control.wall_clock && control.info_log.is_none() {
+            return Err(anyhow!("--wall-clock needs an advanced timing log (-T with -m advanced)"));
+        }
+        if !control.labels.is_empty() && control.info_log.is_none() {
+            return Err(anyhow!("--label needs an advanced timing log (-T with -m advanced)"));
+        }
+        for label in &control.labels {
+            utils::parse_label(label)?;
+        }
+
+        // Listening from before the child is forked
+        if control.trace_exec {
+            if control.info_log.is_none() {
+                return Err(anyhow!("--trace-exec needs an advanced timing log (-T with -m advanced)"));
+            }
+            control.exec_tracer = Some(ExecTracer::new()?);
+        }
+        if let Some(paths) = trace_files {
+            if control.info_log.is_none() {
+                return Err(anyhow!("--trace-files needs an advanced timing log (-T with -m advanced)"));
+            }
+            let paths: Vec<PathBuf> = paths.split(',').filter(|path| !path.is_empty()).map(PathBuf::from).collect();
+            control.file_tracer = Some(FileTracer::new(&paths)?);
+            if control.exec_tracer.is_none() {
+                control.exec_tracer = Some(ExecTracer::new()?);
+            }
+        }
+
+        if let Some(path) = transcript {
+            let compression = control.compression.unwrap_or_else(|| Compression::from_path(&path));
+            let sink = TranscriptSink::new(path, control.append, control.clock.clone())
+                .with_compression(compression)
+                .with_encryption(control.encryption.clone());
+            control.add_sink(Box::new(sink));
+        }
+
+        // Checked once the logs are open, before anything is recorded
+        if let Some(size) = reserve {
+            let logs = control.in_logs.iter().chain(&control.out_logs).map(|log| log.path());
+            let mut reserve = Reserve::new(size, logs);
+            for low in reserve.check() {
+                if !reserve_warn {
+                    return Err(anyhow!(
+                        "Only {} free in {}, less than --reserve {}",
+                        reserve::mib(low.free),
+                        low.dir.display(),
+                        reserve::mib(low.reserve)
+                    ));
+                }
+                if !control.quiet {
+                    eprintln!("{}", control.space_low_message(&low));
+                }
+            }
+            control.reserve = Some(reserve);
+        }
+
+        #[cfg(feature = "timeline")]
+        if meta {
+            let log = control
+                .out_logs
+                .first()
+                .or(control.in_logs.first())
+                .map(|log| log.path().to_path_buf())
+                .ok_or_else(|| anyhow!("--meta needs a log to keep the sidecar beside"))?;
+            let sink = MetaSink::new(logging::sidecar_path(&log), log, control.clock.clone());
+            control.add_sink(Box::new(sink));
+            #[cfg(feature = "analyzers")]
+            {
+                control.meta = true;
+            }
+        }
+
+        #[cfg(feature = "analyzers")]
+        if !analyzers.is_empty() {
+            let (analyzers, spool) = Analyzers::new(analyzers, control.clock.clone())?;
+            control.add_sink(Box::new(spool));
+            control.analyzers = Some(analyzers);
+        }
+
         Ok(control)
     }
 
     fn init_terminal_info(&mut self) -> Result<()> {
         self.tty_name = utils::get_terminal_name();
-        self.tty_type = utils::get_terminal_type();
+        self.tty_type = config::term();
         Ok(())
     }
 
@@ -107,9 +539,21 @@ impl ScriptControl {
         let mut timingfile = None;
         let mut format = LogFormat::Raw;
 
+        // -m takes the timing format, or `plain` for output logs of plain text
+        let forced = match args.logging_format.or_else(config::default_format) {
+            Some(fmt_str) => Some(match fmt_str.to_lowercase().as_str() {
+                "classic" => LogFormat::TimingSimple,
+                "advanced" => LogFormat::TimingMulti,
+                "plain" => LogFormat::Plain,
+                _ => return Err(anyhow!("Unsupported logging format: '{}'", fmt_str)),
+            }),
+            None => None,
+        };
+        let output_format = if forced == Some(LogFormat::Plain) { LogFormat::Plain } else { LogFormat::Raw };
+
         // Handle log-io option (both input and output)
         if let Some(path) = args.log_io {
-            self.associate_log(&path, LogFormat::Raw, true, true)?;
+            self.associate_log(&path, output_format, true, true)?;
             outfile = Some(path.clone());
             infile = Some(path);
         }
@@ -122,10 +566,20 @@ impl ScriptControl {
 
         // Handle log-out option
         if let Some(path) = args.log_out {
-            self.associate_log(&path, LogFormat::Raw, false, true)?;
+            self.associate_log(&path, output_format, false, true)?;
             outfile = Some(path);
         }
 
+        // A ttyrec log carries its own timing, so it stands on its own, as
+        // does a plain text one, which has none
+        let standalone = args.ttyrec.is_some() || args.plain.is_some();
+        if let Some(path) = args.ttyrec {
+            self.associate_log(&path, LogFormat::TtyRec, false, true)?;
+        }
+        if let Some(path) = args.plain {
+            self.associate_log(&path, LogFormat::Plain, false, true)?;
+        }
+
         // Handle timing options
         if let Some(path) = args.log_timing {
             timingfile = Some(path);
@@ -134,15 +588,14 @@ impl ScriptControl {
         }
 
         // Determine timing format
-        if let Some(fmt_str) = args.logging_format {
-            format = match fmt_str.to_lowercase().as_str() {
-                "classic" => LogFormat::TimingSimple,
-                "advanced" => LogFormat::TimingMulti,
-                _ => return Err(anyhow!("Unsupported logging format: '{}'", fmt_str)),
-            };
+        if let Some(forced) = forced {
+            if forced == LogFormat::Plain && timingfile.is_some() {
+                return Err(anyhow!("A plain text log has no bytes for a timing file to count (-m plain with -T)"));
+            }
+            format = forced;
         } else if timingfile.is_some() {
-            // Auto-detect format based on whether we have both input and output
-            format = if infile.is_some() && outfile.is_some() {
+            // Auto-detect format based on whether we have more than the output
+            format = if (infile.is_some() || args.log_err.is_some()) && outfile.is_some() {
                 LogFormat::TimingMulti
             } else {
                 LogFormat::TimingSimple
@@ -160,21 +613,59 @@ impl ScriptControl {
         }
 
         // Default output file if none specified
-        if outfile.is_none() && infile.is_none() {
-            let default_file = args.file.unwrap_or_else(|| PathBuf::from(DEFAULT_TYPESCRIPT_FILENAME));
+        if outfile.is_none() && infile.is_none() && !standalone {
+            let default_file = args.file.unwrap_or_else(|| {
+                config::default_dir().unwrap_or_default().join(DEFAULT_TYPESCRIPT_FILENAME)
+            });
             
             if !self.force {
-                utils::die_if_link(&default_file)?;
+                utils::die_if_link(&default_file).context(Failure::Output(default_file.clone()))?;
             }
             
-            self.associate_log(&default_file, LogFormat::Raw, false, true)?;
+            self.associate_log(&default_file, output_format, false, true)?;
+        }
+
+        // The stderr log has only the one stream; the advanced timing log
+        // has it as E records
+        if let Some(path) = args.log_err {
+            let logger = self.open_log(&path, LogFormat::Raw)?;
+            let mut logs = vec![logger.clone()];
+            logs.extend(self.info_log.clone());
+            for log in logs {
+                self.sinks.push(Sink { log: Box::new(log), input: false, output: false, stderr: true, events: false });
+            }
+            self.err_log = Some(logger);
         }
 
         Ok(())
     }
 
-    fn associate_log(&mut self, path: &PathBuf, format: LogFormat, is_input: bool, is_output: bool) -> Result<()> {
-        let logger = ScriptLogger::new(path.clone(), format, self.append)?;
+    fn open_log(&self, path: &Path, format: LogFormat) -> Result<ScriptLogger> {
+        let compression = self.compression.unwrap_or_else(|| Compression::from_path(path));
+        let transformed = compression != Compression::None || self.encryption.is_some();
+        if self.seek_index.is_some() && format == LogFormat::Raw && transformed {
+            return Err(anyhow!("--seek-index needs logs that are not compressed or encrypted: {}", path.display()));
+        }
+        Ok(ScriptLogger::new(path.to_path_buf(), format, self.append, self.clock.clone())?
+            .with_buffer_size(self.write_buffer)
+            .with_compression(compression)
+            .with_encryption(self.encryption.clone())
+            .with_rotate_size(self.rotate_size)
+            .with_failover_dir(self.failover_dir.clone())
+            .with_seek_index(self.seek_index)
+            .with_timestamps(self.wall_clock))
+    }
+
+    fn associate_log(&mut self, path: &Path, format: LogFormat, is_input: bool, is_output: bool) -> Result<()> {
+        // Reuse the logger if this file is already associated with another stream
+        let existing = self.in_logs.iter()
+            .chain(self.out_logs.iter())
+            .find(|l| l.path() == path && l.format() == format)
+            .cloned();
+        let logger = match existing {
+            Some(logger) => logger,
+            None => self.open_log(path, format)?,
+        };
 
         if is_input {
             self.in_logs.push(logger.clone());
@@ -183,62 +674,210 @@ impl ScriptControl {
             self.out_logs.push(logger.clone());
         }
 
-        // Set up signal and info logs for multi-stream timing
-        if format == LogFormat::TimingMulti {
-            if self.sig_log.is_none() {
-                self.sig_log = Some(logger.clone());
-            }
-            if self.info_log.is_none() {
-                self.info_log = Some(logger);
-            }
+        // The first advanced timing log also takes signals and info
+        let events = format == LogFormat::TimingMulti && self.info_log.is_none();
+        if events {
+            self.info_log = Some(logger.clone());
         }
+        self.sinks.push(Sink {
+            log: Box::new(logger),
+            input: is_input,
+            output: is_output,
+            stderr: false,
+            events,
+        });
 
         Ok(())
     }
 
-    pub async fn run(&mut self) -> Result<()> {
+    // Record the session to another sink as well, with both streams and all
+    // events. Must be called before run().
+    pub fn add_sink(&mut self, sink: Box<dyn LogSink>) {
+        self.sinks.push(Sink {
+            log: sink,
+            input: true,
+            output: true,
+            stderr: true,
+            events: true,
+        });
+    }
+
+    // Returns the child's exit status, 128 + signal number if it was killed
+    pub async fn run(&mut self) -> Result<i32> {
+        let result = self.run_session().await;
+        // The signals go back to what they did before the session, so a
+        // program that records one is left as it was
+        self.dispositions = None;
+        result
+    }
+
+    async fn run_session(&mut self) -> Result<i32> {
         // Create PTY session
-        self.pty = Some(PtySession::new(self.is_term)?);
+        self.pty = Some(PtySession::new(self.is_term).context(Failure::Pty)?);
+        self.session_tty = self.pty.as_ref().and_then(|pty| utils::tty_name(pty.get_slave_fd()));
 
+        let output_log = self.out_logs.iter().find(|l| matches!(l.format(), LogFormat::Raw | LogFormat::Plain));
+        let output_path = output_log.map(|log| log.path().to_path_buf());
         if !self.quiet {
-            println!("Script started");
-            // TODO: Print log file information
-            println!(".");
+            match output_path {
+                Some(ref path) => println!("{}", tr(Message::Started, &[&path.display()])),
+                None => println!("{}", tr(Message::StartedNoLog, &[])),
+            }
         }
+        // As util-linux does; a script started in the session can tell
+        let script = output_path.unwrap_or_else(|| PathBuf::from("/dev/null"));
+        self.child_env.preset("SCRIPT", &script.to_string_lossy());
 
-        // Set up the PTY
+        // Set up the PTY. Without raw mode, the terminal hands the input
+        // over a line at a time and echoes it itself; recording a session
+        // that way beats not recording it.
         if let Some(ref mut pty) = self.pty {
-            pty.setup()?;
+            // Nothing is known about what a dumb or unnamed terminal can do
+            self.degraded = match self.tty_type.as_deref() {
+                // Raw mode is for the keys read from the terminal
+                _ if !self.is_term || !self.read_stdin => None,
+                None => Some("TERM is not set".to_string()),
+                Some("dumb") => Some("TERM=dumb".to_string()),
+                Some(_) => pty.setup().err().map(|e| format!("cannot set up the terminal: {:#}", e)),
+            };
+            if let Some(ref reason) = self.degraded {
+                pty.echo = false;
+                if !self.quiet {
+                    eprintln!("{}", tr(Message::Degraded, &[reason]));
+                }
+            }
+        }
+
+        // Taken before the child can change anything
+        #[cfg(feature = "snapshot")]
+        if let Some(ref mut snapshots) = self.snapshots {
+            snapshots.before()?;
+        }
+
+        // A signal the child sends the recorder as soon as it starts is
+        // neither lost nor, for want of a handler, fatal to the recorder
+        let cooked = self.degraded.is_some();
+        let (signals, dispositions) = Dispositions::catch(|| Signals::new(cooked))?;
+        self.signals = Some(signals);
+        self.dispositions = Some(dispositions);
+
+        // The child reports a failed exec through this pipe; a successful
+        // one closes it
+        let (exec_read, exec_write) = nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC)?;
+        if self.err_log.is_some() {
+            self.stderr_pipe = Some(nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC)?);
         }
 
         // Fork the child process
         match unsafe { fork() }? {
             ForkResult::Parent { child } => {
                 self.child_pid = Some(child);
-                self.run_parent().await?;
+                if let (WaitFor::Pty, Some(ref mut pty)) = (self.wait_for, &mut self.pty) {
+                    pty.close_slave();
+                }
+                let _ = nix::unistd::close(exec_write);
+                if let Some((_, stderr_write)) = self.stderr_pipe {
+                    let _ = nix::unistd::close(stderr_write);
+                }
+                let exec_error = read_exec_error(exec_read);
+                if !exec_error.is_empty() {
+                    let _ = nix::sys::wait::waitpid(child, None);
+                    return Err(Failure::Exec(exec_error).into());
+                }
+                let result = self.run_parent().await;
+                // A session cut short, at the output limit say, leaves
+                // nothing running on the terminal
+                if result.is_err() && self.child_status.is_none() {
+                    self.terminate_session(nix::sys::signal::Signal::SIGTERM);
+                }
+                result?;
             }
             ForkResult::Child => {
-                self.run_child()?;
+                let _ = nix::unistd::close(exec_read);
+                if let Err(e) = self.run_child() {
+                    let _ = nix::unistd::write(exec_write, format!("{:#}", e).as_bytes());
+                }
+                // Skip destructors; the parent owns the terminal and logs
+                unsafe { libc::_exit(failure::EXIT_FAILURE) };
             }
         }
 
-        Ok(())
+        Ok(self.child_status.unwrap_or(0))
     }
 
     async fn run_parent(&mut self) -> Result<()> {
+        // Only the recorder gets the requested scheduling, so it is applied
+        // after the fork
+        if let Some(priority) = self.rt_priority {
+            scheduling::set_rt_priority(priority)?;
+        }
+        if let Some(ref cpus) = self.cpu_affinity {
+            scheduling::set_cpu_affinity(cpus)?;
+        }
+        if let Some(percent) = self.self_limit.cpu {
+            match scheduling::limit_cpu(percent) {
+                Ok(cgroup) => self.cpu_cgroup = Some(cgroup),
+                Err(e) if !self.quiet => eprint!("{}\r\n", tr(Message::CpuLimitUnavailable, &[&format!("{:#}", e)])),
+                Err(_) => {}
+            }
+        }
+
+        // The log writers' threads would keep the privileges, so they wait
+        // for the drop
+        if self.drop_privs {
+            logging::hold_writers();
+        }
+
         // Start logging
         self.start_logging().await?;
 
+        // Everything privileged (PTY, log files) is open at this point
+        if self.drop_privs {
+            privileges::drop_privileges(self.privs_namespace)?;
+            logging::release_writers();
+        }
+
         // Start I/O proxy
         if let Some(ref pty) = self.pty {
             self.proxy_io(pty.get_master_fd()).await?;
         }
 
+        #[cfg(feature = "snapshot")]
+        if let Some(ref mut snapshots) = self.snapshots {
+            let changed = snapshots.after()?;
+            if let Some(ref mut info_log) = self.info_log {
+                info_log.log_info("SNAPSHOT_CHANGES", &changed.to_string())?;
+            }
+        }
+
         // Stop logging
         self.stop_logging().await?;
 
+        // The sidecar sits next to the main log
+        #[cfg(feature = "analyzers")]
+        if let (Some(analyzers), Some(log)) = (&self.analyzers, self.out_logs.first()) {
+            analyzers.run(&logging::sidecar_path(log.path()), self.meta)?;
+        }
+
+        // Only now is everything in the session's directory written
+        if let Some((ref spool, hash)) = self.spool {
+            let dir = spool.complete(hash)?;
+            if !self.quiet {
+                println!("{}", tr(Message::Spooled, &[&dir.display()]));
+            }
+        }
+
+        if self.debug {
+            eprint!(
+                "script: read buffer {} bytes{}, write buffer {} bytes\r\n",
+                self.read_buffer,
+                if self.read_autotune { " (autotuned)" } else { "" },
+                self.write_buffer
+            );
+        }
+
         if !self.quiet {
-            println!("Script done.");
+            println!("{}", tr(Message::Done, &[]));
         }
 
         Ok(())
@@ -252,42 +891,310 @@ impl ScriptControl {
         nix::fcntl::fcntl(master_fd, nix::fcntl::FcntlArg::F_SETFL(nix::fcntl::OFlag::from_bits_truncate(flags) | nix::fcntl::OFlag::O_NONBLOCK))?;
         
         // Set up signal handling
-        let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
-        let mut sigwinch = signal::unix::signal(signal::unix::SignalKind::window_change())?;
+        let signals = match self.signals.take() {
+            Some(signals) => signals,
+            None => Signals::new(self.degraded.is_some())?,
+        };
+        let Signals {
+            terminate: mut sigterm,
+            window_change: mut sigwinch,
+            child: mut sigchld,
+            hangup: mut sighup,
+            interrupt: mut sigint,
+            quit: mut sigquit,
+            user1: mut sigusr1,
+            user2: mut sigusr2,
+            stop: mut sigtstp,
+        } = signals;
+
+        // Woken by the kernel when the session has output, so nothing is
+        // delayed and an idle session costs no CPU
+        let master = AsyncFd::with_interest(master_fd, Interest::READABLE)?;
+
+        // Only the watchdog, the heartbeat and the space checks need waking
+        // up without any I/O
+        let mut watchdog_tick = self.watchdog.as_ref().map(|_| tokio::time::interval(watchdog::CHECK_INTERVAL));
+        let mut heartbeat_tick = self.heartbeat.map(|period| {
+            let mut tick = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            tick
+        });
+        let mut reserve_tick = self.reserve.as_ref().map(|_| {
+            let period = reserve::CHECK_INTERVAL;
+            let mut tick = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            tick
+        });
         
         let mut stdin = tokio::io::stdin();
         let mut stdout = tokio::io::stdout();
         
         let mut stdin_buf = [0u8; 8192];
-        let mut master_buf = [0u8; 8192];
+        let mut master_buf = vec![0u8; self.read_buffer];
+        let mut full_reads = 0;
+        // A chunk at most a quarter of the memory budget, so a few fit
+        let max_read_buffer = match self.self_limit.mem {
+            Some(mem) => MAX_READ_BUFFER.min(mem as usize / 4).max(self.read_buffer),
+            None => MAX_READ_BUFFER,
+        };
+
+        // Owned here so waiting on it doesn't hold a borrow of self
+        let control = self.control.take();
+        let mut control_requests = control.as_ref().map(ControlSocket::requests);
+        let mut exec_tracer = self.exec_tracer.take();
+        if let (Some(tracer), Some(child_pid)) = (&mut exec_tracer, self.child_pid) {
+            tracer.set_root(child_pid.as_raw());
+        }
+        let exec_events = exec_tracer
+            .as_ref()
+            .map(|tracer| AsyncFd::with_interest(tracer.as_raw_fd(), Interest::READABLE))
+            .transpose()?;
+        let mut file_tracer = self.file_tracer.take();
+        let file_events = file_tracer
+            .as_ref()
+            .map(|tracer| AsyncFd::with_interest(tracer.as_raw_fd(), Interest::READABLE))
+            .transpose()?;
+        let read_stdin = self.read_stdin;
+        // Cleared once stdin is at its end, e.g. when it is a pipe. The
+        // child is then sent an end-of-file once it has read everything
+        // before it, checked on this tick.
+        let mut stdin_open = true;
+        let mut line_open = false;
+        let mut eof_tick: Option<tokio::time::Interval> = None;
+        let mut marker_tick = self.auto_markers.as_ref().and_then(AutoMarkers::interval).map(|period| {
+            let mut tick = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            tick
+        });
+        let mut feed_tick = self.feed.as_ref().map(|feed| {
+            let mut tick = tokio::time::interval(feed.interval());
+            tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            tick
+        });
+        let stderr_fd = self.stderr_pipe.map(|(stderr_read, _)| stderr_read);
+        if let Some(fd) = stderr_fd {
+            let flags = nix::fcntl::fcntl(fd, nix::fcntl::FcntlArg::F_GETFL)?;
+            nix::fcntl::fcntl(fd, nix::fcntl::FcntlArg::F_SETFL(nix::fcntl::OFlag::from_bits_truncate(flags) | nix::fcntl::OFlag::O_NONBLOCK))?;
+        }
+        let mut stderr_events = stderr_fd.map(|fd| AsyncFd::with_interest(fd, Interest::READABLE)).transpose()?;
         
         loop {
+            // Checked before waiting, as the child may have exited before
+            // SIGCHLD was being listened for
+            if let (Some(child_pid), None) = (self.child_pid, self.child_status) {
+                match nix::sys::wait::waitpid(child_pid, Some(nix::sys::wait::WaitPidFlag::WNOHANG))? {
+                    nix::sys::wait::WaitStatus::StillAlive => {
+                        // Child still running, make sure it is not wedged
+                        self.check_watchdog(child_pid).await?;
+                    }
+                    status => {
+                        self.child_status = Some(exit_code(status));
+                        // With --wait-for pty the session goes on until the
+                        // terminal is closed, which may well be now
+                        if self.wait_for == WaitFor::Child {
+                            // Pick up whatever it wrote last
+                            self.drain_master(master_fd).await?;
+                            if let (Some(fd), Some(_)) = (stderr_fd, &stderr_events) {
+                                self.read_stderr(fd).await?;
+                            }
+                            break;
+                        }
+                    }
+                }
+            }
+
             tokio::select! {
                 // Handle signals
                 _ = sigterm.recv() => {
                     self.handle_signal("SIGTERM").await?;
-                    // Forward SIGTERM to child process
-                    if let Some(child_pid) = self.child_pid {
-                        let _ = nix::sys::signal::kill(child_pid, nix::sys::signal::Signal::SIGTERM);
-                    }
+                    self.terminated_by = Some(libc::SIGTERM);
+                    self.terminate_session(nix::sys::signal::Signal::SIGTERM);
                     break;
                 }
                 _ = sigwinch.recv() => {
                     self.handle_window_change().await?;
                 }
+                // logrotate has moved the logs, and the session goes on;
+                // unless the recorder's own terminal has hung up (a closed
+                // window, a dropped ssh connection), which ends the session
+                // as it would any other program on it
+                _ = sighup.recv() => {
+                    self.handle_signal("SIGHUP").await?;
+                    if self.terminal_hung_up() {
+                        self.terminated_by = Some(libc::SIGHUP);
+                        // Nothing more can be shown on it
+                        self.quiet = true;
+                        self.terminate_session(nix::sys::signal::Signal::SIGHUP);
+                        break;
+                    }
+                    self.reopen_logs()?;
+                }
+                // The child's state is checked at the top of the loop
+                _ = sigchld.recv() => {}
+                _ = sigint.recv() => {
+                    self.forward_signal(master_fd, nix::sys::signal::Signal::SIGINT).await?;
+                }
+                _ = sigquit.recv() => {
+                    self.forward_signal(master_fd, nix::sys::signal::Signal::SIGQUIT).await?;
+                }
+                _ = sigusr1.recv() => {
+                    self.forward_signal(master_fd, nix::sys::signal::Signal::SIGUSR1).await?;
+                }
+                _ = sigusr2.recv() => {
+                    self.forward_signal(master_fd, nix::sys::signal::Signal::SIGUSR2).await?;
+                }
+                _ = async {
+                    match sigtstp {
+                        Some(ref mut tstp) => tstp.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.send_key(master_fd, libc::VSUSP).await?;
+                }
+                _ = async {
+                    match watchdog_tick {
+                        Some(ref mut tick) => tick.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {}
+                _ = async {
+                    match heartbeat_tick {
+                        Some(ref mut tick) => tick.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.log_heartbeat().await?;
+                }
+                _ = async {
+                    match marker_tick {
+                        Some(ref mut tick) => tick.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    let now = self.clock.now();
+                    if self.auto_markers.as_mut().is_some_and(|markers| markers.tick(now)) {
+                        self.log_event("MARKER", Some("AUTO=interval")).await?;
+                    }
+                }
+                _ = async {
+                    match feed_tick {
+                        Some(ref mut tick) => tick.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    if let Some(data) = self.feed.as_mut().and_then(Feed::next) {
+                        self.send_input(master_fd, InputChunk::Typed(data)).await?;
+                    }
+                    // The end of the file is the end of the input
+                    if let Some(feed) = self.feed.as_ref().filter(|feed| feed.is_done()) {
+                        line_open = feed.line_open();
+                        feed_tick = None;
+                        eof_tick = Some(tokio::time::interval(EOF_CHECK_INTERVAL));
+                    }
+                }
+                _ = async {
+                    match eof_tick {
+                        Some(ref mut tick) => tick.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    if self.send_eof(master_fd, line_open)? {
+                        eof_tick = None;
+                    }
+                }
+                _ = async {
+                    match reserve_tick {
+                        Some(ref mut tick) => tick.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.check_reserve().await?;
+                }
+
+                guard = async {
+                    match exec_events {
+                        Some(ref events) => events.readable().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    guard?.clear_ready();
+                    if let Some(ref mut tracer) = exec_tracer {
+                        self.log_execs(tracer.read()?).await?;
+                    }
+                }
+                guard = async {
+                    match file_events {
+                        Some(ref events) => events.readable().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    guard?.clear_ready();
+                    if let (Some(ref mut files), Some(ref mut tracer)) = (&mut file_tracer, &mut exec_tracer) {
+                        let accesses = files.read()?;
+                        // Forks the accesses were made after are known first
+                        self.log_execs(tracer.read()?).await?;
+                        self.log_file_events(accesses, tracer).await?;
+                    }
+                }
+
+                // --log-err: the child's stderr
+                guard = async {
+                    match stderr_events {
+                        Some(ref events) => events.readable().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    guard?.clear_ready();
+                    if let Some(fd) = stderr_fd {
+                        // Everything holding the write end has gone
+                        if self.read_stderr(fd).await? {
+                            stderr_events = None;
+                        }
+                    }
+                }
+
+                // Commands from the control socket
+                request = async {
+                    match control_requests {
+                        Some(ref mut requests) => requests.next().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    let request = request?;
+                    let result = self.handle_control(&request.command).await;
+                    // The client may not wait for the answer
+                    let _ = request.reply(result).await;
+                }
                 
                 // Read from stdin and write to master
-                result = stdin.read(&mut stdin_buf) => {
+                result = stdin.read(&mut stdin_buf), if read_stdin && stdin_open => {
                     match result {
-                        Ok(0) => break, // EOF
+                        // The child gets the end of its input as it would
+                        // from a keyboard, and the session goes on until it
+                        // has exited
+                        // ^D on a terminal in cooked mode, which goes on
+                        Ok(0) if self.degraded.is_some() => {
+                            if let Some(ref pty) = self.pty {
+                                let eof = pty.get_termios()?.c_cc[libc::VEOF];
+                                self.send_input(master_fd, InputChunk::Typed(vec![eof])).await?;
+                            }
+                        }
+                        Ok(0) => {
+                            stdin_open = false;
+                            eof_tick = Some(tokio::time::interval(EOF_CHECK_INTERVAL));
+                        }
+                        Ok(n) if self.input_locked => {
+                            // Someone else has the keyboard; note the attempt only
+                            self.log_event("INPUT_BLOCKED", Some(&format!("BYTES={}", n))).await?;
+                        }
                         Ok(n) => {
-                            // Log input
-                            self.log_input(&stdin_buf[..n]).await?;
-                            
-                            // Write to master PTY
-                            let bytes_written = nix::unistd::write(master_fd, &stdin_buf[..n])?;
-                            if bytes_written != n {
-                                return Err(anyhow!("Partial write to master PTY"));
+                            line_open = !matches!(stdin_buf[n - 1], b'\n' | b'\r');
+                            let chunks = match self.macros {
+                                Some(ref mut macros) => macros.feed(&stdin_buf[..n]),
+                                None => vec![InputChunk::Typed(stdin_buf[..n].to_vec())],
+                            };
+                            for chunk in chunks {
+                                self.send_input(master_fd, chunk).await?;
                             }
                         }
                         Err(e) => return Err(e.into()),
@@ -295,177 +1202,519 @@ impl ScriptControl {
                 }
                 
                 // Read from master and write to stdout
-                _ = tokio::time::sleep(tokio::time::Duration::from_millis(10)) => {
-                    // Use non-blocking read from master
-                    match nix::unistd::read(master_fd, &mut master_buf) {
-                        Ok(n) if n > 0 => {
-                            // Log output
-                            self.log_output(&master_buf[..n]).await?;
-                            
-                            // Write to stdout
-                            stdout.write_all(&master_buf[..n]).await?;
-                            stdout.flush().await?;
-                        }
-                        Ok(0) => break, // EOF
-                        Ok(_) => {}, // Zero bytes read but not EOF
-                        Err(e) if e == nix::errno::Errno::EAGAIN || e == nix::errno::Errno::EWOULDBLOCK => {
-                            // No data available, continue
-                        }
-                        Err(e) => return Err(anyhow!("Error reading from master PTY: {}", e)),
-                    }
-                }
-            }
-            
-            // Check if child has exited
-            if let Some(child_pid) = self.child_pid {
-                match nix::sys::wait::waitpid(child_pid, Some(nix::sys::wait::WaitPidFlag::WNOHANG))? {
-                    nix::sys::wait::WaitStatus::StillAlive => {
-                        // Child still running, continue
-                    }
-                    status => {
-                        // Child has exited
-                        match status {
-                            nix::sys::wait::WaitStatus::Exited(_, code) => {
-                                self.child_status = Some(code);
+                guard = master.readable() => {
+                    let mut guard = guard?;
+
+                    // Read until the buffer is full or nothing is pending;
+                    // the PTY hands data out in small chunks
+                    let mut n = 0;
+                    let mut eof = false;
+                    while n < master_buf.len() {
+                        match nix::unistd::read(master_fd, &mut master_buf[n..]) {
+                            Ok(0) => {
+                                eof = true;
+                                break;
                             }
-                            nix::sys::wait::WaitStatus::Signaled(_, signal, _) => {
-                                self.child_status = Some(128 + signal as i32);
+                            Ok(count) => n += count,
+                            Err(e) if e == nix::errno::Errno::EAGAIN || e == nix::errno::Errno::EWOULDBLOCK => {
+                                guard.clear_ready();
+                                break;
                             }
-                            _ => {
-                                self.child_status = Some(1);
+                            // No process has the terminal open any more
+                            Err(nix::errno::Errno::EIO) => {
+                                eof = true;
+                                break;
                             }
+                            Err(e) => return Err(anyhow!("Error reading from master PTY: {}", e)),
+                        }
+                    }
+
+                    if n > 0 {
+                        // Log output
+                        self.log_output(&master_buf[..n]).await?;
+                        if let Some(ref mut watchdog) = self.watchdog {
+                            watchdog.activity();
+                        }
+
+                        // Write to stdout
+                        stdout.write_all(&master_buf[..n]).await?;
+                        stdout.flush().await?;
+
+                        // Answered once the question is on the screen
+                        if let Some((rule, response)) = self.expect.as_mut().and_then(|e| e.output(&master_buf[..n])) {
+                            let msg = format!("RULE={} BYTES={}", rule, response.len());
+                            self.log_event("EXPECT", Some(&msg)).await?;
+                            self.send_input(master_fd, InputChunk::Typed(response)).await?;
+                        }
+
+                        // Grow the buffer while the session keeps filling it
+                        full_reads = if n == master_buf.len() { full_reads + 1 } else { 0 };
+                        if self.read_autotune && full_reads >= AUTOTUNE_FULL_READS && master_buf.len() < max_read_buffer {
+                            self.read_buffer = (master_buf.len() * 2).min(max_read_buffer);
+                            master_buf.resize(self.read_buffer, 0);
+                            full_reads = 0;
+                        }
+                    }
+                    if eof {
+                        if let (Some(fd), Some(_)) = (stderr_fd, &stderr_events) {
+                            self.read_stderr(fd).await?;
+                        }
+                        // Its exit status, should it have closed the
+                        // terminal and gone on running
+                        if self.child_status.is_none() {
+                            self.wait_for_child().await?;
                         }
                         break;
                     }
                 }
             }
+
+        }
+
+        // What the session ran and touched last, up to the child's exit
+        if let Some(ref mut tracer) = exec_tracer {
+            let accesses = match file_tracer {
+                Some(ref mut files) => files.read()?,
+                None => Vec::new(),
+            };
+            self.log_execs(tracer.read()?).await?;
+            self.log_file_events(accesses, tracer).await?;
+        }
+        drop(stderr_events);
+        if let Some(fd) = stderr_fd {
+            let _ = nix::unistd::close(fd);
+        }
+        if let Some(requests) = control_requests {
+            requests.close().await;
         }
         
         Ok(())
     }
 
+    async fn send_input(&mut self, master_fd: RawFd, chunk: InputChunk) -> Result<()> {
+        let data = match chunk {
+            InputChunk::Typed(data) => data,
+            InputChunk::Macro(key, text) => {
+                // Mark where canned input starts so it can be told from typing
+                let msg = format!("KEY={} BYTES={}", macros::key_name(key), text.len());
+                self.log_event("MACRO", Some(&msg)).await?;
+                text
+            }
+        };
+
+        // Log input
+        self.log_input(&data).await?;
+
+        // Write to master PTY
+        let bytes_written = nix::unistd::write(master_fd, &data)?;
+        if bytes_written != data.len() {
+            return Err(anyhow!("Partial write to master PTY"));
+        }
+        self.bytes_in += data.len() as u64;
+        Ok(())
+    }
+
+    // The terminal's end-of-file character (^D), once the child has read
+    // what came before it: sent earlier, a shell between two commands could
+    // take it while the terminal is in the wrong mode and lose it. A second
+    // one follows a line without a newline, which the first only hands over.
+    // False while the child still has input to read.
+    fn send_eof(&self, master_fd: RawFd, line_open: bool) -> Result<bool> {
+        let Some(ref pty) = self.pty else {
+            return Ok(true);
+        };
+        if pty.pending_input()? > 0 {
+            return Ok(false);
+        }
+        let eof = pty.get_termios()?.c_cc[libc::VEOF];
+        let count = if line_open { 2 } else { 1 };
+        nix::unistd::write(master_fd, &[eof, eof][..count])?;
+        Ok(true)
+    }
+
+    async fn drain_master(&mut self, master_fd: RawFd) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut stdout = tokio::io::stdout();
+        let mut buf = vec![0u8; self.read_buffer];
+        loop {
+            match nix::unistd::read(master_fd, &mut buf) {
+                Ok(n) if n > 0 => {
+                    self.log_output(&buf[..n]).await?;
+                    stdout.write_all(&buf[..n]).await?;
+                }
+                // EOF, EIO once the slave is gone, or EAGAIN when empty
+                _ => break,
+            }
+        }
+        stdout.flush().await?;
+        Ok(())
+    }
+
+    // Reads what the child wrote to stderr until nothing is pending, passing
+    // it on to our own; true at EOF
+    async fn read_stderr(&mut self, fd: RawFd) -> Result<bool> {
+        let mut buf = [0u8; 8192];
+        loop {
+            match nix::unistd::read(fd, &mut buf) {
+                Ok(0) => return Ok(true),
+                Ok(n) => {
+                    self.write_sinks(LogStream::Stderr, &buf[..n])?;
+                    if let Some(ref mut watchdog) = self.watchdog {
+                        watchdog.activity();
+                    }
+                    // Our terminal is in raw mode and doesn't add the \r
+                    let mut stderr = std::io::stderr();
+                    let data = if self.is_term && stderr.is_terminal() {
+                        utils::crlf(&buf[..n])
+                    } else {
+                        buf[..n].to_vec()
+                    };
+                    stderr.write_all(&data)?;
+                    stderr.flush()?;
+                }
+                Err(nix::errno::Errno::EINTR) => {}
+                Err(_) => return Ok(false),
+            }
+        }
+    }
+
+    async fn check_watchdog(&mut self, child_pid: nix::unistd::Pid) -> Result<()> {
+        let Some(ref mut watchdog) = self.watchdog else {
+            return Ok(());
+        };
+        let Some(stalled) = watchdog.check(child_pid) else {
+            return Ok(());
+        };
+
+        let idle = watchdog.idle().as_secs();
+        let pids: Vec<String> = stalled.iter().map(|p| p.to_string()).collect();
+        watchdog.run_hook(child_pid, &stalled);
+
+        if !self.quiet {
+            eprint!("{}\r\n", tr(Message::WatchdogIdle, &[&idle, &pids.join(" ")]));
+        }
+        let msg = format!("IDLE={} PIDS={}", idle, pids.join(","));
+        self.log_event("WATCHDOG", Some(&msg)).await
+    }
+
+    fn child_state(&self) -> &'static str {
+        // Reaped, its pid could be anyone's
+        if self.child_status.is_some() {
+            return "exited";
+        }
+        match self.child_pid.and_then(|pid| utils::process_state(pid.as_raw())) {
+            Some('T') | Some('t') => "stopped",
+            Some('Z') | None => "exited",
+            Some(_) => "running",
+        }
+    }
+
+    async fn log_heartbeat(&mut self) -> Result<()> {
+        let msg = format!("BYTES_IN={} BYTES_OUT={} CHILD={}", self.bytes_in, self.bytes_out, self.child_state());
+        self.log_event("HEARTBEAT", Some(&msg)).await
+    }
+
     async fn log_input(&mut self, data: &[u8]) -> Result<()> {
-        for logger in &mut self.in_logs {
-            let size = logger.log_data(crate::logging::LogStream::Input, data).await?;
-            self.out_size += size as u64;
-            
-            // Check output limit
-            if self.max_size > 0 && self.out_size >= self.max_size {
-                if !self.quiet {
-                    println!("Script terminated, max output files size {} exceeded.", self.max_size);
+        // Left out too, but the marker only counts the output
+        if self.skip_alt_screen && self.alt_screen.is_active() {
+            return Ok(());
+        }
+
+        self.write_sinks(LogStream::Input, data)?;
+        if let Some(mark) = self.prompts.as_mut().and_then(|prompts| prompts.input(data)) {
+            self.log_event(mark.name(), None).await?;
+        }
+
+        // Keys pressed in a full-screen program aren't command lines
+        if !self.alt_screen.is_active() {
+            for line in self.typed.feed(data) {
+                for sudo in sudo::find(&line) {
+                    let message = sudo.message(self.session_tty.as_deref());
+                    self.log_event("SUDO", Some(&message)).await?;
+                }
+                // A line typed without echo is a password, not a command
+                let now = self.clock.now();
+                let marked = self.auto_markers.is_some()
+                    && self.echoing()?
+                    && self.auto_markers.as_mut().is_some_and(|markers| markers.command(now));
+                if marked {
+                    self.log_event("MARKER", Some(&format!("AUTO=command COMMAND={}", line.trim()))).await?;
                 }
-                return Err(anyhow!("Output size limit exceeded"));
             }
         }
         Ok(())
     }
 
     async fn log_output(&mut self, data: &[u8]) -> Result<()> {
-        for logger in &mut self.out_logs {
-            let size = logger.log_data(crate::logging::LogStream::Output, data).await?;
+        self.bytes_out += data.len() as u64;
+        if let Some(ref mut markers) = self.auto_markers {
+            markers.activity();
+        }
+        let transitions = self.alt_screen.feed(data);
+        #[cfg(feature = "screenshot")]
+        if let Some(ref mut screen) = self.exit_screen {
+            screen.feed(data, 0);
+        }
+
+        if self.skip_alt_screen {
+            // Only log what is written outside the alternate screen, leaving
+            // a marker in place of each full-screen interlude
+            let mut pos = 0;
+            for transition in &transitions {
+                if transition.active {
+                    self.write_output(&data[pos..transition.start]).await?;
+                    self.alt_omitted = 0;
+                    self.alt_entered = Some(self.clock.now());
+                    pos = transition.start;
+                } else {
+                    self.alt_omitted += transition.offset.saturating_sub(pos) as u64;
+                    self.write_alt_screen_marker().await?;
+                    pos = transition.offset;
+                }
+            }
+
+            if self.alt_screen.is_active() {
+                self.alt_omitted += (data.len() - pos) as u64;
+            } else {
+                self.write_output(&data[pos..]).await?;
+            }
+        } else {
+            self.write_output(data).await?;
+        }
+
+        for transition in transitions {
+            let name = if transition.active { "ALTSCREEN_ENTER" } else { "ALTSCREEN_LEAVE" };
+            self.log_event(name, None).await?;
+        }
+        let mut marks = self.shell_marks.feed(data);
+        if !marks.is_empty() {
+            // The shell marks its commands itself
+            self.prompts = None;
+        } else if let Some(prompts) = &mut self.prompts {
+            marks = prompts.output(data);
+        }
+        for mark in marks {
+            if let (Mark::PromptStart, Some(feed)) = (&mark, &mut self.feed) {
+                feed.prompt();
+            }
+            self.log_event(mark.name(), mark.message().as_deref()).await?;
+        }
+        for image in self.images.feed(data) {
+            self.log_event("IMAGE", Some(&image.message())).await?;
+        }
+        Ok(())
+    }
+
+    // Whether what is typed shows on the terminal, as passwords don't: they
+    // are read a line at a time without echo, while line editors like
+    // readline turn both off and echo by themselves. In cooked mode the
+    // recorder's terminal echoes, whatever the session's does.
+    fn echoing(&self) -> Result<bool> {
+        match self.pty {
+            Some(ref pty) if pty.echo => Ok(pty.get_termios()?.c_lflag & (libc::ECHO | libc::ICANON) != libc::ICANON),
+            _ => Ok(true),
+        }
+    }
+
+    async fn write_alt_screen_marker(&mut self) -> Result<()> {
+        let now = self.clock.now();
+        let seconds = self.alt_entered.take()
+            .map(|entered| now.saturating_duration_since(entered).as_secs())
+            .unwrap_or(0);
+        let marker = format!(
+            "[alt-screen session, {} bytes omitted, {} seconds]\r\n",
+            self.alt_omitted, seconds
+        );
+        self.alt_omitted = 0;
+        self.write_output(marker.as_bytes()).await
+    }
+
+    async fn write_output(&mut self, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        self.write_sinks(LogStream::Output, data)
+    }
+
+    fn write_sinks(&mut self, stream: LogStream, data: &[u8]) -> Result<()> {
+        // Every log leaves out the same data, so they still agree
+        if let Some(limit) = self.self_limit.mem {
+            if (logging::queued_bytes() + data.len()) as u64 > limit {
+                self.dropped += data.len() as u64;
+                return Ok(());
+            }
+            if self.dropped > 0 {
+                let msg = format!("BYTES={}", self.dropped);
+                self.dropped = 0;
+                for sink in &mut self.sinks {
+                    sink.log.log_event("DROPPED", Some(&msg))?;
+                }
+            }
+        }
+
+        for sink in self.sinks.iter_mut().filter(|s| match stream {
+            LogStream::Input => s.input,
+            LogStream::Output => s.output,
+            LogStream::Stderr => s.stderr,
+        }) {
+            let size = sink.log.log_data(stream.clone(), data)?;
             self.out_size += size as u64;
-            
+
             // Check output limit
             if self.max_size > 0 && self.out_size >= self.max_size {
                 if !self.quiet {
-                    println!("Script terminated, max output files size {} exceeded.", self.max_size);
+                    println!("{}", tr(Message::OutputLimit, &[&self.max_size]));
                 }
-                return Err(anyhow!("Output size limit exceeded"));
+                return Err(Failure::OutputLimit.into());
+            }
+        }
+        self.check_failover()
+    }
+
+    // Record each log that moved to the --failover-dir, in the logs taking
+    // events and to the webhook
+    fn check_failover(&mut self) -> Result<()> {
+        let moved: Vec<_> = self.sinks.iter_mut().filter_map(|sink| sink.log.take_failover()).collect();
+        for failover in moved {
+            if !self.quiet {
+                eprint!("{}\r\n", tr(Message::StorageFailover, &[&failover.from.display(), &failover.to.display()]));
+            }
+            for sink in self.sinks.iter_mut().filter(|s| s.events) {
+                sink.log.log_event("STORAGE_FAILOVER", Some(&failover.message()))?;
+            }
+            if let Some(ref url) = self.failover_webhook {
+                failover::alert(url, &failover);
             }
         }
         Ok(())
     }
 
+    fn space_low_message(&self, low: &reserve::SpaceLow) -> String {
+        tr(Message::SpaceLow, &[&reserve::mib(low.free), &low.dir.display(), &reserve::mib(low.reserve)])
+    }
+
+    async fn check_reserve(&mut self) -> Result<()> {
+        let short = match self.reserve {
+            Some(ref mut reserve) => reserve.check(),
+            None => return Ok(()),
+        };
+        for low in short {
+            if !self.quiet {
+                eprint!("{}\r\n", self.space_low_message(&low));
+            }
+            self.log_event("SPACE_LOW", Some(&low.message())).await?;
+        }
+        Ok(())
+    }
+
     fn run_child(&self) -> Result<()> {
         // Initialize slave PTY
         if let Some(ref pty) = self.pty {
             pty.init_slave()?;
         }
 
-        // Execute shell or command
-        let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
-        let shell_clone = shell.clone();
-        let shell_name = std::path::Path::new(&shell_clone)
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("sh");
-
-        if let Some(ref command) = self.command {
-            // Execute specific command
-            let args = [shell_name, "-c", command.as_str()];
-            let c_shell = std::ffi::CString::new(shell.clone())?;
-            let c_args: Vec<std::ffi::CString> = args.iter()
-                .map(|&s| std::ffi::CString::new(s).unwrap())
-                .collect();
-            nix::unistd::execv(&c_shell, &c_args)?;
-        } else {
-            // Execute interactive shell
-            let args = [shell_name, "-i"];
-            let c_shell = std::ffi::CString::new(shell.clone())?;
-            let c_args: Vec<std::ffi::CString> = args.iter()
-                .map(|&s| std::ffi::CString::new(s).unwrap())
-                .collect();
-            nix::unistd::execv(&c_shell, &c_args)?;
+        // In place of the terminal; dup2 leaves the copy open across exec
+        if let Some((stderr_read, stderr_write)) = self.stderr_pipe {
+            let _ = nix::unistd::close(stderr_read);
+            nix::unistd::dup2(stderr_write, libc::STDERR_FILENO)?;
         }
 
-        // Should never reach here
-        Err(anyhow!("Failed to execute shell"))
+        // The shell is the recorder's, whatever --env makes of $SHELL
+        let shell = config::shell();
+        self.child_env.apply();
+        match self.exec.as_slice() {
+            [] => exec_shell(&shell, self.command.as_deref()),
+            argv => exec_program(argv),
+        }
     }
 
     async fn start_logging(&mut self) -> Result<()> {
-        // Collect the data we need before borrowing mutably
-        let is_term = self.is_term;
-        let tty_type = self.tty_type.clone();
-        let tty_name = self.tty_name.clone();
-        let tty_cols = self.tty_cols;
-        let tty_lines = self.tty_lines;
-        let command_norm = self.command_norm.clone();
-        
-        // Start all output loggers
-        for i in 0..self.out_logs.len() {
-            self.out_logs[i].start_with_data(
-                is_term, 
-                &tty_type, 
-                &tty_name, 
-                tty_cols, 
-                tty_lines, 
-                &command_norm
-            ).await?;
+        let session = SessionInfo {
+            is_term: self.is_term,
+            tty_type: self.tty_type.clone(),
+            tty_name: self.tty_name.clone(),
+            cols: self.tty_cols,
+            lines: self.tty_lines,
+            command: self.command_norm.clone(),
+            degraded: self.degraded.clone(),
+        };
+        for sink in &mut self.sinks {
+            sink.log.start(&session)?;
         }
-        
-        // Start all input loggers
-        for i in 0..self.in_logs.len() {
-            self.in_logs[i].start_with_data(
-                is_term, 
-                &tty_type, 
-                &tty_name, 
-                tty_cols, 
-                tty_lines, 
-                &command_norm
-            ).await?;
+        #[cfg(feature = "screenshot")]
+        if self.info_log.is_some() {
+            self.exit_screen = Some(Screen::new(session.cols as usize, session.lines as usize));
         }
 
         // Log initial info for multi-stream timing
         if let Some(ref mut info_log) = self.info_log {
-            let now = Local::now();
-            info_log.log_info("START_TIME", &now.to_rfc3339()).await?;
+            let now = self.clock.wall();
+            info_log.log_info("START_TIME", &now.format(TIMESTAMP_FORMAT).to_string())?;
             
-            if is_term {
-                if let Some(ref tty_type) = tty_type {
-                    info_log.log_info("TERM", tty_type).await?;
+            if session.is_term {
+                if let Some(ref tty_type) = session.tty_type {
+                    info_log.log_info("TERM", tty_type)?;
                 }
-                if let Some(ref tty_name) = tty_name {
-                    info_log.log_info("TTY", tty_name).await?;
+                if let Some(ref tty_name) = session.tty_name {
+                    info_log.log_info("TTY", tty_name)?;
                 }
-                info_log.log_info("COLUMNS", &tty_cols.to_string()).await?;
-                info_log.log_info("LINES", &tty_lines.to_string()).await?;
+                info_log.log_info("COLUMNS", &session.cols.to_string())?;
+                info_log.log_info("LINES", &session.lines.to_string())?;
             }
             
-            let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
-            info_log.log_info("SHELL", &shell).await?;
+            info_log.log_info("SHELL", &config::shell().path)?;
+            if let Some(ref reason) = session.degraded {
+                info_log.log_info("DEGRADED", reason)?;
+            }
+
+            // Needed to tell which keys the input bytes came from
+            if let Some(layout) = config::keyboard_layout() {
+                info_log.log_info("KEYBOARD_LAYOUT", &layout)?;
+            }
+            if let Some(locale) = config::input_locale() {
+                info_log.log_info("LOCALE", &locale)?;
+            }
+            if let Some(method) = config::input_method() {
+                info_log.log_info("INPUT_METHOD", &method)?;
+            }
             
-            if let Some(ref command) = command_norm {
-                info_log.log_info("COMMAND", command).await?;
+            if let Some(ref command) = session.command {
+                info_log.log_info("COMMAND", command)?;
+            }
+            for label in &self.labels {
+                info_log.log_info("LABEL", label)?;
+            }
+
+            let timing_log = info_log.path().display().to_string();
+            info_log.log_info("TIMING_LOG", &timing_log)?;
+            let raw_log = |logs: &[ScriptLogger]| {
+                logs.iter()
+                    .find(|l| l.format() == LogFormat::Raw)
+                    .map(|l| l.path().display().to_string())
+            };
+            if let Some(path) = raw_log(&self.out_logs) {
+                info_log.log_info("OUTPUT_LOG", &path)?;
+            }
+            if let Some(path) = raw_log(&self.in_logs) {
+                info_log.log_info("INPUT_LOG", &path)?;
+            }
+            if let Some(ref log) = self.err_log {
+                info_log.log_info("STDERR_LOG", &log.path().display().to_string())?;
+            }
+
+            #[cfg(feature = "snapshot")]
+            if let Some(ref snapshots) = self.snapshots {
+                info_log.log_info("SNAPSHOT_DIR", &snapshots.dir().display().to_string())?;
+            }
+
+            if let Some(priority) = self.rt_priority {
+                info_log.log_info("RT_PRIORITY", &priority.to_string())?;
+            }
+            if let Some(ref cpus) = self.cpu_affinity {
+                let cpus: Vec<String> = cpus.iter().map(|c| c.to_string()).collect();
+                info_log.log_info("CPU_AFFINITY", &cpus.join(","))?;
             }
         }
 
@@ -474,57 +1723,444 @@ impl ScriptControl {
 
     async fn stop_logging(&mut self) -> Result<()> {
         let status = self.child_status.unwrap_or(0);
-        
-        // Close all loggers
-        for logger in &mut self.out_logs {
-            logger.close(status).await?;
+
+        // Close an alternate screen interlude the session ended in
+        if self.skip_alt_screen && self.alt_screen.is_active() {
+            self.write_alt_screen_marker().await?;
         }
-        for logger in &mut self.in_logs {
-            logger.close(status).await?;
+
+        // Record the terminal state the session ended in
+        self.log_exit_state().await?;
+        
+        // Close all logs
+        for sink in &mut self.sinks {
+            sink.log.close(status)?;
         }
 
         Ok(())
     }
 
+    async fn log_exit_state(&mut self) -> Result<()> {
+        if let (Some(info_log), Some(pty)) = (&mut self.info_log, &self.pty) {
+            let termios = pty.get_termios()?;
+            info_log.log_info("EXIT_TERMIOS", &utils::format_termios(&termios))?;
+            info_log.log_info("EXIT_COLUMNS", &pty.window_size.ws_col.to_string())?;
+            info_log.log_info("EXIT_LINES", &pty.window_size.ws_row.to_string())?;
+        }
+        // What the operator was left looking at, for replay --resume-style
+        #[cfg(feature = "screenshot")]
+        if let (Some(info_log), Some(screen)) = (&mut self.info_log, &self.exit_screen) {
+            let (row, col) = screen.cursor();
+            info_log.log_info("EXIT_CURSOR", &format!("ROW={} COL={}", row + 1, col + 1))?;
+            info_log.log_info("EXIT_SCREEN", &exectrace::escape(&screenshot::render(screen, true)))?;
+        }
+        Ok(())
+    }
+
     async fn handle_signal(&mut self, signal_name: &str) -> Result<()> {
-        if let Some(ref mut sig_log) = self.sig_log {
-            sig_log.log_signal(signal_name, None).await?;
+        self.log_event(signal_name, None).await
+    }
+
+    async fn log_execs(&mut self, execs: Vec<Exec>) -> Result<()> {
+        if !self.trace_exec {
+            return Ok(());
+        }
+        if let Some(ref mut info_log) = self.info_log {
+            for exec in execs {
+                info_log.log_exec(&exec)?;
+            }
+        }
+        self.check_failover()
+    }
+
+    // Only those of the session's processes
+    async fn log_file_events(&mut self, events: Vec<FileEvent>, tracer: &ExecTracer) -> Result<()> {
+        for event in events.iter().filter(|event| tracer.contains(event.pid)) {
+            self.log_event(event.name(), Some(&event.message())).await?;
+        }
+        Ok(())
+    }
+
+    async fn log_event(&mut self, name: &str, message: Option<&str>) -> Result<()> {
+        for sink in self.sinks.iter_mut().filter(|s| s.events) {
+            sink.log.log_event(name, message)?;
+        }
+        self.check_failover()
+    }
+
+    async fn handle_control(&mut self, command: &str) -> Result<String> {
+        match command {
+            "stop-child" => self.signal_child(nix::sys::signal::Signal::SIGSTOP).await,
+            "cont-child" => self.signal_child(nix::sys::signal::Signal::SIGCONT).await,
+            "lock-input" => self.set_input_locked(true).await,
+            "unlock-input" => self.set_input_locked(false).await,
+            "status" => Ok(self.status()),
+            "reopen" => {
+                self.log_event("REOPEN", None).await?;
+                self.reopen_logs()?;
+                Ok(String::new())
+            }
+            _ => Err(anyhow!("Unknown command: {}", command)),
+        }
+    }
+
+    // Every log whose file was moved away goes on in a new one at its path
+    fn reopen_logs(&mut self) -> Result<()> {
+        for sink in &mut self.sinks {
+            sink.log.reopen()?;
         }
         Ok(())
     }
 
+    // The terminal is gone once it can't even be asked for its settings
+    fn terminal_hung_up(&self) -> bool {
+        self.controlling_tty && termios::Termios::from_fd(libc::STDIN_FILENO).is_err()
+    }
+
+    // One line, in the form of the event messages
+    fn status(&self) -> String {
+        let mut status = format!(
+            "CHILD={} BYTES_IN={} BYTES_OUT={} INPUT={}",
+            self.child_state(),
+            self.bytes_in,
+            self.bytes_out,
+            if self.input_locked { "locked" } else { "open" }
+        );
+        if let Some(ref reserve) = self.reserve {
+            status.push(' ');
+            status.push_str(&reserve.status());
+        }
+        status
+    }
+
+    async fn set_input_locked(&mut self, locked: bool) -> Result<String> {
+        if self.input_locked != locked {
+            self.input_locked = locked;
+            self.log_event(if locked { "INPUT_LOCKED" } else { "INPUT_UNLOCKED" }, None).await?;
+        }
+        Ok(String::new())
+    }
+
+    // The child leads a session of its own (setsid in `init_slave`); every
+    // process group in it gets the signal, so neither the jobs a shell
+    // started nor what they started keeps the terminal
+    fn terminate_session(&self, signal: nix::sys::signal::Signal) {
+        let Some(child_pid) = self.child_pid else {
+            return;
+        };
+        let groups = utils::session_process_groups(child_pid.as_raw());
+        // Before it got to setsid, the child has no group of its own
+        if nix::sys::signal::killpg(child_pid, signal).is_err() {
+            let _ = nix::sys::signal::kill(child_pid, signal);
+        }
+        for group in groups {
+            if group != child_pid.as_raw() {
+                let _ = nix::sys::signal::killpg(nix::unistd::Pid::from_raw(group), signal);
+            }
+        }
+    }
+
+    // In cooked mode ^C and ^\ signal the recorder, and go on as the keys,
+    // as raw mode would have passed them; a signal sent to the recorder
+    // itself goes on to the session's process groups, as `S` events
+    async fn forward_signal(&mut self, master_fd: RawFd, signal: nix::sys::signal::Signal) -> Result<()> {
+        let key = match signal {
+            nix::sys::signal::Signal::SIGINT => Some(libc::VINTR),
+            nix::sys::signal::Signal::SIGQUIT => Some(libc::VQUIT),
+            _ => None,
+        };
+        if let (Some(key), Some(_)) = (key, &self.degraded) {
+            return self.send_key(master_fd, key).await;
+        }
+        match self.signal_child(signal).await {
+            // The child has exited since the loop last looked
+            Err(e) if e.downcast_ref::<nix::errno::Errno>() == Some(&nix::errno::Errno::ESRCH) => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    async fn send_key(&mut self, master_fd: RawFd, key: usize) -> Result<()> {
+        if let Some(ref pty) = self.pty {
+            let key = pty.get_termios()?.c_cc[key];
+            self.send_input(master_fd, InputChunk::Typed(vec![key])).await?;
+        }
+        Ok(())
+    }
+
+    async fn signal_child(&mut self, signal: nix::sys::signal::Signal) -> Result<String> {
+        let child_pid = self.child_pid.ok_or_else(|| anyhow!("No child process"))?;
+
+        // The child leads its own process group; a job it is running in the
+        // foreground of the terminal has another one
+        let mut groups = vec![child_pid];
+        if let Some(ref pty) = self.pty {
+            if let Ok(foreground) = nix::unistd::tcgetpgrp(pty.get_master_fd()) {
+                if foreground != child_pid {
+                    groups.push(foreground);
+                }
+            }
+        }
+        for &group in &groups {
+            nix::sys::signal::killpg(group, signal)?;
+        }
+
+        let groups: Vec<String> = groups.iter().map(|g| g.to_string()).collect();
+        self.log_event(signal.as_str(), Some(&format!("PGIDS={}", groups.join(",")))).await?;
+        Ok(String::new())
+    }
+
     async fn handle_window_change(&mut self) -> Result<()> {
         let (cols, lines) = utils::get_terminal_size()?;
         self.tty_cols = cols;
         self.tty_lines = lines;
 
-        if let Some(ref mut sig_log) = self.sig_log {
-            let msg = format!("ROWS={} COLS={}", lines, cols);
-            sig_log.log_signal("SIGWINCH", Some(&msg)).await?;
-        }
+        let msg = format!("ROWS={} COLS={}", lines, cols);
+        self.log_event("SIGWINCH", Some(&msg)).await?;
 
         // Update PTY window size
         if let Some(ref mut pty) = self.pty {
             pty.set_window_size(cols, lines)?;
         }
+        #[cfg(feature = "screenshot")]
+        if let Some(ref mut screen) = self.exit_screen {
+            screen.resize(cols as usize, lines as usize);
+        }
 
         Ok(())
     }
 
     async fn wait_for_child(&mut self) -> Result<()> {
         if let Some(child_pid) = self.child_pid {
-            match nix::sys::wait::waitpid(child_pid, None)? {
-                nix::sys::wait::WaitStatus::Exited(_, status) => {
-                    self.child_status = Some(status);
-                }
-                nix::sys::wait::WaitStatus::Signaled(_, signal, _) => {
-                    self.child_status = Some(128 + signal as i32);
-                }
-                _ => {
-                    self.child_status = Some(1);
-                }
-            }
+            self.child_status = Some(exit_code(nix::sys::wait::waitpid(child_pid, None)?));
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+// The shell's way of putting it: 128 plus the signal for a killed child
+fn exit_code(status: nix::sys::wait::WaitStatus) -> i32 {
+    match status {
+        nix::sys::wait::WaitStatus::Exited(_, code) => code,
+        nix::sys::wait::WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+        _ => 1,
+    }
+}
+
+fn parse_buffer_size(size: &str) -> Result<usize> {
+    match utils::parse_size(size)? {
+        n if n == 0 || n > MAX_BUFFER_SIZE as u64 => Err(anyhow!("Invalid buffer size: {} (1 byte to 16m)", size)),
+        n => Ok(n as usize),
+    }
+}
+
+// The signals the session's loop waits for
+struct Signals {
+    terminate: signal::unix::Signal,
+    window_change: signal::unix::Signal,
+    child: signal::unix::Signal,
+    hangup: signal::unix::Signal,
+    // Passed on to the session (see `forward_signal`)
+    interrupt: signal::unix::Signal,
+    quit: signal::unix::Signal,
+    user1: signal::unix::Signal,
+    user2: signal::unix::Signal,
+    // In cooked mode ^Z stops the recorder, which passes it on as the key,
+    // as raw mode would have
+    stop: Option<signal::unix::Signal>,
+}
+
+// Every signal `Signals` may catch
+const CAUGHT: [libc::c_int; 9] = [
+    libc::SIGTERM,
+    libc::SIGWINCH,
+    libc::SIGCHLD,
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+    libc::SIGTSTP,
+];
+
+// What the caught signals did before the first of the sessions running,
+// and tokio's handler for each, as it installed it for the first session
+struct Caught {
+    sessions: usize,
+    before: Vec<(libc::c_int, libc::sigaction)>,
+    handlers: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+static CAUGHT_BY: std::sync::Mutex<Caught> =
+    std::sync::Mutex::new(Caught { sessions: 0, before: Vec::new(), handlers: Vec::new() });
+
+// Puts back what the caught signals did before the session once it and
+// any other running are over. tokio never uninstalls a handler, and a
+// program that records a session would otherwise no longer die of SIGTERM
+// or ^C once it is over.
+struct Dispositions;
+
+impl Dispositions {
+    // Catches the signals with `install` for the session
+    fn catch<T>(install: impl FnOnce() -> Result<T>) -> Result<(T, Self)> {
+        let mut caught = CAUGHT_BY.lock().unwrap_or_else(|e| e.into_inner());
+        if caught.sessions == 0 {
+            caught.before = CAUGHT.iter().map(|&signo| Ok((signo, sigaction(signo, None)?))).collect::<Result<_>>()?;
+            // A later session puts back the handlers an earlier one
+            // removed; tokio, having registered them once, would not
+            for (signo, handler) in &caught.handlers {
+                sigaction(*signo, Some(handler))?;
+            }
+        }
+        caught.sessions += 1;
+        let restore = Dispositions;
+        let signals = install()?;
+        for &signo in &CAUGHT {
+            let current = sigaction(signo, None)?;
+            let replaced = caught.before.iter().any(|(s, before)| *s == signo && before.sa_sigaction != current.sa_sigaction);
+            if replaced && !caught.handlers.iter().any(|(seen, _)| *seen == signo) {
+                caught.handlers.push((signo, current));
+            }
+        }
+        Ok((signals, restore))
+    }
+}
+
+impl Drop for Dispositions {
+    fn drop(&mut self) {
+        let mut caught = CAUGHT_BY.lock().unwrap_or_else(|e| e.into_inner());
+        caught.sessions -= 1;
+        if caught.sessions == 0 {
+            for (signo, before) in &caught.before {
+                let _ = sigaction(*signo, Some(before));
+            }
+        }
+    }
+}
+
+// Sets the action for `signo` if given, and returns the one it had
+fn sigaction(signo: libc::c_int, action: Option<&libc::sigaction>) -> Result<libc::sigaction> {
+    let mut old: libc::sigaction = unsafe { std::mem::zeroed() };
+    let new = action.map_or(std::ptr::null(), |a| a as *const _);
+    if unsafe { libc::sigaction(signo, new, &mut old) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(old)
+}
+
+impl Signals {
+    fn new(cooked: bool) -> Result<Self> {
+        use signal::unix::{signal, SignalKind};
+        Ok(Signals {
+            terminate: signal(SignalKind::terminate())?,
+            window_change: signal(SignalKind::window_change())?,
+            child: signal(SignalKind::child())?,
+            hangup: signal(SignalKind::hangup())?,
+            interrupt: signal(SignalKind::interrupt())?,
+            quit: signal(SignalKind::quit())?,
+            user1: signal(SignalKind::user_defined1())?,
+            user2: signal(SignalKind::user_defined2())?,
+            stop: match cooked {
+                true => Some(signal(SignalKind::from_raw(libc::SIGTSTP))?),
+                false => None,
+            },
+        })
+    }
+}
+
+// Execute $SHELL with the command, or interactively; only returns on error
+pub fn exec_shell(shell: &Shell, command: Option<&str>) -> Result<()> {
+    let shell_name = std::path::Path::new(&shell.path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("sh");
+
+    let mut args = vec![shell_name];
+    args.extend(shell.options.iter().map(String::as_str));
+    match command {
+        Some(command) => args.extend(["-c", command]),
+        None => args.push("-i"),
+    }
+    let c_shell = std::ffi::CString::new(shell.path.clone())?;
+    let c_args: Vec<std::ffi::CString> = args.iter()
+        .map(|&s| std::ffi::CString::new(s))
+        .collect::<Result<_, _>>()?;
+    nix::unistd::execv(&c_shell, &c_args)?;
+
+    // Should never reach here
+    Err(anyhow!("Failed to execute shell"))
+}
+
+// Run straight from PATH, with the arguments as they are
+fn exec_program(argv: &[String]) -> Result<()> {
+    let c_args: Vec<std::ffi::CString> =
+        argv.iter().map(|arg| std::ffi::CString::new(arg.as_str())).collect::<Result<_, _>>()?;
+    nix::unistd::execvp(&c_args[0], &c_args).with_context(|| format!("Cannot run {}", argv[0]))?;
+    Err(anyhow!("Failed to execute {}", argv[0]))
+}
+
+// Everything the child wrote before exec, or nothing if exec succeeded
+fn read_exec_error(fd: RawFd) -> String {
+    let mut message = Vec::new();
+    let mut buf = [0u8; 512];
+    while let Ok(n @ 1..) = nix::unistd::read(fd, &mut buf) {
+        message.extend_from_slice(&buf[..n]);
+    }
+    let _ = nix::unistd::close(fd);
+    String::from_utf8_lossy(&message).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::logging::SessionInfo;
+    use chrono::DateTime;
+    use std::sync::Mutex;
+
+    // Keeps the output it is given
+    struct Collect(Arc<Mutex<Vec<u8>>>);
+
+    impl LogSink for Collect {
+        fn start(&mut self, _session: &SessionInfo) -> Result<()> {
+            Ok(())
+        }
+
+        fn log_data(&mut self, stream: LogStream, data: &[u8]) -> Result<usize> {
+            if let LogStream::Output = stream {
+                self.0.lock().unwrap().extend_from_slice(data);
+            }
+            Ok(data.len())
+        }
+
+        fn log_event(&mut self, _name: &str, _message: Option<&str>) -> Result<()> {
+            Ok(())
+        }
+
+        fn close(&mut self, _exit_status: i32) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_alt_screen_marker_is_timed_by_the_clock() {
+        let args = Args {
+            skip_alt_screen: true,
+            log_out: Some("/dev/null".into()),
+            wait_for: "pty".to_string(),
+            ..Args::default()
+        };
+        let mut control = ScriptControl::new(args).unwrap();
+        let clock = Arc::new(ManualClock::new(DateTime::parse_from_rfc3339("2024-01-02T03:04:05+00:00").unwrap()));
+        control.clock = clock.clone();
+        control.sinks.clear();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        control.add_sink(Box::new(Collect(output.clone())));
+
+        control.log_output(b"before\r\n\x1b[?1049h").await.unwrap();
+        clock.advance(Duration::from_millis(3500));
+        control.log_output(b"vim\x1b[?1049lafter\r\n").await.unwrap();
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert_eq!(output, "before\r\n[alt-screen session, 19 bytes omitted, 3 seconds]\r\nafter\r\n");
+    }
+}
@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::timing::{EntryKind, TimingEntry};
+use crate::utils;
+
+// --seek-index: a sidecar next to a log, <log>.idx, with a line every so
+// many seconds of the session giving the time and the byte offset in the
+// log of the record written then:
+//
+//   60.000412 1048731
+//   120.001055 2201334
+//
+// `script replay --start` reads it to begin at the last point before the
+// requested time, reading neither the log nor the output before it. The
+// offsets count from the start of the file, header included, so they are
+// only kept for logs written as they are (no compression, encryption or
+// rotation).
+pub fn index_path(log: &Path) -> PathBuf {
+    let mut path = log.as_os_str().to_owned();
+    path.push(".idx");
+    PathBuf::from(path)
+}
+
+// The recording side: where the log is, and when the next point is due
+pub struct SeekIndex {
+    every: Duration,
+    next: Duration,
+    offset: u64,
+}
+
+impl SeekIndex {
+    pub fn new(every: Duration, offset: u64) -> Self {
+        SeekIndex { every, next: every, offset }
+    }
+
+    // A record of `size` bytes is about to be written at `elapsed`; the
+    // index line to add for it, if a point is due
+    pub fn record(&mut self, elapsed: Duration, size: usize) -> Option<String> {
+        let mut line = None;
+        if elapsed >= self.next {
+            line = Some(format!("{:.6} {}\n", elapsed.as_secs_f64(), self.offset));
+            while self.next <= elapsed {
+                self.next += self.every;
+            }
+        }
+        self.offset += size as u64;
+        line
+    }
+}
+
+// The points of an index, in order; lines that don't parse are skipped
+pub fn parse(content: &str) -> Vec<(Duration, u64)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (time, offset) = line.split_once(' ')?;
+            let time = utils::secs_to_duration(time.parse().ok()?)?;
+            Some((time, offset.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+// The last point at or before `at`
+pub fn point(index: &[(Duration, u64)], at: Duration) -> Option<(Duration, u64)> {
+    index.iter().take_while(|&&(time, _)| time <= at).last().copied()
+}
+
+// How many timing entries come before the log's first `bytes` bytes of
+// session data run out, and the time they add up to; None if the entries
+// don't end on that byte
+pub fn locate(entries: &[TimingEntry], bytes: u64, input_in_log: bool) -> Option<(usize, Duration)> {
+    let mut consumed = 0;
+    let mut offset = Duration::ZERO;
+    for (i, entry) in entries.iter().enumerate() {
+        let size = match entry.kind {
+            EntryKind::Output(size) => size,
+            EntryKind::Input(size) if input_in_log => size,
+            _ => 0,
+        };
+        if consumed == bytes && size > 0 {
+            return Some((i, offset));
+        }
+        consumed += size as u64;
+        offset += entry.delay;
+        if consumed > bytes {
+            return None;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::parse_timing;
+
+    #[test]
+    fn test_index_points() {
+        let mut index = SeekIndex::new(Duration::from_secs(10), 100);
+        assert_eq!(index.record(Duration::from_secs(3), 5), None);
+        assert_eq!(index.record(Duration::from_secs(12), 7).as_deref(), Some("12.000000 105\n"));
+        assert_eq!(index.record(Duration::from_secs(15), 1), None);
+        // A long pause gives one point, not one for every interval missed
+        assert_eq!(index.record(Duration::from_secs(45), 2).as_deref(), Some("45.000000 113\n"));
+        assert_eq!(index.record(Duration::from_secs(49), 2), None);
+
+        let points = parse("12.000000 105\nbad\n45.000000 113\n");
+        assert_eq!(points, [(Duration::from_secs(12), 105), (Duration::from_secs(45), 113)]);
+        assert_eq!(point(&points, Duration::from_secs(44)), Some((Duration::from_secs(12), 105)));
+        assert_eq!(point(&points, Duration::from_secs(5)), None);
+        assert_eq!(index_path(Path::new("/var/log/s.log")), PathBuf::from("/var/log/s.log.idx"));
+    }
+
+    #[test]
+    fn test_locate_entries() {
+        let entries = parse_timing("H 0.000000 COLUMNS 80\nO 1.0 5\nI 2.0 3\nS 0.5 SIGWINCH ROWS=1 COLS=2\nO 1.0 4\n").unwrap();
+        assert_eq!(locate(&entries, 5, true), Some((2, Duration::from_secs(1))));
+        assert_eq!(locate(&entries, 8, true), Some((4, Duration::from_millis(3500))));
+        assert_eq!(locate(&entries, 5, false), Some((4, Duration::from_millis(3500))));
+        assert_eq!(locate(&entries, 6, true), None);
+        assert_eq!(locate(&entries, 100, true), None);
+    }
+}
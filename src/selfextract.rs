@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use crate::clock::SystemClock;
+use crate::replay;
+use crate::timing::TimingEntry;
+use crate::ttyrec;
+
+// `script export --self-extracting`: a copy of this program with the
+// recording appended to it as ttyrec, and a trailer at the very end:
+//
+//   <program> <ttyrec frames> <their length, u64 little-endian> "rust_script:play"
+//
+// Started, the program finds the trailer in its own file and plays what it
+// carries instead of anything else, so whoever receives it needs nothing
+// installed. The kernel maps an ELF file by its program headers and never
+// looks at what comes after them.
+const MAGIC: &[u8; 16] = b"rust_script:play";
+const TRAILER_LEN: u64 = 8 + MAGIC.len() as u64;
+
+// The recording carried by `file`, if it has one
+fn read_embedded<F: Read + Seek>(file: &mut F) -> Result<Option<Vec<u8>>> {
+    let size = file.seek(SeekFrom::End(0))?;
+    if size < TRAILER_LEN {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+    let mut trailer = [0; TRAILER_LEN as usize];
+    file.read_exact(&mut trailer)?;
+    if trailer[8..] != MAGIC[..] {
+        return Ok(None);
+    }
+    let len = u64::from_le_bytes(trailer[..8].try_into()?);
+    let Some(start) = (size - TRAILER_LEN).checked_sub(len) else {
+        return Ok(None);
+    };
+    file.seek(SeekFrom::Start(start))?;
+    let mut recording = vec![0; len as usize];
+    file.read_exact(&mut recording)?;
+    Ok(Some(recording))
+}
+
+// The program with `recording` in place of any it already carried
+pub fn bundle(program: &[u8], recording: &[u8]) -> Result<Vec<u8>> {
+    let mut end = program.len();
+    if let Some(embedded) = read_embedded(&mut std::io::Cursor::new(program))? {
+        end -= embedded.len() + TRAILER_LEN as usize;
+    }
+    let mut out = program[..end].to_vec();
+    out.extend_from_slice(recording);
+    out.extend_from_slice(&(recording.len() as u64).to_le_bytes());
+    out.extend_from_slice(MAGIC);
+    Ok(out)
+}
+
+// The recording this program was bundled with. Anything going wrong just
+// means it's the recorder as usual.
+pub fn embedded() -> Option<Vec<u8>> {
+    let mut program = File::open(std::env::current_exe().ok()?).ok()?;
+    read_embedded(&mut program).ok().flatten()
+}
+
+pub fn write(path: &Path, entries: &[TimingEntry], data: &[u8]) -> Result<()> {
+    let program = std::env::current_exe()
+        .and_then(std::fs::read)
+        .context("Cannot read this program to bundle the recording with")?;
+    let content = bundle(&program, &ttyrec::write(entries, data)?)?;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o755)
+        .open(path)
+        .and_then(|mut file| file.write_all(&content))
+        .with_context(|| format!("Cannot write {}", path.display()))
+}
+
+pub fn play(recording: &[u8]) -> Result<()> {
+    let (entries, data) = ttyrec::parse(recording)?;
+    let chunks = replay::decode(&entries, data.as_slice(), false)?;
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    replay::replay(&chunks, &mut out, &SystemClock)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_recording_rides_after_the_program() {
+        let program = b"\x7fELF program".to_vec();
+        assert_eq!(read_embedded(&mut Cursor::new(&program)).unwrap(), None);
+
+        let player = bundle(&program, b"frames").unwrap();
+        assert!(player.starts_with(&program));
+        assert_eq!(read_embedded(&mut Cursor::new(&player)).unwrap().as_deref(), Some(&b"frames"[..]));
+
+        // Bundled again, the old recording goes
+        let again = bundle(&player, b"other").unwrap();
+        assert_eq!(again.len(), program.len() + 5 + TRAILER_LEN as usize);
+        assert_eq!(read_embedded(&mut Cursor::new(&again)).unwrap().as_deref(), Some(&b"other"[..]));
+
+        // A length past the start of the file is no trailer
+        let mut broken = again.clone();
+        broken[program.len() + 5] = 0xff;
+        assert_eq!(read_embedded(&mut Cursor::new(&broken)).unwrap(), None);
+    }
+}
@@ -0,0 +1,204 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::getpid;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::failure::{self, Failure};
+use crate::script_control::ScriptControl;
+use crate::utils;
+use crate::cli::Args;
+
+// How often the recorder is told the window changed during a soak
+const RESIZE_INTERVAL: Duration = Duration::from_secs(2);
+
+// Bursts of numbered lines of varying size, a signal to itself and a short
+// idle pause, until time is up
+const TRAFFIC_SCRIPT: &str = r#"
+end=$(( $(date +%s) + SOAK_SECS ))
+n=0
+trap 'printf "soak-signal %d\n" "$n"' USR1
+while [ "$(date +%s)" -lt "$end" ]; do
+    burst=$(( (n % 7 + 1) * 50 ))
+    i=0
+    while [ "$i" -lt "$burst" ]; do
+        n=$((n + 1))
+        printf 'soak-line %d\n' "$n"
+        i=$((i + 1))
+    done
+    kill -USR1 $$
+    sleep 0.2
+done
+printf 'soak-done %d\n' "$n"
+"#;
+
+struct SessionReport {
+    lines: u64,
+    bytes: u64,
+    resizes: usize,
+    signals: usize,
+}
+
+pub async fn run(soak: &str, max_rss: &str) -> Result<()> {
+    let usage = |e| failure::or_tag(e, Failure::Usage);
+    let duration = utils::parse_duration(soak).map_err(usage)?;
+    let max_rss = utils::parse_size(max_rss).map_err(usage)?;
+
+    let dir = std::env::temp_dir().join(format!("rust_script-selftest-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+
+    // A short warm-up session sets up the runtime's one-time resources
+    // (signal pipes and the like) so they don't show up as leaks
+    run_session(&dir, Duration::from_secs(1)).await?;
+    let fds_before = utils::open_fds()?.len();
+
+    let report = run_session(&dir, duration).await?;
+    let fds_after = utils::open_fds()?.len();
+    let peak_rss = peak_rss()?;
+
+    println!(
+        "Soak run: {}s, {} lines, {} bytes, {} resizes, {} child signals",
+        duration.as_secs(),
+        report.lines,
+        report.bytes,
+        report.resizes,
+        report.signals
+    );
+    println!("Peak RSS: {} KiB (limit {} KiB)", peak_rss / 1024, max_rss / 1024);
+    println!("Open fds: {} before, {} after", fds_before, fds_after);
+
+    let mut failures = Vec::new();
+    if peak_rss > max_rss {
+        failures.push(format!("peak RSS {} exceeds {}", peak_rss, max_rss));
+    }
+    if fds_after != fds_before {
+        failures.push(format!("{} file descriptors leaked", fds_after as i64 - fds_before as i64));
+    }
+
+    if !failures.is_empty() {
+        return Err(anyhow!(
+            "Selftest failed: {} (files kept in {})",
+            failures.join(", "),
+            dir.display()
+        ));
+    }
+
+    fs::remove_dir_all(&dir)?;
+    println!("Selftest passed.");
+    Ok(())
+}
+
+async fn run_session(dir: &Path, duration: Duration) -> Result<SessionReport> {
+    let out = dir.join("soak.out");
+    let timing = dir.join("soak.tm");
+    let command = TRAFFIC_SCRIPT.replace("SOAK_SECS", &duration.as_secs().max(1).to_string());
+
+    let argv: Vec<OsString> = vec![
+        "script".into(),
+        "-q".into(),
+        "-O".into(),
+        out.clone().into(),
+        "-T".into(),
+        timing.clone().into(),
+        "-m".into(),
+        "advanced".into(),
+        "-c".into(),
+        command.into(),
+    ];
+    let args = Args::try_parse_from(argv)?;
+
+    let resizer = tokio::spawn(async {
+        loop {
+            tokio::time::sleep(RESIZE_INTERVAL).await;
+            let _ = kill(getpid(), Signal::SIGWINCH);
+        }
+    });
+
+    let mut control = ScriptControl::new(args)?;
+    let result = control.run().await;
+    drop(control);
+    resizer.abort();
+    result?;
+
+    validate(&out, &timing)
+}
+
+fn validate(out: &Path, timing: &Path) -> Result<SessionReport> {
+    let raw = fs::read(out)?;
+    let body_start = raw
+        .iter()
+        .position(|&b| b == b'\n')
+        .map(|i| i + 1)
+        .ok_or_else(|| anyhow!("{}: missing header", out.display()))?;
+    let footer = b"\nScript done on ";
+    let body_end = raw
+        .windows(footer.len())
+        .rposition(|w| w == footer)
+        .ok_or_else(|| anyhow!("{}: missing footer", out.display()))?;
+    let body = &raw[body_start..body_end];
+
+    if !String::from_utf8_lossy(&raw[body_end..]).contains("COMMAND_EXIT_CODE=\"0\"") {
+        return Err(anyhow!("{}: traffic generator did not exit cleanly", out.display()));
+    }
+
+    // The timing file has to account for every byte of output
+    let mut logged = 0u64;
+    let mut resizes = 0;
+    for line in fs::read_to_string(timing)?.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+        match fields.as_slice() {
+            ["O", _, size] => logged += size.parse::<u64>()?,
+            ["S", _, "SIGWINCH", ..] => resizes += 1,
+            _ => {}
+        }
+    }
+    if logged != body.len() as u64 {
+        return Err(anyhow!(
+            "timing records cover {} bytes but the log holds {}",
+            logged,
+            body.len()
+        ));
+    }
+
+    // Every numbered line must be there, in order, exactly once
+    let mut lines = 0u64;
+    let mut signals = 0;
+    let mut done = None;
+    for line in String::from_utf8_lossy(body).lines() {
+        let line = line.trim_end_matches('\r');
+        if let Some(n) = line.strip_prefix("soak-line ") {
+            let n: u64 = n.parse()?;
+            if n != lines + 1 {
+                return Err(anyhow!("expected line {} but found {}", lines + 1, n));
+            }
+            lines = n;
+        } else if line.starts_with("soak-signal ") {
+            signals += 1;
+        } else if let Some(n) = line.strip_prefix("soak-done ") {
+            done = Some(n.parse::<u64>()?);
+        }
+    }
+    if done != Some(lines) {
+        return Err(anyhow!("traffic generator reported {:?} lines, found {}", done, lines));
+    }
+
+    Ok(SessionReport {
+        lines,
+        bytes: logged,
+        resizes,
+        signals,
+    })
+}
+
+fn peak_rss() -> Result<u64> {
+    let status = fs::read_to_string("/proc/self/status")?;
+    status
+        .lines()
+        .find_map(|l| l.strip_prefix("VmHWM:"))
+        .and_then(|v| v.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+        .ok_or_else(|| anyhow!("Cannot read peak memory use"))
+}
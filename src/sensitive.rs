@@ -0,0 +1,111 @@
+use std::io::Read;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use zeroize::{Zeroize, Zeroizing};
+
+// Bytes holding key material or secrets, such as an identity file or a log
+// under review: kept out of swap while live and wiped when dropped. Locking
+// is best effort, as RLIMIT_MEMLOCK allows.
+pub struct SecretBytes {
+    bytes: Zeroizing<Vec<u8>>,
+}
+
+impl SecretBytes {
+    pub fn read(path: &Path) -> std::io::Result<SecretBytes> {
+        let mut file = std::fs::File::open(path)?;
+        let size = file.metadata()?.len() as usize;
+        // Room for it all up front; growing the buffer would leave copies
+        // behind that nothing wipes
+        let bytes: Zeroizing<Vec<u8>> = Zeroizing::new(Vec::with_capacity(size + 1));
+        lock(bytes.as_ptr().cast(), bytes.capacity());
+        let mut secret = SecretBytes { bytes };
+        file.read_to_end(&mut secret.bytes)?;
+        Ok(secret)
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl DerefMut for SecretBytes {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        let (ptr, capacity) = (self.bytes.as_ptr(), self.bytes.capacity());
+        self.bytes.zeroize();
+        unlock(ptr.cast(), capacity);
+    }
+}
+
+// Values that wipe themselves when dropped, such as age identities, kept
+// out of swap while held
+#[cfg(feature = "encrypt")]
+pub struct Locked<T> {
+    items: Vec<T>,
+}
+
+#[cfg(feature = "encrypt")]
+impl<T> Locked<T> {
+    // `items` should not have grown since it was filled, or the copies it
+    // left behind are neither locked nor wiped
+    pub fn new(items: Vec<T>) -> Locked<T> {
+        lock(items.as_ptr().cast(), items.capacity() * std::mem::size_of::<T>());
+        Locked { items }
+    }
+}
+
+#[cfg(feature = "encrypt")]
+impl<T> Deref for Locked<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.items
+    }
+}
+
+#[cfg(feature = "encrypt")]
+impl<T> Drop for Locked<T> {
+    fn drop(&mut self) {
+        let (ptr, size) = (self.items.as_ptr(), self.items.capacity() * std::mem::size_of::<T>());
+        self.items.clear();
+        unlock(ptr.cast(), size);
+    }
+}
+
+fn lock(ptr: *const libc::c_void, size: usize) {
+    if size > 0 {
+        unsafe { libc::mlock(ptr, size) };
+    }
+}
+
+fn unlock(ptr: *const libc::c_void, size: usize) {
+    if size > 0 {
+        unsafe { libc::munlock(ptr, size) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_bytes_read_whole_file() {
+        let path = std::env::temp_dir().join(format!("rust_script-{}-secret", std::process::id()));
+        std::fs::write(&path, b"AGE-SECRET-KEY-1\n").unwrap();
+        let secret = SecretBytes::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        let mut secret = secret.unwrap();
+        assert_eq!(&secret[..], b"AGE-SECRET-KEY-1\n");
+        secret[..3].zeroize();
+        assert_eq!(&secret[..4], b"\0\0\0-");
+    }
+}
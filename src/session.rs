@@ -0,0 +1,217 @@
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+use crate::cli::Args;
+use crate::failure::{self, Failure};
+use crate::logging::LogSink;
+use crate::script_control::ScriptControl;
+
+/// What a finished session reports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionResult {
+    /// The child's exit status, or 128 + N if signal N killed it
+    pub exit_code: i32,
+    /// Bytes passed to the child as input
+    pub bytes_in: u64,
+    /// Bytes of output read from the child
+    pub bytes_out: u64,
+}
+
+/// Records a session the way the `script` command line would.
+///
+/// ```no_run
+/// #[tokio::main(flavor = "current_thread")]
+/// async fn main() -> anyhow::Result<()> {
+/// let result = rust_script::SessionBuilder::new()
+///     .command("make test")
+///     .log_output("make.log")
+///     .run()
+///     .await?;
+/// println!("exit {}, {} bytes", result.exit_code, result.bytes_out);
+/// # Ok(())
+/// }
+/// ```
+///
+/// Sessions are quiet and ignore this process's stdin unless told
+/// otherwise; the child's output is still copied to stdout. Without any
+/// log, output goes to `typescript` as with the command.
+///
+/// `run` forks, and the child sets up the terminal, which allocates,
+/// before it execs. Another thread holding a lock at the fork would leave
+/// the child stuck, so sessions need a `current_thread` runtime and are
+/// refused on a multi-threaded one. While a session runs it catches
+/// SIGTERM, SIGINT, SIGQUIT, SIGHUP, SIGUSR1, SIGUSR2, SIGCHLD, SIGWINCH
+/// and, on a terminal it cannot set up, SIGTSTP; their earlier dispositions
+/// are put back when it ends.
+pub struct SessionBuilder {
+    command: Option<String>,
+    program: Vec<String>,
+    log_out: Option<PathBuf>,
+    log_in: Option<PathBuf>,
+    log_io: Option<PathBuf>,
+    log_timing: Option<PathBuf>,
+    logging_format: Option<String>,
+    append: bool,
+    output_limit: Option<String>,
+    quiet: bool,
+    stdin: bool,
+    sinks: Vec<Box<dyn LogSink>>,
+}
+
+impl std::fmt::Debug for SessionBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionBuilder")
+            .field("command", &self.command)
+            .field("program", &self.program)
+            .field("log_out", &self.log_out)
+            .field("log_in", &self.log_in)
+            .field("log_io", &self.log_io)
+            .field("log_timing", &self.log_timing)
+            .field("logging_format", &self.logging_format)
+            .field("append", &self.append)
+            .field("output_limit", &self.output_limit)
+            .field("quiet", &self.quiet)
+            .field("stdin", &self.stdin)
+            .field("sinks", &self.sinks.len())
+            .finish()
+    }
+}
+
+impl Default for SessionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionBuilder {
+    pub fn new() -> Self {
+        SessionBuilder {
+            command: None,
+            program: Vec::new(),
+            log_out: None,
+            log_in: None,
+            log_io: None,
+            log_timing: None,
+            logging_format: None,
+            append: false,
+            output_limit: None,
+            quiet: true,
+            stdin: false,
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Run the command with `$SHELL -c` instead of an interactive shell
+    pub fn command(mut self, command: impl Into<String>) -> Self {
+        self.command = Some(command.into());
+        self.program.clear();
+        self
+    }
+
+    /// Run a program with these arguments, found in `PATH`, without a shell
+    pub fn program<I, S>(mut self, argv: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.program = argv.into_iter().map(Into::into).collect();
+        self.command = None;
+        self
+    }
+
+    pub fn log_output(mut self, path: impl Into<PathBuf>) -> Self {
+        self.log_out = Some(path.into());
+        self
+    }
+
+    pub fn log_input(mut self, path: impl Into<PathBuf>) -> Self {
+        self.log_in = Some(path.into());
+        self
+    }
+
+    /// Log input and output to the same file
+    pub fn log_io(mut self, path: impl Into<PathBuf>) -> Self {
+        self.log_io = Some(path.into());
+        self
+    }
+
+    pub fn log_timing(mut self, path: impl Into<PathBuf>) -> Self {
+        self.log_timing = Some(path.into());
+        self
+    }
+
+    /// "classic" or "advanced"
+    pub fn logging_format(mut self, format: impl Into<String>) -> Self {
+        self.logging_format = Some(format.into());
+        self
+    }
+
+    pub fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    /// Stop once the logs reach this size, e.g. "10M"
+    pub fn output_limit(mut self, size: impl Into<String>) -> Self {
+        self.output_limit = Some(size.into());
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Forward this process's stdin to the session, putting its terminal
+    /// (if any) into raw mode meanwhile
+    pub fn stdin(mut self, stdin: bool) -> Self {
+        self.stdin = stdin;
+        self
+    }
+
+    /// Also record the session to `sink`, which gets both streams and
+    /// every event
+    pub fn sink(mut self, sink: impl LogSink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Errors say what went wrong the way the `script` command reports it.
+    pub async fn run(self) -> Result<SessionResult> {
+        if tokio::runtime::Handle::current().runtime_flavor() != tokio::runtime::RuntimeFlavor::CurrentThread {
+            let e = anyhow::anyhow!("A session needs a current_thread runtime: it forks from this thread");
+            return Err(failure::or_tag(e, Failure::Usage));
+        }
+        // The recorder is set up from the options the command line would
+        // have given for the same session
+        let args = Args {
+            command: self.command,
+            exec: self.program,
+            log_out: self.log_out,
+            log_in: self.log_in,
+            log_io: self.log_io,
+            log_timing: self.log_timing,
+            logging_format: self.logging_format,
+            append: self.append,
+            output_limit: self.output_limit,
+            quiet: self.quiet,
+            wait_for: "pty".to_string(),
+            ..Args::default()
+        };
+        let mut control = ScriptControl::new(args).map_err(|e| failure::or_tag(e, Failure::Usage))?;
+        if !self.stdin {
+            control.read_stdin = false;
+            control.is_term = false;
+        }
+        for sink in self.sinks {
+            control.add_sink(sink);
+        }
+
+        let exit_code = control.run().await.context("Failed to run script session")?;
+        Ok(SessionResult {
+            exit_code,
+            bytes_in: control.bytes_in,
+            bytes_out: control.bytes_out,
+        })
+    }
+}
@@ -0,0 +1,104 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::utils;
+
+// Gaps longer than this are pauses, not typing, and are left alone
+const PAUSE_THRESHOLD: Duration = Duration::from_secs(1);
+
+// Parse a range such as "30ms-80ms"
+pub fn parse_range(range: &str) -> Result<(Duration, Duration)> {
+    let (min, max) = range
+        .split_once('-')
+        .ok_or_else(|| anyhow!("Invalid range: {} (expected MIN-MAX)", range))?;
+    let (min, max) = (utils::parse_duration(min)?, utils::parse_duration(max)?);
+    if min > max {
+        return Err(anyhow!("Invalid range: {} (minimum above maximum)", range));
+    }
+    Ok((min, max))
+}
+
+// Clamp the delay of every input record typed in a burst into [min, max].
+// Records are relative to the one before, so output that echoes a
+// keystroke moves along with it. Returns the new content and how many
+// records changed.
+pub fn smooth_timing(content: &str, min: Duration, max: Duration) -> Result<(String, usize)> {
+    let mut out = String::with_capacity(content.len());
+    let mut changed = 0;
+    let mut shift = 0.0f64;
+
+    for line in content.split_inclusive('\n') {
+        let fields: Vec<&str> = line.trim_end_matches('\n').split(' ').collect();
+        match fields.as_slice() {
+            ["I", delay, size] => {
+                let delay: f64 = delay
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid input record: {}", line.trim_end()))?;
+                if delay < PAUSE_THRESHOLD.as_secs_f64() {
+                    let smoothed = delay.clamp(min.as_secs_f64(), max.as_secs_f64());
+                    if smoothed != delay {
+                        changed += 1;
+                        shift += smoothed - delay;
+                    }
+                    out.push_str(&format!("I {:.6} {}\n", smoothed, size));
+                    continue;
+                }
+            }
+            ["H", delta, "DURATION", duration] => {
+                if let Ok(duration) = duration.parse::<f64>() {
+                    out.push_str(&format!("H {} DURATION {:.6}\n", delta, (duration + shift).max(0.0)));
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        out.push_str(line);
+    }
+
+    Ok((out, changed))
+}
+
+pub fn run(timing: &Path, range: &str, keep_original: bool) -> Result<()> {
+    let (min, max) = parse_range(range)?;
+    let content = std::fs::read_to_string(timing)
+        .with_context(|| format!("Cannot read timing file {}", timing.display()))?;
+
+    if !content.lines().any(|l| l.starts_with("I ")) {
+        return Err(anyhow!(
+            "{} has no input records; record with -I or -B and an advanced timing file",
+            timing.display()
+        ));
+    }
+
+    let (smoothed, changed) = smooth_timing(&content, min, max)?;
+
+    if keep_original {
+        let mut original = timing.as_os_str().to_owned();
+        original.push(".orig");
+        std::fs::copy(timing, &original)?;
+    }
+    std::fs::write(timing, smoothed)?;
+
+    println!("Smoothed {} input records in {}", changed, timing.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smooth_clamps_typing_only() {
+        let content = "H 0.000000 START_TIME x\nI 0.004000 1\nO 0.001000 1\nI 0.300000 1\nI 5.000000 1\nH 0.000000 DURATION 6.000000\n";
+        let (min, max) = parse_range("30ms-80ms").unwrap();
+        let (smoothed, changed) = smooth_timing(content, min, max).unwrap();
+
+        assert_eq!(changed, 2);
+        assert_eq!(
+            smoothed,
+            "H 0.000000 START_TIME x\nI 0.030000 1\nO 0.001000 1\nI 0.080000 1\nI 5.000000 1\nH 0.000000 DURATION 5.806000\n"
+        );
+        assert!(parse_range("80ms-30ms").is_err());
+    }
+}
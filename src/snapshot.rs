@@ -0,0 +1,201 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+use crate::hash::HashAlgorithm;
+
+// --snapshot-paths: the listed files and directories are looked at before
+// the session and after it, so what was typed can be reviewed next to what
+// changed on disk. It all goes to a directory beside the log,
+// <log>.snapshot:
+//
+//   before.sha256, after.sha256  every regular file, as sha256sum lists them
+//                                (.sha512 or .blake3 by --hash)
+//   changes                      each file added, deleted or modified, as
+//                                A, D or M and its path
+//   before/, after/              copies of the files, with --snapshot-mode copy
+//
+// Symbolic links aren't followed, and a file that can't be read has `-` for
+// its digest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SnapshotMode {
+    Hash,
+    Copy,
+}
+
+impl SnapshotMode {
+    pub fn parse(name: &str) -> Result<SnapshotMode> {
+        match name.to_lowercase().as_str() {
+            "hash" => Ok(SnapshotMode::Hash),
+            "copy" => Ok(SnapshotMode::Copy),
+            _ => Err(anyhow!("Unsupported snapshot mode: '{}' (hash or copy)", name)),
+        }
+    }
+}
+
+// Each file found and its digest
+type Manifest = BTreeMap<PathBuf, String>;
+
+pub struct Snapshots {
+    paths: Vec<PathBuf>,
+    mode: SnapshotMode,
+    hash: HashAlgorithm,
+    dir: PathBuf,
+    before: Option<Manifest>,
+}
+
+// The snapshot directory of a log
+pub fn bundle_path(log: &Path) -> PathBuf {
+    let mut name = log.as_os_str().to_owned();
+    name.push(".snapshot");
+    PathBuf::from(name)
+}
+
+// The regular files at or under `path`
+pub fn find(path: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(meta) = std::fs::symlink_metadata(path) else { return };
+    if meta.is_file() {
+        found.push(path.to_path_buf());
+    } else if meta.is_dir() {
+        if let Ok(entries) = std::fs::read_dir(path) {
+            for entry in entries.flatten() {
+                find(&entry.path(), found);
+            }
+        }
+    }
+}
+
+fn digest(path: &Path, hash: HashAlgorithm) -> String {
+    let mut hasher = hash.hasher();
+    match std::fs::File::open(path).and_then(|mut file| std::io::copy(&mut file, &mut hasher)) {
+        Ok(_) => hasher.finish(),
+        Err(_) => "-".to_string(),
+    }
+}
+
+fn changes(before: &Manifest, after: &Manifest) -> Vec<(char, PathBuf)> {
+    let paths: BTreeSet<&PathBuf> = before.keys().chain(after.keys()).collect();
+    paths
+        .into_iter()
+        .filter_map(|path| match (before.get(path), after.get(path)) {
+            (None, Some(_)) => Some(('A', path.clone())),
+            (Some(_), None) => Some(('D', path.clone())),
+            (Some(old), Some(new)) if old != new => Some(('M', path.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Snapshots {
+    // `paths` is a comma-separated list; relative paths are taken from the
+    // current directory
+    pub fn new(paths: &str, mode: SnapshotMode, hash: HashAlgorithm, dir: PathBuf) -> Result<Snapshots> {
+        let cwd = std::env::current_dir().context("Cannot get the current directory")?;
+        let paths: Vec<PathBuf> =
+            paths.split(',').map(str::trim).filter(|path| !path.is_empty()).map(|path| cwd.join(path)).collect();
+        if paths.is_empty() {
+            return Err(anyhow!("No paths given to --snapshot-paths"));
+        }
+        Ok(Snapshots { paths, mode, hash, dir, before: None })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Write `<name>.sha256` (or as --hash names it), and the copies into `<name>/`
+    fn take(&self, name: &str) -> Result<Manifest> {
+        let mut files = Vec::new();
+        for path in &self.paths {
+            find(path, &mut files);
+        }
+
+        let mut manifest = Manifest::new();
+        let mut list = String::new();
+        for file in files {
+            let digest = digest(&file, self.hash);
+            if self.mode == SnapshotMode::Copy && digest != "-" {
+                let copy = self.dir.join(name).join(file.strip_prefix("/").unwrap_or(&file));
+                if let Some(parent) = copy.parent() {
+                    std::fs::DirBuilder::new().recursive(true).mode(0o700).create(parent)?;
+                }
+                std::fs::copy(&file, &copy).with_context(|| format!("Cannot copy {}", file.display()))?;
+            }
+            list.push_str(&format!("{}  {}\n", digest, file.display()));
+            manifest.insert(file, digest);
+        }
+        let path = self.dir.join(format!("{}.{}", name, self.hash.name()));
+        std::fs::write(&path, list).with_context(|| format!("Cannot write {}", path.display()))?;
+        Ok(manifest)
+    }
+
+    // Start over in a fresh directory, which only we can read as the copies
+    // may be of files others can't
+    pub fn before(&mut self) -> Result<()> {
+        if self.dir.exists() {
+            std::fs::remove_dir_all(&self.dir).with_context(|| format!("Cannot replace {}", self.dir.display()))?;
+        }
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.dir)
+            .with_context(|| format!("Cannot create {}", self.dir.display()))?;
+        self.before = Some(self.take("before")?);
+        Ok(())
+    }
+
+    // Returns how many files changed
+    pub fn after(&mut self) -> Result<usize> {
+        let before = self.before.take().unwrap_or_default();
+        let after = self.take("after")?;
+        let changes = changes(&before, &after);
+        let list: String = changes.iter().map(|(change, path)| format!("{} {}\n", change, path.display())).collect();
+        let path = self.dir.join("changes");
+        std::fs::write(&path, list).with_context(|| format!("Cannot write {}", path.display()))?;
+        Ok(changes.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes_between_snapshots() {
+        let base = std::env::temp_dir().join(format!("rust_script-{}-snapshot", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        let watched = base.join("etc");
+        std::fs::create_dir_all(watched.join("conf.d")).unwrap();
+        std::fs::write(watched.join("main.conf"), "a").unwrap();
+        std::fs::write(watched.join("conf.d/old.conf"), "b").unwrap();
+        std::fs::write(watched.join("same.conf"), "c").unwrap();
+
+        let paths = format!("{}, {}", watched.display(), base.join("missing").display());
+        let mut snapshots =
+            Snapshots::new(&paths, SnapshotMode::Copy, HashAlgorithm::Sha256, bundle_path(&base.join("log"))).unwrap();
+        snapshots.before().unwrap();
+        std::fs::write(watched.join("main.conf"), "changed").unwrap();
+        std::fs::remove_file(watched.join("conf.d/old.conf")).unwrap();
+        std::fs::write(base.join("missing"), "new").unwrap();
+        assert_eq!(snapshots.after().unwrap(), 3);
+
+        let bundle = base.join("log.snapshot");
+        let changes = std::fs::read_to_string(bundle.join("changes")).unwrap();
+        let before = std::fs::read_to_string(bundle.join("before.sha256")).unwrap();
+        let copied = std::fs::read_to_string(bundle.join("before").join(watched.join("main.conf").strip_prefix("/").unwrap()));
+        std::fs::remove_dir_all(&base).unwrap();
+
+        let path = |name: &str| base.join(name).display().to_string();
+        assert_eq!(
+            changes,
+            format!("D {}\nM {}\nA {}\n", path("etc/conf.d/old.conf"), path("etc/main.conf"), path("missing"))
+        );
+        // sha256 of "c"
+        assert!(before.contains(&format!(
+            "2e7d2c03a9507ae265ecf5b5356885a53393a2029d241394997265a1a25aefc6  {}\n",
+            path("etc/same.conf")
+        )));
+        assert_eq!(copied.unwrap(), "a");
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::DateTime;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::convert::{self, Format};
+use crate::logging::TIMESTAMP_FORMAT;
+use crate::runbook;
+use crate::timing::{self, EntryKind, TimingEntry};
+use crate::typed::TypedLines;
+use crate::vt::Lines;
+
+const PER_SLICE_INFO: [&str; 3] = ["START_TIME", "COLUMNS", "LINES"];
+
+// One command cut out of a session: from its first keystroke to the first
+// keystroke of the next one, so it shows the command typed and its output
+#[derive(Debug)]
+pub struct Slice {
+    pub command: String,
+    pub entries: Vec<TimingEntry>,
+    pub data: Vec<u8>,
+}
+
+// "001-git-status-short.cast"
+fn file_name(index: usize, command: &str) -> String {
+    let mut slug = String::new();
+    for c in command.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+        if slug.chars().count() >= 40 {
+            break;
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    format!(
+        "{:03}-{}.cast",
+        index,
+        if slug.is_empty() { "command" } else { slug }
+    )
+}
+
+// Commands start where the line entered with Enter started being typed.
+// Lines typed into a running program look the same as commands to us.
+pub fn split(entries: &[TimingEntry], data: &[u8]) -> Result<Vec<Slice>> {
+    // Where each entry's data starts, and where each command starts
+    let mut positions = Vec::with_capacity(entries.len() + 1);
+    let mut starts: Vec<(usize, String)> = Vec::new();
+    let mut typed = TypedLines::default();
+    let mut typing_from = None;
+    let mut pos = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        positions.push(pos);
+        match entry.kind {
+            EntryKind::Output(size) => pos += size,
+            EntryKind::Input(size) => {
+                let chunk = data
+                    .get(pos..pos + size)
+                    .ok_or_else(|| anyhow!("log data is shorter than its timing"))?;
+                pos += size;
+                let first = *typing_from.get_or_insert(i);
+                // A pasted block runs as one
+                let lines = typed.feed(chunk);
+                if !lines.is_empty() {
+                    starts.push((first, lines.join("; ")));
+                }
+                if chunk.ends_with(b"\r") || chunk.ends_with(b"\n") {
+                    typing_from = None;
+                } else if chunk.contains(&b'\r') || chunk.contains(&b'\n') {
+                    typing_from = Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    positions.push(pos);
+
+    // Info records carry over, except the ones that change along the way
+    let start_time = timing::info(entries, "START_TIME").and_then(|t| DateTime::parse_from_str(t, TIMESTAMP_FORMAT).ok());
+    let info: Vec<&TimingEntry> = entries
+        .iter()
+        .filter(|e| matches!(&e.kind, EntryKind::Info(name, _) if !PER_SLICE_INFO.contains(&name.as_str())))
+        .collect();
+
+    // The window size and time at each command's start
+    let mut size = (timing::info(entries, "COLUMNS"), timing::info(entries, "LINES"));
+    let mut sizes = Vec::with_capacity(starts.len());
+    let mut offset = Duration::ZERO;
+    let mut next = starts.iter().peekable();
+    for (i, entry) in entries.iter().enumerate() {
+        offset += entry.delay;
+        while next.next_if(|(first, _)| *first == i).is_some() {
+            sizes.push((size, offset));
+        }
+        if let EntryKind::Signal(name, Some(message)) = &entry.kind {
+            if name == "SIGWINCH" {
+                let field = |key: &str| message.split(' ').find_map(|f| f.strip_prefix(key));
+                size = (field("COLS=").or(size.0), field("ROWS=").or(size.1));
+            }
+        }
+    }
+
+    let mut slices = Vec::with_capacity(starts.len());
+    for (n, (first, command)) in starts.iter().enumerate() {
+        let end = starts.get(n + 1).map_or(entries.len(), |(next, _)| *next);
+        let ((cols, lines), offset) = sizes[n];
+
+        let mut slice: Vec<TimingEntry> = info.iter().map(|&e| e.clone()).collect();
+        let mut header = |name: &str, value: String| {
+            slice.push(TimingEntry {
+                delay: Duration::ZERO,
+                kind: EntryKind::Info(name.to_string(), value),
+                wall: None,
+            })
+        };
+        if let Some(start) = start_time {
+            let start = start + chrono::Duration::from_std(offset)?;
+            header("START_TIME", start.format(TIMESTAMP_FORMAT).to_string());
+        }
+        if let (Some(cols), Some(lines)) = (cols, lines) {
+            header("COLUMNS", cols.to_string());
+            header("LINES", lines.to_string());
+        }
+        let body = slice.len();
+        slice.extend(entries[*first..end].iter().cloned());
+        slice[body].delay = Duration::ZERO;
+
+        slices.push(Slice {
+            command: command.clone(),
+            entries: slice,
+            data: data[positions[*first]..positions[end]].to_vec(),
+        });
+    }
+    Ok(slices)
+}
+
+// What a command showed and how it went
+#[derive(Debug, PartialEq)]
+pub struct Command {
+    pub command: String,
+    // As it showed, without colors or cursor movement
+    pub output: Vec<String>,
+    // From pressing Enter to the last output before the next command
+    pub duration: Duration,
+    pub exit_code: Option<i32>,
+}
+
+impl Command {
+    fn from_slice(slice: &Slice) -> Command {
+        let mut lines = Lines::default();
+        let mut output = Vec::new();
+        let mut offset = Duration::ZERO;
+        let mut entered = None;
+        let mut finished = Duration::ZERO;
+        let mut exit_code = None;
+        let mut pos = 0;
+        for entry in &slice.entries {
+            offset += entry.delay;
+            match entry.kind {
+                EntryKind::Input(size) => {
+                    let chunk = &slice.data[pos..pos + size];
+                    if entered.is_none() && (chunk.contains(&b'\r') || chunk.contains(&b'\n')) {
+                        entered = Some(offset);
+                    }
+                    pos += size;
+                }
+                EntryKind::Output(size) => {
+                    output.extend(lines.feed(&slice.data[pos..pos + size]));
+                    finished = offset;
+                    pos += size;
+                }
+                // Known when the shell marks commands (OSC 133)
+                EntryKind::Signal(ref name, Some(ref message)) if name == "COMMAND_END" => {
+                    exit_code = message.strip_prefix("EXIT_CODE=").and_then(|code| code.parse().ok());
+                }
+                _ => {}
+            }
+        }
+
+        // The first line is the command as it was echoed; the unfinished
+        // last one is the next prompt
+        if !output.is_empty() {
+            output.remove(0);
+        }
+        Command {
+            command: slice.command.clone(),
+            output,
+            duration: finished.saturating_sub(entered.unwrap_or(finished)),
+            exit_code,
+        }
+    }
+}
+
+pub fn commands(slices: &[Slice]) -> Vec<Command> {
+    slices.iter().map(Command::from_slice).collect()
+}
+
+pub fn run(timing: Option<&Path>, recording: &Path, outdir: Option<&Path>, runbook: Option<&Path>) -> Result<()> {
+    let (entries, data) = convert::load_recording(timing, recording)?;
+    let slices = split(&entries, &data)?;
+    if slices.is_empty() {
+        return Err(anyhow!(
+            "No commands found in {}: it has no typed input (record with -B, or an asciicast with input events)",
+            recording.display()
+        ));
+    }
+
+    if let Some(outdir) = outdir {
+        std::fs::create_dir_all(outdir)
+            .with_context(|| format!("Cannot create {}", outdir.display()))?;
+        for (i, slice) in slices.iter().enumerate() {
+            let path = outdir.join(file_name(i + 1, &slice.command));
+            convert::save(Format::Asciicast, &slice.entries, &slice.data, &[path])?;
+        }
+        println!("Wrote {} command(s) to {}", slices.len(), outdir.display());
+    }
+    if let Some(path) = runbook {
+        let yaml = runbook::render(&recording.display().to_string(), &commands(&slices));
+        std::fs::write(path, yaml).with_context(|| format!("Cannot write {}", path.display()))?;
+        println!("Wrote a runbook of {} step(s) to {}", slices.len(), path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_at_typed_commands() {
+        let entries = timing::parse_timing(
+            "H 0.000000 START_TIME 2024-01-02 03:04:05+00:00\nH 0.000000 COLUMNS 80\nH 0.000000 LINES 24\n\
+             O 0.1 2\nI 1.0 2\nO 0.1 2\nI 0.5 1\nO 0.1 13\nS 0.2 SIGWINCH ROWS=30 COLS=100\n\
+             I 0.3 1\nO 0.1 2\nI 1.0 8\nO 0.1 15\nI 0.5 3\n",
+        )
+        .unwrap();
+        let data = [
+            &b"$ "[..],
+            b"ls",
+            b"ls",
+            b"\r",
+            b"\r\na  b\r\n$ \x1b[K",
+            b"\r",
+            b"\r\n",
+            b"echo hi\r",
+            b"echo hi\r\nhi\r\n$ ",
+            b"vi\x7f",
+        ]
+        .concat();
+
+        let slices = split(&entries, &data).unwrap();
+        assert_eq!(slices.len(), 2);
+        assert_eq!(slices[0].command, "ls");
+        assert_eq!(slices[0].data, b"lsls\r\r\na  b\r\n$ \x1b[K\r\r\n");
+        assert_eq!(slices[0].entries[3].delay, Duration::ZERO);
+        assert_eq!(
+            timing::info(&slices[0].entries, "START_TIME"),
+            Some("2024-01-02 03:04:06+00:00")
+        );
+
+        // The window was resized during the first command
+        assert_eq!(slices[1].command, "echo hi");
+        assert_eq!(slices[1].data, b"echo hi\recho hi\r\nhi\r\n$ vi\x7f");
+        assert_eq!(timing::info(&slices[1].entries, "COLUMNS"), Some("100"));
+        assert_eq!(timing::info(&slices[1].entries, "LINES"), Some("30"));
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(
+            file_name(1, "git status --short"),
+            "001-git-status-short.cast"
+        );
+        assert_eq!(file_name(12, "ls /tmp/"), "012-ls-tmp.cast");
+        assert_eq!(file_name(3, "$(!)"), "003-command.cast");
+    }
+}
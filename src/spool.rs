@@ -0,0 +1,336 @@
+use anyhow::{anyhow, Context, Result};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cli::Args;
+use crate::config;
+use crate::hash::HashAlgorithm;
+use crate::utils;
+
+// Where sessions are written until they are complete
+const INCOMING: &str = ".incoming";
+// Sessions the spooler gave up on
+const FAILED: &str = ".failed";
+// Sessions handed off, with --keep
+const DONE: &str = ".done";
+// The spooler's count of failed hand-offs and when to try next
+const ATTEMPTS: &str = ".spool-attempts";
+// Why the spooler gave up
+const ERROR: &str = ".spool-error";
+// Longest wait between tries
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+// --spool DIR: the session is written to a directory of its own under
+// DIR/.incoming, the logs named by -O, -B, -T and the rest taken as file
+// names in it. Once the recording is over and every log closed, a manifest
+// of the files and their digests goes in last, as `manifest.sha256` (or as
+// --hash names it, `sha256sum -c` can check it), and the directory is
+// renamed into DIR: whatever is there is complete. `script spooler` hands
+// those sessions to an upload or indexing command, so nothing slow or
+// fragile runs in the session's own process.
+pub struct Spool {
+    root: PathBuf,
+    id: String,
+    incoming: PathBuf,
+}
+
+fn create_dir(path: &Path) -> Result<()> {
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(path)
+        .with_context(|| format!("Cannot create {}", path.display()))
+}
+
+impl Spool {
+    // The session's id is its start time and the recorder's pid
+    pub fn create(root: &Path, start: chrono::DateTime<chrono::FixedOffset>) -> Result<Spool> {
+        let id = format!("{}-{}", start.format("%Y%m%d-%H%M%S"), std::process::id());
+        let incoming = root.join(INCOMING).join(&id);
+        create_dir(&incoming)?;
+        Ok(Spool { root: root.to_path_buf(), id, incoming })
+    }
+
+    #[cfg(test)]
+    pub fn dir(&self) -> &Path {
+        &self.incoming
+    }
+
+    // Every log goes in the session's directory under its file name
+    pub fn place(&self, args: &mut Args, default: &str) {
+        let place = |path: &mut PathBuf| *path = self.incoming.join(path.file_name().unwrap_or(path.as_os_str()));
+        let paths = [
+            &mut args.log_in, &mut args.log_out, &mut args.log_io, &mut args.log_err, &mut args.ttyrec, &mut args.plain,
+            &mut args.log_timing, &mut args.transcript,
+        ];
+        paths.into_iter().flatten().for_each(place);
+        if let Some(Some(path)) = &mut args.timing {
+            place(path);
+        }
+        let file = args.file.as_deref().and_then(Path::file_name).unwrap_or(default.as_ref());
+        args.file = Some(self.incoming.join(file));
+    }
+
+    // Write the manifest and hand the session over; returns where it is.
+    // The logs are files right in the directory, which is all the manifest
+    // lists.
+    pub fn complete(&self, hash: HashAlgorithm) -> Result<PathBuf> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&self.incoming)
+            .with_context(|| format!("Cannot read {}", self.incoming.display()))?
+            .flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+            .map(|entry| entry.path())
+            .collect();
+        files.sort();
+        let mut manifest = String::new();
+        for file in &files {
+            let name = file.file_name().unwrap_or_default();
+            manifest.push_str(&format!("{}  {}\n", digest(file, hash)?, Path::new(name).display()));
+        }
+        let path = self.incoming.join(format!("manifest.{}", hash.name()));
+        std::fs::write(&path, manifest).with_context(|| format!("Cannot write {}", path.display()))?;
+
+        let done = self.root.join(&self.id);
+        std::fs::rename(&self.incoming, &done)
+            .with_context(|| format!("Cannot move {} to {}", self.incoming.display(), done.display()))?;
+        Ok(done)
+    }
+}
+
+fn digest(path: &Path, hash: HashAlgorithm) -> Result<String> {
+    let mut hasher = hash.hasher();
+    let mut file = std::fs::File::open(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    std::io::copy(&mut file, &mut hasher).with_context(|| format!("Cannot read {}", path.display()))?;
+    Ok(hasher.finish())
+}
+
+// Whether the files are as the manifest has them. It names files in the
+// session's directory, nothing outside it.
+fn verify(session: &Path, fips: bool) -> Result<()> {
+    let manifest = ["sha256", "sha512", "blake3"]
+        .iter()
+        .map(|name| (session.join(format!("manifest.{}", name)), name))
+        .find(|(path, _)| path.exists())
+        .ok_or_else(|| anyhow!("No manifest"))?;
+    let hash = HashAlgorithm::parse(manifest.1)?.check(fips)?;
+    let content =
+        std::fs::read_to_string(&manifest.0).with_context(|| format!("Cannot read {}", manifest.0.display()))?;
+    for line in content.lines() {
+        let (expected, name) = line.split_once("  ").ok_or_else(|| anyhow!("Invalid manifest line: {}", line))?;
+        if matches!(name, "" | "." | "..") || name.contains('/') {
+            return Err(anyhow!("Invalid file name in the manifest: {}", name));
+        }
+        if digest(&session.join(name), hash)? != expected {
+            return Err(anyhow!("{} does not match the manifest", name));
+        }
+    }
+    Ok(())
+}
+
+// How `script spooler` hands sessions off
+#[derive(Debug, Clone)]
+pub struct SpoolerOptions {
+    // Run with `sh -c`, the session's directory as $1
+    pub exec: String,
+    // Failed hand-offs before the session goes to .failed
+    pub retries: u32,
+    // Before the first retry, doubling after each
+    pub retry_delay: String,
+    pub interval: String,
+    // One pass over the directory, then exit
+    pub once: bool,
+    // Move handed-off sessions to .done rather than deleting them
+    pub keep: bool,
+}
+
+struct Spooler<'a> {
+    root: &'a Path,
+    options: &'a SpoolerOptions,
+    retry_delay: Duration,
+    // SCRIPT_FIPS: manifests must use an approved digest
+    fips: bool,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs())
+}
+
+// The failures so far and the time of the next try
+fn attempts(session: &Path) -> (u32, u64) {
+    let content = std::fs::read_to_string(session.join(ATTEMPTS)).unwrap_or_default();
+    let mut fields = content.split_whitespace().map(|field| field.parse().unwrap_or(0));
+    (fields.next().unwrap_or(0) as u32, fields.next().unwrap_or(0))
+}
+
+impl Spooler<'_> {
+    // Hand off every complete session that is due; returns how many went
+    fn pass(&self) -> Result<usize> {
+        let mut sessions: Vec<PathBuf> = std::fs::read_dir(self.root)
+            .with_context(|| format!("Cannot read {}", self.root.display()))?
+            .flatten()
+            .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+            .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+            .map(|entry| entry.path())
+            .collect();
+        sessions.sort();
+
+        let mut handed = 0;
+        for session in sessions {
+            let (failures, next) = attempts(&session);
+            if next > unix_now() {
+                continue;
+            }
+            // Files that don't match their manifest won't next time either
+            let result = match verify(&session, self.fips) {
+                Ok(()) => self.hand_off(&session).map_err(|e| (e, true)),
+                Err(e) => Err((e, false)),
+            };
+            match result {
+                Ok(()) => {
+                    self.finish(&session)?;
+                    handed += 1;
+                }
+                Err((e, true)) if failures < self.options.retries => {
+                    let delay = self.retry_delay.saturating_mul(1 << failures.min(16)).min(MAX_RETRY_DELAY);
+                    let record = format!("{} {}\n", failures + 1, unix_now() + delay.as_secs());
+                    std::fs::write(session.join(ATTEMPTS), record)?;
+                    eprintln!("script spooler: {}: {:#}; trying again in {}s", session.display(), e, delay.as_secs());
+                }
+                Err((e, _)) => {
+                    std::fs::write(session.join(ERROR), format!("{:#}\n", e))?;
+                    self.move_to(&session, FAILED)?;
+                    eprintln!("script spooler: {}: {:#}; moved to {}", session.display(), e, FAILED);
+                }
+            }
+        }
+        Ok(handed)
+    }
+
+    fn hand_off(&self, session: &Path) -> Result<()> {
+        let status = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(&self.options.exec)
+            .arg("sh")
+            .arg(session)
+            .env("SCRIPT_SPOOL_SESSION", session)
+            .status()
+            .with_context(|| format!("Cannot run {}", self.options.exec))?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(anyhow!("{} failed: {}", self.options.exec, status)),
+        }
+    }
+
+    fn finish(&self, session: &Path) -> Result<()> {
+        let _ = std::fs::remove_file(session.join(ATTEMPTS));
+        match self.options.keep {
+            true => self.move_to(session, DONE),
+            false => std::fs::remove_dir_all(session).with_context(|| format!("Cannot remove {}", session.display())),
+        }
+    }
+
+    fn move_to(&self, session: &Path, dir: &str) -> Result<()> {
+        let dir = self.root.join(dir);
+        create_dir(&dir)?;
+        let to = dir.join(session.file_name().unwrap_or_default());
+        std::fs::rename(session, &to).with_context(|| format!("Cannot move {} to {}", session.display(), to.display()))
+    }
+}
+
+// `script spooler DIR`: one per directory, which a lock in it makes sure of
+pub fn run(root: &Path, options: &SpoolerOptions) -> Result<()> {
+    let retry_delay = utils::parse_duration(&options.retry_delay)?;
+    let interval = utils::parse_duration(&options.interval)?;
+    create_dir(root)?;
+    let lock_path = root.join(".spooler.lock");
+    let lock = std::fs::File::create(&lock_path).with_context(|| format!("Cannot create {}", lock_path.display()))?;
+    nix::fcntl::flock(lock.as_raw_fd(), nix::fcntl::FlockArg::LockExclusiveNonblock)
+        .map_err(|_| anyhow!("Another spooler is running on {}", root.display()))?;
+
+    let spooler = Spooler { root, options, retry_delay, fips: config::fips() };
+    loop {
+        spooler.pass()?;
+        if options.once {
+            return Ok(());
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spooled_sessions_are_handed_off() {
+        let root = std::env::temp_dir().join(format!("rust_script-{}-spool", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let start = chrono::DateTime::parse_from_rfc3339("2024-01-02T03:04:05+00:00").unwrap();
+        let spool = Spool::create(&root, start).unwrap();
+
+        let mut args =
+            Args { log_io: Some("/var/log/s.log".into()), log_timing: Some("s.tm".into()), ..Args::default() };
+        spool.place(&mut args, "typescript");
+        assert_eq!(args.log_io, Some(spool.dir().join("s.log")));
+        assert_eq!(args.file, Some(spool.dir().join("typescript")));
+
+        std::fs::write(spool.dir().join("s.log"), "c").unwrap();
+        let done = spool.complete(HashAlgorithm::Sha256).unwrap();
+        assert!(!root.join(INCOMING).join(done.file_name().unwrap()).exists());
+        let manifest = std::fs::read_to_string(done.join("manifest.sha256")).unwrap();
+        assert_eq!(manifest, "2e7d2c03a9507ae265ecf5b5356885a53393a2029d241394997265a1a25aefc6  s.log\n");
+
+        // A failed hand-off is tried again later, and kept once it works
+        let mut options = SpoolerOptions {
+            exec: "test -f \"$1/s.log\" && false".into(),
+            retries: 1,
+            retry_delay: "0s".into(),
+            interval: "1s".into(),
+            once: true,
+            keep: true,
+        };
+        let spooler = Spooler { root: &root, options: &options, retry_delay: Duration::ZERO, fips: false };
+        assert_eq!(spooler.pass().unwrap(), 0);
+        assert_eq!(attempts(&done).0, 1);
+        options.exec = "test \"$SCRIPT_SPOOL_SESSION\" = \"$1\"".into();
+        let spooler = Spooler { root: &root, options: &options, retry_delay: Duration::ZERO, fips: false };
+        assert_eq!(spooler.pass().unwrap(), 1);
+        assert!(root.join(DONE).join(done.file_name().unwrap()).join("s.log").exists());
+
+        // A file changed since the manifest fails at once
+        let spool = Spool::create(&root, start + chrono::Duration::seconds(1)).unwrap();
+        std::fs::write(spool.dir().join("s.log"), "c").unwrap();
+        let done = spool.complete(HashAlgorithm::Sha256).unwrap();
+        std::fs::write(done.join("s.log"), "changed").unwrap();
+        assert_eq!(spooler.pass().unwrap(), 0);
+        let error = std::fs::read_to_string(root.join(FAILED).join(done.file_name().unwrap()).join(ERROR)).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(error, "s.log does not match the manifest\n");
+    }
+
+    #[test]
+    fn test_manifests_are_checked_before_use() {
+        let session = std::env::temp_dir().join(format!("rust_script-{}-manifest", std::process::id()));
+        std::fs::create_dir_all(&session).unwrap();
+        std::fs::write(session.join("s.log"), "c").unwrap();
+        let digest = "2e7d2c03a9507ae265ecf5b5356885a53393a2029d241394997265a1a25aefc6";
+
+        std::fs::write(session.join("manifest.sha256"), format!("{}  s.log\n", digest)).unwrap();
+        assert!(verify(&session, true).is_ok());
+        // Only files of the session itself
+        for name in ["../s.log", "/etc/passwd", "..", "sub/s.log"] {
+            std::fs::write(session.join("manifest.sha256"), format!("{}  {}\n", digest, name)).unwrap();
+            assert!(verify(&session, false).is_err(), "{}", name);
+        }
+
+        // Not an approved digest in FIPS mode
+        std::fs::remove_file(session.join("manifest.sha256")).unwrap();
+        std::fs::write(session.join("manifest.blake3"), "x  s.log\n").unwrap();
+        let error = verify(&session, true).unwrap_err().to_string();
+        std::fs::remove_dir_all(&session).unwrap();
+        assert!(error.contains("not FIPS 140 approved"), "{}", error);
+    }
+}
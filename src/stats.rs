@@ -0,0 +1,195 @@
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::convert;
+use crate::timing::{self, window_size, EntryKind, TimingEntry};
+use crate::utils;
+
+// Pauses at least this long, with nothing written or typed, count as idle
+const IDLE_GAP: Duration = Duration::from_secs(5);
+
+// What `script info` tells about a recording; anything the recording
+// doesn't say is None
+#[derive(Debug, Default, PartialEq)]
+pub struct RecordingStats {
+    pub start_time: Option<String>,
+    pub duration: Duration,
+    pub bytes_out: u64,
+    pub bytes_in: u64,
+    pub input_events: usize,
+    pub idle: Duration,
+    pub size: Option<(usize, usize)>,
+    pub size_changes: usize,
+    pub exit_code: Option<i32>,
+    // With --wall-clock: the first and last records by the time of day, and
+    // how far the clock was stepped between them (the wall-clock span less
+    // the monotonic one)
+    pub first_stamp: Option<DateTime<FixedOffset>>,
+    pub last_stamp: Option<DateTime<FixedOffset>>,
+    pub clock_step: Option<f64>,
+}
+
+impl RecordingStats {
+    pub fn of(entries: &[TimingEntry]) -> RecordingStats {
+        let number = |name| timing::info(entries, name).and_then(|v| v.parse::<usize>().ok()).filter(|&v| v > 0);
+        let mut stats = RecordingStats {
+            start_time: timing::info(entries, "START_TIME").map(str::to_string),
+            size: number("COLUMNS").zip(number("LINES")),
+            exit_code: timing::info(entries, "EXIT_CODE").and_then(|code| code.parse().ok()),
+            ..RecordingStats::default()
+        };
+
+        let mut elapsed = Duration::ZERO;
+        // Into the session at the first and last stamps
+        let mut span = None;
+        for entry in entries {
+            elapsed += entry.delay;
+            if let Some(wall) = entry.wall {
+                let start = span.map_or(elapsed, |(start, _)| start);
+                span = Some((start, elapsed));
+                stats.first_stamp.get_or_insert(wall);
+                stats.last_stamp = Some(wall);
+            }
+            if entry.delay >= IDLE_GAP {
+                stats.idle += entry.delay;
+            }
+            match entry.kind {
+                EntryKind::Output(size) => stats.bytes_out += size as u64,
+                EntryKind::Input(size) => {
+                    stats.bytes_in += size as u64;
+                    stats.input_events += 1;
+                }
+                EntryKind::Signal(ref name, Some(ref message)) if name == "SIGWINCH" && window_size(message).is_some() => {
+                    stats.size_changes += 1
+                }
+                _ => {}
+            }
+        }
+        if let (Some(first), Some(last), Some((start, end))) = (stats.first_stamp, stats.last_stamp, span) {
+            let wall = (last - first).num_microseconds().unwrap_or(0) as f64 / 1e6;
+            stats.clock_step = Some(wall - (end - start).as_secs_f64());
+        }
+        stats.duration = timing::info(entries, "DURATION")
+            .and_then(|d| d.parse::<f64>().ok())
+            .and_then(utils::secs_to_duration)
+            .unwrap_or(elapsed);
+        stats
+    }
+
+    pub fn report(&self) -> String {
+        let time = |d: Duration| {
+            let ms = d.as_millis();
+            format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+        };
+        let unknown = || "unknown".to_string();
+        let mut out = format!("Start time:    {}\n", self.start_time.clone().unwrap_or_else(unknown));
+        out.push_str(&format!("Duration:      {}\n", time(self.duration)));
+        out.push_str(&format!("Output:        {} bytes\n", self.bytes_out));
+        out.push_str(&format!("Input:         {} bytes in {} events\n", self.bytes_in, self.input_events));
+        out.push_str(&format!("Idle time:     {} (pauses of {}s or more)\n", time(self.idle), IDLE_GAP.as_secs()));
+        let size = self.size.map_or_else(unknown, |(cols, rows)| format!("{}x{}", cols, rows));
+        out.push_str(&format!("Terminal size: {}\n", size));
+        out.push_str(&format!("Size changes:  {}\n", self.size_changes));
+        out.push_str(&format!("Exit code:     {}\n", self.exit_code.map_or_else(unknown, |code| code.to_string())));
+        let stamp = |time: Option<DateTime<FixedOffset>>| {
+            time.map_or_else(unknown, |time| time.format("%Y-%m-%d %H:%M:%S%.3f%:z").to_string())
+        };
+        out.push_str(&format!("Wall clock:    {} to {}\n", stamp(self.first_stamp), stamp(self.last_stamp)));
+        let step = self.clock_step.map_or_else(unknown, |step| format!("{:+.3}s", step));
+        out.push_str(&format!("Clock step:    {}\n", step));
+        out
+    }
+
+    pub fn json(&self) -> String {
+        let stamp = |time: Option<DateTime<FixedOffset>>| time.map(|time| time.to_rfc3339());
+        let string = |value: &Option<String>| match value {
+            Some(value) => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+            None => "null".to_string(),
+        };
+        let (cols, rows) = match self.size {
+            Some((cols, rows)) => (cols.to_string(), rows.to_string()),
+            None => ("null".to_string(), "null".to_string()),
+        };
+        format!(
+            "{{\"start_time\":{},\"duration\":{:.6},\"bytes_out\":{},\"bytes_in\":{},\"input_events\":{},\
+             \"idle_time\":{:.6},\"columns\":{},\"lines\":{},\"size_changes\":{},\"exit_code\":{},\"first_stamp\":{},\
+             \"last_stamp\":{},\"clock_step\":{}}}\n",
+            string(&self.start_time),
+            self.duration.as_secs_f64(),
+            self.bytes_out,
+            self.bytes_in,
+            self.input_events,
+            self.idle.as_secs_f64(),
+            cols,
+            rows,
+            self.size_changes,
+            self.exit_code.map_or_else(|| "null".to_string(), |code| code.to_string()),
+            string(&stamp(self.first_stamp)),
+            string(&stamp(self.last_stamp)),
+            self.clock_step.map_or_else(|| "null".to_string(), |step| format!("{:.6}", step)),
+        )
+    }
+}
+
+pub fn run(timing: Option<&Path>, recording: &Path, json: bool) -> Result<()> {
+    let (entries, _) = convert::load_recording(timing, recording)?;
+    let stats = RecordingStats::of(&entries);
+    print!("{}", if json { stats.json() } else { stats.report() });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::parse_timing;
+
+    #[test]
+    fn test_stats_of_a_session() {
+        let entries = parse_timing(
+            "H 0.000000 START_TIME 2024-01-02 03:04:05+00:00\nH 0.000000 COLUMNS 80\nH 0.000000 LINES 24\n\
+             O 0.1 10\nI 6.0 3\nI 0.2 1\nO 0.01 40\nS 10.0 SIGWINCH ROWS=50 COLS=120\nO 0.5 5\n\
+             H 0.000000 DURATION 17.000000\nH 0.000000 EXIT_CODE 2\n",
+        )
+        .unwrap();
+        let stats = RecordingStats::of(&entries);
+        assert_eq!((stats.bytes_out, stats.bytes_in, stats.input_events, stats.size_changes), (55, 4, 2, 1));
+        assert_eq!(stats.idle, Duration::from_secs(16));
+
+        let report = stats.report();
+        assert!(report.contains("Duration:      00:00:17.000\n"));
+        assert!(report.contains("Terminal size: 80x24\nSize changes:  1\n"));
+        assert!(report.contains("Exit code:     2\n"));
+        assert_eq!(
+            stats.json(),
+            "{\"start_time\":\"2024-01-02 03:04:05+00:00\",\"duration\":17.000000,\"bytes_out\":55,\"bytes_in\":4,\
+             \"input_events\":2,\"idle_time\":16.000000,\"columns\":80,\"lines\":24,\"size_changes\":1,\"exit_code\":2,\
+             \"first_stamp\":null,\"last_stamp\":null,\"clock_step\":null}\n"
+        );
+
+        // A classic timing file knows only the output
+        let classic = RecordingStats::of(&parse_timing("0.5 3\n7.0 2\n").unwrap());
+        assert_eq!(
+            (classic.duration, classic.idle, classic.exit_code),
+            (Duration::from_millis(7500), Duration::from_secs(7), None)
+        );
+        assert!(classic.report().contains("Wall clock:    unknown to unknown\nClock step:    unknown\n"));
+    }
+
+    #[test]
+    fn test_stats_of_a_stamped_session() {
+        // The clock was set back half a second between the two records
+        let entries = parse_timing(
+            "H 0.000000 TIMESTAMPS wall-clock\nO 0.5 3 @2024-01-02T03:04:05.500000+01:00\n\
+             O 2.0 3 @2024-01-02T03:04:07.000000+01:00\n",
+        )
+        .unwrap();
+        let stats = RecordingStats::of(&entries);
+        assert_eq!(stats.duration, Duration::from_millis(2500));
+        let report = stats.report();
+        assert!(report.contains("Wall clock:    2024-01-02 03:04:05.500+01:00 to 2024-01-02 03:04:07.000+01:00\n"));
+        assert!(report.contains("Clock step:    -0.500s\n"));
+        assert!(stats.json().ends_with("\"last_stamp\":\"2024-01-02T03:04:07+01:00\",\"clock_step\":-0.500000}\n"));
+    }
+}
@@ -0,0 +1,129 @@
+// sudo as typed at the session's shell, recorded as SUDO events:
+//
+//   S 1.250000 SUDO USER=root TTY=pts/3 COMMAND=systemctl restart nginx
+//
+// USER, TTY and COMMAND are the fields sudo logs the run under too, so the
+// event can be matched up with its entry in the auth log or journal
+// (`journalctl _COMM=sudo`) by the terminal and time. A command line is
+// read as words, without the shell's quoting; sudo anywhere in a pipeline or
+// list counts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sudo {
+    pub user: String,
+    // Empty for a shell (-i, -s)
+    pub command: String,
+}
+
+// Options taking a value, in the next word or the rest of a short cluster
+const WITH_VALUE: &[char] = &['u', 'g', 'p', 'C', 'D', 'h', 'r', 't', 'U', 'T'];
+const LONG_WITH_VALUE: &[&str] = &[
+    "--user", "--group", "--prompt", "--close-from", "--chdir", "--host", "--role", "--type", "--other-user",
+    "--command-timeout",
+];
+
+impl Sudo {
+    pub fn message(&self, tty: Option<&str>) -> String {
+        let mut message = format!("USER={}", self.user);
+        if let Some(tty) = tty {
+            message.push_str(&format!(" TTY={}", tty.strip_prefix("/dev/").unwrap_or(tty)));
+        }
+        let command = if self.command.is_empty() { "(shell)" } else { &self.command };
+        message.push_str(&format!(" COMMAND={}", command));
+        message
+    }
+}
+
+// One simple command: sudo after any variable assignments, then its options
+fn parse(words: &[&str]) -> Option<Sudo> {
+    let mut words = words.iter().copied().skip_while(|word| word.contains('=')).peekable();
+    let program = words.next()?;
+    if program.rsplit('/').next() != Some("sudo") {
+        return None;
+    }
+
+    let mut user = "root".to_string();
+    let mut shell = false;
+    while let Some(&word) = words.peek() {
+        if word == "--" {
+            words.next();
+            break;
+        }
+        if !word.starts_with('-') || word == "-" {
+            break;
+        }
+        words.next();
+        if let Some(long) = word.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (format!("--{}", name), Some(value.to_string())),
+                None => (word.to_string(), None),
+            };
+            let value = match value {
+                None if LONG_WITH_VALUE.contains(&name.as_str()) => words.next().map(str::to_string),
+                value => value,
+            };
+            match name.as_str() {
+                "--user" => user = value.unwrap_or_default(),
+                "--login" | "--shell" => shell = true,
+                _ => {}
+            }
+            continue;
+        }
+        for (i, option) in word[1..].char_indices() {
+            if WITH_VALUE.contains(&option) {
+                let rest = &word[1 + i + 1..];
+                let value = if rest.is_empty() { words.next().unwrap_or_default() } else { rest };
+                if option == 'u' {
+                    user = value.to_string();
+                }
+                break;
+            }
+            if matches!(option, 'i' | 's') {
+                shell = true;
+            }
+        }
+    }
+
+    let command: Vec<&str> = words.collect();
+    // -v, -l, -k and the like run nothing
+    if command.is_empty() && !shell {
+        return None;
+    }
+    Some(Sudo { user, command: command.join(" ") })
+}
+
+// The sudo runs in a typed command line
+pub fn find(line: &str) -> Vec<Sudo> {
+    let mut found = Vec::new();
+    let mut segment = Vec::new();
+    for word in line.split_whitespace().chain(std::iter::once(";")) {
+        if matches!(word, ";" | "&&" | "||" | "|" | "&") {
+            found.extend(parse(&segment));
+            segment.clear();
+        } else {
+            segment.push(word);
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sudo(user: &str, command: &str) -> Sudo {
+        Sudo { user: user.to_string(), command: command.to_string() }
+    }
+
+    #[test]
+    fn test_find_sudo_runs() {
+        assert_eq!(find("sudo systemctl restart nginx"), vec![sudo("root", "systemctl restart nginx")]);
+        assert_eq!(find("LANG=C /usr/bin/sudo -E -u postgres psql -c x"), vec![sudo("postgres", "psql -c x")]);
+        assert_eq!(find("sudo -iupostgres"), vec![sudo("postgres", "")]);
+        assert_eq!(find("sudo --user=www-data -- ls -l"), vec![sudo("www-data", "ls -l")]);
+        assert_eq!(find("make && echo ok | sudo tee /etc/x ; sudo -v"), vec![sudo("root", "tee /etc/x")]);
+        assert!(find("echo sudo rm -rf /").is_empty());
+
+        assert_eq!(sudo("root", "ls").message(Some("/dev/pts/3")), "USER=root TTY=pts/3 COMMAND=ls");
+        assert_eq!(sudo("bob", "").message(None), "USER=bob COMMAND=(shell)");
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, FixedOffset};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::clock::SystemClock;
+use crate::convert;
+use crate::logging::TIMESTAMP_FORMAT;
+use crate::replay::{self, Chunk};
+use crate::timing::{self, TimingEntry};
+
+// `script sync-replay`: recordings of one event made apart, such as the
+// panes of a session or the two ends of a connection on different hosts,
+// played together on one timeline. Each recording is placed by its
+// START_TIME, moved by its --skew where the clocks it was made by disagree,
+// and the output of all of them is written in the order it happened.
+//
+// START_TIME has whole seconds, so recordings started within the same
+// second need a skew to line up closer than that.
+pub struct Recording {
+    pub entries: Vec<TimingEntry>,
+    pub data: Vec<u8>,
+    // Added to the recording's own times; negative for a clock that ran ahead
+    pub skew: chrono::Duration,
+}
+
+// TIMING:LOG, or an asciicast or ttyrec file that carries its timing
+fn parse_source(source: &str) -> (Option<PathBuf>, PathBuf) {
+    match source.split_once(':') {
+        Some((timing, log)) => (Some(PathBuf::from(timing)), PathBuf::from(log)),
+        None => (None, PathBuf::from(source)),
+    }
+}
+
+// "2=-0.25": the second recording a quarter second earlier
+fn parse_skew(value: &str, recordings: usize) -> Result<(usize, chrono::Duration)> {
+    let invalid = || anyhow!("Invalid --skew: '{}' (e.g. 2=-0.25)", value);
+    let (index, seconds) = value.split_once('=').ok_or_else(invalid)?;
+    let index: usize = index.parse().ok().filter(|&i| i >= 1 && i <= recordings).ok_or_else(invalid)?;
+    let seconds: f64 = seconds.parse().ok().filter(|s: &f64| s.is_finite()).ok_or_else(invalid)?;
+    Ok((index - 1, chrono::Duration::microseconds((seconds * 1e6).round() as i64)))
+}
+
+fn start_time(entries: &[TimingEntry]) -> Option<DateTime<FixedOffset>> {
+    timing::info(entries, "START_TIME").and_then(|time| DateTime::parse_from_str(time, TIMESTAMP_FORMAT).ok())
+}
+
+// The output records of all the recordings, timed from the first to start
+pub fn timeline(recordings: &[Recording]) -> Result<Vec<Chunk>> {
+    let mut starts = Vec::new();
+    for (i, recording) in recordings.iter().enumerate() {
+        let start = start_time(&recording.entries)
+            .ok_or_else(|| anyhow!("Recording {} has no START_TIME to place it by", i + 1))?;
+        starts.push(start + recording.skew);
+    }
+    let Some(&first) = starts.iter().min() else {
+        return Ok(Vec::new());
+    };
+
+    let mut chunks = Vec::new();
+    for (recording, start) in recordings.iter().zip(starts) {
+        let lead = (start - first).to_std()?;
+        for chunk in replay::decode(&recording.entries, recording.data.as_slice(), true)? {
+            chunks.push(Chunk { offset: lead + chunk.offset, data: chunk.data });
+        }
+    }
+    // Stable, so what two recordings wrote at the same moment stays in the
+    // order they were given
+    chunks.sort_by_key(|chunk| chunk.offset);
+    Ok(chunks)
+}
+
+pub fn run(sources: &[String], skews: &[String]) -> Result<()> {
+    let mut recordings = Vec::new();
+    for source in sources {
+        let (timing, log) = parse_source(source);
+        let (entries, data) = convert::load_recording(timing.as_deref(), Path::new(&log))
+            .with_context(|| format!("Cannot read {}", source))?;
+        recordings.push(Recording { entries, data, skew: chrono::Duration::zero() });
+    }
+    for skew in skews {
+        let (index, skew) = parse_skew(skew, recordings.len())?;
+        recordings[index].skew = skew;
+    }
+
+    let chunks = timeline(&recordings)?;
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    replay::replay(&chunks, &mut out, &SystemClock)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, ManualClock};
+    use crate::timing::parse_timing;
+    use std::time::Duration;
+
+    fn recording(timing: &str, data: &[u8]) -> Recording {
+        Recording { entries: parse_timing(timing).unwrap(), data: data.to_vec(), skew: chrono::Duration::zero() }
+    }
+
+    #[test]
+    fn test_recordings_share_a_timeline() {
+        let client = recording("H 0.000000 START_TIME 2024-01-02 03:04:05+00:00\nO 1.0 4\nI 2.0 1\nO 0.5 3\n", b"ask?x>ok");
+        let server = recording("H 0.000000 START_TIME 2024-01-02 04:04:06+01:00\nO 1.0 4\nO 1.5 4\n", b"GET.200.");
+        let mut recordings = [client, server];
+        // The server's clock runs 0.75s ahead
+        recordings[1].skew = parse_skew("2=-0.75", 2).unwrap().1;
+
+        let chunks = timeline(&recordings).unwrap();
+        let order: Vec<_> = chunks.iter().map(|chunk| (chunk.offset.as_millis(), &chunk.data[..])).collect();
+        assert_eq!(order, [(1000, &b"ask?"[..]), (1250, b"GET."), (2750, b"200."), (3500, b">ok")]);
+
+        let clock = ManualClock::new(start_time(&recordings[0].entries).unwrap());
+        let before = clock.now();
+        let mut out = Vec::new();
+        replay::replay(&chunks, &mut out, &clock).unwrap();
+        assert_eq!(out, b"ask?GET.200.>ok");
+        assert_eq!(clock.now() - before, Duration::from_millis(3500));
+
+        assert!(parse_skew("3=1", 2).is_err());
+        assert!(timeline(&[recording("O 1.0 1\n", b"x")]).is_err());
+        assert_eq!(parse_source("s.tm:s.log"), (Some(PathBuf::from("s.tm")), PathBuf::from("s.log")));
+    }
+}
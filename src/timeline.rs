@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+use crate::meta::PRECISE_TIMESTAMP_FORMAT;
+use crate::utils;
+
+// `script timeline-merge`: the timelines of the --meta sidecars of several
+// sessions, maybe by different people on different hosts, as one report in
+// the order things happened, for looking back at an incident. Times are in
+// UTC, so sessions from machines in other time zones line up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    pub host: String,
+    pub user: String,
+    // The file name of the session's log
+    pub session: String,
+    pub what: What,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum What {
+    Command(String),
+    Event(String, Option<String>),
+}
+
+// The timeline of one sidecar
+pub fn load(metadata: &Value) -> Result<Vec<Entry>> {
+    let session = &metadata["session"];
+    let start = session["start_time"]
+        .as_str()
+        .and_then(|time| DateTime::parse_from_str(time, PRECISE_TIMESTAMP_FORMAT).ok())
+        .ok_or_else(|| anyhow!("no session start time (was it recorded with --meta?)"))?;
+    let text = |value: &Value| value.as_str().unwrap_or("?").to_string();
+    let log = session["log"].as_str().map(PathBuf::from).unwrap_or_default();
+    let name = log.file_name().map_or_else(|| "?".to_string(), |name| name.to_string_lossy().into_owned());
+
+    let mut entries = Vec::new();
+    for record in metadata["timeline"].as_array().into_iter().flatten() {
+        let t = record["t"].as_f64().and_then(utils::secs_to_duration).unwrap_or_default();
+        let what = match record["type"].as_str() {
+            Some("command") => What::Command(text(&record["text"])),
+            Some("event") => What::Event(text(&record["name"]), record["message"].as_str().map(str::to_string)),
+            _ => continue,
+        };
+        entries.push(Entry {
+            time: start
+                .checked_add_signed(chrono::Duration::from_std(t)?)
+                .ok_or_else(|| anyhow!("timeline time out of range: {}", record["t"]))?
+                .with_timezone(&Utc),
+            host: text(&session["host"]),
+            user: text(&session["user"]),
+            session: name.clone(),
+            what,
+        });
+    }
+    Ok(entries)
+}
+
+// All the entries by time; those at the same moment keep the order of the
+// files given
+pub fn merge(timelines: Vec<Vec<Entry>>) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = timelines.into_iter().flatten().collect();
+    entries.sort_by_key(|entry| entry.time);
+    entries
+}
+
+fn time(entry: &Entry) -> String {
+    entry.time.format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+}
+
+pub fn markdown(entries: &[Entry]) -> String {
+    // A table cell ends at a | and a line break
+    let cell = |text: &str| text.replace('|', "\\|").replace(['\r', '\n'], " ");
+    let mut out = String::from("# Timeline\n\n| Time (UTC) | Host | User | Session | What |\n|---|---|---|---|---|\n");
+    for entry in entries {
+        let what = match entry.what {
+            What::Command(ref line) if !line.contains('`') => format!("`{}`", cell(line)),
+            What::Command(ref line) => cell(line),
+            What::Event(ref name, Some(ref message)) => format!("**{}** {}", name, cell(message)),
+            What::Event(ref name, None) => format!("**{}**", name),
+        };
+        out.push_str(&format!(
+            "| {} | {} | {} | {} | {} |\n",
+            time(entry),
+            cell(&entry.host),
+            cell(&entry.user),
+            cell(&entry.session),
+            what
+        ));
+    }
+    out
+}
+
+pub fn json(entries: &[Entry]) -> Value {
+    let records: Vec<Value> = entries
+        .iter()
+        .map(|entry| {
+            let mut record = json!({
+                "time": entry.time.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+                "host": entry.host,
+                "user": entry.user,
+                "session": entry.session,
+            });
+            match entry.what {
+                What::Command(ref line) => {
+                    record["type"] = json!("command");
+                    record["text"] = json!(line);
+                }
+                What::Event(ref name, ref message) => {
+                    record["type"] = json!("event");
+                    record["name"] = json!(name);
+                    record["message"] = json!(message);
+                }
+            }
+            record
+        })
+        .collect();
+    json!(records)
+}
+
+pub fn run(files: &[PathBuf], as_json: bool) -> Result<()> {
+    let mut timelines = Vec::new();
+    for file in files {
+        let read = |path: &Path| -> Result<Vec<Entry>> {
+            let metadata: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+            load(&metadata)
+        };
+        timelines.push(read(file).with_context(|| format!("Cannot read {}", file.display()))?);
+    }
+    let entries = merge(timelines);
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&json(&entries))?);
+    } else {
+        print!("{}", markdown(&entries));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_sessions_into_one_timeline() {
+        let alice = json!({
+            "session": {"start_time": "2024-01-02 03:04:05.250000+00:00", "host": "web1", "user": "alice",
+                        "log": "/var/log/a.log"},
+            "timeline": [{"t": 1.0, "type": "command", "text": "tail -f app.log | grep 500"},
+                         {"t": 20.0, "type": "event", "name": "WATCHDOG", "message": "IDLE=15 PIDS=42"}]
+        });
+        let bob = json!({
+            "session": {"start_time": "2024-01-02 04:04:10.000000+01:00", "host": "db1", "user": "bob",
+                        "log": "/var/log/b.log"},
+            "timeline": [{"t": 0.5, "type": "event", "name": "SUDO", "message": "USER=root COMMAND=systemctl restart pg"}]
+        });
+        let entries = merge(vec![load(&alice).unwrap(), load(&bob).unwrap()]);
+        let sessions: Vec<_> = entries.iter().map(|entry| entry.session.as_str()).collect();
+        assert_eq!(sessions, ["a.log", "b.log", "a.log"]);
+
+        let report = markdown(&entries);
+        assert!(report.contains("| 2024-01-02 03:04:06.250 | web1 | alice | a.log | `tail -f app.log \\| grep 500` |\n"));
+        assert!(report.contains("| 2024-01-02 03:04:10.500 | db1 | bob | b.log | **SUDO** USER=root COMMAND=systemctl restart pg |\n"));
+        assert_eq!(json(&entries)[2]["time"], "2024-01-02T03:04:25.250000Z");
+        assert_eq!(json(&entries)[0]["type"], "command");
+
+        assert!(load(&json!({"analyzers": []})).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset};
+use std::io::Read;
+use std::time::Duration;
+
+use crate::exectrace::Exec;
+use crate::logging::WALL_CLOCK_FORMAT;
+use crate::utils;
+
+// One record of a classic or advanced timing file
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimingEntry {
+    // From the monotonic clock, so a step of the system clock (NTP, a
+    // suspend) can't stretch or reverse the replay
+    pub delay: Duration,
+    pub kind: EntryKind,
+    // The time of day it was written, from the realtime clock, for records
+    // stamped with --wall-clock
+    pub wall: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryKind {
+    Output(usize),
+    Input(usize),
+    // The child's stderr (--log-err), whose bytes are in a log of their own
+    Stderr(usize),
+    Signal(String, Option<String>),
+    Info(String, String),
+    // A program run in the session (--trace-exec)
+    Exec(Exec),
+}
+
+fn parse_delay(field: &str, line_no: usize) -> Result<Duration> {
+    field
+        .parse::<f64>()
+        .ok()
+        .and_then(utils::secs_to_duration)
+        .ok_or_else(|| anyhow!("timing file line {}: invalid delay: {}", line_no, field))
+}
+
+fn parse_size(field: &str, line_no: usize) -> Result<usize> {
+    field
+        .parse::<usize>()
+        .map_err(|_| anyhow!("timing file line {}: invalid size: {}", line_no, field))
+}
+
+// Both formats may appear: classic files are just "<delay> <size>" lines
+pub fn parse_timing(content: &str) -> Result<Vec<TimingEntry>> {
+    let mut entries = Vec::new();
+    // Written with --wall-clock: each record after the headers ends in the
+    // time of day, " @2024-01-02T03:04:05.250000+00:00"
+    let mut stamped = false;
+    let mut total = Duration::ZERO;
+
+    for (i, line) in content.lines().enumerate() {
+        let line_no = i + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let is_header = line.starts_with("H ");
+        stamped |= is_header && line.ends_with(" TIMESTAMPS wall-clock");
+        let (line, wall) = match line.rsplit_once(" @") {
+            Some((record, stamp)) if stamped && !is_header => {
+                let wall = DateTime::parse_from_rfc3339(stamp)
+                    .map_err(|_| anyhow!("timing file line {}: invalid time stamp: {}", line_no, stamp))?;
+                (record, Some(wall))
+            }
+            _ => (line, None),
+        };
+
+        let fields: Vec<&str> = line.splitn(4, ' ').collect();
+        let entry = match fields.as_slice() {
+            [delay, size] => TimingEntry {
+                delay: parse_delay(delay, line_no)?,
+                kind: EntryKind::Output(parse_size(size, line_no)?),
+                wall,
+            },
+            ["O", delay, size] => TimingEntry {
+                delay: parse_delay(delay, line_no)?,
+                kind: EntryKind::Output(parse_size(size, line_no)?),
+                wall,
+            },
+            ["I", delay, size] => TimingEntry {
+                delay: parse_delay(delay, line_no)?,
+                kind: EntryKind::Input(parse_size(size, line_no)?),
+                wall,
+            },
+            ["E", delay, size] => TimingEntry {
+                delay: parse_delay(delay, line_no)?,
+                kind: EntryKind::Stderr(parse_size(size, line_no)?),
+                wall,
+            },
+            ["S", delay, name, rest @ ..] => TimingEntry {
+                delay: parse_delay(delay, line_no)?,
+                kind: EntryKind::Signal(name.to_string(), rest.first().map(|m| m.to_string())),
+                wall,
+            },
+            ["H", delay, name, rest @ ..] => TimingEntry {
+                delay: parse_delay(delay, line_no)?,
+                kind: EntryKind::Info(name.to_string(), rest.first().unwrap_or(&"").to_string()),
+                wall,
+            },
+            // The fields are words of their own, however many
+            ["X", delay, ..] => TimingEntry {
+                delay: parse_delay(delay, line_no)?,
+                kind: EntryKind::Exec(
+                    Exec::parse(&line.split(' ').skip(2).collect::<Vec<_>>())
+                        .ok_or_else(|| anyhow!("timing file line {}: invalid exec record", line_no))?,
+                ),
+                wall,
+            },
+            _ => return Err(anyhow!("timing file line {}: unrecognized record", line_no)),
+        };
+        // Every sum of delays a reader makes then fits in a Duration
+        total = total
+            .checked_add(entry.delay)
+            .ok_or_else(|| anyhow!("timing file line {}: delays add up to too long a session", line_no))?;
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+// Skip the "Script started on ..." line util-linux and we put on top
+pub fn skip_header<R: Read>(log: &mut R) -> Result<Vec<u8>> {
+    const HEADER: &[u8] = b"Script started on ";
+
+    let mut start = Vec::new();
+    let mut byte = [0u8; 1];
+    while start.len() < HEADER.len() && log.read(&mut byte)? == 1 {
+        start.push(byte[0]);
+    }
+    if start != HEADER {
+        // Not a header, so it is session data
+        return Ok(start);
+    }
+    while log.read(&mut byte)? == 1 && byte[0] != b'\n' {}
+    Ok(Vec::new())
+}
+
+// Value of the first H record with this name
+pub fn info<'a>(entries: &'a [TimingEntry], name: &str) -> Option<&'a str> {
+    entries.iter().find_map(|entry| match entry.kind {
+        EntryKind::Info(ref n, ref value) if n == name => Some(value.as_str()),
+        _ => None,
+    })
+}
+
+// The markers of a recording (--auto-marker): when each came and the
+// command line it marks, or "" for one dropped at an interval
+#[cfg(any(feature = "asciicast", feature = "html"))]
+pub fn markers(entries: &[TimingEntry]) -> Vec<(Duration, String)> {
+    let mut offset = Duration::ZERO;
+    let mut markers = Vec::new();
+    for entry in entries {
+        offset += entry.delay;
+        if let EntryKind::Signal(ref name, ref message) = entry.kind {
+            if name == "MARKER" {
+                let command = message.as_deref().and_then(|message| message.split_once("COMMAND="));
+                markers.push((offset, command.map_or("", |(_, command)| command).to_string()));
+            }
+        }
+    }
+    markers
+}
+
+// "01:02:03", "02:03", "123" or "1.5", as an offset from the start
+pub fn parse_offset(value: &str) -> Result<Duration> {
+    let invalid = || anyhow!("Invalid time: '{}' (e.g. 00:10:00, 10:00 or 600)", value);
+    let mut secs = 0.0;
+    for part in value.split(':') {
+        let part: f64 = part.parse().map_err(|_| invalid())?;
+        secs = secs * 60.0 + part;
+    }
+    if value.split(':').count() > 3 {
+        return Err(invalid());
+    }
+    utils::secs_to_duration(secs).ok_or_else(invalid)
+}
+
+// The size a SIGWINCH record gives, "ROWS=24 COLS=80"
+pub fn window_size(message: &str) -> Option<(usize, usize)> {
+    let field = |key: &str| message.split(' ').find_map(|f| f.strip_prefix(key)?.parse().ok()).filter(|&v| v > 0);
+    Some((field("COLS=")?, field("ROWS=")?))
+}
+
+// Write entries as a timing file. The classic format only has output
+// records, so the delay of anything else moves to the next one. Time stamps
+// are kept where the TIMESTAMPS header tells readers to expect them.
+pub fn format_timing(entries: &[TimingEntry], advanced: bool) -> String {
+    let mut out = String::new();
+    let mut carry = Duration::ZERO;
+    let stamped = advanced && info(entries, "TIMESTAMPS") == Some("wall-clock");
+
+    for entry in entries {
+        let delay = (carry + entry.delay).as_secs_f64();
+        let line = match (&entry.kind, advanced) {
+            (EntryKind::Output(size), false) => format!("{:.6} {}\n", delay, size),
+            (_, false) => {
+                carry += entry.delay;
+                continue;
+            }
+            (EntryKind::Output(size), true) => format!("O {:.6} {}\n", delay, size),
+            (EntryKind::Input(size), true) => format!("I {:.6} {}\n", delay, size),
+            (EntryKind::Stderr(size), true) => format!("E {:.6} {}\n", delay, size),
+            (EntryKind::Signal(name, Some(message)), true) => format!("S {:.6} {} {}\n", delay, name, message),
+            (EntryKind::Signal(name, None), true) => format!("S {:.6} {}\n", delay, name),
+            (EntryKind::Info(name, value), true) => format!("H {:.6} {} {}\n", delay, name, value),
+            (EntryKind::Exec(exec), true) => format!("X {:.6} {}\n", delay, exec.fields()),
+        };
+        carry = Duration::ZERO;
+        match entry.wall {
+            Some(wall) if stamped && !matches!(entry.kind, EntryKind::Info(..)) => {
+                out.push_str(&format!("{} @{}\n", line.trim_end(), wall.format(WALL_CLOCK_FORMAT)))
+            }
+            _ => out.push_str(&line),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_classic_and_advanced() {
+        let entries = parse_timing("0.5 3\nH 0.000000 COMMAND echo hi\nI 0.25 1\nS 1.0 SIGWINCH ROWS=24 COLS=80\n").unwrap();
+        assert_eq!(
+            entries[0],
+            TimingEntry { delay: Duration::from_millis(500), kind: EntryKind::Output(3), wall: None }
+        );
+        assert_eq!(entries[1].kind, EntryKind::Info("COMMAND".into(), "echo hi".into()));
+        assert_eq!(entries[2].kind, EntryKind::Input(1));
+        assert_eq!(entries[3].kind, EntryKind::Signal("SIGWINCH".into(), Some("ROWS=24 COLS=80".into())));
+        assert!(parse_timing("X 1 2\n").is_err());
+        assert!(parse_timing("-1 2\n").is_err());
+        let err = parse_timing("0.5 3\n1e30 5\n").unwrap_err();
+        assert_eq!(err.to_string(), "timing file line 2: invalid delay: 1e30");
+        assert!(parse_timing("1e19 1\n1e19 1\n").is_err());
+    }
+
+    #[cfg(any(feature = "asciicast", feature = "html"))]
+    #[test]
+    fn test_markers() {
+        let timing = "O 1.5 3\nS 0.5 MARKER AUTO=command COMMAND=make test\nS 60.0 MARKER AUTO=interval\n";
+        let entries = parse_timing(timing);
+        assert_eq!(
+            markers(&entries.unwrap()),
+            [(Duration::from_secs(2), "make test".to_string()), (Duration::from_secs(62), String::new())]
+        );
+    }
+
+    #[test]
+    fn test_format_round_trip() {
+        let advanced = "H 0.000000 COLUMNS 80\nI 0.250000 1\nO 0.500000 3\nS 1.000000 SIGWINCH ROWS=24 COLS=80\n\
+                        X 0.000000 12 11 /tmp ls -l\nE 0.010000 6\nO 0.125000 2\n";
+        let entries = parse_timing(advanced).unwrap();
+        assert_eq!(format_timing(&entries, true), advanced);
+        assert_eq!(format_timing(&entries, false), "0.750000 3\n1.135000 2\n");
+        assert_eq!(info(&entries, "COLUMNS"), Some("80"));
+
+        let stamped = "H 0.000000 TIMESTAMPS wall-clock\nO 0.500000 3 @2024-01-02T03:04:05.500000+01:00\n";
+        let entries = parse_timing(stamped).unwrap();
+        assert_eq!(entries[1].wall, DateTime::parse_from_rfc3339("2024-01-02T03:04:05.5+01:00").ok());
+        assert_eq!(format_timing(&entries, true), stamped);
+        assert!(parse_timing("H 0.000000 TIMESTAMPS wall-clock\nO 0.5 3 @yesterday\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("00:10:00").unwrap(), Duration::from_secs(600));
+        assert_eq!(parse_offset("1:30").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_offset("2.5").unwrap(), Duration::from_millis(2500));
+        assert!(parse_offset("1:2:3:4").is_err());
+        assert!(parse_offset("soon").is_err());
+        assert!(parse_offset("1e30").is_err());
+    }
+}
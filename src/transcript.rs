@@ -0,0 +1,179 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::clock::Clock;
+use crate::compression::{Compression, FileWriter};
+use crate::encryption::Recipients;
+use crate::logging::{file_id, BackgroundWriter, LogSink, LogStream, SessionInfo, DEFAULT_BUFFER_SIZE, TIMESTAMP_FORMAT};
+use crate::vt::Lines;
+
+// journalctl's short format
+const LINE_TIME_FORMAT: &str = "%b %d %H:%M:%S";
+
+// A plain text log of the output, written as the session goes: one line per
+// line of output, stamped with the time it was finished, and markers where
+// the session starts and ends, like journalctl's boot markers
+//
+//   -- Script started on 2024-01-02 03:04:05+00:00 --
+//   Jan 02 03:04:06 $ make
+//   Jan 02 03:04:09 cc -o app main.c
+//   -- Script done on 2024-01-02 03:04:10+00:00 [COMMAND_EXIT_CODE="0"] --
+pub struct TranscriptSink {
+    path: PathBuf,
+    append: bool,
+    compression: Compression,
+    encryption: Option<Recipients>,
+    clock: Arc<dyn Clock>,
+    lines: Lines,
+    writer: Option<BackgroundWriter>,
+    file_id: Option<(u64, u64)>,
+}
+
+impl TranscriptSink {
+    pub fn new(path: PathBuf, append: bool, clock: Arc<dyn Clock>) -> Self {
+        TranscriptSink {
+            path,
+            append,
+            compression: Compression::None,
+            encryption: None,
+            clock,
+            lines: Lines::default(),
+            writer: None,
+            file_id: None,
+        }
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_encryption(mut self, recipients: Option<Recipients>) -> Self {
+        self.encryption = recipients;
+        self
+    }
+
+    fn write(&mut self, text: String) -> Result<()> {
+        let writer = self.writer.as_mut().ok_or_else(|| anyhow!("Transcript not started"))?;
+        if writer.send(text.into_bytes()).is_ok() {
+            return Ok(());
+        }
+        let result = writer.finish();
+        self.writer = None;
+        result
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Err(anyhow!("Log writer stopped")))
+            .with_context(|| format!("Cannot write {}", self.path.display()))
+    }
+
+    fn open(&mut self, append: bool) -> Result<()> {
+        let mut options = OpenOptions::new();
+        options.create(true).write(true).append(append).truncate(!append);
+        let writer = FileWriter::open(&options, &self.path, DEFAULT_BUFFER_SIZE, self.compression, self.encryption.as_ref())?;
+        self.writer = Some(BackgroundWriter::spawn(writer));
+        self.file_id = file_id(&self.path);
+        Ok(())
+    }
+
+    fn stamp(&self, lines: &[String]) -> String {
+        let time = self.clock.wall().format(LINE_TIME_FORMAT).to_string();
+        lines.iter().map(|line| format!("{} {}\n", time, line)).collect()
+    }
+}
+
+impl LogSink for TranscriptSink {
+    fn start(&mut self, _session: &SessionInfo) -> Result<()> {
+        self.open(self.append)?;
+
+        let now = self.clock.wall();
+        self.write(format!("-- Script started on {} --\n", now.format(TIMESTAMP_FORMAT)))
+    }
+
+    // Not counted toward -o; the transcript repeats what the logs hold
+    fn log_data(&mut self, stream: LogStream, data: &[u8]) -> Result<usize> {
+        if let LogStream::Output = stream {
+            let lines = self.lines.feed(data);
+            if !lines.is_empty() {
+                let text = self.stamp(&lines);
+                self.write(text)?;
+            }
+        }
+        Ok(0)
+    }
+
+    fn log_event(&mut self, _name: &str, _message: Option<&str>) -> Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self, exit_status: i32) -> Result<()> {
+        if self.writer.is_none() {
+            return Ok(());
+        }
+        let mut text = String::new();
+        let last = self.lines.take();
+        if !last.is_empty() {
+            text = self.stamp(&[last]);
+        }
+        let now = self.clock.wall();
+        text.push_str(&format!(
+            "-- Script done on {} [COMMAND_EXIT_CODE=\"{}\"] --\n",
+            now.format(TIMESTAMP_FORMAT),
+            exit_status
+        ));
+        self.write(text)?;
+
+        if let Some(mut writer) = self.writer.take() {
+            writer.finish().with_context(|| format!("Cannot write {}", self.path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        if self.writer.is_none() || file_id(&self.path) == self.file_id {
+            return Ok(());
+        }
+        if let Some(mut writer) = self.writer.take() {
+            writer.finish().with_context(|| format!("Cannot write {}", self.path.display()))?;
+        }
+        self.open(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use chrono::DateTime;
+    use std::time::Duration;
+
+    #[test]
+    fn test_transcript_stamps_each_line() {
+        let path = std::env::temp_dir().join(format!("rust_script-{}-transcript.log", std::process::id()));
+        let start = DateTime::parse_from_rfc3339("2024-01-02T03:04:05+00:00").unwrap();
+        let clock = Arc::new(ManualClock::new(start));
+        let mut sink = TranscriptSink::new(path.clone(), false, clock.clone());
+
+        sink.start(&SessionInfo::default()).unwrap();
+        clock.advance(Duration::from_secs(1));
+        sink.log_data(LogStream::Output, b"\x1b[1m$ \x1b[0m").unwrap();
+        sink.log_data(LogStream::Input, b"make\r").unwrap();
+        sink.log_data(LogStream::Output, b"make\r\n").unwrap();
+        clock.advance(Duration::from_secs(3));
+        sink.log_data(LogStream::Output, b"cc -o app main.c\r\n$ ").unwrap();
+        clock.advance(Duration::from_secs(1));
+        sink.close(0).unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            written,
+            "-- Script started on 2024-01-02 03:04:05+00:00 --\n\
+             Jan 02 03:04:06 $ make\n\
+             Jan 02 03:04:09 cc -o app main.c\n\
+             Jan 02 03:04:10 $\n\
+             -- Script done on 2024-01-02 03:04:10+00:00 [COMMAND_EXIT_CODE=\"0\"] --\n"
+        );
+    }
+}
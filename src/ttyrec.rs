@@ -0,0 +1,139 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset, Local};
+use std::time::Duration;
+
+use crate::logging::TIMESTAMP_FORMAT;
+use crate::timing::{self, EntryKind, TimingEntry};
+
+// Every ttyrec frame starts with seconds, microseconds and payload length,
+// each a little-endian u32
+pub const HEADER_LEN: usize = 12;
+
+// Frames this large only show up when the data isn't ttyrec at all
+const MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+
+pub fn frame_header(time: DateTime<FixedOffset>, len: usize) -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    header[0..4].copy_from_slice(&(time.timestamp() as u32).to_le_bytes());
+    header[4..8].copy_from_slice(&time.timestamp_subsec_micros().to_le_bytes());
+    header[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+    header
+}
+
+fn read_header(data: &[u8]) -> Option<(Duration, usize)> {
+    if data.len() < HEADER_LEN {
+        return None;
+    }
+    let field = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+    let (sec, usec, len) = (field(0), field(4), field(8));
+    if usec >= 1_000_000 || len > MAX_FRAME_LEN {
+        return None;
+    }
+    Some((Duration::new(sec as u64, usec * 1000), len as usize))
+}
+
+// ttyrec has no magic number; a sane first frame is the best evidence
+pub fn looks_like_ttyrec(data: &[u8]) -> bool {
+    matches!(read_header(data), Some((_, len)) if len > 0)
+}
+
+// Turn frames into timing entries plus the concatenated payloads, so a
+// ttyrec file plays through the same path as a typescript. The first
+// frame's time becomes the START_TIME H record.
+pub fn parse(data: &[u8]) -> Result<(Vec<TimingEntry>, Vec<u8>)> {
+    let mut entries = Vec::new();
+    let mut payload = Vec::new();
+    let mut last: Option<Duration> = None;
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let (time, len) = read_header(&data[pos..])
+            .ok_or_else(|| anyhow!("ttyrec frame at offset {} is invalid", pos))?;
+        let body = data
+            .get(pos + HEADER_LEN..pos + HEADER_LEN + len)
+            .ok_or_else(|| anyhow!("ttyrec frame at offset {} is truncated", pos))?;
+
+        if last.is_none() {
+            if let Some(start) = DateTime::from_timestamp(time.as_secs() as i64, time.subsec_nanos()) {
+                entries.push(TimingEntry {
+                    delay: Duration::ZERO,
+                    kind: EntryKind::Info("START_TIME".to_string(), start.fixed_offset().format(TIMESTAMP_FORMAT).to_string()),
+                    wall: None,
+                });
+            }
+        }
+        let delay = last.map(|last| time.saturating_sub(last)).unwrap_or_default();
+        last = Some(time);
+        entries.push(TimingEntry {
+            delay,
+            kind: EntryKind::Output(len),
+            wall: None,
+        });
+        payload.extend_from_slice(body);
+        pos += HEADER_LEN + len;
+    }
+
+    Ok((entries, payload))
+}
+
+// Write the output records as frames, timed from START_TIME or from now.
+// Input and events have no place in ttyrec and are dropped.
+pub fn write(entries: &[TimingEntry], data: &[u8]) -> Result<Vec<u8>> {
+    let start = timing::info(entries, "START_TIME")
+        .and_then(|t| DateTime::parse_from_str(t, TIMESTAMP_FORMAT).ok())
+        .unwrap_or_else(|| Local::now().fixed_offset());
+
+    let mut out = Vec::new();
+    let mut offset = Duration::ZERO;
+    let mut pos = 0;
+    for entry in entries {
+        offset += entry.delay;
+        let (size, keep) = match entry.kind {
+            EntryKind::Output(size) => (size, true),
+            EntryKind::Input(size) => (size, false),
+            _ => continue,
+        };
+        let chunk = data
+            .get(pos..pos + size)
+            .ok_or_else(|| anyhow!("log data is shorter than its timing"))?;
+        pos += size;
+        if keep {
+            out.extend_from_slice(&frame_header(start + offset, size));
+            out.extend_from_slice(chunk);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_round_trip() {
+        let start = DateTime::parse_from_rfc3339("2024-01-02T03:04:05.250+00:00").unwrap();
+        let mut data = frame_header(start, 3).to_vec();
+        data.extend_from_slice(b"ls\n");
+        data.extend_from_slice(&frame_header(start + Duration::from_millis(1500), 2));
+        data.extend_from_slice(b"ok");
+
+        assert!(looks_like_ttyrec(&data));
+        assert!(!looks_like_ttyrec(b"Script started on 2024-01-02"));
+
+        let (entries, payload) = parse(&data).unwrap();
+        assert_eq!(payload, b"ls\nok");
+        assert_eq!(entries[0].kind, EntryKind::Info("START_TIME".into(), "2024-01-02 03:04:05+00:00".into()));
+        assert_eq!(entries[1], TimingEntry { delay: Duration::ZERO, kind: EntryKind::Output(3), wall: None });
+        assert_eq!(
+            entries[2],
+            TimingEntry { delay: Duration::from_millis(1500), kind: EntryKind::Output(2), wall: None }
+        );
+        assert!(parse(&data[..data.len() - 1]).is_err());
+
+        // START_TIME has whole seconds, so only those survive
+        let mut whole = data.clone();
+        whole[4..8].fill(0);
+        whole[HEADER_LEN + 3 + 4..HEADER_LEN + 3 + 8].copy_from_slice(&500_000u32.to_le_bytes());
+        assert_eq!(write(&entries, &payload).unwrap(), whole);
+    }
+}
@@ -0,0 +1,57 @@
+// Lines entered at the keyboard, rebuilt from recorded input: escape
+// sequences (arrow keys and such) are skipped and backspaces applied
+#[derive(Debug, Default)]
+pub struct TypedLines {
+    line: String,
+    escape: bool,
+    // The start of a UTF-8 sequence split across records
+    pending: Vec<u8>,
+}
+
+impl TypedLines {
+    // Feed the next input record; returns the lines it finished, leaving out
+    // empty ones
+    pub fn feed(&mut self, data: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(data);
+        let complete = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        let text = String::from_utf8_lossy(&self.pending[..complete]).into_owned();
+        self.pending.drain(..complete);
+
+        let mut lines = Vec::new();
+        for ch in text.chars() {
+            match ch {
+                _ if self.escape => self.escape = !ch.is_ascii_alphabetic() && ch != '~',
+                '\x1b' => self.escape = true,
+                '\r' | '\n' => {
+                    let line = std::mem::take(&mut self.line);
+                    if !line.trim().is_empty() {
+                        lines.push(line);
+                    }
+                }
+                '\x7f' | '\x08' => {
+                    self.line.pop();
+                }
+                c if c.is_control() => {}
+                c => self.line.push(c),
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_lines() {
+        let mut typed = TypedLines::default();
+        assert!(typed.feed(b"ls -l\x7f\x7fa").is_empty());
+        assert_eq!(typed.feed(b"\r\r\x1b[Acd /tmp\rcaf\xc3"), ["ls a", "cd /tmp"]);
+        assert_eq!(typed.feed(b"\xa9\n"), ["café"]);
+    }
+}
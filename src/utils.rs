@@ -1,7 +1,10 @@
 use anyhow::{anyhow, Result};
 use nix::pty::Winsize;
+use std::collections::BTreeMap;
 use std::fs;
+use std::os::unix::io::RawFd;
 use std::path::Path;
+use std::time::Duration;
 
 pub fn is_stdin_tty() -> bool {
     unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
@@ -33,8 +36,12 @@ pub fn get_winsize() -> Result<Winsize> {
 }
 
 pub fn get_terminal_name() -> Option<String> {
+    tty_name(libc::STDIN_FILENO)
+}
+
+pub fn tty_name(fd: RawFd) -> Option<String> {
     unsafe {
-        let tty_name = libc::ttyname(libc::STDIN_FILENO);
+        let tty_name = libc::ttyname(fd);
         if tty_name.is_null() {
             None
         } else {
@@ -44,8 +51,72 @@ pub fn get_terminal_name() -> Option<String> {
     }
 }
 
-pub fn get_terminal_type() -> Option<String> {
-    std::env::var("TERM").ok()
+// The state letter of a process (R, S, D, T, Z...), if it still exists
+pub fn process_state(pid: i32) -> Option<char> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    stat.rfind(')').and_then(|i| stat[i + 1..].trim_start().chars().next())
+}
+
+pub fn session_processes_in_state(session: i32, state: char) -> Vec<i32> {
+    session_processes(session)
+        .into_iter()
+        .filter(|(_, fields)| fields[0].starts_with(state))
+        .map(|(pid, _)| pid)
+        .collect()
+}
+
+// The process groups in the session: its leader's, and those of the jobs
+// started in it
+pub fn session_process_groups(session: i32) -> Vec<i32> {
+    let mut groups: Vec<i32> = session_processes(session)
+        .into_iter()
+        .filter_map(|(_, fields)| fields[2].parse().ok())
+        .collect();
+    groups.sort();
+    groups.dedup();
+    groups
+}
+
+// Each process in the session with its /proc stat fields after the command
+// name: state ppid pgrp session ...
+fn session_processes(session: i32) -> Vec<(i32, Vec<String>)> {
+    let mut processes = Vec::new();
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return processes;
+    };
+
+    for entry in entries.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|n| n.parse::<i32>().ok()) else {
+            continue;
+        };
+        let Ok(stat) = fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+
+        // The command name may contain spaces, so fields are counted from
+        // the closing parenthesis: state ppid pgrp session ...
+        let Some(rest) = stat.rfind(')').map(|i| &stat[i + 1..]) else {
+            continue;
+        };
+        let fields: Vec<String> = rest.split_whitespace().map(str::to_string).collect();
+        if fields.len() > 3 && fields[3] == session.to_string() {
+            processes.push((pid, fields));
+        }
+    }
+
+    processes
+}
+
+pub fn format_termios(termios: &termios::Termios) -> String {
+    let cc: Vec<String> = termios.c_cc.iter().map(|c| format!("{:02x}", c)).collect();
+    format!(
+        "iflag=0x{:x} oflag=0x{:x} cflag=0x{:x} lflag=0x{:x} cc={}",
+        termios.c_iflag,
+        termios.c_oflag,
+        termios.c_cflag,
+        termios.c_lflag,
+        cc.join(":")
+    )
 }
 
 pub fn parse_size(size_str: &str) -> Result<u64> {
@@ -83,7 +154,105 @@ pub fn parse_size(size_str: &str) -> Result<u64> {
     let number: u64 = number_part.parse()
         .map_err(|_| anyhow!("Invalid number in size: {}", number_part))?;
 
-    Ok(number * suffix)
+    number.checked_mul(suffix).ok_or_else(|| anyhow!("Invalid size: {}", size_str))
+}
+
+pub fn parse_duration(duration_str: &str) -> Result<Duration> {
+    let duration_str = duration_str.trim().to_lowercase();
+
+    let (number_part, unit) = if let Some(n) = duration_str.strip_suffix("ms") {
+        (n, 0.001)
+    } else if let Some(n) = duration_str.strip_suffix('s') {
+        (n, 1.0)
+    } else if let Some(n) = duration_str.strip_suffix('m') {
+        (n, 60.0)
+    } else if let Some(n) = duration_str.strip_suffix('h') {
+        (n, 3600.0)
+    } else {
+        (&duration_str[..], 1.0)
+    };
+
+    let number: f64 = number_part.trim().parse()
+        .map_err(|_| anyhow!("Invalid duration: {}", duration_str))?;
+    if !number.is_finite() || number < 0.0 {
+        return Err(anyhow!("Invalid duration: {}", duration_str));
+    }
+
+    secs_to_duration(number * unit).ok_or_else(|| anyhow!("Invalid duration: {}", duration_str))
+}
+
+// Seconds from a file or the command line as a Duration, or None for what
+// no Duration holds: negative, not a number, or too large
+pub fn secs_to_duration(secs: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+// --label ticket=OPS-12: a word for the key, anything on one line for the value
+pub fn parse_label(label: &str) -> Result<(&str, &str)> {
+    let invalid = || anyhow!("Invalid label: '{}' (e.g. ticket=OPS-12)", label);
+    let (key, value) = label.split_once('=').ok_or_else(invalid)?;
+    let word = |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | '.');
+    if key.is_empty() || !key.chars().all(word) || value.contains(['\r', '\n']) {
+        return Err(invalid());
+    }
+    Ok((key, value))
+}
+
+// Words as a shell command line that reads back as the same words, for
+// showing a command that runs without a shell
+pub fn shell_join(words: &[String]) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-+=.,:/@%".contains(c);
+    let quoted: Vec<String> = words
+        .iter()
+        .map(|word| match word.chars().all(plain) && !word.is_empty() {
+            true => word.clone(),
+            false => format!("'{}'", word.replace('\'', "'\\''")),
+        })
+        .collect();
+    quoted.join(" ")
+}
+
+// Line ends as a terminal in raw mode needs them, \n going to \r\n
+pub fn crlf(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for (i, &byte) in data.iter().enumerate() {
+        if byte == b'\n' && (i == 0 || data[i - 1] != b'\r') {
+            out.push(b'\r');
+        }
+        out.push(byte);
+    }
+    out
+}
+
+// Open file descriptors of this process and what each one points to
+pub fn open_fds() -> Result<BTreeMap<i32, String>> {
+    let own_dir = format!("/proc/{}/fd", std::process::id());
+    let mut fds = BTreeMap::new();
+
+    for entry in fs::read_dir("/proc/self/fd")? {
+        let entry = entry?;
+        let Ok(fd) = entry.file_name().to_string_lossy().parse::<i32>() else {
+            continue;
+        };
+        let target = fs::read_link(entry.path())
+            .map(|p| p.display().to_string())
+            .unwrap_or_default();
+        // Skip the descriptor read_dir itself is holding
+        if target != own_dir {
+            fds.insert(fd, target);
+        }
+    }
+
+    Ok(fds)
+}
+
+// Descriptors open now that were not open in `before`, as "fd -> target"
+pub fn fd_leaks(before: &BTreeMap<i32, String>) -> Result<Vec<String>> {
+    Ok(open_fds()?
+        .into_iter()
+        .filter(|(fd, target)| before.get(fd) != Some(target))
+        .map(|(fd, target)| format!("{} -> {}", fd, target))
+        .collect())
 }
 
 pub fn die_if_link<P: AsRef<Path>>(path: P) -> Result<()> {
@@ -128,5 +297,56 @@ mod tests {
         assert_eq!(parse_size("1gb").unwrap(), 1024 * 1024 * 1024);
         assert_eq!(parse_size("2K").unwrap(), 2 * 1024);
         assert_eq!(parse_size("5M").unwrap(), 5 * 1024 * 1024);
+        assert!(parse_size("100000000000g").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("45s").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("10m").unwrap(), Duration::from_secs(600));
+        assert_eq!(parse_duration("1H").unwrap(), Duration::from_secs(3600));
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("1e300h").is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_label() {
+        assert_eq!(parse_label("ticket=OPS-12").unwrap(), ("ticket", "OPS-12"));
+        assert_eq!(parse_label("note=db restart, take 2").unwrap(), ("note", "db restart, take 2"));
+        assert!(parse_label("ticket").is_err());
+        assert!(parse_label("my ticket=1").is_err());
+        assert!(parse_label("=1").is_err());
+    }
+
+    #[test]
+    fn test_shell_join() {
+        let words = ["printf", r"%s\n", "a b", "it's", ""].map(String::from);
+        assert_eq!(shell_join(&words), r"printf '%s\n' 'a b' 'it'\''s' ''");
+    }
+
+    #[test]
+    fn test_crlf() {
+        assert_eq!(crlf(b"a\nb\r\n\n"), b"a\r\nb\r\n\r\n");
+    }
+
+    #[test]
+    fn test_process_state() {
+        assert!(process_state(std::process::id() as i32).is_some_and(|state| state != 'Z'));
+        assert_eq!(process_state(i32::MAX), None);
+    }
+
+    #[test]
+    fn test_fd_leaks() {
+        let before = open_fds().unwrap();
+        let file = fs::File::open("/proc/self/status").unwrap();
+        let leaks = fd_leaks(&before).unwrap();
+        assert_eq!(leaks.len(), 1);
+        assert!(leaks[0].ends_with("/status"));
+        drop(file);
+        assert!(fd_leaks(&before).unwrap().is_empty());
+    }
+}
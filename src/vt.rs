@@ -0,0 +1,852 @@
+// A small terminal emulator: enough of VT100/xterm to know what is on the
+// screen after some output, and which write put each character there.
+// Every character takes one column; wide characters are not special-cased.
+// The parser and the line assembly below are always built; the screen
+// itself only with the `vt` feature.
+
+#[cfg(feature = "vt")]
+use std::sync::Arc;
+
+#[cfg(feature = "vt")]
+use crate::images::{self, Image};
+
+#[cfg(feature = "vt")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub ch: char,
+    // Tag of the write that put the character here
+    pub origin: Option<usize>,
+    pub style: Style,
+    // The OSC 8 hyperlink it is part of (see `Screen::link`)
+    pub link: Option<u32>,
+    // The inline image whose top left corner it is (see `Screen::image`)
+    pub image: Option<u32>,
+}
+
+#[cfg(feature = "vt")]
+const BLANK: Cell = Cell { ch: ' ', origin: None, style: Style::DEFAULT, link: None, image: None };
+
+#[cfg(feature = "vt")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Default,
+    // The eight colors, their bright versions, then the rest of the 256
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+// The sixteen colors as xterm shows them
+#[cfg(feature = "vt")]
+const BASIC: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+#[cfg(feature = "vt")]
+impl Color {
+    // The color as xterm shows it; None for the terminal's own
+    #[cfg_attr(not(any(feature = "html", feature = "gif")), allow(dead_code))]
+    pub fn rgb(self) -> Option<(u8, u8, u8)> {
+        // The 6x6x6 cube, then 24 grays
+        let level = |v: u8| if v == 0 { 0 } else { 55 + 40 * v };
+        match self {
+            Color::Default => None,
+            Color::Indexed(n) if n < 16 => Some(BASIC[n as usize]),
+            Color::Indexed(n) if n < 232 => Some((level((n - 16) / 36), level((n - 16) / 6 % 6), level((n - 16) % 6))),
+            Color::Indexed(n) => Some((8 + 10 * (n - 232), 8 + 10 * (n - 232), 8 + 10 * (n - 232))),
+            Color::Rgb(r, g, b) => Some((r, g, b)),
+        }
+    }
+}
+
+// The colors and attributes SGR (ESC [ ... m) sets for the characters that
+// follow. Bit n of `attrs` is SGR n, from 1 (bold) to 9 (crossed out).
+#[cfg(feature = "vt")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub attrs: u16,
+}
+
+#[cfg(feature = "vt")]
+impl Style {
+    pub const DEFAULT: Style = Style { fg: Color::Default, bg: Color::Default, attrs: 0 };
+
+    // Parameters with subparameters (4:3 and the like) are skipped
+    fn apply(&mut self, params: &str) {
+        let values: Vec<Option<u16>> =
+            params.split(';').map(|p| if p.contains(':') { None } else { Some(p.parse().unwrap_or(0)) }).collect();
+        let mut values = values.into_iter();
+        while let Some(value) = values.next() {
+            let Some(value) = value else { continue };
+            match value {
+                0 => *self = Style::DEFAULT,
+                1..=9 => self.attrs |= 1 << value,
+                22 => self.attrs &= !(1 << 1 | 1 << 2),
+                23 | 24 | 27..=29 => self.attrs &= !(1 << (value - 20)),
+                25 => self.attrs &= !(1 << 5 | 1 << 6),
+                30..=37 => self.fg = Color::Indexed((value - 30) as u8),
+                40..=47 => self.bg = Color::Indexed((value - 40) as u8),
+                90..=97 => self.fg = Color::Indexed((value - 90 + 8) as u8),
+                100..=107 => self.bg = Color::Indexed((value - 100 + 8) as u8),
+                39 => self.fg = Color::Default,
+                49 => self.bg = Color::Default,
+                38 | 48 => {
+                    let mut next = || values.next().flatten().unwrap_or(0).min(255) as u8;
+                    let color = match next() {
+                        5 => Color::Indexed(next()),
+                        2 => Color::Rgb(next(), next(), next()),
+                        _ => continue,
+                    };
+                    if value == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // The sequence that sets this style from any other
+    #[cfg_attr(not(any(feature = "screenshot", feature = "player")), allow(dead_code))]
+    pub fn sgr(&self) -> String {
+        let mut params = vec!["0".to_string()];
+        params.extend((1..=9).filter(|n| self.attrs & (1 << n) != 0).map(|n| n.to_string()));
+        for (color, base) in [(self.fg, 30), (self.bg, 40)] {
+            match color {
+                Color::Default => {}
+                Color::Indexed(n) if n < 8 => params.push((base + n as u16).to_string()),
+                Color::Indexed(n) if n < 16 => params.push((base + 60 + n as u16 - 8).to_string()),
+                Color::Indexed(n) => params.push(format!("{};5;{}", base + 8, n)),
+                Color::Rgb(r, g, b) => params.push(format!("{};2;{};{};{}", base + 8, r, g, b)),
+            }
+        }
+        format!("\x1b[{}m", params.join(";"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    // ESC ( and friends take one more byte
+    Charset,
+    Csi(String),
+    // OSC, DCS and other strings, ended by BEL or ESC \; what an OSC or a
+    // DCS says is kept, up to OSC_MAX bytes, or IMAGE_MAX for an image
+    Text(Str, Option<Vec<u8>>),
+    TextEscape(Str, Option<Vec<u8>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Str {
+    Osc,
+    Dcs,
+}
+
+// Longer strings are swallowed like the others
+const OSC_MAX: usize = 4096;
+const IMAGE_MAX: usize = 16 << 20;
+
+// A Sixel image is a DCS "[params]q...", an iTerm2 one an OSC "1337;File=..."
+fn image(kind: Str, text: &[u8]) -> bool {
+    match kind {
+        Str::Osc => text.starts_with(b"1337;File="),
+        Str::Dcs => text.iter().find(|&&b| !(b.is_ascii_digit() || b == b';')) == Some(&b'q'),
+    }
+}
+
+// What a byte of output amounts to, once sequences are put together
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Print(char),
+    // C0 controls and DEL
+    Control(u8),
+    // ESC and the byte after it
+    Escape(u8),
+    // Parameters and final byte of a CSI sequence
+    Csi(String, char),
+    // What an OSC sequence says, such as "8;;https://example.com"
+    Osc(String),
+    // What a DCS sequence says, such as "q" and a Sixel image
+    Dcs(Vec<u8>),
+}
+
+// Splits output into characters and control sequences. Strings other than
+// OSC and DCS, and charset selections, are swallowed.
+#[derive(Debug)]
+pub struct Parser {
+    state: State,
+    utf8: Vec<u8>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser { state: State::Ground, utf8: Vec::new() }
+    }
+}
+
+fn text_action(kind: Str, text: Vec<u8>) -> Action {
+    match kind {
+        Str::Osc => Action::Osc(String::from_utf8_lossy(&text).into_owned()),
+        Str::Dcs => Action::Dcs(text),
+    }
+}
+
+impl Parser {
+    pub fn advance(&mut self, byte: u8) -> Option<Action> {
+        match std::mem::replace(&mut self.state, State::Ground) {
+            State::Ground => self.ground(byte),
+            State::Escape => match byte {
+                b'[' => {
+                    self.state = State::Csi(String::new());
+                    None
+                }
+                b']' => {
+                    self.state = State::Text(Str::Osc, Some(Vec::new()));
+                    None
+                }
+                b'P' => {
+                    self.state = State::Text(Str::Dcs, Some(Vec::new()));
+                    None
+                }
+                b'X' | b'^' | b'_' => {
+                    self.state = State::Text(Str::Dcs, None);
+                    None
+                }
+                b'(' | b')' | b'*' | b'+' | b'#' => {
+                    self.state = State::Charset;
+                    None
+                }
+                _ => Some(Action::Escape(byte)),
+            },
+            State::Charset => None,
+            State::Csi(mut params) => {
+                if (0x40..=0x7e).contains(&byte) {
+                    return Some(Action::Csi(params, byte as char));
+                }
+                params.push(byte as char);
+                self.state = State::Csi(params);
+                None
+            }
+            State::Text(kind, mut kept) => match byte {
+                0x07 => kept.map(|text| text_action(kind, text)),
+                0x1b => {
+                    self.state = State::TextEscape(kind, kept);
+                    None
+                }
+                _ => {
+                    if let Some(ref mut text) = kept {
+                        text.push(byte);
+                    }
+                    let fits = |text: &Vec<u8>| text.len() <= OSC_MAX || (text.len() <= IMAGE_MAX && image(kind, text));
+                    self.state = State::Text(kind, kept.filter(fits));
+                    None
+                }
+            },
+            State::TextEscape(kind, kept) => {
+                if byte == b'\\' {
+                    return kept.map(|text| text_action(kind, text));
+                }
+                self.state = State::Text(kind, kept);
+                None
+            }
+        }
+    }
+
+    fn ground(&mut self, byte: u8) -> Option<Action> {
+        if !self.utf8.is_empty() || byte >= 0x80 {
+            self.utf8.push(byte);
+            return match std::str::from_utf8(&self.utf8) {
+                Ok(s) => {
+                    let ch = s.chars().next().unwrap_or(char::REPLACEMENT_CHARACTER);
+                    self.utf8.clear();
+                    Some(Action::Print(ch))
+                }
+                Err(e) if e.error_len().is_some() || self.utf8.len() >= 4 => {
+                    self.utf8.clear();
+                    Some(Action::Print(char::REPLACEMENT_CHARACTER))
+                }
+                Err(_) => None,
+            };
+        }
+
+        match byte {
+            0x1b => {
+                self.state = State::Escape;
+                None
+            }
+            0x20..=0x7e => Some(Action::Print(byte as char)),
+            _ => Some(Action::Control(byte)),
+        }
+    }
+}
+
+#[cfg(feature = "vt")]
+pub struct Screen {
+    cols: usize,
+    rows: usize,
+    cells: Vec<Cell>,
+    row: usize,
+    col: usize,
+    // The cursor sits past the last column until the next character wraps
+    wrap_pending: bool,
+    saved: (usize, usize),
+    scroll_top: usize,
+    scroll_bottom: usize,
+    // The main screen while the alternate screen is up
+    main: Option<Vec<Cell>>,
+    // What the next character is printed with
+    style: Style,
+    // The targets of the OSC 8 hyperlinks seen, and the one open
+    links: Vec<String>,
+    link: Option<u32>,
+    // The inline images seen
+    images: Vec<Arc<Image>>,
+    parser: Parser,
+}
+
+#[cfg(feature = "vt")]
+impl Screen {
+    pub fn new(cols: usize, rows: usize) -> Screen {
+        let (cols, rows) = (cols.max(1), rows.max(1));
+        Screen {
+            cols,
+            rows,
+            cells: vec![BLANK; cols * rows],
+            row: 0,
+            col: 0,
+            wrap_pending: false,
+            saved: (0, 0),
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            main: None,
+            style: Style::DEFAULT,
+            links: Vec::new(),
+            link: None,
+            images: Vec::new(),
+            parser: Parser::default(),
+        }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    // Keeps what fits in the top left corner, as terminals do
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        let (cols, rows) = (cols.max(1), rows.max(1));
+        let (old_cols, old_rows) = (self.cols, self.rows);
+        let resize = |cells: &[Cell]| {
+            let mut resized = vec![BLANK; cols * rows];
+            for row in 0..rows.min(old_rows) {
+                for col in 0..cols.min(old_cols) {
+                    resized[row * cols + col] = cells[row * old_cols + col];
+                }
+            }
+            resized
+        };
+        self.main = self.main.as_deref().map(resize);
+        self.cells = resize(&self.cells);
+        self.cols = cols;
+        self.rows = rows;
+        self.row = self.row.min(rows - 1);
+        self.col = self.col.min(cols - 1);
+        self.wrap_pending = false;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+    }
+
+    // Row and column, from 0
+    #[cfg_attr(not(any(feature = "screenshot", feature = "player")), allow(dead_code))]
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.col)
+    }
+
+    #[cfg_attr(not(feature = "player"), allow(dead_code))]
+    pub fn style(&self) -> Style {
+        self.style
+    }
+
+    // Output so far ends partway through a sequence or character
+    #[cfg_attr(not(feature = "player"), allow(dead_code))]
+    pub fn in_sequence(&self) -> bool {
+        self.parser.state != State::Ground || !self.parser.utf8.is_empty()
+    }
+
+    pub fn cell(&self, row: usize, col: usize) -> Option<&Cell> {
+        (row < self.rows && col < self.cols).then(|| &self.cells[row * self.cols + col])
+    }
+
+    // Where the hyperlink a cell is part of goes
+    #[cfg_attr(not(feature = "html"), allow(dead_code))]
+    pub fn link(&self, link: u32) -> Option<&str> {
+        self.links.get(link as usize).map(String::as_str)
+    }
+
+    #[cfg_attr(not(any(feature = "html", feature = "gif")), allow(dead_code))]
+    pub fn image(&self, image: u32) -> Option<&Arc<Image>> {
+        self.images.get(image as usize)
+    }
+
+    // Apply output; every character it leaves on screen is tagged `origin`
+    pub fn feed(&mut self, data: &[u8], origin: usize) {
+        for &byte in data {
+            match self.parser.advance(byte) {
+                Some(Action::Print(ch)) => self.print(ch, origin),
+                Some(Action::Control(byte)) => self.control(byte),
+                Some(Action::Escape(byte)) => self.escape(byte),
+                Some(Action::Csi(params, action)) => self.csi(&params, action),
+                Some(Action::Osc(text)) => self.osc(&text),
+                Some(Action::Dcs(text)) => {
+                    if let Some(image) = images::sixel(&text) {
+                        self.place(image);
+                    }
+                }
+                None => {}
+            }
+        }
+    }
+
+    // OSC 8, "8;params;URI", opens a hyperlink, or with no URI closes it;
+    // OSC 1337 may show an image
+    fn osc(&mut self, text: &str) {
+        if text.starts_with("1337;") {
+            if let Some(image) = images::iterm2(text, (self.cols, self.rows)) {
+                self.place(image);
+            }
+            return;
+        }
+        let Some(uri) = text.strip_prefix("8;").and_then(|rest| rest.split_once(';')).map(|(_, uri)| uri) else {
+            return;
+        };
+        self.link = match self.links.iter().position(|link| link == uri) {
+            _ if uri.is_empty() => None,
+            Some(i) => Some(i as u32),
+            None => {
+                self.links.push(uri.to_string());
+                Some(self.links.len() as u32 - 1)
+            }
+        };
+    }
+
+    // An image covers the cells from the cursor on, down and to the right,
+    // and leaves the cursor on the line below it, scrolling as need be
+    fn place(&mut self, image: Arc<Image>) {
+        let (col, rows) = (self.col, image.rows);
+        self.images.push(image);
+        self.cells[self.row * self.cols + col] = Cell { image: Some(self.images.len() as u32 - 1), ..BLANK };
+        for _ in 0..rows {
+            self.line_feed();
+        }
+        self.col = col;
+    }
+
+    fn control(&mut self, byte: u8) {
+        match byte {
+            b'\r' => {
+                self.col = 0;
+                self.wrap_pending = false;
+            }
+            b'\n' | 0x0b | 0x0c => self.line_feed(),
+            0x08 => {
+                self.col = self.col.saturating_sub(1);
+                self.wrap_pending = false;
+            }
+            b'\t' => {
+                self.col = ((self.col / 8 + 1) * 8).min(self.cols - 1);
+                self.wrap_pending = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn escape(&mut self, byte: u8) {
+        match byte {
+            b'7' => self.saved = (self.row, self.col),
+            b'8' => self.restore_cursor(),
+            b'D' => self.line_feed(),
+            b'E' => {
+                self.col = 0;
+                self.line_feed();
+            }
+            b'M' => self.reverse_index(),
+            b'c' => *self = Screen::new(self.cols, self.rows),
+            _ => {}
+        }
+    }
+
+    fn csi(&mut self, params: &str, action: char) {
+        let private = params.starts_with('?');
+        let values: Vec<usize> = params
+            .trim_start_matches(['?', '>', '='])
+            .split(';')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect();
+        let arg = |i: usize, default: usize| values.get(i).copied().filter(|&v| v != 0).unwrap_or(default);
+        let n = arg(0, 1);
+        self.wrap_pending = false;
+
+        match action {
+            'A' => self.row = self.row.saturating_sub(n).max(if self.row >= self.scroll_top { self.scroll_top } else { 0 }),
+            'B' | 'e' => self.row = (self.row + n).min(if self.row <= self.scroll_bottom { self.scroll_bottom } else { self.rows - 1 }),
+            'C' | 'a' => self.col = (self.col + n).min(self.cols - 1),
+            'D' => self.col = self.col.saturating_sub(n),
+            'E' => {
+                self.row = (self.row + n).min(self.rows - 1);
+                self.col = 0;
+            }
+            'F' => {
+                self.row = self.row.saturating_sub(n);
+                self.col = 0;
+            }
+            'G' | '`' => self.col = (n - 1).min(self.cols - 1),
+            'd' => self.row = (n - 1).min(self.rows - 1),
+            'H' | 'f' => {
+                self.row = (arg(0, 1) - 1).min(self.rows - 1);
+                self.col = (arg(1, 1) - 1).min(self.cols - 1);
+            }
+            'J' => match values[0] {
+                0 => self.clear(self.row * self.cols + self.col, self.cells.len()),
+                1 => self.clear(0, self.row * self.cols + self.col + 1),
+                _ => self.clear(0, self.cells.len()),
+            },
+            'K' => {
+                let line = self.row * self.cols;
+                match values[0] {
+                    0 => self.clear(line + self.col, line + self.cols),
+                    1 => self.clear(line, line + self.col + 1),
+                    _ => self.clear(line, line + self.cols),
+                }
+            }
+            'X' => {
+                let at = self.row * self.cols + self.col;
+                self.clear(at, at + n.min(self.cols - self.col));
+            }
+            '@' => {
+                let line = &mut self.cells[self.row * self.cols..(self.row + 1) * self.cols];
+                let n = n.min(self.cols - self.col);
+                line[self.col..].rotate_right(n);
+                line[self.col..self.col + n].fill(BLANK);
+            }
+            'P' => {
+                let line = &mut self.cells[self.row * self.cols..(self.row + 1) * self.cols];
+                let n = n.min(self.cols - self.col);
+                line[self.col..].rotate_left(n);
+                line[self.cols - n..].fill(BLANK);
+            }
+            'L' if (self.scroll_top..=self.scroll_bottom).contains(&self.row) => {
+                self.scroll_down(self.row, self.scroll_bottom, n);
+            }
+            'M' if (self.scroll_top..=self.scroll_bottom).contains(&self.row) => {
+                self.scroll_up(self.row, self.scroll_bottom, n);
+            }
+            'S' => self.scroll_up(self.scroll_top, self.scroll_bottom, n),
+            'T' if values.len() <= 1 => self.scroll_down(self.scroll_top, self.scroll_bottom, n),
+            'r' if !private => {
+                let (top, bottom) = (arg(0, 1) - 1, arg(1, self.rows) - 1);
+                if top < bottom && bottom < self.rows {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.row = 0;
+                    self.col = 0;
+                }
+            }
+            'm' if !params.starts_with(['?', '>', '=']) => self.style.apply(params),
+            's' if !private => self.saved = (self.row, self.col),
+            'u' if !private => self.restore_cursor(),
+            'h' | 'l' if private => {
+                for mode in &values {
+                    if matches!(mode, 47 | 1047 | 1049) {
+                        self.alternate_screen(action == 'h', *mode == 1049);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn print(&mut self, ch: char, origin: usize) {
+        if self.wrap_pending {
+            self.col = 0;
+            self.line_feed();
+        }
+        self.cells[self.row * self.cols + self.col] = Cell { ch, origin: Some(origin), style: self.style, link: self.link, image: None };
+        if self.col + 1 < self.cols {
+            self.col += 1;
+        } else {
+            self.wrap_pending = true;
+        }
+    }
+
+    fn line_feed(&mut self) {
+        self.wrap_pending = false;
+        if self.row == self.scroll_bottom {
+            self.scroll_up(self.scroll_top, self.scroll_bottom, 1);
+        } else if self.row + 1 < self.rows {
+            self.row += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        self.wrap_pending = false;
+        if self.row == self.scroll_top {
+            self.scroll_down(self.scroll_top, self.scroll_bottom, 1);
+        } else {
+            self.row = self.row.saturating_sub(1);
+        }
+    }
+
+    fn restore_cursor(&mut self) {
+        (self.row, self.col) = self.saved;
+        self.row = self.row.min(self.rows - 1);
+        self.col = self.col.min(self.cols - 1);
+        self.wrap_pending = false;
+    }
+
+    fn clear(&mut self, from: usize, to: usize) {
+        let to = to.min(self.cells.len());
+        if from < to {
+            self.cells[from..to].fill(BLANK);
+        }
+    }
+
+    // Move rows top..=bottom up by n, blanking the rows that come in
+    fn scroll_up(&mut self, top: usize, bottom: usize, n: usize) {
+        let region = &mut self.cells[top * self.cols..(bottom + 1) * self.cols];
+        let n = n.min(bottom + 1 - top) * self.cols;
+        region.rotate_left(n);
+        let len = region.len();
+        region[len - n..].fill(BLANK);
+    }
+
+    fn scroll_down(&mut self, top: usize, bottom: usize, n: usize) {
+        let region = &mut self.cells[top * self.cols..(bottom + 1) * self.cols];
+        let n = n.min(bottom + 1 - top) * self.cols;
+        region.rotate_right(n);
+        region[..n].fill(BLANK);
+    }
+
+    fn alternate_screen(&mut self, enter: bool, save_cursor: bool) {
+        if enter && self.main.is_none() {
+            if save_cursor {
+                self.saved = (self.row, self.col);
+            }
+            self.main = Some(std::mem::replace(&mut self.cells, vec![BLANK; self.cols * self.rows]));
+        } else if !enter {
+            if let Some(main) = self.main.take() {
+                self.cells = main;
+                if save_cursor {
+                    self.restore_cursor();
+                }
+            }
+        }
+    }
+}
+
+// Output put back together into the lines it would read as on a terminal
+// of endless width: carriage returns and backspaces overwrite, the line
+// editing sequences shells use apply, and cursor movement between rows,
+// colors and titles are dropped, as is the alternate screen
+#[derive(Debug, Default)]
+pub struct Lines {
+    parser: Parser,
+    line: Vec<char>,
+    col: usize,
+    alternate: bool,
+}
+
+impl Lines {
+    // Feed output; returns the lines it finished
+    pub fn feed(&mut self, data: &[u8]) -> Vec<String> {
+        let mut finished = Vec::new();
+        for &byte in data {
+            match self.parser.advance(byte) {
+                Some(Action::Print(ch)) if !self.alternate => self.print(ch),
+                Some(Action::Control(b'\n' | 0x0b | 0x0c)) if !self.alternate => finished.push(self.take()),
+                Some(Action::Control(b'\r')) => self.col = 0,
+                Some(Action::Control(0x08)) => self.col = self.col.saturating_sub(1),
+                Some(Action::Control(b'\t')) => self.col = (self.col / 8 + 1) * 8,
+                Some(Action::Escape(b'E')) if !self.alternate => finished.push(self.take()),
+                Some(Action::Csi(params, action)) => self.csi(&params, action),
+                _ => {}
+            }
+        }
+        finished
+    }
+
+    // What is left of an unfinished line
+    pub fn take(&mut self) -> String {
+        self.col = 0;
+        let line: String = self.line.drain(..).collect();
+        line.trim_end().to_string()
+    }
+
+    // The unfinished line as it stands, trailing blanks and all
+    pub fn current(&self) -> String {
+        self.line.iter().collect()
+    }
+
+    fn print(&mut self, ch: char) {
+        if self.col >= self.line.len() {
+            self.line.resize(self.col, ' ');
+            self.line.push(ch);
+        } else {
+            self.line[self.col] = ch;
+        }
+        self.col += 1;
+    }
+
+    fn csi(&mut self, params: &str, action: char) {
+        if let Some(modes) = params.strip_prefix('?') {
+            if matches!(action, 'h' | 'l') && modes.split(';').any(|m| matches!(m, "47" | "1047" | "1049")) {
+                self.alternate = action == 'h';
+            }
+            return;
+        }
+        if self.alternate {
+            return;
+        }
+
+        let n = params.split(';').next().and_then(|p| p.parse().ok()).filter(|&n| n > 0);
+        let len = self.line.len();
+        match action {
+            'C' => self.col += n.unwrap_or(1),
+            'D' => self.col = self.col.saturating_sub(n.unwrap_or(1)),
+            'G' => self.col = n.unwrap_or(1) - 1,
+            'K' => match n.unwrap_or(0) {
+                0 => self.line.truncate(self.col),
+                1 => self.line[..(self.col + 1).min(len)].fill(' '),
+                _ => self.line.clear(),
+            },
+            'P' if self.col < len => {
+                self.line.drain(self.col..(self.col + n.unwrap_or(1)).min(len));
+            }
+            '@' if self.col < len => {
+                self.line.splice(self.col..self.col, std::iter::repeat_n(' ', n.unwrap_or(1)));
+            }
+            'X' if self.col < len => {
+                self.line[self.col..(self.col + n.unwrap_or(1)).min(len)].fill(' ');
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "vt")]
+    fn text(screen: &Screen, row: usize) -> String {
+        let (cols, _) = screen.size();
+        (0..cols).map(|col| screen.cell(row, col).unwrap().ch).collect::<String>().trim_end().to_string()
+    }
+
+    #[cfg(feature = "vt")]
+    #[test]
+    fn test_screen_contents_and_origins() {
+        let mut screen = Screen::new(10, 3);
+        screen.feed(b"$ ls\r\n", 0);
+        screen.feed("a\tb\r\nh\u{e9}llo".as_bytes(), 1);
+        assert_eq!(text(&screen, 0), "$ ls");
+        assert_eq!(text(&screen, 1), "a       b");
+        assert_eq!(text(&screen, 2), "héllo");
+        assert_eq!(screen.cell(0, 3).unwrap().origin, Some(0));
+        assert_eq!(screen.cell(2, 1).unwrap().origin, Some(1));
+
+        // Overwrite in place, then scroll the first line away
+        screen.feed(b"\x1b[1;3HPS\x1b[K\x1b[3;1H\n", 2);
+        assert_eq!(text(&screen, 0), "a       b");
+        assert_eq!(text(&screen, 1), "héllo");
+        assert_eq!(text(&screen, 2), "");
+
+        screen.feed(b"\x1b]0;title\x07\x1b[2;2H\x1b[1P\x1b[?1049h\x1b[Hfull", 3);
+        assert_eq!(text(&screen, 1), "");
+        assert_eq!(text(&screen, 0), "full");
+        screen.feed(b"\x1b[?1049l", 4);
+        assert_eq!(text(&screen, 1), "hllo");
+        assert_eq!(screen.cell(1, 0).unwrap().origin, Some(1));
+
+        // Lines wrap at the right margin
+        screen.feed(b"\x1b[2J\x1b[H0123456789ab", 5);
+        assert_eq!(text(&screen, 0), "0123456789");
+        assert_eq!(text(&screen, 1), "ab");
+        screen.resize(4, 2);
+        assert_eq!(text(&screen, 1), "ab");
+        assert_eq!(screen.cell(2, 0), None);
+    }
+
+    #[cfg(feature = "vt")]
+    #[test]
+    fn test_cells_keep_their_style() {
+        let mut screen = Screen::new(10, 1);
+        screen.feed(b"a\x1b[1;31mb\x1b[22;4;48;5;200mc\x1b[38;2;1;2;3;24;49md\x1b[4:3;0;97me\x1b[>4;1mf", 0);
+        let style = |col| screen.cell(0, col).unwrap().style;
+        assert_eq!(style(0), Style::DEFAULT);
+        assert_eq!(style(1), Style { fg: Color::Indexed(1), bg: Color::Default, attrs: 1 << 1 });
+        assert_eq!(style(2), Style { fg: Color::Indexed(1), bg: Color::Indexed(200), attrs: 1 << 4 });
+        assert_eq!(style(3), Style { fg: Color::Rgb(1, 2, 3), bg: Color::Default, attrs: 0 });
+        assert_eq!(style(4), Style { fg: Color::Indexed(15), ..Style::DEFAULT });
+        assert_eq!(style(5), style(4));
+
+        assert_eq!(style(2).sgr(), "\x1b[0;4;31;48;5;200m");
+        assert_eq!(style(3).sgr(), "\x1b[0;38;2;1;2;3m");
+        assert_eq!(style(4).sgr(), "\x1b[0;97m");
+    }
+
+    #[cfg(feature = "vt")]
+    #[test]
+    fn test_images_take_cells() {
+        let mut screen = Screen::new(10, 4);
+        screen.feed(b"$ cat\r\n \x1bPq\"1;1;25;45~\x1b\\x", 0);
+        // Three rows down from the second, the screen scrolled by one
+        let anchor = screen.cell(0, 1).unwrap().image.unwrap();
+        assert_eq!(screen.image(anchor).map(|image| (image.cols, image.rows)), Some((3, 3)));
+        assert_eq!(text(&screen, 3), " x");
+
+        // An iTerm2 image not shown inline is skipped; one is kept whole
+        // past the length of other strings
+        screen.feed(b"\x1b]1337;File=name=eA==:iVBORw0KGgoAAAANSUhEUgAAAGQAAAAo\x07", 1);
+        assert_eq!(screen.images.len(), 1);
+        let mut parser = Parser::default();
+        let long = [b"\x1b]1337;File=inline=1:".as_slice(), &vec![b'A'; OSC_MAX * 2], b"\x07"].concat();
+        let actions: Vec<Action> = long.iter().filter_map(|&byte| parser.advance(byte)).collect();
+        assert!(matches!(actions[..], [Action::Osc(ref text)] if text.len() > OSC_MAX * 2));
+        let long = [b"\x1b]0;".as_slice(), &vec![b'A'; OSC_MAX * 2], b"\x07"].concat();
+        assert_eq!(long.iter().filter_map(|&byte| parser.advance(byte)).count(), 0);
+    }
+
+    #[test]
+    fn test_lines_apply_line_editing() {
+        let mut lines = Lines::default();
+        assert!(lines.feed(b"\x1b[32m$ \x1b[0mls -l").is_empty());
+        // Readline moving back over a typo, then output
+        assert_eq!(lines.feed(b"\x08\x08\x1b[Ka\r\ntotal 0\r\n"), ["$ ls a", "total 0"]);
+
+        // A progress bar redrawn in place keeps only its last state
+        assert_eq!(lines.feed(b"10%\r50%\r100%\x1b[K done\n"), ["100% done"]);
+
+        // Nothing of a full-screen program makes it through
+        assert_eq!(lines.feed(b"\x1b[?1049h\x1b[Hvim\r\n~\r\n\x1b[?1049l$ \x1b]0;title\x07"), Vec::<String>::new());
+        assert_eq!(lines.feed(b"a\tb\x1b[1G\x1b[1P\x1b[1@ "), Vec::<String>::new());
+        assert_eq!(lines.take(), "  a     b");
+    }
+}
@@ -0,0 +1,72 @@
+use nix::unistd::Pid;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use crate::utils;
+
+// How often /proc is rescanned once the session has been idle long enough
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Watchdog {
+    timeout: Duration,
+    hook: Option<String>,
+    last_activity: Instant,
+    last_check: Instant,
+    fired: bool,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration, hook: Option<String>) -> Self {
+        let now = Instant::now();
+        Watchdog {
+            timeout,
+            hook,
+            last_activity: now,
+            last_check: now,
+            fired: false,
+        }
+    }
+
+    pub fn activity(&mut self) {
+        self.last_activity = Instant::now();
+        self.fired = false;
+    }
+
+    pub fn idle(&self) -> Duration {
+        self.last_activity.elapsed()
+    }
+
+    // Returns the stuck processes the first time the session is found idle
+    // past the timeout with something in uninterruptible sleep
+    pub fn check(&mut self, session: Pid) -> Option<Vec<i32>> {
+        if self.fired || self.idle() < self.timeout || self.last_check.elapsed() < CHECK_INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+
+        let stalled = utils::session_processes_in_state(session.as_raw(), 'D');
+        if stalled.is_empty() {
+            return None;
+        }
+
+        self.fired = true;
+        Some(stalled)
+    }
+
+    pub fn run_hook(&self, child: Pid, stalled: &[i32]) {
+        let Some(ref hook) = self.hook else {
+            return;
+        };
+
+        let pids: Vec<String> = stalled.iter().map(|p| p.to_string()).collect();
+        let _ = tokio::process::Command::new("/bin/sh")
+            .arg("-c")
+            .arg(hook)
+            .env("SCRIPT_CHILD_PID", child.as_raw().to_string())
+            .env("SCRIPT_STALLED_PIDS", pids.join(" "))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+    }
+}
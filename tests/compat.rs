@@ -0,0 +1,452 @@
+// Compatibility checks against util-linux script(1)
+//
+// Every directory under tests/fixtures/util-linux holds the files util-linux
+// produced for the runs listed in its `args` file (see generate.sh). The same
+// runs are repeated with our binary and the results must match once times
+// and deltas are masked.
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+// Header records we write on top of what util-linux does
+const EXTENSION_HEADERS: &[&str] = &["EXIT_TERMIOS", "EXIT_COLUMNS", "EXIT_LINES", "EXIT_CURSOR", "EXIT_SCREEN"];
+
+const RUN_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn fixture_dir(scenario: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/util-linux")
+        .join(scenario)
+}
+
+// Run one session in `dir` and return its exit status
+fn run_once(dir: &Path, args: &[&str]) -> Option<i32> {
+    run_with_input(dir, args, None)
+}
+
+// With `input`, stdin is a pipe that gives it and then ends
+fn run_with_input(dir: &Path, args: &[&str], input: Option<&[u8]>) -> Option<i32> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust_script"))
+        .arg("-q")
+        .args(args)
+        .current_dir(dir)
+        .env("SHELL", "/bin/sh")
+        .env_remove("SCRIPT")
+        .env("XDG_STATE_HOME", std::env::temp_dir().join(format!("rust_script-compat-{}-state", std::process::id())))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Keep stdin open but silent, like a terminal nobody types into
+    let mut stdin = child.stdin.take();
+    if let Some(input) = input {
+        use std::io::Write;
+        stdin.take().unwrap().write_all(input).unwrap();
+    }
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status.code();
+        }
+        if started.elapsed() > RUN_TIMEOUT {
+            let _ = child.kill();
+            panic!("`{}` did not finish", args.join(" "));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn run_scenario(scenario: &str) -> PathBuf {
+    let work_dir = std::env::temp_dir()
+        .join(format!("rust_script-compat-{}-{}", std::process::id(), scenario));
+    let _ = fs::remove_dir_all(&work_dir);
+    fs::create_dir_all(&work_dir).unwrap();
+
+    let runs = fs::read_to_string(fixture_dir(scenario).join("args")).unwrap();
+    for line in runs.lines().filter(|l| !l.is_empty()) {
+        let args: Vec<&str> = line.split('\t').collect();
+        run_once(&work_dir, &args);
+    }
+
+    work_dir
+}
+
+fn is_delta(field: &str) -> bool {
+    match field.split_once('.') {
+        Some((secs, frac)) => {
+            !secs.is_empty()
+                && secs.bytes().all(|b| b.is_ascii_digit())
+                && frac.len() == 6
+                && frac.bytes().all(|b| b.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+fn normalize_line(line: &str) -> Option<String> {
+    for prefix in ["Script started on ", "Script done on "] {
+        if let Some(rest) = line.strip_prefix(prefix) {
+            if let Some(pos) = rest.find(" [") {
+                return Some(format!("{}<TIME>{}", prefix, &rest[pos..]));
+            }
+        }
+    }
+
+    let fields: Vec<&str> = line.trim_end_matches('\n').split(' ').collect();
+    match fields.as_slice() {
+        [delta, bytes] if is_delta(delta) => Some(format!("<DELTA> {}\n", bytes)),
+        ["H", _, name, ..] if EXTENSION_HEADERS.contains(name) => None,
+        ["H", delta, "START_TIME", ..] => Some(format!("H {} START_TIME <TIME>\n", delta)),
+        ["H", delta, "DURATION", _] => Some(format!("H {} DURATION <DELTA>\n", delta)),
+        [kind @ ("I" | "O" | "S"), delta, rest @ ..] if is_delta(delta) => {
+            Some(format!("{} <DELTA> {}\n", kind, rest.join(" ")))
+        }
+        _ => Some(line.to_string()),
+    }
+}
+
+fn normalize(content: &str) -> String {
+    content.split_inclusive('\n').filter_map(normalize_line).collect()
+}
+
+fn produced_files(dir: &Path) -> BTreeSet<String> {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name != "args" && name != "generate.sh")
+        .collect()
+}
+
+fn check(scenario: &str) {
+    let expected_dir = fixture_dir(scenario);
+    let actual_dir = run_scenario(scenario);
+
+    assert_eq!(
+        produced_files(&actual_dir),
+        produced_files(&expected_dir),
+        "{}: different set of files written",
+        scenario
+    );
+
+    for name in produced_files(&expected_dir) {
+        let expected = fs::read_to_string(expected_dir.join(&name)).unwrap();
+        let actual = fs::read_to_string(actual_dir.join(&name)).unwrap();
+        assert_eq!(
+            normalize(&actual),
+            normalize(&expected),
+            "{}/{} differs from util-linux",
+            scenario,
+            name
+        );
+    }
+
+    fs::remove_dir_all(&actual_dir).unwrap();
+}
+
+#[test]
+fn default_typescript() {
+    check("default");
+}
+
+#[test]
+fn log_io_uses_advanced_timing() {
+    check("log_io");
+}
+
+#[test]
+fn log_out_uses_classic_timing() {
+    check("log_out_classic");
+}
+
+#[test]
+fn separate_input_and_output_logs() {
+    check("log_in_out");
+}
+
+#[test]
+fn forced_advanced_format() {
+    check("advanced_forced");
+}
+
+#[test]
+fn append_keeps_raw_and_truncates_timing() {
+    check("append");
+}
+
+// Exit statuses util-linux 2.38 gives for the same runs
+#[test]
+fn return_propagates_exit_code() {
+    let dir = std::env::temp_dir().join(format!("rust_script-compat-{}-return", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    assert_eq!(run_once(&dir, &["-c", "exit 3", "/dev/null"]), Some(0));
+    assert_eq!(run_once(&dir, &["-e", "-c", "exit 3", "/dev/null"]), Some(3));
+    assert_eq!(run_once(&dir, &["-e", "-c", "kill -TERM $$", "/dev/null"]), Some(143));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Piped input is read to its end, and the end passed on, as util-linux does
+#[test]
+fn piped_stdin_ends_with_eof() {
+    let dir = std::env::temp_dir().join(format!("rust_script-compat-{}-pipe", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let args = ["-e", "-c", "cat; echo cat $?", "out"];
+    assert_eq!(run_with_input(&dir, &args, Some(b"one\ntwo")), Some(0));
+    // Echoed, and printed by cat, which only stops at the end of its input
+    let out = fs::read_to_string(dir.join("out")).unwrap();
+    assert_eq!(out.matches("two").count(), 2, "{}", out);
+    assert!(out.contains("cat 0"), "{}", out);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Not in util-linux: the session runs without any input
+#[test]
+fn no_stdin_records_output_alone() {
+    let dir = std::env::temp_dir().join(format!("rust_script-compat-{}-no-stdin", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let args = ["--no-stdin", "-c", "stty size; echo done", "out"];
+    assert_eq!(run_with_input(&dir, &args, Some(b"ignored\n")), Some(0));
+    let out = fs::read_to_string(dir.join("out")).unwrap();
+    assert!(out.contains("24 80\r\ndone\r\n"), "{}", out);
+    assert!(!out.contains("ignored"), "{}", out);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Not in util-linux: a full-screen program is left out of the log, with a
+// marker counting the output it wrote, switching sequences included
+#[test]
+fn skip_alt_screen_leaves_a_marker() {
+    let dir = std::env::temp_dir().join(format!("rust_script-compat-{}-alt-screen", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let command = r"printf 'before\n\033[?1049hfull screen\033[?1049lafter\n'";
+    let args = ["--no-stdin", "--skip-alt-screen", "-c", command, "out"];
+    assert_eq!(run_once(&dir, &args), Some(0));
+    let out = fs::read_to_string(dir.join("out")).unwrap();
+    assert!(out.contains("before\r\n[alt-screen session, 27 bytes omitted, 0 seconds]\r\nafter\r\n"), "{}", out);
+    // The header line quotes the command
+    let session = out.split_once('\n').map_or("", |(_, rest)| rest);
+    assert!(!session.contains("full screen"), "{}", out);
+    assert!(!session.contains("\x1b[?1049"), "{}", out);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Not in util-linux: a job left writing to the terminal is recorded until
+// it closes it, unless only the command is waited for
+#[test]
+fn wait_for_pty_outlives_the_command() {
+    let dir = std::env::temp_dir().join(format!("rust_script-compat-{}-wait-for", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    // The job is sent SIGHUP as the shell exits, likely before it could
+    // have set a trap of its own
+    let command = "trap '' HUP; (sleep 0.5; echo late) & echo early; exit 3";
+    assert_eq!(run_once(&dir, &["--no-stdin", "-e", "-c", command, "pty"]), Some(3));
+    assert_eq!(run_once(&dir, &["--no-stdin", "-e", "--wait-for", "child", "-c", command, "child"]), Some(3));
+    let pty = fs::read_to_string(dir.join("pty")).unwrap();
+    assert!(pty.contains("early\r\nlate\r\n"), "{}", pty);
+    let child = fs::read_to_string(dir.join("child")).unwrap();
+    assert!(child.contains("early\r\n") && !child.contains("late\r\n"), "{}", child);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Not in util-linux: a program and its arguments run without a shell
+#[test]
+fn program_after_double_dash_runs_without_a_shell() {
+    let dir = std::env::temp_dir().join(format!("rust_script-compat-{}-exec", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let args = ["--no-stdin", "log", "--", "printf", "[%s]", "a b", "$HOME", "it's"];
+    assert_eq!(run_once(&dir, &args), Some(0));
+    let log = fs::read_to_string(dir.join("log")).unwrap();
+    assert!(log.contains("[a b][$HOME][it's]"), "{}", log);
+    assert!(log.contains(r#"COMMAND="printf '[%s]' 'a b' '$HOME' 'it'\''s'""#), "{}", log);
+    assert_eq!(run_once(&dir, &["--no-stdin", "-e", "log", "--", "sh", "-c", "exit 4"]), Some(4));
+    assert_eq!(run_once(&dir, &["--no-stdin", "log", "--", "no-such-program"]), Some(5));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn child_environment_is_set_and_cleared() {
+    let dir = std::env::temp_dir().join(format!("rust_script-compat-{}-env", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("demo.env"), "# demo\nA='from file'\nB=2\n").unwrap();
+
+    let env = ["--clear-env", "--env-file", "demo.env", "--env", "B=3"];
+    let args = [&["--no-stdin"][..], &env, &["-c", "echo \"[$A][$B][$HOME]\"", "log"]].concat();
+    assert_eq!(run_once(&dir, &args), Some(0));
+    let log = fs::read_to_string(dir.join("log")).unwrap();
+    assert!(log.contains("[from file][3][]"), "{}", log);
+    assert_eq!(run_once(&dir, &["--no-stdin", "--env", "1A=x", "-c", "true", "log"]), Some(2));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn nested_recordings_are_refused() {
+    let dir = std::env::temp_dir().join(format!("rust_script-compat-{}-nested", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    assert_eq!(run_once(&dir, &["--no-stdin", "-c", "echo \"[$SCRIPT]\"", "log"]), Some(0));
+    assert!(fs::read_to_string(dir.join("log")).unwrap().contains("[log]"));
+    let inner = format!("{} -q --no-stdin -c true inner", env!("CARGO_BIN_EXE_rust_script"));
+    assert_eq!(run_once(&dir, &["--no-stdin", "-e", "-c", &inner, "log"]), Some(2));
+    assert!(!dir.join("inner").exists());
+    let inner = format!("{} -q --no-stdin --force-nested -c true inner", env!("CARGO_BIN_EXE_rust_script"));
+    assert_eq!(run_once(&dir, &["--no-stdin", "-e", "-c", &inner, "log"]), Some(0));
+    assert!(dir.join("inner").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn recorder_failures_have_distinct_codes() {
+    let dir = std::env::temp_dir().join(format!("rust_script-compat-{}-failures", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    assert_eq!(run_once(&dir, &["--read-buffer", "0", "-c", "true", "/dev/null"]), Some(2));
+    assert_eq!(run_once(&dir, &["--write-buffer", "1000g", "-c", "true", "/dev/null"]), Some(2));
+    assert_eq!(run_once(&dir, &["-c", "true", "missing/typescript"]), Some(3));
+    assert_eq!(run_once(&dir, &["-o", "10", "-c", "seq 1 1000", "/dev/null"]), Some(6));
+    // A failure beats the child's status even with -e
+    assert_eq!(run_once(&dir, &["-e", "-o", "10", "-c", "seq 1 1000; exit 9", "/dev/null"]), Some(6));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn output_limit_ends_the_whole_session() {
+    let dir = std::env::temp_dir().join(format!("rust_script-compat-{}-limit", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    // Deaf to the hangup of the terminal closing, in a job of its own
+    let command = "set -m; sh -c 'trap \"\" HUP; exec sleep 30' & echo $! > pid; seq 1 1000; wait";
+    assert_eq!(run_once(&dir, &["--no-stdin", "-o", "100", "-c", command, "/dev/null"]), Some(6));
+    let pid = fs::read_to_string(dir.join("pid")).unwrap();
+    let stat = Path::new("/proc").join(pid.trim()).join("stat");
+    let gone = (0..50).any(|_| {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::read_to_string(&stat).map_or(true, |stat| stat.contains(") Z"))
+    });
+    assert!(gone, "{} is still running", pid.trim());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn signals_to_the_recorder_reach_the_session() {
+    let dir = std::env::temp_dir().join(format!("rust_script-compat-{}-signals", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    // The recorder is the command's parent. The signals go to the whole
+    // process group, the sleeps included, so they run in the background:
+    // a signal ends the wait, and the status is the shell's own.
+    let command = "trap 'echo got USR1' USR1; trap 'echo got INT' INT; \
+        kill -USR1 $PPID; sleep 1 & wait $!; kill -INT $PPID; sleep 1 & wait $!; exit 0";
+    let args = ["--no-stdin", "-e", "-m", "advanced", "-T", "tm", "-c", command, "-B", "log"];
+    assert_eq!(run_once(&dir, &args), Some(0));
+    let log = fs::read_to_string(dir.join("log")).unwrap();
+    assert!(log.contains("got USR1") && log.contains("got INT"), "{}", log);
+    let timing = fs::read_to_string(dir.join("tm")).unwrap();
+    let event = |name: &str| timing.lines().any(|line| line.starts_with("S ") && line.contains(name));
+    assert!(event(" SIGUSR1 PGIDS=") && event(" SIGINT PGIDS="), "{}", timing);
+
+    // Without a terminal to hang up, SIGHUP is logrotate's: the logs are
+    // reopened and the session goes on
+    let command = "kill -HUP $PPID; sleep 1; echo still here";
+    let args = ["--no-stdin", "-e", "-m", "advanced", "-T", "tm", "-c", command, "-B", "log"];
+    assert_eq!(run_once(&dir, &args), Some(0));
+    assert!(fs::read_to_string(dir.join("log")).unwrap().contains("still here"));
+    assert!(fs::read_to_string(dir.join("tm")).unwrap().lines().any(|line| line.ends_with(" SIGHUP")));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn dropped_privileges_hold_in_every_recorder_thread() {
+    // Only root has capabilities to drop
+    let status = fs::read_to_string("/proc/self/status").unwrap();
+    if status.lines().any(|line| line == "CapEff:\t0000000000000000") {
+        return;
+    }
+    let dir = std::env::temp_dir().join(format!("rust_script-compat-{}-privs", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    // The output comes first, so the log writers' threads are running when
+    // the recorder's threads are looked at
+    let command = "echo started; sleep 0.5; grep -H CapEff /proc/$PPID/task/*/status";
+    let args = ["--no-stdin", "--drop-privs", "-m", "advanced", "-T", "tm", "-c", command, "log"];
+    assert_eq!(run_once(&dir, &args), Some(0));
+    let log = fs::read_to_string(dir.join("log")).unwrap();
+    let threads: Vec<&str> = log.lines().filter(|line| line.contains("CapEff:")).collect();
+    assert!(threads.len() > 1, "{}", log);
+    assert!(threads.iter().all(|line| line.ends_with("\t0000000000000000")), "{}", log);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn dropped_privileges_can_leave_the_recorder_in_its_own_namespaces() {
+    // Kernels can be built or configured without user namespaces
+    let allowed = fs::read_to_string("/proc/sys/user/max_user_namespaces").is_ok_and(|max| max.trim() != "0");
+    if !allowed {
+        return;
+    }
+    let dir = std::env::temp_dir().join(format!("rust_script-compat-{}-namespace", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let command = "readlink /proc/$PPID/ns/user /proc/self/ns/user /proc/$PPID/ns/mnt /proc/self/ns/mnt";
+    let args = ["--no-stdin", "-q", "--drop-privs", "--drop-privs-namespace", "-c", command, "log"];
+    assert_eq!(run_once(&dir, &args), Some(0));
+    let log = fs::read_to_string(dir.join("log")).unwrap();
+    let ns: Vec<&str> = log.lines().filter(|line| line.contains(":[")).collect();
+    assert_eq!(ns.len(), 4, "{}", log);
+    // The recorder moved, the session it runs stayed where it was started
+    assert_ne!(ns[0], ns[1], "{}", log);
+    assert_ne!(ns[2], ns[3], "{}", log);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn dropped_privileges_leave_root_for_the_sudo_user() {
+    use std::os::unix::fs::MetadataExt;
+    if fs::metadata("/proc/self").unwrap().uid() != 0 {
+        return;
+    }
+    let dir = std::env::temp_dir().join(format!("rust_script-compat-{}-leave-root", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    // The recorder as sudo would start it, reporting its own ids once dropped
+    let inner = format!(
+        "SUDO_UID=65534 SUDO_GID=65534 {} -q --no-stdin --force-nested --drop-privs -c 'sleep 0.2; grep -E \"^(Uid|Gid|Groups)\" /proc/$PPID/status' inner",
+        env!("CARGO_BIN_EXE_rust_script")
+    );
+    assert_eq!(run_once(&dir, &["--no-stdin", "-e", "-c", &inner, "log"]), Some(0));
+    let log = fs::read_to_string(dir.join("inner")).unwrap();
+    assert!(log.contains("Uid:\t65534\t65534\t65534\t65534"), "{}", log);
+    assert!(log.contains("Gid:\t65534\t65534\t65534\t65534"), "{}", log);
+    assert!(log.contains("Groups:\t65534"), "{}", log);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn normalize_masks_times() {
+    assert_eq!(
+        normalize("H 0.000000 START_TIME 2024-01-02 03:04:05+00:00\nO 0.010183 7\n0.5 x\n"),
+        "H 0.000000 START_TIME <TIME>\nO <DELTA> 7\n0.5 x\n"
+    );
+}
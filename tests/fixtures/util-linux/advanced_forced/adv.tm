@@ -0,0 +1,8 @@
+H 0.000000 START_TIME 2026-10-16 14:11:30+00:00
+H 0.000000 SHELL /bin/sh
+H 0.000000 COMMAND printf "hello\n"
+H 0.000000 TIMING_LOG adv.tm
+H 0.000000 OUTPUT_LOG out
+O 0.000160 7
+H 0.000000 DURATION 0.020563
+H 0.000000 EXIT_CODE 0
@@ -0,0 +1 @@
+0.000030 8
@@ -0,0 +1,28 @@
+#!/bin/bash
+
+# Regenerate the util-linux reference outputs used by tests/compat.rs
+#
+# Each scenario directory holds an `args` file with one script(1) run per
+# line, arguments separated by tabs. The runs are executed in order inside
+# the scenario directory, so every file they produce becomes a fixture.
+
+set -e
+
+if ! script --version 2>/dev/null | grep -q util-linux; then
+    echo "Error: util-linux script(1) is required to generate fixtures."
+    exit 1
+fi
+
+cd "$(dirname "$0")"
+export SHELL=/bin/sh
+
+for dir in */; do
+    dir=${dir%/}
+    find "$dir" -type f ! -name args -delete
+
+    while IFS=$'\t' read -r -a argv; do
+        (cd "$dir" && script -q "${argv[@]}" < /dev/null > /dev/null)
+    done < "$dir/args"
+
+    echo "Generated $dir"
+done
@@ -0,0 +1,9 @@
+H 0.000000 START_TIME 2026-10-16 14:11:30+00:00
+H 0.000000 SHELL /bin/sh
+H 0.000000 COMMAND printf "hello\n"; exit 3
+H 0.000000 TIMING_LOG io.tm
+H 0.000000 OUTPUT_LOG io
+H 0.000000 INPUT_LOG io
+O 0.010139 7
+H 0.000000 DURATION 0.020378
+H 0.000000 EXIT_CODE 3
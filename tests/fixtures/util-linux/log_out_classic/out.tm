@@ -0,0 +1 @@
+0.000034 7
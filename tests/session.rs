@@ -0,0 +1,120 @@
+// The library API, driven the way an embedding program would
+use anyhow::Result;
+use rust_script::{LogSink, LogStream, SessionBuilder, SessionInfo};
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+// One session at a time: the signal dispositions are the process's
+static SESSIONS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+#[tokio::test(flavor = "current_thread")]
+async fn builder_records_a_command() {
+    let _one = SESSIONS.lock().await;
+    let dir = std::env::temp_dir().join(format!("rust_script-session-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("make.log");
+
+    let result = SessionBuilder::new()
+        .command("printf 'built\\n'; exit 4")
+        .log_output(&log)
+        .log_timing(dir.join("make.tm"))
+        .run()
+        .await
+        .unwrap();
+
+    assert_eq!(result.exit_code, 4);
+    assert_eq!(result.bytes_in, 0);
+    // The PTY turns \n into \r\n
+    assert_eq!(result.bytes_out, 7);
+    let content = fs::read_to_string(&log).unwrap();
+    assert!(content.contains("built\r\n"), "{}", content);
+    assert!(fs::read_to_string(dir.join("make.tm")).unwrap().ends_with(" 7\n"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Collects what it is sent, in order
+#[derive(Clone, Default)]
+struct Collect(Arc<Mutex<Vec<String>>>);
+
+impl LogSink for Collect {
+    fn start(&mut self, session: &SessionInfo) -> Result<()> {
+        self.0.lock().unwrap().push(format!("start {:?}", session.command));
+        Ok(())
+    }
+
+    fn log_data(&mut self, stream: LogStream, data: &[u8]) -> Result<usize> {
+        self.0.lock().unwrap().push(format!("{:?} {}", stream, String::from_utf8_lossy(data)));
+        Ok(0)
+    }
+
+    fn log_event(&mut self, name: &str, _message: Option<&str>) -> Result<()> {
+        self.0.lock().unwrap().push(name.to_string());
+        Ok(())
+    }
+
+    fn close(&mut self, exit_status: i32) -> Result<()> {
+        self.0.lock().unwrap().push(format!("close {}", exit_status));
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn custom_sink_sees_the_session() {
+    let _one = SESSIONS.lock().await;
+    let dir = std::env::temp_dir().join(format!("rust_script-sink-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let sink = Collect::default();
+
+    let result = SessionBuilder::new()
+        .command("printf 'hi'")
+        .log_output(dir.join("out.log"))
+        .sink(sink.clone())
+        .run()
+        .await
+        .unwrap();
+
+    assert_eq!(result.exit_code, 0);
+    let records = sink.0.lock().unwrap().clone();
+    assert_eq!(records.first().unwrap(), "start Some(\"printf 'hi'\")");
+    assert!(records.contains(&"Output hi".to_string()), "{:?}", records);
+    assert_eq!(records.last().unwrap(), "close 0");
+    assert!(fs::read_to_string(dir.join("out.log")).unwrap().contains("hi"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn handler(signo: libc::c_int) -> libc::sighandler_t {
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    assert_eq!(unsafe { libc::sigaction(signo, std::ptr::null(), &mut action) }, 0);
+    action.sa_sigaction
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn sessions_leave_signals_as_they_found_them() {
+    let _one = SESSIONS.lock().await;
+    let dir = std::env::temp_dir().join(format!("rust_script-signals-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    assert_eq!(handler(libc::SIGTERM), libc::SIG_DFL);
+
+    // Without its handlers back, the second session's SIGTERM would kill
+    // this process
+    for _ in 0..2 {
+        SessionBuilder::new()
+            .command("kill -TERM $PPID; sleep 5")
+            .log_output(dir.join("out.log"))
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(handler(libc::SIGTERM), libc::SIG_DFL);
+        assert_eq!(handler(libc::SIGINT), libc::SIG_DFL);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn multi_threaded_runtime_is_refused() {
+    let e = SessionBuilder::new().command("true").log_output("/dev/null").run().await.unwrap_err();
+    assert!(format!("{:#}", e).contains("current_thread"), "{:#}", e);
+}